@@ -0,0 +1,23 @@
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int work(int n) {
+    return n * 2;
+}
+
+int main(int argc, char *argv[]) {
+    pid_t pid = fork();
+    if (pid == 0) {
+        printf("child: %d\n", work(21));
+        // With an argument, the child goes on to run that program
+        if (argc > 1) {
+            fflush(stdout);
+            execl(argv[1], argv[1], NULL);
+        }
+        return 0;
+    }
+    waitpid(pid, NULL, 0);
+    printf("parent: %d\n", work(1));
+    return 0;
+}
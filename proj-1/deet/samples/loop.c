@@ -0,0 +1,16 @@
+#include <stdio.h>
+
+int sum_to(int n) {
+    int sum = 0;
+    for (int i = 1; i <= n; i++) {
+        sum += i;
+    }
+    printf("sum = %d\n", sum);
+    return sum;
+}
+
+int main() {
+    int total = sum_to(10);
+    printf("total = %d\n", total);
+    return 0;
+}
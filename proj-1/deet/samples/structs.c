@@ -0,0 +1,30 @@
+#include <stdio.h>
+#include <stddef.h>
+
+struct point {
+    int x;
+    int y;
+};
+
+struct node {
+    int value;
+    struct node *next;
+};
+
+typedef struct {
+    struct point corners[2];
+    const char *label;
+} rect_t;
+
+int main() {
+    struct point s = {3, -4};
+    int arr[4] = {10, 20, 30, 40};
+    struct node third = {3, NULL};
+    struct node second = {2, &third};
+    struct node first = {1, &second};
+    struct node *list = &first;
+    rect_t box = {{{0, 0}, {5, 6}}, "box"};
+    int *p = &arr[1];
+    printf("%d %d %d %s %d\n", s.x, arr[2], list->next->value, box.label, *p);
+    return 0;
+}
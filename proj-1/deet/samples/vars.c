@@ -0,0 +1,22 @@
+#include <stdio.h>
+
+const char *greeting = "hello, world";
+int primes[5] = {2, 3, 5, 7, 11};
+
+int sum(int *values, int count) {
+    int total = 0;
+    for (int i = 0; i < count; i++) {
+        total += values[i];
+    }
+    return total;
+}
+
+int main() {
+    char name[8] = "deet";
+    double ratio = 0.5;
+    char *message = "a \"quoted\" message";
+    int squares[4] = {0, 1, 4, 9};
+    int total = sum(squares, 4);
+    printf("%s %s %s %f %d %d\n", greeting, name, message, ratio, total, primes[0]);
+    return 0;
+}
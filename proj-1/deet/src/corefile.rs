@@ -0,0 +1,383 @@
+//! Reading and writing ELF core files: a snapshot of a process's registers and memory, as the
+//! kernel writes when a program crashes, for looking at after the process is gone.
+//!
+//! Only what deet needs is handled: the registers of one thread (from NT_PRSTATUS), the command
+//! line (from NT_PRPSINFO) and the memory segments. Files deet writes have the same layout as the
+//! kernel's, so gdb can read them too.
+
+use crate::maps::Mapping;
+use libc::user_regs_struct;
+use nix::sys::signal::Signal;
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::mem::size_of;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+/// Sizes and field offsets of the x86-64 `elf_prstatus` and `elf_prpsinfo` structs
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REGS: usize = 112;
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_FNAME: usize = 40;
+const PRPSINFO_PSARGS: usize = 56;
+const PSARGS_LEN: usize = 80;
+
+/// Segment permission flags
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// A range of the process's memory and what was in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: usize,
+    /// PF_R, PF_W and PF_X
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct Core {
+    pub pid: i32,
+    /// The signal the process was stopped or killed by, if any
+    pub signal: Option<Signal>,
+    pub regs: user_regs_struct,
+    /// The command line, cut short to 80 bytes as the kernel does
+    pub command_line: String,
+    pub segments: Vec<Segment>,
+}
+
+impl Core {
+    /// Returns the `len` bytes at `addr`, or None if they weren't all saved in one segment.
+    pub fn read(&self, addr: usize, len: usize) -> Option<&[u8]> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.start <= addr && addr < segment.start + segment.data.len())?;
+        segment
+            .data
+            .get(addr - segment.start..addr - segment.start + len)
+    }
+
+    /// Returns the saved segments as memory mappings. A core file doesn't say which files were
+    /// mapped, so they all look like anonymous memory.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.segments
+            .iter()
+            .map(|segment| {
+                let perm = |flag: u32, c: char| if segment.flags & flag != 0 { c } else { '-' };
+                Mapping {
+                    start: segment.start,
+                    end: segment.start + segment.data.len(),
+                    perms: format!("{}{}{}p", perm(PF_R, 'r'), perm(PF_W, 'w'), perm(PF_X, 'x')),
+                    path: String::new(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Loads the core file at `path`. Returns a message saying what's wrong if it isn't a readable
+/// x86-64 core file.
+pub fn load(path: &str) -> Result<Core, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse(&bytes).ok_or_else(|| format!("\"{}\" is not an x86-64 core file", path))
+}
+
+fn parse(bytes: &[u8]) -> Option<Core> {
+    if bytes.get(..4)? != b"\x7fELF" || bytes.get(4..6)? != [2, 1] {
+        return None;
+    }
+    if read_u16(bytes, 16)? != ET_CORE || read_u16(bytes, 18)? != EM_X86_64 {
+        return None;
+    }
+    let phoff = read_u64(bytes, 32)? as usize;
+    let phnum = read_u16(bytes, 56)? as usize;
+    let mut core = Core {
+        pid: 0,
+        signal: None,
+        regs: unsafe { std::mem::zeroed() },
+        command_line: String::new(),
+        segments: Vec::new(),
+    };
+    let mut found_registers = false;
+    for idx in 0..phnum {
+        // The offsets and sizes come from the file, so a corrupt one mustn't overflow
+        let header_start = idx.checked_mul(PROGRAM_HEADER_SIZE)?.checked_add(phoff)?;
+        let header = bytes.get(header_start..header_start.checked_add(PROGRAM_HEADER_SIZE)?)?;
+        let offset = read_u64(header, 8)? as usize;
+        let filesz = read_u64(header, 32)? as usize;
+        let data = bytes.get(offset..offset.checked_add(filesz)?)?;
+        match read_u32(header, 0)? {
+            PT_LOAD => {
+                let start = read_u64(header, 16)? as usize;
+                // read() and mappings() work out where the segment ends
+                start.checked_add(data.len())?;
+                core.segments.push(Segment {
+                    start,
+                    flags: read_u32(header, 4)?,
+                    // Memory the kernel didn't save (e.g. unmodified code) isn't in the file
+                    data: data.to_vec(),
+                })
+            }
+            PT_NOTE => {
+                for (note_type, desc) in notes(data) {
+                    match note_type {
+                        // Every thread has one of these; the first is the thread that crashed
+                        NT_PRSTATUS if !found_registers && desc.len() >= PRSTATUS_SIZE => {
+                            found_registers = true;
+                            let signal = read_u16(desc, PRSTATUS_CURSIG)? as i32;
+                            core.signal = Signal::try_from(signal).ok();
+                            core.pid = read_u32(desc, PRSTATUS_PID)? as i32;
+                            core.regs = unsafe {
+                                std::ptr::read_unaligned(
+                                    desc[PRSTATUS_REGS..].as_ptr() as *const user_regs_struct
+                                )
+                            };
+                        }
+                        NT_PRPSINFO if desc.len() >= PRPSINFO_SIZE => {
+                            let psargs = &desc[PRPSINFO_PSARGS..PRPSINFO_PSARGS + PSARGS_LEN];
+                            let len = psargs.iter().position(|b| *b == 0).unwrap_or(PSARGS_LEN);
+                            core.command_line = String::from_utf8_lossy(&psargs[..len])
+                                .trim_end()
+                                .to_string();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if found_registers {
+        Some(core)
+    } else {
+        None
+    }
+}
+
+/// Splits the contents of a PT_NOTE segment into (type, description) pairs.
+fn notes(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut notes = Vec::new();
+    while data.len() >= 12 {
+        let namesz = read_u32(data, 0).unwrap() as usize;
+        let descsz = read_u32(data, 4).unwrap() as usize;
+        let note_type = read_u32(data, 8).unwrap();
+        let desc_start = 12 + align4(namesz);
+        let desc = match data.get(desc_start..desc_start + descsz) {
+            Some(desc) => desc,
+            None => break,
+        };
+        notes.push((note_type, desc));
+        data = data.get(desc_start + align4(descsz)..).unwrap_or(&[]);
+    }
+    notes
+}
+
+/// Writes `core` to the file at `path`.
+pub fn save(path: &str, core: &Core) -> std::io::Result<()> {
+    let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+    let signal = core.signal.map_or(0, |signal| signal as i32);
+    prstatus[0..4].copy_from_slice(&signal.to_le_bytes());
+    prstatus[PRSTATUS_CURSIG..PRSTATUS_CURSIG + 2].copy_from_slice(&(signal as u16).to_le_bytes());
+    prstatus[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&core.pid.to_le_bytes());
+    let regs = unsafe {
+        std::slice::from_raw_parts(
+            &core.regs as *const user_regs_struct as *const u8,
+            size_of::<user_regs_struct>(),
+        )
+    };
+    prstatus[PRSTATUS_REGS..PRSTATUS_REGS + regs.len()].copy_from_slice(regs);
+
+    let mut prpsinfo = vec![0u8; PRPSINFO_SIZE];
+    prpsinfo[PRPSINFO_PID..PRPSINFO_PID + 4].copy_from_slice(&core.pid.to_le_bytes());
+    let program = core.command_line.split(' ').next().unwrap_or("");
+    let fname = program.rsplit('/').next().unwrap_or("").as_bytes();
+    let fname = &fname[..fname.len().min(15)];
+    prpsinfo[PRPSINFO_FNAME..PRPSINFO_FNAME + fname.len()].copy_from_slice(fname);
+    let psargs = core.command_line.as_bytes();
+    let psargs = &psargs[..psargs.len().min(PSARGS_LEN - 1)];
+    prpsinfo[PRPSINFO_PSARGS..PRPSINFO_PSARGS + psargs.len()].copy_from_slice(psargs);
+
+    let mut notes = Vec::new();
+    push_note(&mut notes, NT_PRSTATUS, &prstatus);
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo);
+
+    let phnum = 1 + core.segments.len();
+    let mut offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\x7fELF");
+    // 64-bit, little-endian, ELF version 1, System V ABI
+    out.extend_from_slice(&[2, 1, 1, 0]);
+    out.resize(16, 0);
+    out.extend_from_slice(&ET_CORE.to_le_bytes());
+    out.extend_from_slice(&EM_X86_64.to_le_bytes());
+    out.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    out.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    out.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(phnum as u16).to_le_bytes());
+    out.extend_from_slice(&[0; 6]); // no section headers
+
+    push_program_header(&mut out, PT_NOTE, 0, offset, 0, notes.len(), 4);
+    offset += notes.len();
+    for segment in &core.segments {
+        let len = segment.data.len();
+        push_program_header(
+            &mut out,
+            PT_LOAD,
+            segment.flags,
+            offset,
+            segment.start,
+            len,
+            1,
+        );
+        offset += len;
+    }
+    out.extend_from_slice(&notes);
+    for segment in &core.segments {
+        out.extend_from_slice(&segment.data);
+    }
+    fs::write(path, out)
+}
+
+fn push_note(out: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    let name = b"CORE\0";
+    out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    out.extend_from_slice(&note_type.to_le_bytes());
+    out.extend_from_slice(name);
+    out.resize(align4(out.len()), 0);
+    out.extend_from_slice(desc);
+    out.resize(align4(out.len()), 0);
+}
+
+fn push_program_header(
+    out: &mut Vec<u8>,
+    segment_type: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    len: usize,
+    align: u64,
+) {
+    out.extend_from_slice(&segment_type.to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&(offset as u64).to_le_bytes());
+    out.extend_from_slice(&(vaddr as u64).to_le_bytes()); // p_vaddr
+    out.extend_from_slice(&0u64.to_le_bytes()); // p_paddr
+    out.extend_from_slice(&(len as u64).to_le_bytes()); // p_filesz
+    out.extend_from_slice(&(len as u64).to_le_bytes()); // p_memsz
+    out.extend_from_slice(&align.to_le_bytes());
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401136;
+        regs.rsp = 0x7ffc0000;
+        let core = Core {
+            pid: 1234,
+            signal: Some(Signal::SIGSEGV),
+            regs,
+            command_line: "samples/segfault arg".to_string(),
+            segments: vec![
+                Segment {
+                    start: 0x401000,
+                    flags: PF_R | PF_X,
+                    data: vec![0x55, 0x48, 0x89, 0xe5],
+                },
+                Segment {
+                    start: 0x7ffc0000,
+                    flags: PF_R | PF_W,
+                    data: (0..16).collect(),
+                },
+            ],
+        };
+        let path = std::env::temp_dir().join(format!("deet-core-test.{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(path, &core).unwrap();
+        let loaded = load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.pid, 1234);
+        assert_eq!(loaded.signal, Some(Signal::SIGSEGV));
+        assert_eq!(loaded.regs.rip, 0x401136);
+        assert_eq!(loaded.regs.rsp, 0x7ffc0000);
+        assert_eq!(loaded.command_line, "samples/segfault arg");
+        assert_eq!(loaded.segments, core.segments);
+        assert_eq!(loaded.read(0x7ffc0004, 4), Some(&[4, 5, 6, 7][..]));
+        assert_eq!(loaded.read(0x40100e, 1), None);
+        assert_eq!(loaded.read(0x401002, 4), None);
+    }
+
+    /// Returns an x86-64 core file header with a program header table at `phoff` holding `phnum`
+    /// entries.
+    fn core_header(phoff: u64, phnum: u16) -> Vec<u8> {
+        let mut bytes = vec![0u8; ELF_HEADER_SIZE];
+        bytes[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        bytes[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+        bytes[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        bytes[32..40].copy_from_slice(&phoff.to_le_bytes());
+        bytes[56..58].copy_from_slice(&phnum.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        assert!(load("samples/segfault").is_err());
+        assert!(load("/nonexistent/core").is_err());
+
+        // Corrupt headers whose offsets and sizes run off the end of the address space
+        let mut huge_segment = core_header(ELF_HEADER_SIZE as u64, 1);
+        let mut program_header = vec![0u8; PROGRAM_HEADER_SIZE];
+        program_header[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        program_header[8..16].copy_from_slice(&1u64.to_le_bytes());
+        program_header[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        huge_segment.extend_from_slice(&program_header);
+        let path = std::env::temp_dir().join(format!("deet-corrupt-core.{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        for bytes in [core_header(u64::MAX, 2), huge_segment] {
+            fs::write(path, bytes).unwrap();
+            assert!(load(path).is_err());
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
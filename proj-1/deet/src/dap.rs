@@ -0,0 +1,560 @@
+//! A Debug Adapter Protocol server, so that editors like VS Code can drive deet. With
+//! `deet --dap <port> <target>`, deet waits for a client to connect to the port and then takes
+//! requests from it instead of reading commands from the terminal.
+//!
+//! Each request is carried out by running the matching debugger command with output captured (see
+//! `output`), and reading what happened back from the events it reports. Text the debugger would
+//! have printed is passed on to the client as `output` events. The inferior has one thread as far
+//! as the client is concerned, and its own input and output stay on deet's terminal.
+
+use crate::debugger::Debugger;
+use crate::debugger_command::DebuggerCommand;
+use crate::inferior::Redirections;
+use crate::json::{self, Json};
+use crate::output::{self, Mode};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+/// The id of the inferior's only thread
+const THREAD_ID: usize = 1;
+
+/// The largest message we'll accept. Requests are small, so anything bigger is a broken or
+/// hostile client, and we'd rather not allocate whatever it claims to be sending.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// Waits for a client on `port` (on localhost only, since anyone connecting can run programs as
+/// us) and serves its requests until it disconnects.
+pub fn serve(debugger: &mut Debugger, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    say!("Waiting for a DAP client on port {}", port);
+    let (stream, client) = listener.accept()?;
+    say!("Connected to {}", client);
+    output::set_mode(Mode::Capture);
+    let mut session = Session {
+        debugger,
+        writer: stream.try_clone()?,
+        seq: 0,
+        args: Vec::new(),
+    };
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_message(&mut reader)? {
+        if !session.handle(&request)? {
+            break;
+        }
+    }
+    output::set_mode(Mode::Text);
+    Ok(())
+}
+
+/// Reads a message: `Content-Length` and any other headers, a blank line, and then that many
+/// bytes of JSON. Returns None if the client has hung up, and an error if the headers don't give a
+/// sensible length.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut length = None;
+    let mut headers = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            // Stray blank lines between messages are harmless
+            if headers > 0 {
+                break;
+            }
+            continue;
+        }
+        headers += 1;
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = match value.trim().parse::<usize>() {
+                Ok(length) if length <= MAX_MESSAGE_LEN => Some(length),
+                Ok(length) => return Err(invalid(format!("message too long: {} bytes", length))),
+                Err(_) => return Err(invalid(format!("invalid Content-Length: {}", value))),
+            };
+        }
+    }
+    let length = length.ok_or_else(|| invalid("message has no Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    json::parse(&String::from_utf8_lossy(&body))
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `message` with the header the protocol puts in front of it.
+pub fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Whether `a` and `b` name the same source file, allowing either to be given by just its
+/// trailing path components, as DWARF file names and editors' paths often differ that way.
+fn same_file(a: &str, b: &str) -> bool {
+    a == b || a.ends_with(&format!("/{}", b)) || b.ends_with(&format!("/{}", a))
+}
+
+struct Session<'a, W: Write> {
+    debugger: &'a mut Debugger,
+    writer: W,
+    /// The sequence number of the last message sent
+    seq: usize,
+    /// The arguments to run the program with, from the `launch` request
+    args: Vec<String>,
+}
+
+impl<'a, W: Write> Session<'a, W> {
+    fn send(&mut self, message_type: &str, mut fields: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq", self.seq.into()));
+        fields.insert(1, ("type", message_type.into()));
+        write_message(&mut self.writer, &Json::object(fields))
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        let fields = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", true.into()),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+            ("body", body),
+        ];
+        self.send("response", fields)
+    }
+
+    fn respond_error(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        let fields = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", false.into()),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+            ("message", message.into()),
+        ];
+        self.send("response", fields)
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send("event", vec![("event", event.into()), ("body", body)])
+    }
+
+    /// Runs `cmd` and returns the events it reported, leaving out its text. Returns None if the
+    /// debugger should exit.
+    fn run_quietly(&mut self, cmd: DebuggerCommand) -> Option<Vec<Json>> {
+        let keep_going = self.debugger.execute(cmd);
+        let events = output::take_captured()
+            .into_iter()
+            .filter(|event| event_name(event) != Some("console"))
+            .collect();
+        if keep_going {
+            Some(events)
+        } else {
+            None
+        }
+    }
+
+    /// Runs `cmd`, passing on its text to the client, and tells the client if the inferior
+    /// stopped or exited.
+    fn run(&mut self, cmd: DebuggerCommand) -> io::Result<()> {
+        self.debugger.execute(cmd);
+        for event in output::take_captured() {
+            let name = event_name(&event).unwrap_or_default().to_string();
+            let field = |key: &str| event.get(key).cloned().unwrap_or(Json::Null);
+            match name.as_str() {
+                "console" => {
+                    let text = format!("{}\n", field("text").as_str().unwrap_or_default());
+                    let body = Json::object(vec![
+                        ("category", "console".into()),
+                        ("output", text.into()),
+                    ]);
+                    self.send_event("output", body)?;
+                }
+                "stopped" => {
+                    let reason = match field("reason").as_str() {
+                        Some("breakpoint-hit") => "breakpoint",
+                        Some("signal-received") => "exception",
+                        _ => "step",
+                    };
+                    let mut fields = vec![
+                        ("reason", reason.into()),
+                        ("threadId", THREAD_ID.into()),
+                        ("allThreadsStopped", true.into()),
+                    ];
+                    if let Some(signal) = event.get("signal") {
+                        fields.push(("description", signal.clone()));
+                    }
+                    self.send_event("stopped", Json::object(fields))?;
+                }
+                "exited" | "signaled" => {
+                    // A process killed by a signal has no exit status, so report 128 plus the
+                    // signal number as a shell would
+                    let code = match field("code").as_i64() {
+                        Some(code) => code,
+                        None => 128 + field("number").as_i64().unwrap_or(0),
+                    };
+                    self.send_event("exited", Json::object(vec![("exitCode", code.into())]))?;
+                    self.send_event("terminated", Json::object(vec![]))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Carries out a request. Returns false once the client has disconnected.
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let empty = Json::object(vec![]);
+        let arguments = request.get("arguments").unwrap_or(&empty);
+        let argument = |key: &str| arguments.get(key);
+        match request
+            .get("command")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+        {
+            "initialize" => {
+                let capabilities = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                ]);
+                self.respond(request, capabilities)?;
+                self.send_event("initialized", Json::object(vec![]))?;
+            }
+            "launch" => {
+                self.args = argument("args")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|arg| arg.as_str().map(str::to_string))
+                    .collect();
+                self.respond(request, Json::object(vec![]))?;
+            }
+            "setBreakpoints" => {
+                let path = argument("source")
+                    .and_then(|source| source.get("path"))
+                    .and_then(Json::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                let lines: Vec<i64> = argument("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|bp| bp.get("line").and_then(Json::as_i64))
+                    .collect();
+                let breakpoints = self.set_breakpoints(&path, &lines);
+                self.respond(
+                    request,
+                    Json::object(vec![("breakpoints", Json::Array(breakpoints))]),
+                )?;
+            }
+            "configurationDone" => {
+                self.respond(request, Json::object(vec![]))?;
+                let args = self.args.clone();
+                self.run(DebuggerCommand::Run(args, Redirections::default()))?;
+            }
+            "threads" => {
+                let thread = Json::object(vec![("id", THREAD_ID.into()), ("name", "main".into())]);
+                self.respond(
+                    request,
+                    Json::object(vec![("threads", Json::Array(vec![thread]))]),
+                )?;
+            }
+            "stackTrace" => {
+                let frames: Vec<Json> = self
+                    .backtrace()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, frame)| {
+                        let field = |key: &str| frame.get(key).cloned().unwrap_or(Json::Null);
+                        let name = match field("function") {
+                            Json::Null => field("addr"),
+                            function => function,
+                        };
+                        let source = match field("file") {
+                            Json::Null => Json::Null,
+                            file => Json::object(vec![("path", file)]),
+                        };
+                        Json::object(vec![
+                            ("id", idx.into()),
+                            ("name", name),
+                            ("source", source),
+                            ("line", field("line").as_i64().unwrap_or(0).into()),
+                            ("column", 0usize.into()),
+                        ])
+                    })
+                    .collect();
+                let total = frames.len();
+                self.respond(
+                    request,
+                    Json::object(vec![
+                        ("stackFrames", Json::Array(frames)),
+                        ("totalFrames", total.into()),
+                    ]),
+                )?;
+            }
+            "scopes" => {
+                let frame = argument("frameId").and_then(Json::as_i64).unwrap_or(0);
+                // Each frame's variables are looked up by its id plus one, as 0 means none
+                let scope = Json::object(vec![
+                    ("name", "Locals".into()),
+                    ("variablesReference", (frame + 1).into()),
+                    ("expensive", false.into()),
+                ]);
+                self.respond(
+                    request,
+                    Json::object(vec![("scopes", Json::Array(vec![scope]))]),
+                )?;
+            }
+            "variables" => {
+                let frame = argument("variablesReference")
+                    .and_then(Json::as_i64)
+                    .unwrap_or(1)
+                    .max(1) as usize
+                    - 1;
+                let variables = self.variables(frame);
+                self.respond(
+                    request,
+                    Json::object(vec![("variables", Json::Array(variables))]),
+                )?;
+            }
+            "evaluate" => {
+                let expression = argument("expression")
+                    .and_then(Json::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                if let Some(frame) = argument("frameId").and_then(Json::as_i64) {
+                    self.run_quietly(DebuggerCommand::Frame(Some(frame as usize)));
+                }
+                self.debugger.execute(DebuggerCommand::Print(expression));
+                let events = output::take_captured();
+                match events
+                    .iter()
+                    .find(|event| event_name(event) == Some("value"))
+                {
+                    Some(value) => {
+                        let result = value.get("value").cloned().unwrap_or(Json::Null);
+                        self.respond(
+                            request,
+                            Json::object(vec![
+                                ("result", result),
+                                ("variablesReference", 0usize.into()),
+                            ]),
+                        )?;
+                    }
+                    None => {
+                        let text: Vec<&str> = events
+                            .iter()
+                            .filter_map(|event| event.get("text").and_then(Json::as_str))
+                            .collect();
+                        self.respond_error(request, &text.join("\n"))?;
+                    }
+                }
+            }
+            "continue" => {
+                self.respond(
+                    request,
+                    Json::object(vec![("allThreadsContinued", true.into())]),
+                )?;
+                self.run(DebuggerCommand::Continue)?;
+            }
+            "next" => {
+                self.respond(request, Json::object(vec![]))?;
+                self.run(DebuggerCommand::Next(1))?;
+            }
+            "stepIn" => {
+                self.respond(request, Json::object(vec![]))?;
+                self.run(DebuggerCommand::Step(1))?;
+            }
+            "disconnect" => {
+                self.run_quietly(DebuggerCommand::Quit);
+                self.respond(request, Json::object(vec![]))?;
+                return Ok(false);
+            }
+            command => {
+                self.respond_error(request, &format!("deet doesn't support {}", command))?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Replaces the breakpoints in the source file `path` with ones at `lines`, as
+    /// `setBreakpoints` asks. Returns the protocol's description of each new breakpoint.
+    fn set_breakpoints(&mut self, path: &str, lines: &[i64]) -> Vec<Json> {
+        let existing = self
+            .run_quietly(DebuggerCommand::InfoBreakpoints)
+            .unwrap_or_default();
+        let table = existing
+            .iter()
+            .find(|event| event_name(event) == Some("breakpoints"))
+            .and_then(|event| event.get("breakpoints"))
+            .and_then(Json::as_array)
+            .unwrap_or_default();
+        let mut in_file: Vec<usize> = table
+            .iter()
+            .filter(|bp| {
+                bp.get("file")
+                    .and_then(Json::as_str)
+                    .is_some_and(|file| same_file(file, path))
+            })
+            .filter_map(|bp| bp.get("number").and_then(Json::as_i64))
+            .map(|number| number as usize)
+            .collect();
+        // Deleting a breakpoint renumbers the ones after it
+        in_file.sort_unstable();
+        for number in in_file.into_iter().rev() {
+            self.run_quietly(DebuggerCommand::Delete(number));
+        }
+
+        let mut breakpoints = Vec::new();
+        for &line in lines {
+            let location = format!("{}:{}", path, line);
+            self.debugger
+                .execute(DebuggerCommand::BreakPoint(location, None));
+            let events = output::take_captured();
+            let created = events
+                .iter()
+                .find(|event| event_name(event) == Some("breakpoint-created"));
+            breakpoints.push(match created {
+                Some(created) => Json::object(vec![
+                    ("id", created.get("number").cloned().unwrap_or(Json::Null)),
+                    ("verified", true.into()),
+                    (
+                        "line",
+                        created.get("line").cloned().unwrap_or_else(|| line.into()),
+                    ),
+                ]),
+                None => {
+                    let text: Vec<&str> = events
+                        .iter()
+                        .filter_map(|event| event.get("text").and_then(Json::as_str))
+                        .collect();
+                    Json::object(vec![
+                        ("verified", false.into()),
+                        ("line", line.into()),
+                        ("message", text.join("\n").into()),
+                    ])
+                }
+            });
+        }
+        breakpoints
+    }
+
+    /// Returns the frames of the inferior's stack, innermost first, as the `backtrace` event
+    /// describes them.
+    fn backtrace(&mut self) -> Vec<Json> {
+        self.run_quietly(DebuggerCommand::BackTrace)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|event| event_name(event) == Some("backtrace"))
+            .and_then(|event| {
+                event
+                    .get("frames")
+                    .and_then(Json::as_array)
+                    .map(<[Json]>::to_vec)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the arguments and then the local variables of stack frame `frame`.
+    fn variables(&mut self, frame: usize) -> Vec<Json> {
+        let args = self
+            .backtrace()
+            .get(frame)
+            .and_then(|frame| {
+                frame
+                    .get("args")
+                    .and_then(Json::as_array)
+                    .map(<[Json]>::to_vec)
+            })
+            .unwrap_or_default();
+        self.run_quietly(DebuggerCommand::Frame(Some(frame)));
+        let locals = self
+            .run_quietly(DebuggerCommand::InfoLocals)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|event| event_name(event) == Some("locals"))
+            .and_then(|event| {
+                event
+                    .get("variables")
+                    .and_then(Json::as_array)
+                    .map(<[Json]>::to_vec)
+            })
+            .unwrap_or_default();
+        args.iter()
+            .chain(locals.iter())
+            .map(|var| {
+                let field = |key: &str| var.get(key).cloned().unwrap_or(Json::Null);
+                let mut fields = vec![
+                    ("name", field("name")),
+                    ("value", field("value")),
+                    ("variablesReference", 0usize.into()),
+                ];
+                // Arguments come from the backtrace, which doesn't give their types
+                if let Some(var_type) = var.get("type") {
+                    fields.push(("type", var_type.clone()));
+                }
+                Json::object(fields)
+            })
+            .collect()
+    }
+}
+
+/// Returns the name of an event reported by the debugger.
+fn event_name(event: &Json) -> Option<&str> {
+    event.get("event").and_then(Json::as_str)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_messages() {
+        let message = Json::object(vec![("seq", 1usize.into()), ("command", "threads".into())]);
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &Json::object(vec![("seq", 2usize.into())])).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 29\r\n\r\n{\"seq\":1,"));
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        let second = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(second.get("seq").and_then(Json::as_i64), Some(2));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bad_messages() {
+        let read = |text: &str| read_message(&mut Cursor::new(text.as_bytes().to_vec()));
+        assert!(read("Content-Type: json\r\n\r\n{}").is_err());
+        assert!(read("Content-Length: lots\r\n\r\n{}").is_err());
+        assert!(read("Content-Length: 99999999999\r\n\r\n{}").is_err());
+        assert!(read("Content-Length: 5\r\n\r\n{,}[]").is_err());
+        // A body cut short by the client hanging up
+        assert!(read("Content-Length: 10\r\n\r\n{}").is_err());
+        assert_eq!(
+            read("\r\nContent-Length: 2\r\n\r\n{}").unwrap(),
+            Some(Json::object(vec![]))
+        );
+    }
+
+    #[test]
+    fn test_same_file() {
+        assert!(same_file("/src/samples/loop.c", "/src/samples/loop.c"));
+        assert!(same_file("/src/samples/loop.c", "samples/loop.c"));
+        assert!(same_file("loop.c", "/home/me/src/samples/loop.c"));
+        assert!(!same_file("/src/samples/loop.c", "/src/other/loop.c"));
+        assert!(!same_file("/src/samples/loop.c", "op.c"));
+    }
+}
@@ -1,9 +1,108 @@
-use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use crate::inferior::{Inferior, Status};
+use crate::corefile;
+use crate::debugger_command::{parse_line, split_words, DebuggerCommand};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Type, TypeKind};
+use crate::examine::{self, Spec};
+use crate::expr::{self, Condition};
+use crate::inferior::{FollowFork, Frame, Inferior, Status, WATCHPOINT_SLOTS};
+use crate::json::Json;
+use crate::maps;
+use crate::output::{self, output};
+use crate::record::RECORD_LIMIT;
+use crate::signals::{self, Action, Handling};
+use crate::source::SourceCache;
+use crate::value;
+use nix::sys::signal::Signal;
+use nix::unistd::isatty;
+use nix::unistd::Pid;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::mem::size_of;
 use std::num::ParseIntError;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Number of instructions shown by the `disas` command.
+const DISAS_INSTRUCTION_COUNT: usize = 8;
+
+/// How deeply `source` commands may nest, so that a script that sources itself doesn't recurse
+/// forever.
+const MAX_SOURCE_DEPTH: usize = 16;
+/// How many lines `list` shows at a time, unless changed with `set listsize`.
+const DEFAULT_LIST_SIZE: usize = 10;
+
+/// Prints an error message and records that the command being run failed, so that `source` can
+/// stop the script there.
+macro_rules! fail {
+    ($debugger:expr, $($arg:tt)*) => {{
+        $debugger.command_failed.set(true);
+        say!($($arg)*)
+    }};
+}
+
+/// Whether `cmd` resumes or changes the inferior, which can't be done to a core file.
+fn needs_live_process(cmd: &DebuggerCommand) -> bool {
+    matches!(
+        cmd,
+        DebuggerCommand::Continue
+            | DebuggerCommand::Next(_)
+            | DebuggerCommand::Step(_)
+            | DebuggerCommand::StepInstruction(_)
+            | DebuggerCommand::Until(_)
+            | DebuggerCommand::Signal(_)
+            | DebuggerCommand::Watch(_)
+            | DebuggerCommand::SetRegister(..)
+            | DebuggerCommand::Detach
+            | DebuggerCommand::Record(_)
+            | DebuggerCommand::ReverseStepInstruction(_)
+            | DebuggerCommand::ReverseStep(_)
+            | DebuggerCommand::ReverseContinue
+    )
+}
+
+/// A breakpoint as the user asked for it. The location it was given as is kept around so that it
+/// can be resolved again if the target is rebuilt.
+struct UserBreakpoint {
+    addr: usize,
+    location: String,
+    /// Commands to run whenever the inferior stops here, set with `commands`
+    commands: Vec<DebuggerCommand>,
+    /// How many times the inferior has stopped here since it was last started
+    hits: usize,
+    /// Disabled breakpoints are kept in the table but aren't installed in the inferior
+    enabled: bool,
+    /// If set, the inferior only stops here when this holds, and carries on silently otherwise
+    condition: Option<Condition>,
+}
+
+/// A hardware watchpoint. Its number is also the debug register slot it uses.
+struct Watchpoint {
+    expression: String,
+    addr: usize,
+    /// The type of the watched value, or None for a raw address, which is watched as a word
+    value_type: Option<Type>,
+    /// The watched bytes as of the last time the inferior stopped
+    old_value: Vec<u8>,
+}
+
+/// Identifies a particular build of the target, so that we can tell when it has been rebuilt.
+#[derive(Debug, Clone, PartialEq)]
+struct TargetStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl TargetStamp {
+    fn of(path: &str) -> Option<TargetStamp> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(TargetStamp {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
 
 pub struct Debugger {
     target: String,
@@ -11,22 +110,60 @@ pub struct Debugger {
     readline: Editor<()>,
     inferior: Option<Inferior>,
     debug_data: DwarfData,
-    breakpoints: Vec<usize>,
+    /// The build of the target that debug_data was loaded from
+    target_stamp: Option<TargetStamp>,
+    breakpoints: Vec<UserBreakpoint>,
+    /// Watchpoints only last as long as the inferior they were set in
+    watchpoints: Vec<Watchpoint>,
+    /// Whether to ask before killing a running inferior on `run` or `quit`
+    confirm: bool,
+    /// Index into the inferior's call stack of the frame picked with `frame`, 0 being the
+    /// innermost one. Goes back to 0 whenever the inferior resumes.
+    selected_frame: usize,
+    /// Source files read to show the line the inferior stopped at
+    source_cache: SourceCache,
+    /// Whether to show the address range of the current line, and how many of its instructions are
+    /// left, whenever the inferior stops
+    preview: bool,
+    /// How many lines `list` shows at a time
+    list_size: usize,
+    /// Where `list` with no location carries on from: a file and the first line of it not shown
+    /// yet. Cleared whenever the inferior stops or another frame is selected, so that `list` shows
+    /// the code around there instead.
+    list_next: Option<(String, usize)>,
+    /// Which process to keep debugging when the inferior forks
+    follow_fork: FollowFork,
+    /// Signals whose handling was changed with `handle`; the rest are handled the default way
+    signal_handling: HashMap<Signal, Handling>,
+    /// The signal the inferior last stopped with, to deliver to it when it's continued, if it's
+    /// one that gets passed on
+    pending_signal: Option<Signal>,
+    /// Whether a script run with `source` keeps going past lines that fail
+    ignore_errors: bool,
+    /// How many `source` commands are currently being run
+    source_depth: usize,
+    /// Whether the last command executed failed. A Cell so that `fail!` works from methods that
+    /// only borrow the debugger.
+    command_failed: Cell<bool>,
 }
 
 impl Debugger {
-    /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
+    /// Initializes the debugger. If `confirm` is false, `run` and `quit` kill a running inferior
+    /// without asking first. If `ignore_errors` is true, scripts run with `source` carry on past
+    /// invalid or failing commands instead of stopping at the first one.
+    pub fn new(target: &str, confirm: bool, ignore_errors: bool) -> Debugger {
+        let target_stamp = TargetStamp::of(target);
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
             Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
+                say!("Could not open file {}", target);
                 std::process::exit(1);
             }
             Err(DwarfError::DwarfFormatError(err)) => {
-                println!(
+                say!(
                     "Could not extract debugging symbols from {}: {:?}",
-                    target, err
+                    target,
+                    err
                 );
                 std::process::exit(1);
             }
@@ -44,119 +181,1854 @@ impl Debugger {
             readline,
             inferior: None,
             debug_data,
+            target_stamp,
             breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            confirm,
+            selected_frame: 0,
+            source_cache: SourceCache::new(),
+            preview: false,
+            list_size: DEFAULT_LIST_SIZE,
+            list_next: None,
+            follow_fork: FollowFork::Parent,
+            signal_handling: HashMap::new(),
+            pending_signal: None,
+            ignore_errors,
+            source_depth: 0,
+            command_failed: Cell::new(false),
         }
     }
 
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    // If an inferior is already running, kill it before starting a new one.
-                    if let Some(ref mut inferior) = self.inferior {
-                        println!("Killing running inferior (pid {})", inferior.pid());
-                        if let Err(e) = inferior.kill() {
-                            println!("Failed to kill inferior: {}", e);
+            let cmd = self.get_next_command();
+            if !self.execute(cmd) {
+                return;
+            }
+        }
+    }
+
+    /// Attaches to the already-running process `pid`, killing any inferior we're running first.
+    /// If the process is running a different program than the target, that program's debugging
+    /// symbols are loaded instead.
+    pub fn attach(&mut self, pid: Pid) {
+        let exe = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe.to_string_lossy().into_owned(),
+            Err(e) => {
+                fail!(
+                    self,
+                    "Cannot find the program process {} is running: {}",
+                    pid,
+                    e
+                );
+                return;
+            }
+        };
+        if !self.confirm_kill() {
+            return;
+        }
+        self.kill_inferior();
+        if fs::canonicalize(&exe).ok() != fs::canonicalize(&self.target).ok() {
+            match DwarfData::from_file(&exe) {
+                Ok(debug_data) => {
+                    say!("Reading symbols from {}", exe);
+                    self.target = exe;
+                    let stamp = TargetStamp::of(&self.target);
+                    self.use_debug_data(debug_data, stamp);
+                }
+                Err(err) => {
+                    fail!(
+                        self,
+                        "Could not load debugging symbols from {}: {:?}",
+                        exe,
+                        err
+                    );
+                    return;
+                }
+            }
+        } else {
+            self.reload_if_rebuilt();
+        }
+        let bp_addrs = self.reset_for_new_inferior();
+        if let Some(mut inferior) = Inferior::attach(pid, &bp_addrs) {
+            inferior.set_follow_fork(self.follow_fork);
+            say!("Attached to process {}", pid);
+            let status = inferior
+                .instruction_ptr()
+                .map(|rip| Status::Stopped(Signal::SIGSTOP, rip));
+            self.inferior = Some(inferior);
+            match status {
+                Ok(status) => self.report_status(&status),
+                Err(e) => fail!(self, "Failed to read registers: {}", e),
+            }
+        } else {
+            // Inferior::attach has already said why
+            self.command_failed.set(true);
+        }
+    }
+
+    /// Runs the commands in the file at `path`, one per line, as if they had been typed at the
+    /// prompt. Blank lines and `#` comments are skipped. The script stops at the first line that
+    /// isn't a valid command or whose command fails, unless errors are ignored. Returns false if
+    /// the debugger should exit.
+    pub fn source(&mut self, path: &str) -> bool {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                fail!(self, "Can't read {}: {}", path, err);
+                return true;
+            }
+        };
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            fail!(self, "{}: source commands nested too deeply", path);
+            return true;
+        }
+        self.source_depth += 1;
+        let mut keep_going = true;
+        let mut stopped = false;
+        for (idx, line) in text.lines().enumerate() {
+            let failed = match parse_line(line) {
+                Ok(Some(cmd)) => {
+                    if !self.execute(cmd) {
+                        keep_going = false;
+                        break;
+                    }
+                    self.command_failed.get()
+                }
+                Ok(None) => false,
+                Err(err) => {
+                    say!("{}:{}: {}", path, idx + 1, err);
+                    true
+                }
+            };
+            if failed && !self.ignore_errors {
+                say!(
+                    "Stopping {} at line {} (use --ignore-errors to carry on)",
+                    path,
+                    idx + 1
+                );
+                stopped = true;
+                break;
+            }
+        }
+        self.source_depth -= 1;
+        // A script that was cut short counts as a failed command itself, so that nested scripts
+        // stop too
+        self.command_failed.set(stopped);
+        keep_going
+    }
+
+    /// Carries out a single command. Returns false if the debugger should exit. Afterwards,
+    /// `command_failed` says whether the command failed.
+    pub fn execute(&mut self, cmd: DebuggerCommand) -> bool {
+        self.command_failed.set(false);
+        if self.inferior.as_ref().is_some_and(Inferior::is_core) && needs_live_process(&cmd) {
+            fail!(self, "The program is not being run.");
+            return true;
+        }
+        match cmd {
+            DebuggerCommand::Run(args, redirections) => {
+                if !self.confirm_kill() {
+                    return true;
+                }
+                // If an inferior is already running, kill it before starting a new one.
+                self.kill_inferior();
+                self.reload_if_rebuilt();
+                let bp_addrs = self.reset_for_new_inferior();
+                // Attempt to start a new inferior process.
+                if let Some(mut inferior) =
+                    Inferior::spawn(&self.target, &args, &redirections, &bp_addrs)
+                {
+                    inferior.set_follow_fork(self.follow_fork);
+                    self.inferior = Some(inferior);
+                    // Continue execution until it stops or terminates.
+                    self.continue_inferior();
+                } else {
+                    fail!(self, "Error starting subprocess");
+                }
+            }
+            DebuggerCommand::Continue => {
+                // If no inferior is running, print an error message.
+                if self.inferior.is_some() {
+                    self.continue_inferior();
+                } else {
+                    fail!(self, "No inferior to continue");
+                }
+            }
+            DebuggerCommand::Attach(pid) => {
+                self.attach(pid);
+            }
+            DebuggerCommand::Detach => {
+                if self.inferior.is_some() {
+                    self.detach();
+                } else {
+                    fail!(self, "The program is not being run.");
+                }
+            }
+            DebuggerCommand::Quit => {
+                let attached = self.inferior.as_ref().map(Inferior::is_attached);
+                if attached != Some(true) && !self.confirm_kill() {
+                    return true;
+                }
+                // On quitting, kill any running inferior, or let go of one we attached to.
+                if attached == Some(true) {
+                    self.detach();
+                } else {
+                    self.kill_inferior();
+                }
+                return false;
+            }
+            DebuggerCommand::BackTrace => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    if let Err(e) = inferior.print_backtrace(&self.debug_data) {
+                        fail!(self, "Failed to print backtrace: {}", e);
+                    }
+                }
+            }
+            DebuggerCommand::BreakPoint(target, condition) => {
+                let bp_addr_opt = self.resolve_location(&target, true);
+                if let Some(addr) = bp_addr_opt {
+                    if let Some(idx) = self.breakpoints.iter().position(|bp| bp.addr == addr) {
+                        match condition {
+                            Some(condition) => {
+                                say!("Breakpoint {} now stops only if {}", idx, condition);
+                                self.breakpoints[idx].condition = Some(condition);
+                            }
+                            None => say!("Breakpoint {} is already set at {:#x}", idx, addr),
                         }
+                        return true;
                     }
-                    // Attempt to start a new inferior process.
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &self.breakpoints) {
-                        self.inferior = Some(inferior);
-                        // Continue execution until it stops or terminates.
-                        let status = self
-                            .inferior
-                            .as_mut()
-                            .unwrap()
-                            .cont()
-                            .expect("Error continuing inferior");
-                        if let Status::Stopped(_, pointer) = status {
-                            self.inferior
-                                .as_mut()
-                                .unwrap()
-                                .print_current_frame(pointer, &self.debug_data);
+                    // If the program is running, make sure the breakpoint can actually be
+                    // installed before recording it. Otherwise it gets checked on `run`.
+                    if let Some(inferior) = self.inferior.as_mut() {
+                        if let Err(e) = inferior.install_break_points(addr) {
+                            fail!(self, "Cannot set breakpoint at {:#x}: {}", addr, e);
+                            return true;
                         }
-                    } else {
-                        println!("Error starting subprocess");
                     }
+                    say!("Set breakpoint {} at {:#x}", self.breakpoints.len(), addr);
+                    let mut fields = vec![
+                        ("number", Json::from(self.breakpoints.len())),
+                        ("location", Json::from(target.clone())),
+                    ];
+                    fields.extend(output::location(addr, &self.debug_data));
+                    output().event("breakpoint-created", fields);
+                    self.breakpoints.push(UserBreakpoint {
+                        addr,
+                        location: target,
+                        commands: Vec::new(),
+                        hits: 0,
+                        enabled: true,
+                        condition,
+                    });
                 }
-                DebuggerCommand::Continue => {
-                    // If no inferior is running, print an error message.
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        inferior.cont().expect("Error continuing inferior");
-                    } else {
-                        println!("No inferior to continue");
+            }
+            DebuggerCommand::Clear(function) => {
+                self.clear_breakpoints(function.as_deref());
+            }
+            DebuggerCommand::Delete(n) => {
+                self.delete_breakpoint(n);
+            }
+            DebuggerCommand::Disable(n) => {
+                self.set_breakpoint_enabled(n, false);
+            }
+            DebuggerCommand::Enable(n) => {
+                self.set_breakpoint_enabled(n, true);
+            }
+            DebuggerCommand::Commands(n) => {
+                let n = match n.or_else(|| self.breakpoints.len().checked_sub(1)) {
+                    Some(n) if n < self.breakpoints.len() => n,
+                    Some(n) => {
+                        fail!(self, "No breakpoint number {}.", n);
+                        return true;
+                    }
+                    None => {
+                        fail!(self, "No breakpoints specified.");
+                        return true;
                     }
+                };
+                self.breakpoints[n].commands = self.read_breakpoint_commands(n);
+            }
+            DebuggerCommand::SetConfirm(confirm) => {
+                self.confirm = confirm;
+            }
+            DebuggerCommand::SetFollowFork(follow_fork) => {
+                self.follow_fork = follow_fork;
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.set_follow_fork(follow_fork);
                 }
-                DebuggerCommand::Quit => {
-                    // On quitting, kill any running inferior.
-                    if let Some(ref mut inferior) = self.inferior {
-                        println!("Killing running inferior (pid {})", inferior.pid());
-                        if let Err(e) = inferior.kill() {
-                            println!("Failed to kill inferior: {}", e);
-                        }
+            }
+            DebuggerCommand::SetPreview(preview) => {
+                self.preview = preview;
+            }
+            DebuggerCommand::SetListSize(size) => {
+                self.list_size = size;
+            }
+            DebuggerCommand::List(location) => {
+                self.list(location.as_deref());
+            }
+            DebuggerCommand::Frame(n) => {
+                let n = n.unwrap_or(self.selected_frame);
+                if let Some(frame) = self.frame(n) {
+                    self.selected_frame = n;
+                    self.list_next = None;
+                    self.print_frame(n, &frame);
+                }
+            }
+            DebuggerCommand::InfoLine(target) => {
+                if let Some(addr) = self.resolve_location(&target, false) {
+                    self.print_line_info(addr);
+                }
+            }
+            DebuggerCommand::Next(count) => {
+                self.repeat_step(count, |inferior, debug_data| inferior.next_line(debug_data));
+            }
+            DebuggerCommand::Step(count) => {
+                self.repeat_step(count, |inferior, debug_data| inferior.step_into(debug_data));
+            }
+            DebuggerCommand::StepInstruction(count) => {
+                self.repeat_step(count, |inferior, _| inferior.step_once());
+            }
+            DebuggerCommand::InfoBreakpoints => {
+                self.print_breakpoints();
+            }
+            DebuggerCommand::InfoLocals => {
+                self.info_locals();
+            }
+            DebuggerCommand::InfoRegisters => match self.inferior.as_ref() {
+                Some(inferior) => {
+                    if let Err(e) = inferior.print_registers(&self.debug_data) {
+                        fail!(self, "Failed to read registers: {}", e);
+                    }
+                }
+                None => fail!(self, "The program has no registers now."),
+            },
+            DebuggerCommand::SetRegister(name, value) => match self.inferior.as_mut() {
+                Some(inferior) => match inferior.set_register(&name, value) {
+                    Ok(true) => {}
+                    Ok(false) => fail!(self, "Invalid register `{}'", name),
+                    Err(e) => fail!(self, "Failed to set register {}: {}", name, e),
+                },
+                None => fail!(self, "The program has no registers now."),
+            },
+            DebuggerCommand::InfoSharedLibrary => {
+                self.print_loaded_objects();
+            }
+            DebuggerCommand::Until(target) => {
+                self.until(&target);
+            }
+            DebuggerCommand::Print(expression) => {
+                self.print_expression(&expression);
+            }
+            DebuggerCommand::Examine(spec, address) => {
+                self.examine(&spec, &address);
+            }
+            DebuggerCommand::Watch(expression) => {
+                self.watch(&expression);
+            }
+            DebuggerCommand::Echo(text) => {
+                say!("{}", text);
+            }
+            DebuggerCommand::Source(path) => {
+                return self.source(&path);
+            }
+            DebuggerCommand::Handle(signal, actions) => {
+                self.handle(signal, &actions);
+            }
+            DebuggerCommand::Signal(signal) => {
+                if self.inferior.is_some() {
+                    match signal {
+                        Some(signal) => say!("Continuing with signal {}.", signal),
+                        None => say!("Continuing with no signal."),
+                    }
+                    self.pending_signal = signal;
+                    self.continue_inferior();
+                } else {
+                    fail!(self, "The program is not being run.");
+                }
+            }
+            DebuggerCommand::Disassemble(function) => {
+                self.disassemble(function.as_deref());
+            }
+            DebuggerCommand::GenerateCore(path) => {
+                self.generate_core(path.as_deref());
+            }
+            DebuggerCommand::Record(on) => match self.inferior.as_mut() {
+                Some(inferior) => match (on, inferior.recorded_instructions()) {
+                    (true, None) => inferior.start_recording(),
+                    (true, Some(_)) => fail!(self, "The process is already being recorded."),
+                    (false, Some(_)) => {
+                        inferior.stop_recording();
+                        say!("Process record is stopped and all execution logs are deleted.");
                     }
+                    (false, None) => fail!(self, "No recording is currently active."),
+                },
+                None => fail!(self, "The program is not being run."),
+            },
+            DebuggerCommand::InfoRecord => {
+                match self
+                    .inferior
+                    .as_ref()
+                    .and_then(Inferior::recorded_instructions)
+                {
+                    Some(count) => say!(
+                        "Recording is on: {} instructions recorded (at most {} are kept).",
+                        count,
+                        RECORD_LIMIT
+                    ),
+                    None => say!("No recording is currently active."),
+                }
+            }
+            DebuggerCommand::ReverseStepInstruction(count) => {
+                self.reverse(count, |inferior, _| inferior.reverse_step_once());
+            }
+            DebuggerCommand::ReverseStep(count) => {
+                self.reverse(count, |inferior, debug_data| {
+                    inferior.reverse_step_line(debug_data)
+                });
+            }
+            DebuggerCommand::ReverseContinue => {
+                self.reverse(1, |inferior, _| inferior.reverse_cont());
+            }
+        }
+        true
+    }
+
+    /// Loads the core file at `path` in place of any inferior, so that the state the program was
+    /// in can be looked at with `bt`, `print` and so on.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match corefile::load(path) {
+            Ok(core) => core,
+            Err(err) => {
+                fail!(self, "{}", err);
+                return;
+            }
+        };
+        if !self.confirm_kill() {
+            return;
+        }
+        self.kill_inferior();
+        self.reset_for_new_inferior();
+        say!("Core was generated by `{}'.", core.command_line);
+        if let Some(signal) = core.signal {
+            say!("Program terminated with signal {}.", signal);
+        }
+        let status = Status::Stopped(
+            core.signal.unwrap_or(Signal::SIGSTOP),
+            core.regs.rip as usize,
+        );
+        self.inferior = Some(Inferior::from_core(core));
+        self.report_status(&status);
+    }
+
+    /// Saves the stopped inferior's registers and memory to the core file at `path`, or
+    /// `core.<pid>` by default.
+    fn generate_core(&self, path: Option<&str>) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) if !inferior.is_core() => inferior,
+            _ => {
+                fail!(self, "The program is not being run.");
+                return;
+            }
+        };
+        let path = path.map_or_else(|| format!("core.{}", inferior.pid()), str::to_string);
+        match inferior
+            .snapshot()
+            .and_then(|core| corefile::save(&path, &core))
+        {
+            Ok(()) => say!("Saved corefile {}", path),
+            Err(e) => fail!(self, "Can't create a corefile: {}", e),
+        }
+    }
+
+    /// Kills the inferior, if there is one, and forgets about it. There's nothing to kill for a
+    /// core file, so that's just dropped.
+    fn kill_inferior(&mut self) {
+        if let Some(mut inferior) = self.inferior.take() {
+            if inferior.is_core() {
+                return;
+            }
+            say!("Killing running inferior (pid {})", inferior.pid());
+            if let Err(e) = inferior.kill() {
+                fail!(self, "Failed to kill inferior: {}", e);
+            }
+        }
+    }
+
+    /// Disassembles the function called `function`, or if none is given the one the inferior is
+    /// stopped in. Without debugging information for the current function, shows the few
+    /// instructions around rip instead.
+    fn disassemble(&self, function: Option<&str>) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                fail!(self, "No inferior to disassemble");
+                return;
+            }
+        };
+        let func = match function {
+            Some(name) => match self.debug_data.get_addr_for_function(None, name) {
+                Some(addr) => self.debug_data.get_function_containing(addr),
+                None => {
+                    fail!(
+                        self,
+                        "No function \"{}\" in the debugging information.",
+                        name
+                    );
                     return;
                 }
-                DebuggerCommand::BackTrace => {
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        inferior
-                            .print_backtrace(&self.debug_data)
-                            .expect("Error printing backtrace");
+            },
+            None => inferior
+                .instruction_ptr()
+                .ok()
+                .and_then(|rip| self.debug_data.get_function_containing(rip)),
+        };
+        let result = match func {
+            Some(func) => {
+                say!("Dump of assembler code for function {}:", func.name);
+                inferior.print_function_disassembly(func, &self.debug_data)
+            }
+            None => inferior.print_disassembly(&self.debug_data, DISAS_INSTRUCTION_COUNT),
+        };
+        if let Err(e) = result {
+            fail!(self, "Failed to disassemble: {}", e);
+        }
+    }
+
+    /// Returns stack frame `n` of the inferior (0 being the innermost one). Prints an error and
+    /// returns None if there is no such frame.
+    fn frame(&self, n: usize) -> Option<Frame> {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                fail!(self, "No stack.");
+                return None;
+            }
+        };
+        match inferior.frames(&self.debug_data) {
+            Ok(frames) => frames.get(n).copied().or_else(|| {
+                fail!(self, "No frame at level {}.", n);
+                None
+            }),
+            Err(e) => {
+                fail!(self, "Failed to walk the stack: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Prints a one-line summary of stack frame `n`, for `frame`.
+    fn print_frame(&self, n: usize, frame: &Frame) {
+        let func = self
+            .debug_data
+            .get_function_from_addr(frame.instruction_ptr);
+        let line = self.debug_data.get_line_from_addr(frame.instruction_ptr);
+        match (func, line) {
+            (Some(func), Some(line)) => say!(
+                "#{}  {:#x} in {} ({}:{})",
+                n,
+                frame.instruction_ptr,
+                func,
+                line.file,
+                line.number
+            ),
+            (Some(func), None) => say!("#{}  {:#x} in {}", n, frame.instruction_ptr, func),
+            (None, _) => say!("#{}  {:#x}", n, frame.instruction_ptr),
+        }
+    }
+
+    /// Takes the breakpoints out of the inferior and lets it carry on running untraced.
+    fn detach(&mut self) {
+        if let Some(mut inferior) = self.inferior.take() {
+            say!("Detaching from process {}", inferior.pid());
+            if let Err(e) = inferior.detach() {
+                fail!(self, "Failed to detach from process: {}", e);
+            }
+        }
+    }
+
+    /// Forgets the state of the previous inferior (breakpoint hit counts, watchpoints and the
+    /// selected frame) before starting or attaching to a new one. Returns the addresses of the
+    /// enabled breakpoints, to install in it.
+    fn reset_for_new_inferior(&mut self) -> Vec<usize> {
+        self.selected_frame = 0;
+        for bp in self.breakpoints.iter_mut() {
+            bp.hits = 0;
+        }
+        self.watchpoints.clear();
+        self.pending_signal = None;
+        self.breakpoints
+            .iter()
+            .filter(|bp| bp.enabled)
+            .map(|bp| bp.addr)
+            .collect()
+    }
+
+    /// If an inferior is running, asks the user whether it's okay to kill it. Returns true if we
+    /// may go ahead (including when there's nothing to kill, when confirmation is turned off, or
+    /// when stdin isn't a terminal, so that scripted sessions don't block).
+    fn confirm_kill(&mut self) -> bool {
+        if self.inferior.as_ref().is_none_or(Inferior::is_core)
+            || !self.confirm
+            || !isatty(0).unwrap_or(false)
+        {
+            return true;
+        }
+        loop {
+            match self
+                .readline
+                .readline(output().prompt("Inferior is running. Kill it? (y/n) "))
+            {
+                Ok(answer) => match answer.trim().to_lowercase().as_str() {
+                    "y" | "yes" => return true,
+                    "n" | "no" => return false,
+                    _ => say!("Please answer y or n."),
+                },
+                // ctrl+d means there's no one left to ask
+                Err(ReadlineError::Eof) => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// If the target binary has changed since its debugging symbols were loaded (e.g. it was
+    /// rebuilt after an edit), reloads them and resolves the breakpoints again, so that they
+    /// don't get installed at stale addresses. Breakpoints that no longer resolve are deleted.
+    fn reload_if_rebuilt(&mut self) {
+        let stamp = TargetStamp::of(&self.target);
+        if stamp.is_none() || stamp == self.target_stamp {
+            return;
+        }
+        let debug_data = match DwarfData::from_file(&self.target) {
+            Ok(val) => val,
+            Err(err) => {
+                say!(
+                    "Warning: {} has changed, but its debugging symbols could not be reloaded: {:?}",
+                    self.target, err
+                );
+                return;
+            }
+        };
+        say!(
+            "{} has changed; reloading its debugging symbols",
+            self.target
+        );
+        self.use_debug_data(debug_data, stamp);
+    }
+
+    /// Switches to newly loaded debugging symbols for the target, whose build is `stamp`, and
+    /// resolves the breakpoints again. Breakpoints that no longer resolve are deleted.
+    fn use_debug_data(&mut self, debug_data: DwarfData, stamp: Option<TargetStamp>) {
+        self.debug_data = debug_data;
+        self.target_stamp = stamp;
+        self.source_cache.clear();
+
+        // Breakpoints that no longer resolve are dropped with a warning, which doesn't make the
+        // command that noticed the rebuild fail
+        let command_failed = self.command_failed.get();
+        let old_breakpoints = std::mem::take(&mut self.breakpoints);
+        for (idx, bp) in old_breakpoints.into_iter().enumerate() {
+            if bp.location.starts_with('*') {
+                say!(
+                    "Warning: breakpoint {} was set at raw address {:#x}, which may now be wrong",
+                    idx,
+                    bp.addr
+                );
+                self.breakpoints.push(bp);
+                continue;
+            }
+            match self.resolve_location(&bp.location, true) {
+                Some(addr) => {
+                    if addr != bp.addr {
+                        say!(
+                            "Breakpoint {} ({}) moved from {:#x} to {:#x}",
+                            idx,
+                            bp.location,
+                            bp.addr,
+                            addr
+                        );
                     }
+                    self.breakpoints.push(UserBreakpoint { addr, ..bp });
                 }
-                DebuggerCommand::BreakPoint(target) => {
-                    // Convert the target string to an address.
-                    let bp_addr_opt = if target.starts_with('*') {
-                        // Raw address: remove the '*' and parse as hexadecimal.
-                        let addr_str = target.trim_start_matches('*');
-                        // Allow both "0x" prefixed and plain hexadecimal.
-                        usize::from_str_radix(addr_str.trim_start_matches("0x"), 16)
-                            .map_err(|e: ParseIntError| {
-                                println!("Invalid raw address '{}': {}", addr_str, e);
-                                e
-                            })
-                            .ok()
-                    } else if let Ok(line) = target.parse::<usize>() {
-                        // Treat as a source line number.
-                        self.debug_data.get_addr_for_line(None, line).or_else(|| {
-                            println!("No source information for line {}", line);
-                            None
-                        })
-                    } else {
-                        // Treat as a function name.
-                        self.debug_data
-                            .get_addr_for_function(None, target.as_str())
-                            .or_else(|| {
-                                println!("No function named '{}' found", target);
-                                None
-                            })
-                    };
-
-                    if let Some(addr) = bp_addr_opt {
-                        println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), addr);
-                        if let Some(inferior) = self.inferior.as_mut() {
-                            if let Err(e) = inferior.install_break_points(addr) {
-                                println!("Failed to install breakpoint: {}", e);
-                            }
-                        } else {
-                            self.breakpoints.push(addr);
-                        }
+                None => say!(
+                    "Warning: deleting breakpoint {} ({}), which no longer resolves",
+                    idx,
+                    bp.location
+                ),
+            }
+        }
+        self.command_failed.set(command_failed);
+    }
+
+    /// Converts a location given to `break` or `info line` to an address. The location may be a
+    /// raw address (`*0x401136`), a line number in the main file (`12`), a line number in a given
+    /// file (`foo.c:12`), or a function name (`main`). If `skip_prologue` is set, function names
+    /// resolve to the first instruction after the function's prologue instead of its entry point.
+    /// Prints an error and returns None if the location can't be resolved.
+    fn resolve_location(&self, target: &str, skip_prologue: bool) -> Option<usize> {
+        if target.starts_with('*') {
+            // Raw address: remove the '*' and parse as hexadecimal.
+            let addr_str = target.trim_start_matches('*');
+            // Allow both "0x" prefixed and plain hexadecimal.
+            usize::from_str_radix(addr_str.trim_start_matches("0x"), 16)
+                .map_err(|e: ParseIntError| {
+                    fail!(self, "Invalid raw address '{}': {}", addr_str, e);
+                    e
+                })
+                .ok()
+        } else if let Ok(line) = target.parse::<usize>() {
+            // Treat as a source line number.
+            self.debug_data.get_addr_for_line(None, line).or_else(|| {
+                fail!(self, "No source information for line {}", line);
+                None
+            })
+        } else if let Some((file, Ok(line))) = target
+            .rsplit_once(':')
+            .map(|(file, line)| (file, line.parse::<usize>()))
+        {
+            // Treat as a line number in a specific file.
+            self.debug_data
+                .get_addr_for_line(Some(file), line)
+                .or_else(|| {
+                    fail!(self, "No source information for {}:{}", file, line);
+                    None
+                })
+        } else {
+            // Treat as a function name.
+            let addr = self
+                .debug_data
+                .get_addr_for_function(None, target)
+                .or_else(|| {
+                    fail!(self, "No function named '{}' found", target);
+                    None
+                })?;
+            if skip_prologue {
+                self.debug_data.get_addr_after_prologue(addr)
+            } else {
+                Some(addr)
+            }
+        }
+    }
+
+    /// Prints the value of an expression (a variable, possibly with member accesses, indexing and
+    /// dereferences), as seen from the selected frame.
+    fn print_expression(&self, text: &str) {
+        let expr = match expr::parse(text) {
+            Ok(expr) => expr,
+            Err(err) => {
+                fail!(self, "{}", err);
+                return;
+            }
+        };
+        let frame = match self.frame(self.selected_frame) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        match inferior.format_expression(&expr, &frame, &self.debug_data) {
+            Ok(value) => {
+                say!("{} = {}", text, value);
+                output().event(
+                    "value",
+                    vec![
+                        ("expression", Json::String(text.to_string())),
+                        ("value", Json::String(value)),
+                    ],
+                );
+            }
+            Err(err) => fail!(self, "{}", err),
+        }
+    }
+
+    /// Prints the name, type and value of each local variable of the selected frame's function,
+    /// for `info locals`. Variables declared in inner blocks are included, whether or not they're
+    /// in scope yet.
+    fn info_locals(&self) {
+        let frame = match self.frame(self.selected_frame) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let func = match self
+            .debug_data
+            .get_function_containing(frame.instruction_ptr)
+        {
+            Some(func) => func,
+            None => {
+                fail!(self, "No symbol table info available.");
+                return;
+            }
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        let mut locals = func
+            .variables
+            .iter()
+            .filter(|var| !var.is_parameter)
+            .peekable();
+        if locals.peek().is_none() {
+            say!("No locals.");
+        }
+        let mut variables = Vec::new();
+        for var in locals {
+            let value = inferior
+                .format_variable(var, &frame)
+                .unwrap_or_else(|err| err);
+            say!("{}: {} = {}", var.name, var.entity_type.name, value);
+            variables.push(Json::object(vec![
+                ("name", Json::String(var.name.clone())),
+                ("type", Json::String(var.entity_type.name.clone())),
+                ("value", Json::String(value)),
+            ]));
+        }
+        output().event("locals", vec![("variables", Json::Array(variables))]);
+    }
+
+    /// Dumps the memory at `address` as described by `spec`, for `x`.
+    fn examine(&self, spec: &Spec, address: &str) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                fail!(self, "The program is not being run.");
+                return;
+            }
+        };
+        let start = match self.examine_address(inferior, address) {
+            Ok(start) => start,
+            Err(err) => {
+                fail!(self, "{}", err);
+                return;
+            }
+        };
+        match inferior.read_memory(start, spec.count * spec.unit) {
+            Ok(bytes) => {
+                for line in examine::format_lines(start, &bytes, spec) {
+                    say!("{}", line);
+                }
+                if bytes.len() < spec.count * spec.unit {
+                    fail!(
+                        self,
+                        "Cannot access memory at address {:#x}",
+                        start + bytes.len()
+                    );
+                }
+            }
+            Err(e) => fail!(self, "Cannot access memory at address {:#x}: {}", start, e),
+        }
+    }
+
+    /// Works out the address `x` should start at: a hex number, a register, the address of an
+    /// expression (`&expr`), or the value of an expression, in which case an array stands for its
+    /// first element.
+    fn examine_address(&self, inferior: &Inferior, address: &str) -> Result<usize, String> {
+        if let Some(hex) = address.strip_prefix("0x") {
+            return usize::from_str_radix(hex, 16)
+                .map_err(|e| format!("Invalid address '{}': {}", address, e));
+        }
+        if let Some(name) = address.strip_prefix('$') {
+            return match inferior.register(name) {
+                Ok(Some(value)) => Ok(value as usize),
+                Ok(None) => Err(format!("Invalid register `{}'", name)),
+                Err(e) => Err(format!("Failed to read registers: {}", e)),
+            };
+        }
+        let (text, address_of) = match address.strip_prefix('&') {
+            Some(text) => (text, true),
+            None => (address, false),
+        };
+        let expr = expr::parse(text)?;
+        let frame = self
+            .frame(self.selected_frame)
+            .ok_or_else(|| "No frame selected.".to_string())?;
+        let (addr, value_type) = inferior.evaluate(&expr, &frame, &self.debug_data)?;
+        if address_of {
+            return Ok(addr);
+        }
+        if let TypeKind::Array(..) = value_type.kind {
+            return Ok(addr);
+        }
+        let bytes = inferior
+            .read_memory(addr, value_type.size)
+            .map_err(|e| format!("Cannot access memory at address {:#x}: {}", addr, e))?;
+        value::to_integer(&value_type, &bytes)
+            .map(|value| value as usize)
+            .ok_or_else(|| {
+                format!(
+                    "Cannot use a value of type {} as an address",
+                    value_type.name
+                )
+            })
+    }
+
+    /// Prints the source line that `addr` belongs to, for `info line`.
+    fn print_line_info(&self, addr: usize) {
+        let line = match self.debug_data.get_line_from_addr(addr) {
+            Some(line) => line,
+            None => {
+                say!(
+                    "No line number information available for address {:#x}",
+                    addr
+                );
+                return;
+            }
+        };
+        match self.debug_data.get_function_containing(addr) {
+            Some(func) => say!(
+                "Line {} of \"{}\" is at address {:#x} <{}+{}>",
+                line.number,
+                line.file,
+                addr,
+                func.name,
+                addr - func.address
+            ),
+            None => say!(
+                "Line {} of \"{}\" is at address {:#x}",
+                line.number,
+                line.file,
+                addr
+            ),
+        }
+    }
+
+    /// Runs `step` on the inferior up to `count` times, stopping early if it lands on a breakpoint
+    /// or the inferior exits, then prints where it ended up.
+    fn repeat_step<F>(&mut self, count: usize, step: F)
+    where
+        F: Fn(&mut Inferior, &DwarfData) -> Result<Status, nix::Error>,
+    {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => {
+                fail!(self, "No inferior to step");
+                return;
+            }
+        };
+        self.selected_frame = 0;
+        let mut status = step(inferior, &self.debug_data).expect("Error stepping inferior");
+        for _ in 1..count {
+            match status {
+                // Any other signal (e.g. a segfault) also ends the stepping early
+                Status::Stopped(Signal::SIGTRAP, pointer)
+                    if !self
+                        .breakpoints
+                        .iter()
+                        .any(|bp| bp.enabled && bp.addr == pointer) =>
+                {
+                    status = step(inferior, &self.debug_data).expect("Error stepping inferior");
+                }
+                _ => break,
+            }
+        }
+        self.receive_signal(&status);
+        self.report_watchpoint_changes();
+        self.report_status(&status);
+    }
+
+    /// Runs the inferior backwards through the recorded history with `reverse` up to `count`
+    /// times, stopping early if the history runs out, then prints where it ended up.
+    fn reverse<F>(&mut self, count: usize, reverse: F)
+    where
+        F: Fn(&mut Inferior, &DwarfData) -> Result<bool, nix::Error>,
+    {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) if inferior.recorded_instructions().is_some() => inferior,
+            Some(_) => {
+                fail!(
+                    self,
+                    "The program is not being recorded. Start recording with `record`."
+                );
+                return;
+            }
+            None => {
+                fail!(self, "The program is not being run.");
+                return;
+            }
+        };
+        self.selected_frame = 0;
+        for _ in 0..count {
+            match reverse(inferior, &self.debug_data) {
+                Ok(true) => {}
+                Ok(false) => {
+                    say!("\nNo more reverse-execution history.");
+                    break;
+                }
+                Err(e) => {
+                    fail!(self, "Failed to go back: {}", e);
+                    break;
+                }
+            }
+        }
+        match inferior.instruction_ptr() {
+            Ok(rip) => self.report_status(&Status::Stopped(Signal::SIGTRAP, rip)),
+            Err(e) => fail!(self, "Failed to read registers: {}", e),
+        }
+    }
+
+    /// Runs the inferior until it reaches `target` in the current function, or until the function
+    /// returns, for `until`. A bare line number refers to the file execution is currently in.
+    fn until(&mut self, target: &str) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                fail!(self, "The program is not being run.");
+                return;
+            }
+        };
+        let rip = inferior
+            .instruction_ptr()
+            .expect("Error reading the inferior's registers");
+        let func = match self.debug_data.get_function_containing(rip) {
+            Some(func) => func.clone(),
+            None => {
+                fail!(
+                    self,
+                    "No function contains the current instruction {:#x}",
+                    rip
+                );
+                return;
+            }
+        };
+        let current_file = self
+            .debug_data
+            .get_line_from_addr(rip)
+            .map(|line| line.file);
+        let addr = match (target.parse::<usize>(), current_file) {
+            (Ok(line), Some(file)) => self
+                .debug_data
+                .get_addr_for_line(Some(&file), line)
+                .or_else(|| {
+                    fail!(self, "No source information for {}:{}", file, line);
+                    None
+                }),
+            _ => self.resolve_location(target, false),
+        };
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return,
+        };
+        if addr < func.address || addr >= func.address + func.text_length {
+            fail!(
+                self,
+                "{} is not in the current function ({})",
+                target,
+                func.name
+            );
+            return;
+        }
+
+        self.selected_frame = 0;
+        let status = self
+            .inferior
+            .as_mut()
+            .unwrap()
+            .run_until(addr)
+            .expect("Error continuing inferior");
+        self.receive_signal(&status);
+        self.record_breakpoint_hit(&status);
+        self.report_status(&status);
+    }
+
+    /// Resumes the inferior and reports where it stopped. If it stopped at a breakpoint that has
+    /// commands attached, runs them, and keeps going for as long as the command lists end in
+    /// `continue`.
+    fn continue_inferior(&mut self) {
+        loop {
+            let inferior = match self.inferior.as_mut() {
+                Some(inferior) => inferior,
+                None => return,
+            };
+            self.selected_frame = 0;
+            if !self.watchpoints.is_empty() {
+                // Forget about watchpoints triggered while stepping, which were reported then
+                let _ = inferior.take_triggered_watchpoints();
+            }
+            let status = inferior
+                .cont_with_signal(self.pending_signal.take())
+                .expect("Error continuing inferior");
+            let triggered = match status {
+                Status::Stopped(Signal::SIGTRAP, _) if !self.watchpoints.is_empty() => {
+                    inferior.take_triggered_watchpoints().unwrap_or_default()
+                }
+                _ => Vec::new(),
+            };
+            if !self.receive_signal(&status) {
+                continue;
+            }
+            if let Status::Execed(_) = status {
+                self.follow_exec();
+                continue;
+            }
+            // A write that leaves the value as it was doesn't count
+            if !self.report_watchpoint_changes() && !triggered.is_empty() {
+                continue;
+            }
+            if !self.breakpoint_condition_holds(&status) {
+                continue;
+            }
+            let hit = self.record_breakpoint_hit(&status);
+            self.report_status(&status);
+            let commands = match hit {
+                Some(idx) => self.breakpoints[idx].commands.clone(),
+                None => return,
+            };
+            if !self.run_breakpoint_commands(&commands) {
+                return;
+            }
+        }
+    }
+
+    /// How the inferior receiving `signal` is handled.
+    fn signal_handling(&self, signal: Signal) -> Handling {
+        self.signal_handling
+            .get(&signal)
+            .copied()
+            .unwrap_or_else(|| signals::default_handling(signal))
+    }
+
+    /// If the inferior stopped because it received a signal (other than the SIGTRAP that
+    /// breakpoints and stepping cause), says so if that signal is printed, and remembers to deliver
+    /// it on the next `continue` if it's passed on. Returns whether the debugger should stop for
+    /// it.
+    fn receive_signal(&mut self, status: &Status) -> bool {
+        let (signal, pointer) = match *status {
+            Status::Stopped(signal, pointer) if signal != Signal::SIGTRAP => (signal, pointer),
+            _ => return true,
+        };
+        let handling = self.signal_handling(signal);
+        if handling.print {
+            say!("Program received signal {} at {:#x}", signal, pointer);
+        }
+        self.pending_signal = Some(signal).filter(|_| handling.pass);
+        handling.stop
+    }
+
+    /// Loads the debugging symbols of the program the inferior has just exec'd, as its new
+    /// target, and installs the breakpoints in it again. Watchpoints don't survive the exec.
+    fn follow_exec(&mut self) {
+        let pid = match self.inferior.as_ref() {
+            Some(inferior) => inferior.pid(),
+            None => return,
+        };
+        self.watchpoints.clear();
+        let exe = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe.to_string_lossy().into_owned(),
+            Err(e) => {
+                say!("Cannot find the program process {} is running: {}", pid, e);
+                return;
+            }
+        };
+        say!("process {} is executing new program: {}", pid, exe);
+        match DwarfData::from_file(&exe) {
+            Ok(debug_data) => {
+                self.target = exe;
+                let stamp = TargetStamp::of(&self.target);
+                self.use_debug_data(debug_data, stamp);
+            }
+            Err(err) => {
+                say!("Could not load debugging symbols from {}: {:?}", exe, err);
+                return;
+            }
+        }
+        let inferior = self.inferior.as_mut().unwrap();
+        for bp in self.breakpoints.iter().filter(|bp| bp.enabled) {
+            if let Err(e) = inferior.install_break_points(bp.addr) {
+                say!("Skipping breakpoint at {:#x}: {}", bp.addr, e);
+            }
+        }
+    }
+
+    /// Applies `actions` to how `signal` is handled for `handle`, then shows how it's handled now.
+    fn handle(&mut self, signal: Signal, actions: &[Action]) {
+        let mut handling = self.signal_handling(signal);
+        for action in actions {
+            handling.apply(*action);
+        }
+        self.signal_handling.insert(signal, handling);
+        let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+        say!("Signal        Stop\tPrint\tPass to program");
+        say!(
+            "{:<14}{}\t{}\t{}",
+            signal.as_str(),
+            yes_no(handling.stop),
+            yes_no(handling.print),
+            yes_no(handling.pass)
+        );
+    }
+
+    /// Sets a hardware watchpoint on `text`, which is either an expression evaluated in the
+    /// selected frame or a raw address written as `*0x...`.
+    fn watch(&mut self, text: &str) {
+        if self.inferior.is_none() {
+            fail!(self, "The program is not being run.");
+            return;
+        }
+        if self.watchpoints.len() >= WATCHPOINT_SLOTS {
+            fail!(
+                self,
+                "All {} hardware watchpoints are in use.",
+                WATCHPOINT_SLOTS
+            );
+            return;
+        }
+        let (addr, value_type) = if let Some(hex) = text.strip_prefix("*0x") {
+            match usize::from_str_radix(hex, 16) {
+                Ok(addr) => (addr, None),
+                Err(e) => {
+                    fail!(self, "Invalid raw address '{}': {}", &text[1..], e);
+                    return;
+                }
+            }
+        } else {
+            let expr = match expr::parse(text) {
+                Ok(expr) => expr,
+                Err(err) => {
+                    fail!(self, "{}", err);
+                    return;
+                }
+            };
+            let frame = match self.frame(self.selected_frame) {
+                Some(frame) => frame,
+                None => return,
+            };
+            let inferior = self.inferior.as_ref().unwrap();
+            match inferior.evaluate(&expr, &frame, &self.debug_data) {
+                Ok((addr, value_type)) => (addr, Some(value_type)),
+                Err(err) => {
+                    fail!(self, "{}", err);
+                    return;
+                }
+            }
+        };
+        let len = value_type
+            .as_ref()
+            .map_or(size_of::<usize>(), |value_type| value_type.size);
+        let slot = self.watchpoints.len();
+        let inferior = self.inferior.as_mut().unwrap();
+        let old_value = match inferior.read_memory(addr, len) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                fail!(self, "Cannot access memory at address {:#x}: {}", addr, e);
+                return;
+            }
+        };
+        if let Err(e) = inferior.set_watchpoint(slot, addr, len) {
+            fail!(
+                self,
+                "Cannot watch the {} bytes at {:#x} (must be 1, 2, 4 or 8 bytes, aligned): {}",
+                len,
+                addr,
+                e
+            );
+            return;
+        }
+        say!("Watchpoint {}: {}", slot, text);
+        self.watchpoints.push(Watchpoint {
+            expression: text.to_string(),
+            addr,
+            value_type,
+            old_value,
+        });
+    }
+
+    /// Prints the old and new values of every watched value that has changed since the inferior
+    /// last stopped. Returns true if any had.
+    fn report_watchpoint_changes(&mut self) -> bool {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => return false,
+        };
+        let mut changed = false;
+        for (slot, watchpoint) in self.watchpoints.iter_mut().enumerate() {
+            let new_value = match inferior.read_memory(watchpoint.addr, watchpoint.old_value.len())
+            {
+                Ok(bytes) if bytes != watchpoint.old_value => bytes,
+                _ => continue,
+            };
+            let format = |bytes: &[u8]| match &watchpoint.value_type {
+                Some(value_type) => inferior.format_value(value_type, bytes),
+                None => format!("{:#x}", value::read_uint(bytes)),
+            };
+            say!("Watchpoint {}: {}", slot, watchpoint.expression);
+            say!("Old value = {}", format(&watchpoint.old_value));
+            say!("New value = {}", format(&new_value));
+            watchpoint.old_value = new_value;
+            changed = true;
+        }
+        changed
+    }
+
+    /// If the inferior stopped at one of the user's breakpoints and that breakpoint has a
+    /// condition, evaluates it in the innermost frame. Returns false only if the condition is
+    /// false; if it can't be evaluated, says why and returns true so that the inferior stays
+    /// stopped.
+    fn breakpoint_condition_holds(&self, status: &Status) -> bool {
+        let pointer = match *status {
+            Status::Stopped(Signal::SIGTRAP, pointer) => pointer,
+            _ => return true,
+        };
+        let idx = match self
+            .breakpoints
+            .iter()
+            .position(|bp| bp.enabled && bp.addr == pointer)
+        {
+            Some(idx) => idx,
+            None => return true,
+        };
+        let condition = match &self.breakpoints[idx].condition {
+            Some(condition) => condition,
+            None => return true,
+        };
+        let inferior = self.inferior.as_ref().unwrap();
+        let result = inferior
+            .frames(&self.debug_data)
+            .map_err(|e| format!("Failed to walk the stack: {}", e))
+            .and_then(|frames| {
+                inferior.evaluate_condition(condition, &frames[0], &self.debug_data)
+            });
+        match result {
+            Ok(holds) => holds,
+            Err(err) => {
+                say!("Error in testing condition for breakpoint {}: {}", idx, err);
+                true
+            }
+        }
+    }
+
+    /// If the inferior stopped at one of the user's breakpoints, counts the hit and announces it.
+    /// Returns the index of the breakpoint.
+    fn record_breakpoint_hit(&mut self, status: &Status) -> Option<usize> {
+        let pointer = match *status {
+            Status::Stopped(Signal::SIGTRAP, pointer) => pointer,
+            _ => return None,
+        };
+        let idx = self
+            .breakpoints
+            .iter()
+            .position(|bp| bp.enabled && bp.addr == pointer)?;
+        let bp = &mut self.breakpoints[idx];
+        bp.hits += 1;
+        say!(
+            "Breakpoint {}, hit {} time{}",
+            idx,
+            bp.hits,
+            if bp.hits == 1 { "" } else { "s" }
+        );
+        Some(idx)
+    }
+
+    /// Lists the breakpoints, for `info breakpoints`.
+    fn print_breakpoints(&self) {
+        let table = self
+            .breakpoints
+            .iter()
+            .enumerate()
+            .map(|(idx, bp)| {
+                let mut fields = vec![
+                    ("number", Json::from(idx)),
+                    ("enabled", Json::Bool(bp.enabled)),
+                    ("hits", Json::from(bp.hits)),
+                    ("location", Json::String(bp.location.clone())),
+                    ("condition", Json::string_or_null(bp.condition.as_ref())),
+                ];
+                fields.extend(output::location(bp.addr, &self.debug_data));
+                Json::object(fields)
+            })
+            .collect();
+        output().event("breakpoints", vec![("breakpoints", Json::Array(table))]);
+        if self.breakpoints.is_empty() {
+            say!("No breakpoints.");
+            return;
+        }
+        say!(
+            "{:<4} {:<4} {:<18} {:<5} Location",
+            "Num",
+            "Enb",
+            "Address",
+            "Hits"
+        );
+        for (idx, bp) in self.breakpoints.iter().enumerate() {
+            say!(
+                "{:<4} {:<4} {:<18} {:<5} {}{}",
+                idx,
+                if bp.enabled { "y" } else { "n" },
+                format!("{:#x}", bp.addr),
+                bp.hits,
+                bp.location,
+                self.describe_address(bp.addr)
+                    .map(|symbol| format!(", {}", symbol))
+                    .unwrap_or_default()
+            );
+            if let Some(condition) = &bp.condition {
+                say!("\tstop only if {}", condition);
+            }
+        }
+    }
+
+    /// Describes where `addr` is in the source, as `in func at file.c:12`, or None if the
+    /// debugging information doesn't cover it.
+    fn describe_address(&self, addr: usize) -> Option<String> {
+        let func = self.debug_data.get_function_containing(addr)?;
+        let line = self.debug_data.get_line_from_addr(addr)?;
+        let file = Path::new(&line.file)
+            .file_name()
+            .map_or(line.file.clone(), |name| {
+                name.to_string_lossy().into_owned()
+            });
+        Some(format!("in {} at {}:{}", func.name, file, line.number))
+    }
+
+    /// Loads debugging information for the shared libraries mapped into the inferior, so that
+    /// addresses in them can be looked up. The dynamic linker maps them in after the target starts
+    /// (and the program may load more itself), so this is done whenever the inferior stops.
+    fn load_libraries(&mut self) {
+        let mappings = match self.inferior.as_ref().and_then(Inferior::mappings) {
+            Some(mappings) => mappings,
+            None => return,
+        };
+        let target = fs::canonicalize(&self.target).ok();
+        // Data files can be mapped too (e.g. locale-archive), but only code is worth looking up
+        let libraries: Vec<maps::MappedObject> = maps::loaded_objects(&mappings)
+            .into_iter()
+            .filter(|object| Some(Path::new(&object.path)) != target.as_deref())
+            .filter(|object| object.perms.iter().any(|perms| perms.contains('x')))
+            .collect();
+        self.debug_data.load_libraries(&libraries);
+    }
+
+    /// Lists the object files mapped into the inferior with the address range and permissions of
+    /// each run of adjacent mappings, for `info sharedlibrary`. For a position-independent target,
+    /// the start of its first mapping is the base address its code was loaded at. As in gdb, the
+    /// libraries that symbols were read from are marked "Yes", with a `*` if there was no DWARF.
+    fn print_loaded_objects(&self) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                fail!(self, "The program is not being run.");
+                return;
+            }
+        };
+        let objects = match inferior.mappings() {
+            Some(mappings) => maps::loaded_objects(&mappings),
+            None => {
+                fail!(self, "Could not read the inferior's memory mappings");
+                return;
+            }
+        };
+        say!(
+            "{:<18} {:<18} {:<24} {:<9} Object",
+            "From",
+            "To",
+            "Perms",
+            "Syms Read"
+        );
+        let mut missing_dwarf = false;
+        for object in objects {
+            let library = self
+                .debug_data
+                .libraries()
+                .iter()
+                .find(|lib| lib.path == object.path && lib.base == object.start);
+            let syms_read = match library {
+                Some(lib) if lib.has_debug_info() => "Yes",
+                Some(_) => {
+                    missing_dwarf = true;
+                    "Yes (*)"
+                }
+                None => "",
+            };
+            say!(
+                "{:<18} {:<18} {:<24} {:<9} {}",
+                format!("{:#x}", object.start),
+                format!("{:#x}", object.end),
+                object.perms.join(" "),
+                syms_read,
+                object.path
+            );
+        }
+        if missing_dwarf {
+            say!("(*): Shared library is missing debugging information.");
+        }
+    }
+
+    /// Runs the commands attached to a breakpoint. Returns true if the list ends in `continue`,
+    /// i.e. the inferior should be resumed.
+    /// Whether these commands fail has no bearing on the command that stopped the inferior.
+    fn run_breakpoint_commands(&mut self, commands: &[DebuggerCommand]) -> bool {
+        let command_failed = self.command_failed.get();
+        let mut resume = false;
+        for cmd in commands {
+            if let DebuggerCommand::Continue = cmd {
+                resume = self.inferior.is_some();
+                break;
+            }
+            self.execute(cmd.clone());
+        }
+        self.command_failed.set(command_failed);
+        resume
+    }
+
+    /// Prints `list_size` lines of source for `list`. With no location, carries on from where the
+    /// last listing ended, or shows the lines around where the selected frame is. The current line
+    /// is marked with `=>`, and lines with a breakpoint with `b` (or `B` if it's disabled).
+    fn list(&mut self, location: Option<&str>) {
+        let (file, first) = match (location, self.list_next.clone()) {
+            (None, Some(next)) => next,
+            (location, _) => {
+                let found = match location {
+                    Some(location) => self.list_location(location),
+                    None => self.current_source_line(),
+                };
+                match found {
+                    Some((file, line)) => (file, line.saturating_sub(self.list_size / 2).max(1)),
+                    None => return,
+                }
+            }
+        };
+        let current = self
+            .inferior
+            .as_ref()
+            .and_then(|_| self.current_source_line())
+            .filter(|(current_file, _)| *current_file == file)
+            .map(|(_, line)| line);
+        let breakpoint_lines: Vec<(usize, bool)> = self
+            .breakpoints
+            .iter()
+            .filter_map(|bp| {
+                let line = self.debug_data.get_line_from_addr(bp.addr)?;
+                Some((line.number, bp.enabled)).filter(|_| line.file == file)
+            })
+            .collect();
+        let mut shown = 0;
+        for number in first..first + self.list_size {
+            let text = match self.source_cache.line(&file, number) {
+                Some(text) => text,
+                None => break,
+            };
+            let breakpoint = if breakpoint_lines.contains(&(number, true)) {
+                "b"
+            } else if breakpoint_lines.contains(&(number, false)) {
+                "B"
+            } else {
+                " "
+            };
+            let arrow = if current == Some(number) { "=>" } else { "  " };
+            say!("{}{} {:>4}\t{}", arrow, breakpoint, number, text);
+            shown += 1;
+        }
+        if shown == 0 {
+            say!(
+                "Line number {} out of range; \"{}\" has fewer lines.",
+                first,
+                file
+            );
+        }
+        self.list_next = Some((file, first + shown));
+    }
+
+    /// Returns the source file and line the selected frame is at, or where main is if the
+    /// inferior isn't running.
+    fn current_source_line(&self) -> Option<(String, usize)> {
+        let addr = match self.inferior.as_ref() {
+            Some(inferior) => {
+                inferior
+                    .frames(&self.debug_data)
+                    .ok()?
+                    .get(self.selected_frame)?
+                    .instruction_ptr
+            }
+            None => self.debug_data.get_addr_for_function(None, "main")?,
+        };
+        let line = self.debug_data.get_line_from_addr(addr)?;
+        Some((line.file, line.number))
+    }
+
+    /// Works out which source file and line a location given to `list` refers to. A bare line
+    /// number is in the file last listed, or otherwise the one the selected frame is in.
+    fn list_location(&self, location: &str) -> Option<(String, usize)> {
+        if let Ok(number) = location.parse::<usize>() {
+            let file = match &self.list_next {
+                Some((file, _)) => Some(file.clone()),
+                None => self.current_source_line().map(|(file, _)| file),
+            };
+            return match file {
+                Some(file) => Some((file, number)),
+                None => {
+                    fail!(self, "No source file is selected.");
+                    None
+                }
+            };
+        }
+        if let Some((file, Ok(number))) = location
+            .rsplit_once(':')
+            .map(|(file, line)| (file, line.parse::<usize>()))
+        {
+            // Any address in the file will do to find its full path
+            return match self
+                .debug_data
+                .get_addr_for_line(Some(file), number)
+                .or_else(|| self.debug_data.get_addr_for_line(Some(file), 0))
+                .and_then(|addr| self.debug_data.get_line_from_addr(addr))
+            {
+                Some(line) => Some((line.file, number)),
+                None => {
+                    fail!(self, "No source file named {}.", file);
+                    None
+                }
+            };
+        }
+        let addr = self.resolve_location(location, false)?;
+        match self.debug_data.get_line_from_addr(addr) {
+            Some(line) => Some((line.file, line.number)),
+            None => {
+                fail!(
+                    self,
+                    "No line number information available for {}",
+                    location
+                );
+                None
+            }
+        }
+    }
+
+    /// Prints the text of the source line containing `addr`, if its source file can be read.
+    fn print_source_line(&mut self, addr: usize) {
+        if let Some(line) = self.debug_data.get_line_from_addr(addr) {
+            if let Some(text) = self.source_cache.line(&line.file, line.number) {
+                say!("{}\t{}", line.number, text);
+            }
+        }
+    }
+
+    /// Removes every breakpoint, or only those within `function`'s code, taking them out of the
+    /// inferior too if it's running. The remaining breakpoints are renumbered.
+    fn clear_breakpoints(&mut self, function: Option<&str>) {
+        let range = match function {
+            None => None,
+            Some(name) => match self
+                .debug_data
+                .get_addr_for_function(None, name)
+                .and_then(|addr| self.debug_data.get_function_containing(addr))
+            {
+                Some(func) => Some(func.address..func.address + func.text_length),
+                None => {
+                    fail!(self, "No function named {}.", name);
+                    return;
+                }
+            },
+        };
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Err(e) = inferior.clear_break_points(range.as_ref()) {
+                fail!(self, "Failed to remove breakpoints: {}", e);
+            }
+        }
+        let before = self.breakpoints.len();
+        self.breakpoints
+            .retain(|bp| !range.as_ref().is_none_or(|range| range.contains(&bp.addr)));
+        let cleared = before - self.breakpoints.len();
+        say!(
+            "Cleared {} breakpoint{}",
+            cleared,
+            if cleared == 1 { "" } else { "s" }
+        );
+    }
+
+    /// Removes breakpoint `n`, taking it out of the inferior too if it's running. The breakpoints
+    /// after it are renumbered.
+    fn delete_breakpoint(&mut self, n: usize) {
+        if n >= self.breakpoints.len() {
+            fail!(self, "No breakpoint number {}.", n);
+            return;
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Err(e) = inferior.remove_break_point(self.breakpoints[n].addr) {
+                fail!(self, "Failed to remove breakpoint {}: {}", n, e);
+                return;
+            }
+        }
+        let bp = self.breakpoints.remove(n);
+        say!("Deleted breakpoint {} at {:#x}", n, bp.addr);
+    }
+
+    /// Enables or disables breakpoint `n`, installing it in or removing it from the inferior if
+    /// it's running.
+    fn set_breakpoint_enabled(&mut self, n: usize, enabled: bool) {
+        let addr = match self.breakpoints.get(n) {
+            Some(bp) => bp.addr,
+            None => {
+                fail!(self, "No breakpoint number {}.", n);
+                return;
+            }
+        };
+        if let Some(inferior) = self.inferior.as_mut() {
+            let result = if enabled {
+                inferior.install_break_points(addr)
+            } else {
+                inferior.remove_break_point(addr).map(|_| ())
+            };
+            if let Err(e) = result {
+                fail!(
+                    self,
+                    "Failed to {} breakpoint {}: {}",
+                    if enabled { "enable" } else { "disable" },
+                    n,
+                    e
+                );
+                return;
+            }
+        }
+        self.breakpoints[n].enabled = enabled;
+    }
+
+    /// Prints the address range of the source line containing `addr` and how many of its
+    /// instructions are still to run, as a rough guide to how much a `next` will execute.
+    fn print_line_preview(&self, addr: usize) {
+        let (inferior, range) = match (self.inferior.as_ref(), self.debug_data.get_line_range(addr))
+        {
+            (Some(inferior), Some(range)) => (inferior, range),
+            _ => return,
+        };
+        match (
+            inferior.count_instructions(range.start, range.end),
+            inferior.count_instructions(addr, range.end),
+        ) {
+            (Ok(total), Ok(left)) => say!(
+                "Line spans {:#x}-{:#x} ({} bytes); {} of its {} instructions left",
+                range.start,
+                range.end,
+                range.len(),
+                left,
+                total
+            ),
+            (Err(err), _) | (_, Err(err)) => say!("Could not read the line's code: {}", err),
+        }
+    }
+
+    /// Prints where the inferior stopped, or how it exited (in which case it is forgotten about).
+    fn report_status(&mut self, status: &Status) {
+        match *status {
+            Status::Stopped(_, pointer) | Status::Execed(pointer) => {
+                if let Status::Execed(_) = status {
+                    self.follow_exec();
+                }
+                self.load_libraries();
+                self.list_next = None;
+                if let Some(inferior) = self.inferior.as_ref() {
+                    inferior.print_current_frame(pointer, &self.debug_data);
+                    self.print_source_line(pointer);
+                    if self.preview {
+                        self.print_line_preview(pointer);
                     }
+                    output().event("stopped", self.stop_reason(status));
                 }
-                DebuggerCommand::Next => {
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        let status = inferior
-                            .next_line(&self.debug_data)
-                            .expect("Error executing next command");
-                        if let Status::Stopped(_, pointer) = status {
-                            inferior.print_current_frame(pointer, &self.debug_data);
-                        }
-                    } else {
-                        println!("No inferior to step");
+            }
+            Status::Exited(code) => {
+                say!("Child exited (status {})", code);
+                output().event("exited", vec![("code", Json::from(code as i64))]);
+                self.inferior = None;
+            }
+            Status::Signaled(signal) => {
+                say!("Child exited due to signal {}", signal);
+                output().event(
+                    "signaled",
+                    vec![
+                        ("signal", Json::String(signal.to_string())),
+                        ("number", Json::from(signal as i64)),
+                    ],
+                );
+                self.inferior = None;
+            }
+        }
+    }
+
+    /// Describes why and where the inferior stopped, for a `stopped` event.
+    fn stop_reason(&self, status: &Status) -> Vec<(&'static str, Json)> {
+        let (mut fields, pointer) = match *status {
+            Status::Execed(pointer) => {
+                (vec![("reason", Json::String("exec".to_string()))], pointer)
+            }
+            Status::Stopped(Signal::SIGTRAP, pointer) => {
+                match self
+                    .breakpoints
+                    .iter()
+                    .position(|bp| bp.enabled && bp.addr == pointer)
+                {
+                    Some(idx) => (
+                        vec![
+                            ("reason", Json::String("breakpoint-hit".to_string())),
+                            ("breakpoint", Json::from(idx)),
+                        ],
+                        pointer,
+                    ),
+                    None => (
+                        vec![("reason", Json::String("end-stepping-range".to_string()))],
+                        pointer,
+                    ),
+                }
+            }
+            Status::Stopped(signal, pointer) => (
+                vec![
+                    ("reason", Json::String("signal-received".to_string())),
+                    ("signal", Json::String(signal.to_string())),
+                ],
+                pointer,
+            ),
+            Status::Exited(_) | Status::Signaled(_) => unreachable!(),
+        };
+        fields.push((
+            "frame",
+            Json::object(output::location(pointer, &self.debug_data)),
+        ));
+        fields
+    }
+
+    /// Reads the command list for breakpoint `n` from a sub-prompt, one command per line, up to a
+    /// line saying just "end". Lines that don't parse are reported and left out.
+    fn read_breakpoint_commands(&mut self, n: usize) -> Vec<DebuggerCommand> {
+        say!(
+            "Type commands for breakpoint {}, one per line.\nEnd with a line saying just \"end\".",
+            n
+        );
+        let mut commands = Vec::new();
+        loop {
+            let line = match self.readline.readline(output().prompt(">")) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    say!("Quit (breakpoint {} keeps its old commands)", n);
+                    return std::mem::take(&mut self.breakpoints[n].commands);
+                }
+                Err(_) => break,
+            };
+            let words = match split_words(&line) {
+                Ok(words) => words,
+                Err(err) => {
+                    say!("{}", err);
+                    continue;
+                }
+            };
+            let tokens: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            if tokens == ["end"] {
+                break;
+            }
+            match DebuggerCommand::from_tokens(&tokens) {
+                Some(DebuggerCommand::Run(..))
+                | Some(DebuggerCommand::Quit)
+                | Some(DebuggerCommand::Commands(_)) => {
+                    say!(
+                        "'{}' can't be used in a breakpoint command list.",
+                        tokens[0]
+                    );
+                }
+                Some(cmd) => {
+                    if let Some(DebuggerCommand::Continue) = commands.last() {
+                        say!("Commands after 'continue' will not be run.");
                     }
+                    commands.push(cmd);
                 }
+                None => say!("Unrecognized command."),
             }
         }
+        commands
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
@@ -166,10 +2038,10 @@ impl Debugger {
     fn get_next_command(&mut self) -> DebuggerCommand {
         loop {
             // Print prompt and get next line of user input.
-            match self.readline.readline("(deet) ") {
+            match self.readline.readline(output().prompt("(deet) ")) {
                 Err(ReadlineError::Interrupted) => {
                     // User pressed ctrl+c. We're going to ignore it.
-                    println!("Type \"quit\" to exit");
+                    say!("Type \"quit\" to exit");
                 }
                 Err(ReadlineError::Eof) => {
                     // User pressed ctrl+d, which is the equivalent of "quit" for our purposes.
@@ -184,16 +2056,16 @@ impl Debugger {
                     }
                     self.readline.add_history_entry(line.as_str());
                     if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
+                        say!(
                             "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
+                            self.history_path,
+                            err
                         );
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    match parse_line(&line) {
+                        Ok(Some(cmd)) => return cmd,
+                        Ok(None) => {}
+                        Err(err) => say!("{}", err),
                     }
                 }
             }
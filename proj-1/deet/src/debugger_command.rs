@@ -1,10 +1,89 @@
+use crate::examine::{self, Spec};
+use crate::expr::{self, Condition};
+use crate::inferior::{FollowFork, Redirections};
+use crate::registers;
+use crate::signals::{self, Action};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+#[derive(Clone)]
 pub enum DebuggerCommand {
     Quit,
-    Run(Vec<String>),
+    /// Starts the target with the given arguments, its input and output redirected as given
+    Run(Vec<String>, Redirections),
+    /// Attaches to an already-running process
+    Attach(Pid),
+    /// Lets the inferior carry on running without the debugger
+    Detach,
     Continue,
     BackTrace,
-    Next,
-    BreakPoint(String),
+    Next(usize),
+    /// Like `Next`, but stops in any function (with debug info) that gets called
+    Step(usize),
+    StepInstruction(usize),
+    /// Sets a breakpoint at a location, which only stops the inferior if the condition holds
+    BreakPoint(String, Option<Condition>),
+    /// Disassembles the given function, or the current one
+    Disassemble(Option<String>),
+    InfoLine(String),
+    InfoBreakpoints,
+    InfoRegisters,
+    /// Lists the local variables of the selected frame's function, with their values
+    InfoLocals,
+    /// Sets a register (named without the `$`) to a value
+    SetRegister(String, u64),
+    /// Lists the object files mapped into the inferior
+    InfoSharedLibrary,
+    SetConfirm(bool),
+    /// Turns on or off showing the address range of the current line whenever the inferior stops
+    SetPreview(bool),
+    /// Sets how many lines `list` shows at a time
+    SetListSize(usize),
+    /// Sets which process to keep debugging when the inferior forks
+    SetFollowFork(FollowFork),
+    /// Shows the source around a location, or carries on from the last listing
+    List(Option<String>),
+    /// Selects the given stack frame, or just shows the selected one if no number was given
+    Frame(Option<usize>),
+    /// Sets the commands to run when the given breakpoint (by default the last one set) is hit
+    Commands(Option<usize>),
+    /// Runs until the given line in the current function is reached, or the function returns
+    Until(String),
+    /// Prints the value of an expression (e.g. `s.x`, `arr[2]` or `*p`) in the selected frame
+    Print(String),
+    /// Dumps memory starting at an address, which may be given as `0x...`, `$reg`, `&expr` or a
+    /// pointer expression
+    Examine(Spec, String),
+    /// Stops the inferior whenever it changes the value of an expression, or the word at `*0x...`
+    Watch(String),
+    /// Removes every breakpoint, or only those within the given function
+    Clear(Option<String>),
+    /// Removes the given breakpoint
+    Delete(usize),
+    /// Stops the given breakpoint from being hit, without forgetting it
+    Disable(usize),
+    /// Makes a disabled breakpoint get hit again
+    Enable(usize),
+    /// Prints the given text, so that scripts can annotate their output
+    Echo(String),
+    /// Runs the commands in the given file
+    Source(String),
+    /// Changes how a signal is handled, or just shows how it is if no actions were given
+    Handle(Signal, Vec<Action>),
+    /// Resumes the inferior, delivering the given signal (or none) instead of the pending one
+    Signal(Option<Signal>),
+    /// Saves the inferior's registers and memory to a core file, by default `core.<pid>`
+    GenerateCore(Option<String>),
+    /// Starts or stops recording the instructions the inferior executes
+    Record(bool),
+    /// Says whether the inferior is being recorded, and how much has been
+    InfoRecord,
+    /// Goes back the given number of instructions
+    ReverseStepInstruction(usize),
+    /// Goes back to the start of the previous source line, the given number of times
+    ReverseStep(usize),
+    /// Goes back until a breakpoint is reached
+    ReverseContinue,
 }
 
 fn parse_address(addr: &str) -> Option<usize> {
@@ -16,6 +95,179 @@ fn parse_address(addr: &str) -> Option<usize> {
     usize::from_str_radix(addr_without_0x, 16).ok()
 }
 
+/// Parses the optional repeat count given to a stepping command, which defaults to 1.
+fn parse_count(tokens: &[&str]) -> Option<usize> {
+    match tokens.get(1) {
+        None => Some(1),
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Some(count),
+            _ => {
+                say!("Invalid step count '{}'", count);
+                None
+            }
+        },
+    }
+}
+
+/// Parses the breakpoint number given to `delete`, `disable` or `enable`.
+fn parse_breakpoint_number(tokens: &[&str]) -> Option<usize> {
+    match tokens.get(1) {
+        None => {
+            say!("Usage: {} <breakpoint number>", tokens[0]);
+            None
+        }
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                say!("Invalid breakpoint number '{}'", n);
+                None
+            }
+        },
+    }
+}
+
+/// Separates the arguments given to `run` from shell-style redirections of the program's input
+/// (`< file`) and output (`> file`, or `>> file` to append). The file name may also be attached
+/// to the operator, as in `<in.txt`.
+fn parse_run_args(tokens: &[&str]) -> Option<(Vec<String>, Redirections)> {
+    let mut args = Vec::new();
+    let mut redirections = Redirections::default();
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        let (op, attached) = if let Some(rest) = token.strip_prefix(">>") {
+            (">>", rest)
+        } else if let Some(rest) = token.strip_prefix('>') {
+            (">", rest)
+        } else if let Some(rest) = token.strip_prefix('<') {
+            ("<", rest)
+        } else {
+            args.push(token.to_string());
+            continue;
+        };
+        let path = if !attached.is_empty() {
+            attached.to_string()
+        } else if let Some(path) = tokens.next() {
+            path.to_string()
+        } else {
+            say!("Missing file name after '{}'", op);
+            return None;
+        };
+        if op == "<" {
+            redirections.stdin = Some(path);
+        } else {
+            redirections.stdout = Some(path);
+            redirections.append = op == ">>";
+        }
+    }
+    Some((args, redirections))
+}
+
+/// Parses the signal given to `handle` or `signal`.
+fn parse_signal_argument(name: &str) -> Option<Signal> {
+    let signal = signals::parse_signal(name);
+    if signal.is_none() {
+        say!("Only signals 1-31 are valid as numeric signals, or give a name like SIGUSR1");
+    }
+    signal
+}
+
+/// Parses `set $reg = value`, where there may or may not be spaces around the `=`.
+fn parse_set_register(tokens: &[&str]) -> Option<DebuggerCommand> {
+    let assignment = tokens[1..].join(" ");
+    let (register, value) = match assignment.split_once('=') {
+        Some((register, value)) => (register.trim().trim_start_matches('$'), value.trim()),
+        None => {
+            say!("Usage: set $<register> = <value>");
+            return None;
+        }
+    };
+    match registers::parse_value(value) {
+        Some(value) => Some(DebuggerCommand::SetRegister(register.to_string(), value)),
+        None => {
+            say!("Invalid register value '{}'", value);
+            None
+        }
+    }
+}
+
+/// Splits a command line into words the way a shell would: words are separated by whitespace,
+/// single quotes keep everything up to the closing quote literally, double quotes do the same
+/// except that `\"` and `\\` are escapes, and outside of quotes a backslash escapes the next
+/// character. Returns an error for an unterminated quote.
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Distinguishes an empty quoted word ("") from no word at all
+    let mut in_word = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.push(chars.next().unwrap_or('\\'));
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("Unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated double quote".to_string()),
+                    }
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Parses a line of input. Returns Ok(None) for a blank line or a `#` comment, and an error for a
+/// line that isn't a valid command.
+pub fn parse_line(line: &str) -> Result<Option<DebuggerCommand>, String> {
+    if line.trim_start().starts_with('#') {
+        return Ok(None);
+    }
+    let words = split_words(line)?;
+    let tokens: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    DebuggerCommand::from_tokens(&tokens)
+        .map(Some)
+        .ok_or_else(|| "Unrecognized command.".to_string())
+}
+
 impl DebuggerCommand {
     pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
         if tokens.is_empty() {
@@ -24,21 +276,497 @@ impl DebuggerCommand {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
-                let args = tokens[1..].iter().map(|s| s.to_string()).collect();
-                Some(DebuggerCommand::Run(args))
+                let (args, redirections) = parse_run_args(&tokens[1..])?;
+                Some(DebuggerCommand::Run(args, redirections))
             }
+            "l" | "list" => Some(DebuggerCommand::List(
+                tokens.get(1).map(|location| location.to_string()),
+            )),
+            "detach" => Some(DebuggerCommand::Detach),
+            "attach" => match tokens.get(1).map(|pid| pid.parse::<i32>()) {
+                Some(Ok(pid)) if pid > 0 => Some(DebuggerCommand::Attach(Pid::from_raw(pid))),
+                Some(_) => {
+                    say!("Invalid process ID '{}'", tokens[1]);
+                    None
+                }
+                None => {
+                    say!("Usage: attach <pid>");
+                    None
+                }
+            },
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
             "bt" | "backtrace" => Some(DebuggerCommand::BackTrace),
-            "n" | "next" => Some(DebuggerCommand::Next),
-            "break" | "b" => {
+            "n" | "next" => Some(DebuggerCommand::Next(parse_count(tokens)?)),
+            "s" | "step" => Some(DebuggerCommand::Step(parse_count(tokens)?)),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction(parse_count(tokens)?)),
+            "rsi" | "reverse-stepi" => Some(DebuggerCommand::ReverseStepInstruction(parse_count(
+                tokens,
+            )?)),
+            "rs" | "reverse-step" => Some(DebuggerCommand::ReverseStep(parse_count(tokens)?)),
+            "rc" | "reverse-continue" => Some(DebuggerCommand::ReverseContinue),
+            "rec" | "record" => match tokens.get(1) {
+                None | Some(&"full") => Some(DebuggerCommand::Record(true)),
+                Some(&"stop") => Some(DebuggerCommand::Record(false)),
+                Some(_) => {
+                    say!("Usage: record [full|stop]");
+                    None
+                }
+            },
+            "disas" | "disassemble" => Some(DebuggerCommand::Disassemble(
+                tokens.get(1).map(|function| function.to_string()),
+            )),
+            "info" | "i" => match tokens.get(1) {
+                Some(&"line") => match tokens.get(2) {
+                    Some(target) => Some(DebuggerCommand::InfoLine(target.to_string())),
+                    None => {
+                        say!("No location specified");
+                        None
+                    }
+                },
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some(DebuggerCommand::InfoBreakpoints)
+                }
+                Some(&"shared") | Some(&"sharedlibrary") => {
+                    Some(DebuggerCommand::InfoSharedLibrary)
+                }
+                Some(&"r") | Some(&"reg") | Some(&"registers") => {
+                    Some(DebuggerCommand::InfoRegisters)
+                }
+                Some(&"locals") => Some(DebuggerCommand::InfoLocals),
+                Some(&"record") | Some(&"rec") => Some(DebuggerCommand::InfoRecord),
+                _ => {
+                    say!("Usage: info line <function|*addr|file:line>");
+                    say!("       info breakpoints");
+                    say!("       info sharedlibrary");
+                    say!("       info registers");
+                    say!("       info locals");
+                    say!("       info record");
+                    None
+                }
+            },
+            "set" => match (tokens.get(1), tokens.get(2)) {
+                (Some(&"confirm"), Some(&"on")) => Some(DebuggerCommand::SetConfirm(true)),
+                (Some(&"confirm"), Some(&"off")) => Some(DebuggerCommand::SetConfirm(false)),
+                (Some(&"preview"), Some(&"on")) => Some(DebuggerCommand::SetPreview(true)),
+                (Some(&"preview"), Some(&"off")) => Some(DebuggerCommand::SetPreview(false)),
+                (Some(&"listsize"), Some(size)) => match size.parse::<usize>() {
+                    Ok(size) if size > 0 => Some(DebuggerCommand::SetListSize(size)),
+                    _ => {
+                        say!("Invalid list size '{}'", size);
+                        None
+                    }
+                },
+                (Some(&"follow-fork-mode"), Some(&"parent")) => {
+                    Some(DebuggerCommand::SetFollowFork(FollowFork::Parent))
+                }
+                (Some(&"follow-fork-mode"), Some(&"child")) => {
+                    Some(DebuggerCommand::SetFollowFork(FollowFork::Child))
+                }
+                (Some(register), _) if register.starts_with('$') => parse_set_register(tokens),
+                _ => {
+                    say!("Usage: set confirm <on|off>");
+                    say!("       set preview <on|off>");
+                    say!("       set listsize <lines>");
+                    say!("       set follow-fork-mode <parent|child>");
+                    say!("       set $<register> = <value>");
+                    None
+                }
+            },
+            "f" | "frame" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Frame(None)),
+                Some(n) => match n.parse::<usize>() {
+                    Ok(n) => Some(DebuggerCommand::Frame(Some(n))),
+                    Err(_) => {
+                        say!("Invalid frame number '{}'", n);
+                        None
+                    }
+                },
+            },
+            "u" | "until" => match tokens.get(1) {
+                Some(target) => Some(DebuggerCommand::Until(target.to_string())),
+                None => {
+                    say!("Usage: until <line|file:line>");
+                    None
+                }
+            },
+            "p" | "print" => {
+                if tokens.len() >= 2 {
+                    Some(DebuggerCommand::Print(tokens[1..].join(" ")))
+                } else {
+                    say!("Usage: print <expression>");
+                    None
+                }
+            }
+            x if x == "x" || x.starts_with("x/") => {
+                let spec = match x.strip_prefix("x/").map(examine::parse_spec) {
+                    None => Spec::default(),
+                    Some(Ok(spec)) => spec,
+                    Some(Err(err)) => {
+                        say!("{}", err);
+                        return None;
+                    }
+                };
+                if tokens.len() >= 2 {
+                    Some(DebuggerCommand::Examine(spec, tokens[1..].join(" ")))
+                } else {
+                    say!("Usage: x/<count><format><unit> <address>");
+                    None
+                }
+            }
+            "watch" => {
                 if tokens.len() >= 2 {
-                    Some(DebuggerCommand::BreakPoint(tokens[1].to_string()))
+                    Some(DebuggerCommand::Watch(tokens[1..].join(" ")))
                 } else {
-                    println!("No breakpoint target specified");
+                    say!("Usage: watch <expression|*address>");
                     None
                 }
             }
+            "commands" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Commands(None)),
+                Some(n) => match n.parse::<usize>() {
+                    Ok(n) => Some(DebuggerCommand::Commands(Some(n))),
+                    Err(_) => {
+                        say!("Invalid breakpoint number '{}'", n);
+                        None
+                    }
+                },
+            },
+            "clear" => Some(DebuggerCommand::Clear(
+                tokens.get(1).map(|function| function.to_string()),
+            )),
+            "d" | "delete" => Some(DebuggerCommand::Delete(parse_breakpoint_number(tokens)?)),
+            "disable" => Some(DebuggerCommand::Disable(parse_breakpoint_number(tokens)?)),
+            "enable" => Some(DebuggerCommand::Enable(parse_breakpoint_number(tokens)?)),
+            "handle" => {
+                let signal = match tokens.get(1) {
+                    Some(name) => parse_signal_argument(name)?,
+                    None => {
+                        say!("Usage: handle <signal> [stop|nostop] [print|noprint] [pass|nopass]");
+                        return None;
+                    }
+                };
+                let mut actions = Vec::new();
+                for keyword in &tokens[2..] {
+                    match signals::parse_action(keyword) {
+                        Some(action) => actions.push(action),
+                        None => {
+                            say!("Unrecognized or ambiguous flag word: \"{}\".", keyword);
+                            return None;
+                        }
+                    }
+                }
+                Some(DebuggerCommand::Handle(signal, actions))
+            }
+            "signal" => match tokens.get(1) {
+                Some(&"0") => Some(DebuggerCommand::Signal(None)),
+                Some(name) => Some(DebuggerCommand::Signal(Some(parse_signal_argument(name)?))),
+                None => {
+                    say!("Usage: signal <signal>");
+                    None
+                }
+            },
+            "gcore" | "generate-core-file" | "generate-core" => Some(
+                DebuggerCommand::GenerateCore(tokens.get(1).map(|path| path.to_string())),
+            ),
+            "echo" => Some(DebuggerCommand::Echo(tokens[1..].join(" "))),
+            "source" => match tokens.get(1) {
+                Some(path) => Some(DebuggerCommand::Source(path.to_string())),
+                None => {
+                    say!("Usage: source <file>");
+                    None
+                }
+            },
+            "break" | "b" => match (tokens.get(1), tokens.get(2)) {
+                (None, _) => {
+                    say!("No breakpoint target specified");
+                    None
+                }
+                (Some(target), None) => Some(DebuggerCommand::BreakPoint(target.to_string(), None)),
+                (Some(target), Some(&"if")) if tokens.len() > 3 => {
+                    match expr::parse_condition(&tokens[3..].join(" ")) {
+                        Ok(condition) => Some(DebuggerCommand::BreakPoint(
+                            target.to_string(),
+                            Some(condition),
+                        )),
+                        Err(err) => {
+                            say!("{}", err);
+                            None
+                        }
+                    }
+                }
+                _ => {
+                    say!("Usage: break <location> [if <condition>]");
+                    None
+                }
+            },
             _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        let words = |line: &str| split_words(line).unwrap();
+        assert_eq!(words("  run a   b "), vec!["run", "a", "b"]);
+        assert_eq!(words("run \"hello world\""), vec!["run", "hello world"]);
+        assert_eq!(words("run 'it''s' x"), vec!["run", "its", "x"]);
+        assert_eq!(words("run 'a \\ \"b\"'"), vec!["run", "a \\ \"b\""]);
+        assert_eq!(words("run \"say \\\"hi\\\"\""), vec!["run", "say \"hi\""]);
+        assert_eq!(words("run \"a\\nb\" c\\ d"), vec!["run", "a\\nb", "c d"]);
+        assert_eq!(words("run \\'quoted\\'"), vec!["run", "'quoted'"]);
+        assert_eq!(words("run \"\" ''"), vec!["run", "", ""]);
+        assert_eq!(words("run pre\"fix suf\"fix"), vec!["run", "prefix suffix"]);
+        assert!(words("").is_empty());
+        assert!(split_words("run \"unterminated").is_err());
+        assert!(split_words("run 'unterminated").is_err());
+    }
+
+    #[test]
+    fn test_run_with_quoted_args() {
+        let words = split_words("run \"hello world\" 'don\"t' plain").unwrap();
+        let tokens: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+        match DebuggerCommand::from_tokens(&tokens) {
+            Some(DebuggerCommand::Run(args, redirections)) => {
+                assert_eq!(args, vec!["hello world", "don\"t", "plain"]);
+                assert_eq!(redirections, Redirections::default());
+            }
+            _ => panic!("expected a run command"),
+        }
+    }
+
+    #[test]
+    fn test_run_with_redirections() {
+        match parse_line("run -v < in.txt >out.txt 3") {
+            Ok(Some(DebuggerCommand::Run(args, redirections))) => {
+                assert_eq!(args, vec!["-v", "3"]);
+                assert_eq!(redirections.stdin.as_deref(), Some("in.txt"));
+                assert_eq!(redirections.stdout.as_deref(), Some("out.txt"));
+                assert!(!redirections.append);
+            }
+            _ => panic!("expected a run command"),
+        }
+        match parse_line("run >> log.txt") {
+            Ok(Some(DebuggerCommand::Run(args, redirections))) => {
+                assert!(args.is_empty());
+                assert_eq!(redirections.stdout.as_deref(), Some("log.txt"));
+                assert!(redirections.append);
+            }
+            _ => panic!("expected a run command"),
+        }
+        assert!(parse_line("run <").is_err());
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert!(matches!(parse_line("   "), Ok(None)));
+        assert!(matches!(parse_line("# break main"), Ok(None)));
+        assert!(matches!(parse_line("  #comment"), Ok(None)));
+        match parse_line("echo \"x  =\" 1") {
+            Ok(Some(DebuggerCommand::Echo(text))) => assert_eq!(text, "x  = 1"),
+            _ => panic!("expected an echo command"),
+        }
+        assert!(matches!(
+            parse_line("echo"),
+            Ok(Some(DebuggerCommand::Echo(_)))
+        ));
+        match parse_line("source 'my script.deet'") {
+            Ok(Some(DebuggerCommand::Source(path))) => assert_eq!(path, "my script.deet"),
+            _ => panic!("expected a source command"),
+        }
+        assert!(parse_line("source").is_err());
+        assert!(parse_line("frobnicate").is_err());
+        assert!(parse_line("echo \"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_parse_breakpoint_management() {
+        assert!(matches!(
+            parse_line("delete 2"),
+            Ok(Some(DebuggerCommand::Delete(2)))
+        ));
+        assert!(matches!(
+            parse_line("d 0"),
+            Ok(Some(DebuggerCommand::Delete(0)))
+        ));
+        assert!(matches!(
+            parse_line("disable 1"),
+            Ok(Some(DebuggerCommand::Disable(1)))
+        ));
+        assert!(matches!(
+            parse_line("enable 1"),
+            Ok(Some(DebuggerCommand::Enable(1)))
+        ));
+        assert!(parse_line("delete").is_err());
+        assert!(parse_line("disable one").is_err());
+    }
+
+    #[test]
+    fn test_parse_conditional_breakpoint() {
+        match parse_line("break foo if x > 5") {
+            Ok(Some(DebuggerCommand::BreakPoint(target, Some(condition)))) => {
+                assert_eq!(target, "foo");
+                assert_eq!(condition.to_string(), "x > 5");
+            }
+            _ => panic!("expected a conditional breakpoint"),
+        }
+        assert!(matches!(
+            parse_line("b loop.c:6"),
+            Ok(Some(DebuggerCommand::BreakPoint(_, None)))
+        ));
+        assert!(parse_line("break foo if").is_err());
+        assert!(parse_line("break foo x > 5").is_err());
+        assert!(parse_line("break foo if x >").is_err());
+    }
+
+    #[test]
+    fn test_parse_registers() {
+        assert!(matches!(
+            parse_line("info registers"),
+            Ok(Some(DebuggerCommand::InfoRegisters))
+        ));
+        match parse_line("set $rax = 0x10") {
+            Ok(Some(DebuggerCommand::SetRegister(name, value))) => {
+                assert_eq!(name, "rax");
+                assert_eq!(value, 0x10);
+            }
+            _ => panic!("expected a set register command"),
+        }
+        assert!(matches!(
+            parse_line("set $rdi=-1"),
+            Ok(Some(DebuggerCommand::SetRegister(_, u64::MAX)))
+        ));
+        assert!(parse_line("set $rax 5").is_err());
+        assert!(parse_line("set $rax = five").is_err());
+    }
+
+    #[test]
+    fn test_parse_examine() {
+        match parse_line("x/16xb $rsp") {
+            Ok(Some(DebuggerCommand::Examine(spec, address))) => {
+                assert_eq!(spec, examine::parse_spec("16xb").unwrap());
+                assert_eq!(address, "$rsp");
+            }
+            _ => panic!("expected an examine command"),
+        }
+        match parse_line("x &s.x") {
+            Ok(Some(DebuggerCommand::Examine(spec, address))) => {
+                assert_eq!(spec, Spec::default());
+                assert_eq!(address, "&s.x");
+            }
+            _ => panic!("expected an examine command"),
+        }
+        assert!(parse_line("x/4").is_err());
+        assert!(parse_line("x/4z 0x1000").is_err());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert!(matches!(
+            parse_line("list"),
+            Ok(Some(DebuggerCommand::List(None)))
+        ));
+        match parse_line("l loop.c:6") {
+            Ok(Some(DebuggerCommand::List(Some(location)))) => assert_eq!(location, "loop.c:6"),
+            _ => panic!("expected a list command"),
+        }
+        assert!(matches!(
+            parse_line("set listsize 20"),
+            Ok(Some(DebuggerCommand::SetListSize(20)))
+        ));
+        assert!(parse_line("set listsize 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_disassemble() {
+        assert!(matches!(
+            parse_line("disas"),
+            Ok(Some(DebuggerCommand::Disassemble(None)))
+        ));
+        match parse_line("disassemble main") {
+            Ok(Some(DebuggerCommand::Disassemble(Some(function)))) => assert_eq!(function, "main"),
+            _ => panic!("expected a disassemble command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_generate_core() {
+        assert!(matches!(
+            parse_line("gcore"),
+            Ok(Some(DebuggerCommand::GenerateCore(None)))
+        ));
+        match parse_line("generate-core /tmp/saved.core") {
+            Ok(Some(DebuggerCommand::GenerateCore(Some(path)))) => {
+                assert_eq!(path, "/tmp/saved.core")
+            }
+            _ => panic!("expected a generate-core command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_record() {
+        assert!(matches!(
+            parse_line("record"),
+            Ok(Some(DebuggerCommand::Record(true)))
+        ));
+        assert!(matches!(
+            parse_line("record stop"),
+            Ok(Some(DebuggerCommand::Record(false)))
+        ));
+        assert!(parse_line("record sometimes").is_err());
+        assert!(matches!(
+            parse_line("reverse-stepi 3"),
+            Ok(Some(DebuggerCommand::ReverseStepInstruction(3)))
+        ));
+        assert!(matches!(
+            parse_line("rs"),
+            Ok(Some(DebuggerCommand::ReverseStep(1)))
+        ));
+        assert!(matches!(
+            parse_line("reverse-continue"),
+            Ok(Some(DebuggerCommand::ReverseContinue))
+        ));
+    }
+
+    #[test]
+    fn test_parse_follow_fork_mode() {
+        assert!(matches!(
+            parse_line("set follow-fork-mode child"),
+            Ok(Some(DebuggerCommand::SetFollowFork(FollowFork::Child)))
+        ));
+        assert!(matches!(
+            parse_line("set follow-fork-mode parent"),
+            Ok(Some(DebuggerCommand::SetFollowFork(FollowFork::Parent)))
+        ));
+        assert!(parse_line("set follow-fork-mode both").is_err());
+    }
+
+    #[test]
+    fn test_parse_signal_commands() {
+        match parse_line("handle SIGUSR1 nostop noprint") {
+            Ok(Some(DebuggerCommand::Handle(signal, actions))) => {
+                assert_eq!(signal, Signal::SIGUSR1);
+                assert_eq!(actions, vec![Action::NoStop, Action::NoPrint]);
+            }
+            _ => panic!("expected a handle command"),
+        }
+        assert!(matches!(
+            parse_line("handle segv"),
+            Ok(Some(DebuggerCommand::Handle(Signal::SIGSEGV, _)))
+        ));
+        assert!(parse_line("handle SIGUSR1 sometimes").is_err());
+        assert!(parse_line("handle").is_err());
+        assert!(matches!(
+            parse_line("signal SIGUSR2"),
+            Ok(Some(DebuggerCommand::Signal(Some(Signal::SIGUSR2))))
+        ));
+        assert!(matches!(
+            parse_line("signal 0"),
+            Ok(Some(DebuggerCommand::Signal(None)))
+        ));
+        assert!(parse_line("signal SIGNOPE").is_err());
+    }
+}
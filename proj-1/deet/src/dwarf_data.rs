@@ -1,7 +1,10 @@
 use crate::gimli_wrapper;
+use crate::maps::MappedObject;
 use addr2line::Context;
-use object::Object;
+use object::{Object, SymbolKind};
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::ops::Range;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -13,6 +16,9 @@ pub enum Error {
 pub struct DwarfData {
     files: Vec<File>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// The shared libraries loaded into the inferior, which addresses outside the target are
+    /// looked up in
+    libraries: Vec<Library>,
 }
 
 impl fmt::Debug for DwarfData {
@@ -21,6 +27,89 @@ impl fmt::Debug for DwarfData {
     }
 }
 
+/// A shared library loaded into the inferior, with whatever debugging information it has. Its own
+/// addresses are relative to `base`, where the dynamic linker put it.
+pub struct Library {
+    pub path: String,
+    pub base: usize,
+    /// One past the end of the library's mappings
+    pub end: usize,
+    debug_data: DwarfData,
+    /// Its functions from the symbol table, by address. Libraries that come with the system are
+    /// usually stripped of their DWARF, but still say where their exported functions are.
+    symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Symbol {
+    name: String,
+    address: usize,
+    size: usize,
+}
+
+impl Library {
+    fn load(object: &MappedObject) -> Result<Library, Error> {
+        let file = fs::File::open(&object.path).or(Err(Error::ErrorOpeningFile))?;
+        let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
+        let parsed = object::File::parse(&mmap)
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
+        let mut symbols: Vec<Symbol> = parsed
+            .symbols()
+            .chain(parsed.dynamic_symbols())
+            .map(|(_, symbol)| symbol)
+            .filter(|symbol| {
+                symbol.kind() == SymbolKind::Text && !symbol.is_undefined() && symbol.address() != 0
+            })
+            .filter_map(|symbol| {
+                Some(Symbol {
+                    name: symbol.name()?.to_string(),
+                    address: symbol.address() as usize,
+                    size: symbol.size() as usize,
+                })
+            })
+            .collect();
+        // Functions often have aliases (printf is also _IO_printf); put the public name first
+        symbols.sort_by(|a, b| {
+            (a.address, a.name.starts_with('_'), &a.name).cmp(&(
+                b.address,
+                b.name.starts_with('_'),
+                &b.name,
+            ))
+        });
+        symbols.dedup();
+        Ok(Library {
+            path: object.path.clone(),
+            base: object.start,
+            end: object.end,
+            debug_data: DwarfData::from_object(&parsed)?,
+            symbols,
+        })
+    }
+
+    /// Whether the library came with DWARF, rather than just a symbol table.
+    pub fn has_debug_info(&self) -> bool {
+        !self.debug_data.files.is_empty()
+    }
+
+    /// Returns the function symbol covering `addr`, relative to the library's base.
+    fn get_symbol_containing(&self, addr: usize) -> Option<&Symbol> {
+        let after = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= addr);
+        let start = self.symbols.get(after.checked_sub(1)?)?.address;
+        let symbol = &self.symbols[self
+            .symbols
+            .partition_point(|symbol| symbol.address < start)];
+        // Some symbols (often hand-written assembly) have no size; take them to run up to the
+        // next one
+        if symbol.size == 0 || addr < symbol.address + symbol.size {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+}
+
 impl From<gimli_wrapper::Error> for Error {
     fn from(err: gimli_wrapper::Error) -> Self {
         Error::DwarfFormatError(err)
@@ -33,28 +122,79 @@ impl DwarfData {
         let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::File::parse(&*mmap)
             .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        DwarfData::from_object(&object)
+    }
+
+    fn from_object(object: &object::File) -> Result<DwarfData, Error> {
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            files: gimli_wrapper::load_file(object, endian)?,
+            addr2line: Context::new(object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            libraries: Vec::new(),
         })
     }
 
+    /// Makes the shared libraries in `objects` (the object files mapped into the inferior, other
+    /// than the target) the ones addresses are looked up in. Libraries already loaded at the same
+    /// address are kept rather than read again; ones that can't be read are reported and left out.
+    pub fn load_libraries(&mut self, objects: &[MappedObject]) {
+        let mut loaded = std::mem::take(&mut self.libraries);
+        for object in objects {
+            match loaded
+                .iter()
+                .position(|lib| lib.path == object.path && lib.base == object.start)
+            {
+                Some(idx) => self.libraries.push(loaded.swap_remove(idx)),
+                None => match Library::load(object) {
+                    Ok(lib) => self.libraries.push(lib),
+                    Err(err) => say!("Could not load symbols from {}: {:?}", object.path, err),
+                },
+            }
+        }
+    }
+
+    pub fn libraries(&self) -> &[Library] {
+        &self.libraries
+    }
+
+    fn get_library_containing(&self, addr: usize) -> Option<&Library> {
+        self.libraries
+            .iter()
+            .find(|lib| lib.base <= addr && addr < lib.end)
+    }
+
+    /// Finds the compilation unit for `file`, which may be the name it was compiled as, a shorter
+    /// path that the compiled name ends in (e.g. just its base name, or `src/foo.c` for a unit
+    /// compiled as `/home/me/proj/src/foo.c`), or a longer path ending in the compiled name
+    /// (addr2line reports absolute paths for units compiled with relative ones).
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
         self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+            f.name == file
+                || f.name.ends_with(&format!("/{}", file))
+                || file.ends_with(&format!("/{}", f.name))
         })
     }
 
+    /// Returns the address of `line_number` (or the first line after it with code) in `file`, or
+    /// in the target's first compilation unit if no file is given. A named file is looked for in
+    /// the shared libraries too, if the target doesn't have it.
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
+            Some(filename) => match self.get_target_file(filename) {
+                Some(target_file) => target_file,
+                None => {
+                    return self.libraries.iter().find_map(|lib| {
+                        let addr = lib.debug_data.get_addr_for_line(file, line_number)?;
+                        Some(lib.base + addr)
+                    })
+                }
+            },
             None => self.files.get(0)?,
         };
         Some(
@@ -66,29 +206,52 @@ impl DwarfData {
         )
     }
 
+    /// Returns the entry point of the function called `func_name`, in `file` if one is given.
+    /// Otherwise the target is searched first, then the shared libraries (their debugging
+    /// information, then their symbol tables).
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        // Functions the target only declares (like printf) are listed with address 0
+        let defines = |func: &&Function| func.name == func_name && func.address != 0;
         match file {
             Some(filename) => Some(
                 self.get_target_file(filename)?
                     .functions
                     .iter()
-                    .find(|func| func.name == func_name)?
+                    .find(defines)?
                     .address,
             ),
             None => {
                 for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
+                    if let Some(func) = file.functions.iter().find(defines) {
                         return Some(func.address);
                     }
                 }
-                None
+                self.libraries.iter().find_map(|lib| {
+                    let addr = lib
+                        .debug_data
+                        .get_addr_for_function(None, func_name)
+                        .or_else(|| {
+                            lib.symbols
+                                .iter()
+                                .find(|symbol| symbol.name == func_name)
+                                .map(|symbol| symbol.address)
+                        })?;
+                    Some(lib.base + addr)
+                })
             }
         }
     }
 
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
+        if let Some(lib) = self.get_library_containing(curr_addr) {
+            let line = lib.debug_data.get_line_from_addr(curr_addr - lib.base)?;
+            return Some(Line {
+                address: curr_addr,
+                ..line
+            });
+        }
         let location = self
             .addr2line
             .find_location(curr_addr.try_into().unwrap())
@@ -102,6 +265,13 @@ impl DwarfData {
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
+        if let Some(lib) = self.get_library_containing(curr_addr) {
+            let addr = curr_addr - lib.base;
+            return lib.debug_data.get_function_from_addr(addr).or_else(|| {
+                lib.get_symbol_containing(addr)
+                    .map(|symbol| symbol.name.clone())
+            });
+        }
         let frame = self
             .addr2line
             .find_frames(curr_addr.try_into().unwrap())
@@ -111,58 +281,213 @@ impl DwarfData {
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
+    /// Returns the address of the first instruction after the prologue of the function starting
+    /// at `func_addr`, i.e. the address of the function's second line table entry (this is what
+    /// GDB does too). Breaking there, rather than on the first instruction, means the frame has
+    /// been set up by the time we stop. Falls back to `func_addr` if the function has only one
+    /// line entry, or if it's a library function that there's no line information for.
+    pub fn get_addr_after_prologue(&self, func_addr: usize) -> Option<usize> {
+        if let Some(lib) = self.get_library_containing(func_addr) {
+            let addr = func_addr - lib.base;
+            return match lib.debug_data.get_addr_after_prologue(addr) {
+                Some(body) => Some(lib.base + body),
+                None if lib.debug_data.get_function_from_addr(addr).is_none() => Some(func_addr),
+                None => None,
+            };
+        }
+        let (file, func) = self.files.iter().find_map(|file| {
+            file.functions
+                .iter()
+                .find(|func| func.address == func_addr)
+                .map(|func| (file, func))
+        })?;
+        Some(
+            file.lines
+                .iter()
+                .map(|line| line.address)
+                .filter(|&addr| addr > func.address && addr < func.address + func.text_length)
+                .min()
+                .unwrap_or(func.address),
+        )
+    }
+
+    /// Returns the addresses of the machine code for the source line containing `addr`, within the
+    /// function containing it: from the first of the line table entries leading up to `addr` that
+    /// belong to the same line, up to the next entry for a different line (or the end of the
+    /// function). This is the code a `next` at `addr` runs through, leaving aside jumps and calls.
+    pub fn get_line_range(&self, addr: usize) -> Option<Range<usize>> {
+        let func = self.get_function_containing(addr)?;
+        let func_end = func.address + func.text_length;
+        // Compare lines the same way Inferior::next_line does
+        let line_at = |addr| {
+            self.get_line_from_addr(addr)
+                .map(|line| (line.file, line.number))
+        };
+        let current = line_at(addr)?;
+        let mut addrs: Vec<usize> = self
+            .files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .map(|line| line.address)
+            .filter(|&line_addr| line_addr >= func.address && line_addr < func_end)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        let start = addrs
+            .iter()
+            .rev()
+            .filter(|&&line_addr| line_addr <= addr)
+            .take_while(|&&line_addr| line_at(line_addr).as_ref() == Some(&current))
+            .last()
+            .copied()
+            .unwrap_or(addr);
+        let end = addrs
+            .iter()
+            .copied()
+            .find(|&line_addr| line_addr > addr && line_at(line_addr).as_ref() != Some(&current))
+            .unwrap_or(func_end);
+        Some(start..end)
+    }
+
+    /// Looks up a variable by name as seen from code at `addr`: the locals and arguments of the
+    /// function containing `addr` come first, then global variables.
+    pub fn get_variable(&self, addr: usize, name: &str) -> Option<&Variable> {
+        if let Some(func) = self.get_function_containing(addr) {
+            if let Some(var) = func.variables.iter().find(|var| var.name == name) {
+                return Some(var);
+            }
+        }
+        self.files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Returns the struct or union type that a `TypeKind::StructRef` refers to.
+    pub fn get_struct(&self, offset: usize) -> Option<&Type> {
+        self.files.iter().find_map(|file| file.structs.get(&offset))
+    }
+
+    /// Whether `addr` is the entry point of a function, in the target or a shared library.
+    pub fn is_function_start(&self, addr: usize) -> bool {
+        if let Some(lib) = self.get_library_containing(addr) {
+            let addr = addr - lib.base;
+            return lib.debug_data.is_function_start(addr)
+                || lib
+                    .get_symbol_containing(addr)
+                    .is_some_and(|symbol| symbol.address == addr);
+        }
+        self.get_function_containing(addr)
+            .is_some_and(|func| func.address == addr)
+    }
+
+    /// Returns the function whose text section contains `addr`, if any.
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= addr && addr < func.address + func.text_length)
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
+            say!("------");
+            say!("{}", file.name);
+            say!("------");
 
-            println!("Global variables:");
+            say!("Global variables:");
             for var in &file.global_variables {
-                println!(
+                say!(
                     "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
+                    var.name,
+                    var.entity_type.name,
+                    var.location,
+                    var.line_number
                 );
             }
 
-            println!("Functions:");
+            say!("Functions:");
             for func in &file.functions {
-                println!(
+                say!(
                     "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
+                    func.name,
+                    func.line_number,
+                    func.address,
+                    func.text_length
                 );
                 for var in &func.variables {
-                    println!(
+                    say!(
                         "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
+                        var.name,
+                        var.entity_type.name,
+                        var.location,
+                        var.line_number
                     );
                 }
             }
 
-            println!("Line numbers:");
+            say!("Line numbers:");
             for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
+                say!("  * {} (at {:#x})", line.number, line.address);
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, kind: TypeKind) -> Self {
         Type {
             name: name,
             size: size,
+            kind: kind,
         }
     }
 }
 
+/// What sort of value a type describes, which decides how its bytes are displayed.
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    /// A number, character or bool, stored the given way
+    Base(Encoding),
+    /// A pointer to a value of the given type, or None if that type isn't known (e.g. void *)
+    Pointer(Option<Box<Type>>),
+    /// A fixed-size array holding the given number of elements
+    Array(Box<Type>, usize),
+    /// A struct or union, with its members in declaration order
+    Struct(Vec<Member>),
+    /// A struct or union that's only referred to, by its offset in the DWARF data, because it's
+    /// behind a pointer. `DwarfData::get_struct` gives its members.
+    StructRef(usize),
+}
+
+/// A member of a struct or union.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Where the member starts, in bytes from the start of the struct
+    pub offset: usize,
+    pub member_type: Type,
+}
+
+/// How the bytes of a base type are interpreted (DW_AT_encoding).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Float,
+    Boolean,
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
@@ -191,6 +516,7 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -208,6 +534,8 @@ pub struct File {
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
+    /// The unit's struct and union types, by their offset in the DWARF data
+    pub structs: HashMap<usize, Type>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -223,4 +551,95 @@ impl fmt::Display for Line {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_addr_after_prologue() {
+        let debug_data = DwarfData::from_file("samples/function_calls")
+            .expect("Could not load samples/function_calls. Have you run make?");
+        let entry = debug_data.get_addr_for_function(None, "func2").unwrap();
+        let addr = debug_data.get_addr_after_prologue(entry).unwrap();
+        assert!(addr > entry);
+        // The prologue belongs to the line the function is declared on; the body starts on the
+        // next line
+        assert_eq!(debug_data.get_line_from_addr(entry).unwrap().number, 9);
+        assert_eq!(debug_data.get_line_from_addr(addr).unwrap().number, 10);
+        assert!(debug_data.get_addr_after_prologue(entry + 1).is_none());
+    }
+
+    #[test]
+    fn test_get_line_range() {
+        let debug_data = DwarfData::from_file("samples/function_calls")
+            .expect("Could not load samples/function_calls. Have you run make?");
+        let entry = debug_data.get_addr_for_function(None, "func2").unwrap();
+        let body = debug_data.get_addr_after_prologue(entry).unwrap();
+        // The prologue is a line of its own, ending where the body starts
+        assert_eq!(debug_data.get_line_range(entry), Some(entry..body));
+        assert_eq!(debug_data.get_line_range(body - 1), Some(entry..body));
+        let range = debug_data.get_line_range(body).unwrap();
+        assert_eq!(range.start, body);
+        assert_eq!(
+            debug_data.get_line_from_addr(range.end - 1).unwrap().number,
+            10
+        );
+        assert_ne!(debug_data.get_line_from_addr(range.end).unwrap().number, 10);
+        assert!(debug_data.get_line_range(0).is_none());
+    }
+
+    #[test]
+    fn test_get_addr_for_line_in_file() {
+        let debug_data = DwarfData::from_file("samples/function_calls")
+            .expect("Could not load samples/function_calls. Have you run make?");
+        let addr = debug_data.get_addr_for_line(None, 10).unwrap();
+        for file in &[
+            "samples/function_calls.c",
+            "function_calls.c",
+            "/src/deet/samples/function_calls.c",
+        ] {
+            assert_eq!(debug_data.get_addr_for_line(Some(file), 10), Some(addr));
+        }
+        // Only whole path components match
+        assert!(debug_data.get_addr_for_line(Some("calls.c"), 10).is_none());
+        assert!(debug_data
+            .get_addr_for_line(Some("other/function_calls.c"), 10)
+            .is_none());
+    }
 
+    #[test]
+    fn test_shared_library_lookup() {
+        let mut debug_data = DwarfData::from_file("samples/function_calls")
+            .expect("Could not load samples/function_calls. Have you run make?");
+        // function_calls only declares printf; it's defined in libc, which this test is linked
+        // against too. Pretend it was loaded somewhere else.
+        let maps = fs::read_to_string("/proc/self/maps").unwrap();
+        let libc = crate::maps::loaded_objects(&crate::maps::parse(&maps))
+            .into_iter()
+            .find(|object| object.path.contains("/libc"))
+            .expect("Could not find libc");
+        let base = 0x7f00_0000_0000;
+        let end = base + libc.end - libc.start;
+        assert!(debug_data.get_addr_for_function(None, "printf").is_none());
+        debug_data.load_libraries(&[MappedObject {
+            start: base,
+            end,
+            ..libc
+        }]);
+
+        let printf = debug_data.get_addr_for_function(None, "printf").unwrap();
+        assert!(base < printf && printf < end);
+        assert_eq!(debug_data.get_function_from_addr(printf).unwrap(), "printf");
+        assert!(debug_data.is_function_start(printf));
+        assert!(!debug_data.is_function_start(printf + 1));
+        assert_eq!(debug_data.get_addr_after_prologue(printf), Some(printf));
+        // The target's own functions are still found first
+        let main = debug_data.get_addr_for_function(None, "main").unwrap();
+        assert!(main < base);
+        assert_eq!(debug_data.get_function_from_addr(main).unwrap(), "main");
+
+        debug_data.load_libraries(&[]);
+        assert!(debug_data.get_addr_for_function(None, "printf").is_none());
+        assert!(debug_data.get_function_from_addr(printf).is_none());
+    }
+}
@@ -0,0 +1,149 @@
+//! Formatting raw memory for `x/NFU addr`, where N is how many units to show, F how to show each
+//! one and U how big a unit is, as in gdb.
+
+use crate::value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Hex,
+    Decimal,
+    Unsigned,
+    /// Each byte as a number and the character it stands for
+    Char,
+}
+
+/// What `x` should show, from the part of the command after the `/`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spec {
+    pub count: usize,
+    pub format: Format,
+    /// Bytes per unit: 1, 2, 4 or 8
+    pub unit: usize,
+}
+
+impl Default for Spec {
+    fn default() -> Spec {
+        Spec {
+            count: 1,
+            format: Format::Hex,
+            unit: 4,
+        }
+    }
+}
+
+/// Parses the `NFU` after `x/`. Each part is optional, and the format and unit letters may come in
+/// either order.
+pub fn parse_spec(text: &str) -> Result<Spec, String> {
+    let mut spec = Spec::default();
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        spec.count = match text[..digits].parse() {
+            Ok(count) if count > 0 => count,
+            _ => return Err(format!("Invalid count '{}'", &text[..digits])),
+        };
+    }
+    let mut unit = None;
+    for letter in text[digits..].chars() {
+        match letter {
+            'x' => spec.format = Format::Hex,
+            'd' => spec.format = Format::Decimal,
+            'u' => spec.format = Format::Unsigned,
+            'c' => spec.format = Format::Char,
+            'b' => unit = Some(1),
+            'h' => unit = Some(2),
+            'w' => unit = Some(4),
+            'g' => unit = Some(8),
+            _ => return Err(format!("Invalid format or unit letter '{}'", letter)),
+        }
+    }
+    spec.unit = match (spec.format, unit) {
+        (Format::Char, _) => 1,
+        (_, Some(unit)) => unit,
+        (_, None) => spec.unit,
+    };
+    Ok(spec)
+}
+
+/// Formats `bytes`, read from `start` in the inferior, as lines of units with the address of the
+/// first one at the start of each line.
+pub fn format_lines(start: usize, bytes: &[u8], spec: &Spec) -> Vec<String> {
+    let per_line = if spec.unit >= 4 { 16 / spec.unit } else { 8 };
+    bytes
+        .chunks(spec.unit * per_line)
+        .enumerate()
+        .map(|(idx, line)| {
+            let units: Vec<String> = line
+                .chunks_exact(spec.unit)
+                .map(|unit| format_unit(unit, spec.format))
+                .collect();
+            format!(
+                "{:#x}:\t{}",
+                start + idx * spec.unit * per_line,
+                units.join("\t")
+            )
+        })
+        .collect()
+}
+
+fn format_unit(bytes: &[u8], format: Format) -> String {
+    match format {
+        Format::Hex => format!(
+            "0x{:0width$x}",
+            value::read_uint(bytes),
+            width = 2 * bytes.len()
+        ),
+        Format::Decimal => value::read_int(bytes).to_string(),
+        Format::Unsigned => value::read_uint(bytes).to_string(),
+        Format::Char => format!("{} {}", bytes[0] as i8, value::quote_char(bytes[0])),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(parse_spec(""), Ok(Spec::default()));
+        assert_eq!(
+            parse_spec("16xb"),
+            Ok(Spec {
+                count: 16,
+                format: Format::Hex,
+                unit: 1
+            })
+        );
+        assert_eq!(
+            parse_spec("gd"),
+            Ok(Spec {
+                count: 1,
+                format: Format::Decimal,
+                unit: 8
+            })
+        );
+        assert_eq!(parse_spec("4cw").unwrap().unit, 1);
+        assert!(parse_spec("0x").is_err());
+        assert!(parse_spec("4q").is_err());
+    }
+
+    #[test]
+    fn test_format_lines() {
+        let bytes: Vec<u8> = (0..10).collect();
+        let spec = parse_spec("10xb").unwrap();
+        assert_eq!(
+            format_lines(0x1000, &bytes, &spec),
+            vec![
+                "0x1000:\t0x00\t0x01\t0x02\t0x03\t0x04\t0x05\t0x06\t0x07",
+                "0x1008:\t0x08\t0x09",
+            ]
+        );
+        let spec = parse_spec("2dw").unwrap();
+        let bytes = [(-2i32).to_le_bytes(), 7i32.to_le_bytes()].concat();
+        assert_eq!(format_lines(0x10, &bytes, &spec), vec!["0x10:\t-2\t7"]);
+        let spec = parse_spec("2c").unwrap();
+        assert_eq!(
+            format_lines(0x10, b"A\n", &spec),
+            vec!["0x10:\t65 'A'\t10 '\\n'"]
+        );
+    }
+}
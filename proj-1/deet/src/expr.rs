@@ -0,0 +1,362 @@
+//! Parsing the expressions that `print` accepts: a variable, optionally followed by member
+//! accesses (`s.x`, `p->next`) and array indexing (`arr[2]`), and possibly dereferenced (`*p`).
+//! Breakpoint conditions compare two such expressions, or one and an integer (`x > 5`).
+
+use std::fmt;
+
+/// A parsed `print` expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Variable(String),
+    /// `expr.name`. Applied to a pointer to a struct, this accesses the member of the struct it
+    /// points to, so `p.next` means the same as `p->next`
+    Member(Box<Expr>, String),
+    /// `expr[index]`, on an array or a pointer
+    Index(Box<Expr>, usize),
+    /// `*expr`
+    Deref(Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Member(inner, name) => match &**inner {
+                Expr::Deref(pointer) => write!(f, "{}->{}", pointer, name),
+                inner => write!(f, "{}.{}", inner, name),
+            },
+            Expr::Index(inner, index) => write!(f, "{}[{}]", inner, index),
+            Expr::Deref(inner) => match &**inner {
+                Expr::Variable(_) | Expr::Deref(_) => write!(f, "*{}", inner),
+                inner => write!(f, "*({})", inner),
+            },
+        }
+    }
+}
+
+/// One side of a comparison in a breakpoint condition.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Expr(Expr),
+    Int(i64),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Expr(expr) => write!(f, "{}", expr),
+            Operand::Int(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// The operators, longest first so that `<=` isn't read as `<` followed by `=`.
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    pub fn apply(self, lhs: i64, rhs: i64) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// A breakpoint condition, `lhs op rhs`. A lone operand `x` is taken to mean `x != 0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub op: Comparison,
+    pub rhs: Operand,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (op, _) = Comparison::OPERATORS
+            .iter()
+            .find(|(_, op)| *op == self.op)
+            .unwrap();
+        write!(f, "{} {} {}", self.lhs, op, self.rhs)
+    }
+}
+
+/// Parses a `print` expression, returning a description of the problem if it's malformed.
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser::new(text);
+    let expr = parser.unary()?;
+    parser.end()?;
+    Ok(expr)
+}
+
+/// Parses a breakpoint condition, returning a description of the problem if it's malformed.
+pub fn parse_condition(text: &str) -> Result<Condition, String> {
+    let mut parser = Parser::new(text);
+    let lhs = parser.operand()?;
+    let condition = match Comparison::OPERATORS
+        .iter()
+        .find(|(text, _)| parser.eat(text))
+    {
+        Some(&(_, op)) => Condition {
+            lhs,
+            op,
+            rhs: parser.operand()?,
+        },
+        None => Condition {
+            lhs,
+            op: Comparison::Ne,
+            rhs: Operand::Int(0),
+        },
+    };
+    parser.end()?;
+    Ok(condition)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Parser {
+        Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    /// Fails unless all of the input has been consumed.
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(()),
+            Some(c) => Err(format!("Unexpected '{}' in expression", c)),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `text` if the input continues with it (after any whitespace).
+    fn eat(&mut self, text: &str) -> bool {
+        self.skip_whitespace();
+        let matches = text
+            .chars()
+            .enumerate()
+            .all(|(idx, c)| self.chars.get(self.pos + idx) == Some(&c));
+        if matches {
+            self.pos += text.chars().count();
+        }
+        matches
+    }
+
+    /// operand := ['-'] number | unary
+    fn operand(&mut self) -> Result<Operand, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos = start;
+            return Ok(Operand::Expr(self.unary()?));
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .map(Operand::Int)
+            .map_err(|_| format!("Number {} is out of range", digits))
+    }
+
+    /// unary := '*' unary | postfix
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("*") {
+            Ok(Expr::Deref(Box::new(self.unary()?)))
+        } else {
+            self.postfix()
+        }
+    }
+
+    /// postfix := primary ('.' name | '->' name | '[' number ']')*
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(".") {
+                expr = Expr::Member(Box::new(expr), self.name()?);
+            } else if self.eat("->") {
+                expr = Expr::Member(Box::new(Expr::Deref(Box::new(expr))), self.name()?);
+            } else if self.eat("[") {
+                self.skip_whitespace();
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let digits: String = self.chars[start..self.pos].iter().collect();
+                let index = digits
+                    .parse()
+                    .map_err(|_| "Expected an array index after '['".to_string())?;
+                if !self.eat("]") {
+                    return Err("Expected ']' after array index".to_string());
+                }
+                expr = Expr::Index(Box::new(expr), index);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// primary := name | '(' unary ')'
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let expr = self.unary()?;
+            if !self.eat(")") {
+                return Err("Expected ')'".to_string());
+            }
+            return Ok(expr);
+        }
+        Ok(Expr::Variable(self.name()?))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(match self.peek() {
+                Some(c) => format!("Expected a name, found '{}'", c),
+                None => "Expected a name at the end of the expression".to_string(),
+            });
+        }
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("count"), Ok(*var("count")));
+        assert_eq!(parse("s.x"), Ok(Expr::Member(var("s"), "x".to_string())));
+        assert_eq!(parse("arr [ 2 ]"), Ok(Expr::Index(var("arr"), 2)));
+        assert_eq!(parse("*p"), Ok(Expr::Deref(var("p"))));
+        assert_eq!(
+            parse("p->next->value"),
+            Ok(Expr::Member(
+                Box::new(Expr::Deref(Box::new(Expr::Member(
+                    Box::new(Expr::Deref(var("p"))),
+                    "next".to_string()
+                )))),
+                "value".to_string()
+            ))
+        );
+        // * applies to everything after it, unless parenthesized
+        assert_eq!(
+            parse("*box.corners[1]"),
+            Ok(Expr::Deref(Box::new(Expr::Index(
+                Box::new(Expr::Member(var("box"), "corners".to_string())),
+                1
+            ))))
+        );
+        assert_eq!(
+            parse("(*p).x"),
+            Ok(Expr::Member(
+                Box::new(Expr::Deref(var("p"))),
+                "x".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("s.").is_err());
+        assert!(parse("arr[").is_err());
+        assert!(parse("arr[-1]").is_err());
+        assert!(parse("arr[1").is_err());
+        assert!(parse("(s").is_err());
+        assert!(parse("s + 1").is_err());
+        assert!(parse("1x").is_err());
+        assert!(parse("list next").is_err());
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(
+            parse_condition("x > 5"),
+            Ok(Condition {
+                lhs: Operand::Expr(*var("x")),
+                op: Comparison::Gt,
+                rhs: Operand::Int(5),
+            })
+        );
+        assert_eq!(
+            parse_condition("p->len<=-1"),
+            Ok(Condition {
+                lhs: Operand::Expr(Expr::Member(
+                    Box::new(Expr::Deref(var("p"))),
+                    "len".to_string()
+                )),
+                op: Comparison::Le,
+                rhs: Operand::Int(-1),
+            })
+        );
+        assert_eq!(
+            parse_condition("done"),
+            Ok(Condition {
+                lhs: Operand::Expr(*var("done")),
+                op: Comparison::Ne,
+                rhs: Operand::Int(0),
+            })
+        );
+        assert_eq!(
+            parse_condition("a[1] == *b").unwrap().to_string(),
+            "a[1] == *b"
+        );
+        assert!(parse_condition("").is_err());
+        assert!(parse_condition("x >").is_err());
+        assert!(parse_condition("x = 5").is_err());
+        assert!(parse_condition("x < 99999999999999999999").is_err());
+    }
+}
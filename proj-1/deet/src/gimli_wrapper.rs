@@ -6,7 +6,9 @@
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    Encoding, File, Function, Line, Location, Member, Type, TypeKind, Variable,
+};
 use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
@@ -39,15 +41,15 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
-
     let mut compilation_units: Vec<File> = Vec::new();
 
     // Iterate over the compilation units.
     let mut iter = dwarf.units();
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
+        // Collect the unit's types up front, since variables can refer to types that are only
+        // defined further down
+        let types = collect_types(&unit, &dwarf)?;
 
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
@@ -72,41 +74,15 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         global_variables: Vec::new(),
                         functions: Vec::new(),
                         lines: Vec::new(),
+                        structs: resolve_structs(&types),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
+                        //say!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
                             gimli::DW_AT_name => {
                                 if let Ok(DebugValue::Str(name)) = val {
@@ -119,7 +95,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 }
                             }
                             gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
+                                //say!("low pc {:?}", attr.value());
                                 if let Ok(DebugValue::Uint(low_pc)) = val {
                                     func.address = low_pc.try_into().unwrap();
                                 }
@@ -142,7 +118,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
+                        //say!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
                             gimli::DW_AT_name => {
                                 if let Ok(DebugValue::Str(attr_name)) = val {
@@ -151,9 +127,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                                    entity_type = resolve_type(&types, offset, 0, false);
                                 }
                             }
                             gimli::DW_AT_location => {
@@ -175,6 +149,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             entity_type: entity_type.unwrap(),
                             location: location.unwrap(),
                             line_number: line_number.try_into().unwrap(),
+                            is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
                         };
                         if depth == 1 {
                             compilation_units
@@ -266,6 +241,321 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     Ok(compilation_units)
 }
 
+/// A type as it appears in the DWARF data, referring to other types by their offset in the
+/// .debug_info section.
+enum RawType {
+    Base {
+        name: String,
+        size: usize,
+        encoding: Option<Encoding>,
+    },
+    Pointer {
+        size: usize,
+        target: Option<usize>,
+    },
+    /// The element type, and the length of each dimension
+    Array {
+        element: Option<usize>,
+        lengths: Vec<usize>,
+    },
+    /// Typedefs and const/volatile qualifiers: the target type under another name
+    Alias {
+        name: Option<String>,
+        qualifier: Option<&'static str>,
+        target: Option<usize>,
+    },
+    /// Structs and unions. `name` includes the keyword, e.g. "struct point"
+    Struct {
+        name: String,
+        size: usize,
+        members: Vec<RawMember>,
+    },
+    /// Enums, which are only known by name for now
+    Other {
+        name: String,
+    },
+}
+
+/// A member of a struct or union: its name, its offset from the start of the struct, and its type.
+struct RawMember {
+    name: String,
+    offset: usize,
+    target: Option<usize>,
+}
+
+/// Pointers to pointers (and so on) are followed at most this deep.
+const MAX_TYPE_DEPTH: usize = 16;
+
+/// Returns the offset of a DIE in the .debug_info section, which is how DW_AT_type refers to it.
+fn section_offset<R: Reader>(unit: &gimli::Unit<R>, offset: UnitOffset) -> Option<usize> {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(offset) => Some(offset.0),
+        UnitSectionOffset::DebugTypesOffset(_) => None,
+    }
+}
+
+/// Returns the DIE that an entry's DW_AT_type refers to, if it has one.
+fn type_attr<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_type).ok()??, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
+fn name_attr<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+fn udata_attr<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Option<usize> {
+    entry.attr(name).ok()??.udata_value()?.try_into().ok()
+}
+
+/// Collects the types defined in a unit, keyed by their offset in the .debug_info section.
+fn collect_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<HashMap<usize, RawType>, Error> {
+    let mut types = HashMap::new();
+    // The array whose dimensions (DW_TAG_subrange_type children) are being read
+    let mut current_array = None;
+    // The structs being read, innermost last, along with the depth of their entries. A member
+    // belongs to the innermost one if it's a direct child.
+    let mut open_structs: Vec<(isize, usize)> = Vec::new();
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while open_structs
+            .last()
+            .is_some_and(|(struct_depth, _)| *struct_depth >= depth)
+        {
+            open_structs.pop();
+        }
+        let offset = match section_offset(unit, entry.offset()) {
+            Some(offset) => offset,
+            None => continue,
+        };
+        if entry.tag() != gimli::DW_TAG_subrange_type {
+            current_array = None;
+        }
+        let raw_type = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding) {
+                    Ok(Some(gimli::AttributeValue::Encoding(encoding))) => match encoding {
+                        gimli::DW_ATE_signed => Some(Encoding::Signed),
+                        gimli::DW_ATE_unsigned => Some(Encoding::Unsigned),
+                        gimli::DW_ATE_signed_char => Some(Encoding::SignedChar),
+                        gimli::DW_ATE_unsigned_char => Some(Encoding::UnsignedChar),
+                        gimli::DW_ATE_float => Some(Encoding::Float),
+                        gimli::DW_ATE_boolean => Some(Encoding::Boolean),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                RawType::Base {
+                    name: name_attr(entry, unit, dwarf).unwrap_or("<unknown>".to_string()),
+                    size: udata_attr(entry, gimli::DW_AT_byte_size).unwrap_or(0),
+                    encoding,
+                }
+            }
+            gimli::DW_TAG_pointer_type => RawType::Pointer {
+                size: udata_attr(entry, gimli::DW_AT_byte_size).unwrap_or(8),
+                target: type_attr(entry, unit, dwarf),
+            },
+            gimli::DW_TAG_array_type => {
+                current_array = Some(offset);
+                RawType::Array {
+                    element: type_attr(entry, unit, dwarf),
+                    lengths: Vec::new(),
+                }
+            }
+            gimli::DW_TAG_subrange_type => {
+                // Arrays declared without a size (e.g. `int values[]`) have no bound
+                let length = udata_attr(entry, gimli::DW_AT_count)
+                    .or_else(|| udata_attr(entry, gimli::DW_AT_upper_bound).map(|bound| bound + 1))
+                    .unwrap_or(0);
+                if let Some(RawType::Array { lengths, .. }) =
+                    current_array.and_then(|array| types.get_mut(&array))
+                {
+                    lengths.push(length);
+                }
+                continue;
+            }
+            gimli::DW_TAG_typedef => RawType::Alias {
+                name: name_attr(entry, unit, dwarf),
+                qualifier: None,
+                target: type_attr(entry, unit, dwarf),
+            },
+            gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => RawType::Alias {
+                name: None,
+                qualifier: Some(if entry.tag() == gimli::DW_TAG_const_type {
+                    "const"
+                } else {
+                    "volatile"
+                }),
+                target: type_attr(entry, unit, dwarf),
+            },
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+                let keyword = if entry.tag() == gimli::DW_TAG_structure_type {
+                    "struct"
+                } else {
+                    "union"
+                };
+                let name = name_attr(entry, unit, dwarf).unwrap_or("<anonymous>".to_string());
+                open_structs.push((depth, offset));
+                RawType::Struct {
+                    name: format!("{} {}", keyword, name),
+                    size: udata_attr(entry, gimli::DW_AT_byte_size).unwrap_or(0),
+                    members: Vec::new(),
+                }
+            }
+            gimli::DW_TAG_member => {
+                // Bit fields don't start on a byte boundary, so they can't be shown as they are
+                let is_bit_field = udata_attr(entry, gimli::DW_AT_bit_size).is_some();
+                let parent = match open_structs.last() {
+                    Some((struct_depth, parent)) if *struct_depth == depth - 1 => *parent,
+                    _ => continue,
+                };
+                if let (false, Some(name), Some(RawType::Struct { members, .. })) = (
+                    is_bit_field,
+                    name_attr(entry, unit, dwarf),
+                    types.get_mut(&parent),
+                ) {
+                    members.push(RawMember {
+                        name,
+                        // Union members have no location, since they all start at the beginning
+                        offset: udata_attr(entry, gimli::DW_AT_data_member_location).unwrap_or(0),
+                        target: type_attr(entry, unit, dwarf),
+                    });
+                }
+                continue;
+            }
+            gimli::DW_TAG_enumeration_type => {
+                let name = name_attr(entry, unit, dwarf).unwrap_or("<anonymous>".to_string());
+                RawType::Other {
+                    name: format!("enum {}", name),
+                }
+            }
+            _ => continue,
+        };
+        types.insert(offset, raw_type);
+    }
+    Ok(types)
+}
+
+/// Builds every struct and union type in the unit, keyed by their offset, for looking up the
+/// structs that other types only refer to (see `TypeKind::StructRef`).
+fn resolve_structs(types: &HashMap<usize, RawType>) -> HashMap<usize, Type> {
+    types
+        .iter()
+        .filter(|(_, raw_type)| matches!(raw_type, RawType::Struct { .. }))
+        .filter_map(|(offset, _)| Some((*offset, resolve_type(types, *offset, 0, false)?)))
+        .collect()
+}
+
+/// Builds the type at the given offset, or returns None if it isn't one deet can display. If
+/// `by_ref` is set, a struct is only referred to by its offset rather than built with its members.
+/// Pointers set it for the type they point to, which keeps recursive types like linked list nodes
+/// from being expanded forever.
+fn resolve_type(
+    types: &HashMap<usize, RawType>,
+    offset: usize,
+    depth: usize,
+    by_ref: bool,
+) -> Option<Type> {
+    if depth > MAX_TYPE_DEPTH {
+        return None;
+    }
+    match types.get(&offset)? {
+        RawType::Base {
+            name,
+            size,
+            encoding,
+        } => Some(Type::new(name.clone(), *size, TypeKind::Base((*encoding)?))),
+        RawType::Pointer { size, target } => {
+            let pointee = target.and_then(|target| resolve_type(types, target, depth + 1, true));
+            let pointee_name = match (&pointee, target.and_then(|target| types.get(&target))) {
+                (Some(pointee), _) => pointee.name.clone(),
+                (None, Some(RawType::Other { name })) => name.clone(),
+                _ => "void".to_string(),
+            };
+            Some(Type::new(
+                format!("{} *", pointee_name),
+                *size,
+                TypeKind::Pointer(pointee.map(Box::new)),
+            ))
+        }
+        RawType::Array { element, lengths } => {
+            let element = resolve_type(types, (*element)?, depth + 1, false)?;
+            let element_name = element.name.clone();
+            // int a[2][3] is an array of two arrays of three ints
+            let mut array = element;
+            for dim in (0..lengths.len()).rev() {
+                let dims: String = lengths[dim..]
+                    .iter()
+                    .map(|length| format!("[{}]", length))
+                    .collect();
+                array = Type::new(
+                    format!("{} {}", element_name, dims),
+                    array.size * lengths[dim],
+                    TypeKind::Array(Box::new(array), lengths[dim]),
+                );
+            }
+            Some(array)
+        }
+        RawType::Alias {
+            name,
+            qualifier,
+            target,
+        } => {
+            let mut target = resolve_type(types, (*target)?, depth + 1, by_ref)?;
+            if let Some(name) = name {
+                target.name = name.clone();
+            }
+            if let Some(qualifier) = qualifier {
+                target.name = format!("{} {}", qualifier, target.name);
+            }
+            Some(target)
+        }
+        RawType::Struct { name, size, .. } if by_ref => {
+            Some(Type::new(name.clone(), *size, TypeKind::StructRef(offset)))
+        }
+        RawType::Struct {
+            name,
+            size,
+            members,
+        } => {
+            // Members whose type can't be displayed are left out
+            let members = members
+                .iter()
+                .filter_map(|member| {
+                    Some(Member {
+                        name: member.name.clone(),
+                        offset: member.offset,
+                        member_type: resolve_type(types, member.target?, depth + 1, false)?,
+                    })
+                })
+                .collect();
+            Some(Type::new(name.clone(), *size, TypeKind::Struct(members)))
+        }
+        RawType::Other { .. } => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
@@ -1,11 +1,42 @@
-use crate::dwarf_data::DwarfData;
+use crate::corefile::{self, Core, Segment};
+use crate::dwarf_data::{DwarfData, Function, Location, Type, TypeKind, Variable};
+use crate::expr::{Condition, Expr, Operand};
+use crate::json::Json;
+use crate::maps;
+use crate::output::{self, output};
+use crate::record::{self, Entry, History, RECORD_LIMIT};
+use crate::registers;
+use crate::value;
+use iced_x86::{Decoder, DecoderOptions, Formatter, GasFormatter, Instruction, Mnemonic, OpKind};
+use nix::errno::Errno;
 use nix::sys::ptrace;
 use nix::sys::ptrace::AddressType;
-use nix::sys::signal;
-use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::Pid;
+use nix::sys::signal::{self, SigHandler};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{getpgid, getpgrp, Pid};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fs::{self, File, OpenOptions};
 use std::mem::size_of;
-use std::process::{Child, Command};
+use std::ops::Range;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The longest possible x86-64 instruction, in bytes.
+const MAX_INSTRUCTION_LEN: usize = 15;
+/// How many instructions preceding rip `print_disassembly` tries to show.
+const DISAS_CONTEXT_BEFORE: usize = 3;
+/// Stop walking the stack after this many frames, in case the chain of saved rbp values is
+/// corrupt or loops (e.g. through code compiled without frame pointers).
+const MAX_BACKTRACE_DEPTH: usize = 256;
+/// Where the debug registers (`u_debugreg`) are in `struct user`, for PTRACE_PEEKUSER/POKEUSER.
+const DEBUG_REGS_OFFSET: usize = 848;
+/// DR6, which says which watchpoints were triggered.
+const DEBUG_STATUS_REG: usize = 6;
+/// DR7, which enables the watchpoints and says what each one watches for.
+const DEBUG_CONTROL_REG: usize = 7;
+/// How many hardware watchpoints there are, one per address register DR0-DR3.
+pub const WATCHPOINT_SLOTS: usize = 4;
 
 #[derive(Clone)]
 struct Breakpoint {
@@ -13,10 +44,45 @@ struct Breakpoint {
     orig_byte: u8,
 }
 
+/// Returns the address of a variable as seen from `frame`.
+fn variable_address(var: &Variable, frame: &Frame) -> usize {
+    match var.location {
+        Location::Address(addr) => addr,
+        // Locals are addressed relative to the canonical frame address, which is the stack
+        // pointer before the call: 16 bytes above the saved rbp and return address
+        Location::FramePointerOffset(offset) => (frame.base_ptr as isize + 16 + offset) as usize,
+    }
+}
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+/// Writes the byte at `addr` in the traced process `pid`, returning the byte that was there.
+fn write_byte_in(pid: Pid, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    let aligned_addr = align_addr_to_word(addr);
+    let byte_offset = addr - aligned_addr;
+    let word = ptrace::read(pid, aligned_addr as ptrace::AddressType)? as u64;
+    let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+    let masked_word = word & !(0xff << (8 * byte_offset));
+    let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
+    ptrace::write(
+        pid,
+        aligned_addr as ptrace::AddressType,
+        updated_word as *mut std::ffi::c_void,
+    )?;
+    Ok(orig_byte as u8)
+}
+
+/// A stack frame, found by following the chain of saved frame pointers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// Where execution is (or, for callers, will resume) in this frame
+    pub instruction_ptr: usize,
+    /// The frame's rbp, which its locals and arguments are addressed relative to
+    pub base_ptr: usize,
+}
+
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
@@ -28,6 +94,28 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior called exec and is stopped at the start of the new program, whose
+    /// instruction pointer this contains. The breakpoints went away with the old program.
+    Execed(usize),
+}
+
+/// Files to connect the inferior's standard input and output to instead of the terminal, as given
+/// to `run` with `< file`, `> file` or `>> file`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Redirections {
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    /// Whether to append to the stdout file rather than truncating it
+    pub append: bool,
+}
+
+/// Which process to keep debugging when the inferior forks; the other one is detached from and
+/// carries on by itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FollowFork {
+    Parent,
+    Child,
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -39,9 +127,47 @@ fn child_traceme() -> Result<(), std::io::Error> {
     )))
 }
 
+/// The inferior that Ctrl-C should be passed on to while we're waiting for it to stop, or 0 if
+/// there's none, or it's in our process group and so gets the terminal's SIGINT directly.
+static INTERRUPT_TARGET: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_interrupt(_signal: libc::c_int) {
+    let pid = INTERRUPT_TARGET.load(Ordering::SeqCst);
+    if pid != 0 {
+        unsafe { libc::kill(pid, libc::SIGINT) };
+    }
+}
+
+/// Makes Ctrl-C interrupt the inferior rather than the debugger: while we wait for the inferior,
+/// SIGINT is passed on to it if it's in another process group (e.g. we attached to it), so that
+/// it stops with SIGINT and control comes back to the prompt. At other times the debugger itself
+/// ignores SIGINT.
+pub fn forward_interrupts() -> Result<(), nix::Error> {
+    unsafe { signal::signal(signal::SIGINT, SigHandler::Handler(forward_interrupt)) }.map(|_| ())
+}
+
+/// How a stretch of single-stepping within a source line ended.
+enum Stepped {
+    Done(Status),
+    /// A call instruction was executed, so the inferior is stopped at the start of the callee
+    EnteredCall(Status),
+}
+
 pub struct Inferior {
-    child: Child,
-    breakpoints: Vec<Breakpoint>,
+    pid: Pid,
+    /// The process we spawned, or None if we attached to one that was already running
+    child: Option<Child>,
+    /// Installed breakpoints, keyed by address. The original bytes are read from this process
+    /// when each breakpoint is installed, so nothing is carried over from an earlier inferior.
+    breakpoints: HashMap<usize, Breakpoint>,
+    /// Whether the inferior is in our process group, so that Ctrl-C at the terminal reaches it
+    /// without our help
+    in_our_process_group: bool,
+    follow_fork: FollowFork,
+    /// The core file being looked at, if this isn't a live process
+    core: Option<Core>,
+    /// The instructions executed since recording was turned on, if it is
+    history: Option<History>,
 }
 
 impl Inferior {
@@ -58,10 +184,16 @@ impl Inferior {
     /// # Parameters
     /// - `target`: A string slice representing the path to the target executable.
     /// - `args`: A vector of strings representing the command-line arguments for the target.
+    /// - `redirections`: Files to use as the target's stdin and stdout, if not the terminal's.
     ///
     /// # Returns
     /// `Some(Inferior)` if the process is successfully spawned and stops with SIGTRAP, or `None` on failure.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &Vec<usize>) -> Option<Inferior> {
+    pub fn spawn(
+        target: &str,
+        args: &Vec<String>,
+        redirections: &Redirections,
+        breakpoints: &Vec<usize>,
+    ) -> Option<Inferior> {
         // Import the Unix-specific process extension for using pre_exec.
         use std::os::unix::process::CommandExt;
 
@@ -69,6 +201,36 @@ impl Inferior {
         let mut cmd = Command::new(target);
         cmd.args(args);
 
+        // Hook up any files the target's input or output was redirected to.
+        if let Some(path) = &redirections.stdin {
+            match File::open(path) {
+                Ok(file) => {
+                    cmd.stdin(Stdio::from(file));
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    return None;
+                }
+            }
+        }
+        if let Some(path) = &redirections.stdout {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(redirections.append)
+                .truncate(!redirections.append)
+                .open(path);
+            match file {
+                Ok(file) => {
+                    cmd.stdout(Stdio::from(file));
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    return None;
+                }
+            }
+        }
+
         // Install a pre-exec hook to enable ptrace in the child process.
         // Safety: pre_exec is unsafe because it executes in the child process context.
         unsafe {
@@ -88,15 +250,7 @@ impl Inferior {
         // Wait for the child process to stop due to SIGTRAP, which indicates successful PTRACE_TRACEME.
         match waitpid(pid, None) {
             Ok(WaitStatus::Stopped(_, signal)) if signal == signal::SIGTRAP => {
-                let mut res = Inferior {
-                    child,
-                    breakpoints: Vec::new(),
-                };
-                for bp in breakpoints {
-                    res.install_break_points(*bp)
-                        .expect("Failed to install breakpoint");
-                }
-                Some(res)
+                Some(Inferior::stopped(pid, Some(child), breakpoints))
             }
             Ok(status) => {
                 eprintln!("Unexpected wait status: {:?}", status);
@@ -109,78 +263,523 @@ impl Inferior {
         }
     }
 
+    /// Attaches to the already-running process `pid` with PTRACE_ATTACH and waits for it to stop.
+    /// Returns None, after printing why, if it can't be traced (e.g. it doesn't exist, or it
+    /// belongs to someone else).
+    pub fn attach(pid: Pid, breakpoints: &Vec<usize>) -> Option<Inferior> {
+        if let Err(e) = ptrace::attach(pid) {
+            eprintln!("Failed to attach to process {}: {}", pid, e);
+            return None;
+        }
+        // PTRACE_ATTACH stops the process with SIGSTOP, but it may stop for something else first,
+        // e.g. the SIGTRAP of an exec that was under way. Let it carry on until the SIGSTOP
+        // arrives, passing on any real signal.
+        loop {
+            match waitpid(pid, None) {
+                Ok(WaitStatus::Stopped(_, signal::SIGSTOP)) => {
+                    return Some(Inferior::stopped(pid, None, breakpoints))
+                }
+                Ok(WaitStatus::Stopped(_, signal)) => {
+                    let signal = Some(signal).filter(|signal| *signal != signal::SIGTRAP);
+                    if let Err(e) = ptrace::cont(pid, signal) {
+                        eprintln!("Failed to resume process {}: {}", pid, e);
+                        return None;
+                    }
+                }
+                Ok(status) => {
+                    eprintln!("Unexpected wait status: {:?}", status);
+                    return None;
+                }
+                Err(e) => {
+                    eprintln!("waitpid failed: {}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Sets up an inferior for the stopped, traced process `pid` and installs `breakpoints`.
+    fn stopped(pid: Pid, child: Option<Child>, breakpoints: &Vec<usize>) -> Inferior {
+        let mut res = Inferior {
+            pid,
+            child,
+            breakpoints: HashMap::new(),
+            in_our_process_group: getpgid(Some(pid)).ok() == Some(getpgrp()),
+            follow_fork: FollowFork::Parent,
+            core: None,
+            history: None,
+        };
+        // Have forks and execs reported to us; the processes forked off are traced from the start
+        let options = ptrace::Options::PTRACE_O_TRACEFORK | ptrace::Options::PTRACE_O_TRACEEXEC;
+        if let Err(e) = ptrace::setoptions(pid, options) {
+            say!("Warning: forks and execs won't be followed: {}", e);
+        }
+        for bp in breakpoints {
+            if let Err(e) = res.install_break_points(*bp) {
+                say!("Skipping breakpoint at {:#x}: {}", bp, e);
+            }
+        }
+        res
+    }
+
+    /// Sets up an inferior for looking at a process after its death, from a core file. Its
+    /// registers and memory can be read, but nothing can be changed and it can't be resumed.
+    pub fn from_core(core: Core) -> Inferior {
+        Inferior {
+            pid: Pid::from_raw(core.pid),
+            child: None,
+            breakpoints: HashMap::new(),
+            in_our_process_group: false,
+            follow_fork: FollowFork::Parent,
+            core: Some(core),
+            history: None,
+        }
+    }
+
+    /// Whether this inferior is a core file rather than a live process.
+    pub fn is_core(&self) -> bool {
+        self.core.is_some()
+    }
+
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Whether this inferior was attached to rather than spawned by us. A process forked off one
+    /// we spawned counts as spawned by us.
+    pub fn is_attached(&self) -> bool {
+        self.child.is_none() && self.core.is_none()
+    }
+
+    /// Sets which process to keep debugging the next time the inferior forks.
+    pub fn set_follow_fork(&mut self, follow_fork: FollowFork) {
+        self.follow_fork = follow_fork;
+    }
+
+    /// Returns the address of the instruction the inferior is stopped at.
+    pub fn instruction_ptr(&self) -> Result<usize, nix::Error> {
+        Ok(self.regs()?.rip as usize)
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+    /// Returns the inferior's registers, from the core file if that's what this is.
+    fn regs(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        match &self.core {
+            Some(core) => Ok(core.regs),
+            None => ptrace::getregs(self.pid()),
+        }
+    }
+
+    /// Reads the word at `addr`, which needn't be aligned, from the core file if that's what this
+    /// is. Fails with EFAULT if the core file doesn't have it.
+    fn peek(&self, addr: usize) -> Result<i64, nix::Error> {
+        match &self.core {
+            Some(core) => match core.read(addr, size_of::<i64>()) {
+                Some(bytes) => Ok(i64::from_le_bytes(bytes.try_into().unwrap())),
+                None => Err(nix::Error::Sys(Errno::EFAULT)),
+            },
+            None => ptrace::read(self.pid(), addr as AddressType),
+        }
+    }
+
+    /// Calls waitpid on this inferior, passing Ctrl-C on to it in the meantime if need be.
+    fn wait(&self) -> Result<WaitStatus, nix::Error> {
+        if !self.in_our_process_group {
+            INTERRUPT_TARGET.store(self.pid.as_raw(), Ordering::SeqCst);
+        }
+        let status = waitpid(self.pid(), None);
+        INTERRUPT_TARGET.store(0, Ordering::SeqCst);
+        status
+    }
+
+    /// Returns a Status to indicate the state of the process after waitpid returned `status`. If
+    /// the inferior stopped because it hit one of our breakpoints, rip is rewound to the
+    /// breakpoint address.
+    fn status_from(&self, status: WaitStatus) -> Result<Status, nix::Error> {
+        Ok(match status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
             WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
+                let mut regs = ptrace::getregs(self.pid())?;
+                // If we just executed the int3 of one of our breakpoints, rip points one byte past
+                // it. Rewind so that rip points at the breakpoint address, i.e. the start of the
+                // original instruction, which is where execution should resume.
+                if signal == signal::SIGTRAP
+                    && self.breakpoints.contains_key(&(regs.rip as usize - 1))
+                {
+                    regs.rip -= 1;
+                    ptrace::setregs(self.pid(), regs)?;
+                }
                 Status::Stopped(signal, regs.rip as usize)
             }
             other => panic!("waitpid returned unexpected status: {:?}", other),
         })
     }
 
+    /// Resumes the inferior, single-stepping it if `step` is set, and waits for it to stop. If it
+    /// forks on the way, the process not being followed is detached from and the other one is
+    /// resumed in the same way.
+    ///
+    /// While recording, every instruction is single-stepped so that it can be recorded first, and
+    /// continuing stops on arriving at a breakpoint or when a watchpoint is triggered.
+    fn resume(&mut self, step: bool, signal: Option<signal::Signal>) -> Result<Status, nix::Error> {
+        let mut signal = signal;
+        loop {
+            if self.history.is_some() {
+                self.record_instruction()?;
+                ptrace::step(self.pid(), signal)?;
+            } else if step {
+                ptrace::step(self.pid(), signal)?;
+            } else {
+                ptrace::cont(self.pid(), signal)?;
+            }
+            signal = None;
+            match self.wait()? {
+                WaitStatus::PtraceEvent(_pid, _signal, libc::PTRACE_EVENT_FORK) => {
+                    self.follow_fork()?
+                }
+                WaitStatus::PtraceEvent(_pid, _signal, libc::PTRACE_EVENT_EXEC) => {
+                    // The new program image has none of our breakpoints in it, and none of the
+                    // recorded history applies to it
+                    self.breakpoints.clear();
+                    if let Some(history) = self.history.as_mut() {
+                        history.clear();
+                    }
+                    return Ok(Status::Execed(self.instruction_ptr()?));
+                }
+                status => {
+                    let status = self.status_from(status)?;
+                    match status {
+                        Status::Stopped(signal::SIGTRAP, ip)
+                            if !step
+                                && self.history.is_some()
+                                && !self.breakpoints.contains_key(&ip)
+                                && self.read_debug_register(DEBUG_STATUS_REG)? & 0xf == 0 => {}
+                        _ => return Ok(status),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts recording the instructions the inferior executes, so that it can be run backwards.
+    /// Continuing single-steps the inferior while recording, which is much slower.
+    pub fn start_recording(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(History::new(RECORD_LIMIT));
+        }
+    }
+
+    /// Stops recording, forgetting the recorded history.
+    pub fn stop_recording(&mut self) {
+        self.history = None;
+    }
+
+    /// Returns how many instructions have been recorded, or None if we aren't recording.
+    pub fn recorded_instructions(&self) -> Option<usize> {
+        self.history.as_ref().map(History::len)
+    }
+
+    /// Saves what it will take to undo the instruction at rip, before it runs.
+    fn record_instruction(&mut self) -> Result<(), nix::Error> {
+        let regs = self.regs()?;
+        let rip = regs.rip as usize;
+        let code = self.read_memory(rip, MAX_INSTRUCTION_LEN)?;
+        let mut memory = Vec::new();
+        if let Some(instr) = Inferior::decode_instructions(&code, rip).first() {
+            for (addr, len) in record::memory_written(instr, &regs) {
+                // A write that's going to fault doesn't need undoing
+                if let Ok(bytes) = self.read_memory(addr, len) {
+                    if bytes.len() == len {
+                        memory.push((addr, bytes));
+                    }
+                }
+            }
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.push(Entry { regs, memory });
+        }
+        Ok(())
+    }
+
+    /// Undoes the most recently executed instruction, putting back the registers and the memory
+    /// it wrote to. Returns false if there's no recorded history left to go back through.
+    pub fn reverse_step_once(&mut self) -> Result<bool, nix::Error> {
+        let entry = match self.history.as_mut().and_then(History::pop) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        for (addr, bytes) in &entry.memory {
+            for (i, byte) in bytes.iter().enumerate() {
+                match self.breakpoints.get_mut(&(addr + i)) {
+                    // Keep the breakpoint in place, so it'll be hit going forward again
+                    Some(bp) => bp.orig_byte = *byte,
+                    None => {
+                        self.write_byte(addr + i, *byte)?;
+                    }
+                }
+            }
+        }
+        ptrace::setregs(self.pid(), entry.regs)?;
+        Ok(true)
+    }
+
+    /// Goes back to the start of the source line executed before the current one, which may be
+    /// in a function called from the current line, as `step` would go into it going forward.
+    /// Returns false if the recorded history ran out first.
+    pub fn reverse_step_line(&mut self, debug_data: &DwarfData) -> Result<bool, nix::Error> {
+        let start_line = Inferior::line_at(debug_data, self.instruction_ptr()?);
+        let line = loop {
+            if !self.reverse_step_once()? {
+                return Ok(false);
+            }
+            let line = Inferior::line_at(debug_data, self.instruction_ptr()?);
+            if line.is_some() && line != start_line {
+                break line;
+            }
+        };
+        while let Some(entry) = self.history.as_ref().and_then(History::last) {
+            if Inferior::line_at(debug_data, entry.regs.rip as usize) != line {
+                break;
+            }
+            self.reverse_step_once()?;
+        }
+        Ok(true)
+    }
+
+    /// Goes back until arriving at a breakpoint. Returns false if the recorded history ran out
+    /// first.
+    pub fn reverse_cont(&mut self) -> Result<bool, nix::Error> {
+        loop {
+            if !self.reverse_step_once()? {
+                return Ok(false);
+            }
+            if self.breakpoints.contains_key(&self.instruction_ptr()?) {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Having stopped at a fork, detaches from whichever of the parent and the new child isn't
+    /// being followed, after taking our breakpoints out of it (the child starts with a copy of
+    /// them), and switches to the child if that's the one being followed.
+    fn follow_fork(&mut self) -> Result<(), nix::Error> {
+        let child = Pid::from_raw(ptrace::getevent(self.pid())? as i32);
+        // The child starts out stopped with SIGSTOP
+        waitpid(child, None)?;
+        let leaving = match self.follow_fork {
+            FollowFork::Parent => {
+                say!("[Detaching after fork from child process {}]", child);
+                child
+            }
+            FollowFork::Child => {
+                say!(
+                    "[Attaching after process {} fork to child process {}]",
+                    self.pid,
+                    child
+                );
+                // What was recorded happened in the parent
+                if let Some(history) = self.history.as_mut() {
+                    history.clear();
+                }
+                std::mem::replace(&mut self.pid, child)
+            }
+        };
+        for bp in self.breakpoints.values() {
+            write_byte_in(leaving, bp.addr, bp.orig_byte)?;
+        }
+        ptrace::detach(leaving, None)
+    }
+
     /// Resumes the execution of the inferior process and waits until it stops or terminates.
     ///
     /// This method first uses `ptrace::cont` to continue the process execution (passing `None` for the signal),
-    /// and then waits for the process to stop or terminate by calling `self.wait(None)`.
+    /// and then waits for the process to stop or terminate by calling `self.wait()`.
     ///
     /// # Returns
     /// A `Result` containing the `Status` of the process after resuming, or a `nix::Error` if an error occurs.
     pub fn cont(&mut self) -> Result<Status, nix::Error> {
-        // Check if the inferior is stopped at a breakpoint.
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
+        self.cont_with_signal(None)
+    }
 
-        if let Some(bp) = self
-            .breakpoints
-            .iter()
-            .find(|bp| bp.addr == rip - 1)
-            .cloned()
-        {
-            // Remove the breakpoint temporarily by restoring the original byte.
-            self.write_byte(bp.addr, bp.orig_byte)?;
+    /// Like `cont`, but delivers `signal` to the inferior as it resumes, e.g. to pass on a signal
+    /// it stopped with.
+    pub fn cont_with_signal(
+        &mut self,
+        signal: Option<signal::Signal>,
+    ) -> Result<Status, nix::Error> {
+        // If we're stopped at a breakpoint, execute the original instruction before continuing.
+        if let Some(status) = self.step_over_breakpoint()? {
+            if let Status::Exited(_) | Status::Signaled(_) = status {
+                return Ok(status);
+            }
+        }
 
-            // Rewind the instruction pointer so it points at the breakpoint location.
-            regs.rip = bp.addr as u64;
-            ptrace::setregs(self.pid(), regs)?;
+        // Now, continue normal execution.
+        self.resume(false, signal)
+    }
 
-            // Single-step the process so that the restored instruction executes.
-            ptrace::step(self.pid(), None)?;
-            self.wait(None)?;
+    /// Continues until execution reaches `addr` in the current stack frame, like GDB's `until`. If
+    /// the current function returns first, stops in the caller right after the call instead.
+    ///
+    /// Temporary breakpoints are installed at both places and removed again afterwards, unless
+    /// there was already a breakpoint there. Breakpoints that were installed beforehand still stop
+    /// execution if they're hit on the way. Hitting `addr` in a different call of the function
+    /// (i.e. through recursion) doesn't count.
+    pub fn run_until(&mut self, addr: usize) -> Result<Status, nix::Error> {
+        let frame_base = ptrace::getregs(self.pid())?.rbp as usize;
+        let return_addr = ptrace::read(self.pid(), (frame_base + 8) as AddressType)? as usize;
+        let mut temporary = Vec::new();
+        for &temp_addr in &[addr, return_addr] {
+            if !self.breakpoints.contains_key(&temp_addr) {
+                self.install_break_points(temp_addr)?;
+                temporary.push(temp_addr);
+            }
+        }
 
-            // Reinstall the breakpoint by writing 0xcc again.
-            self.write_byte(bp.addr, 0xcc)?;
+        let status = self.cont_until(addr, return_addr, frame_base, &temporary);
+        for temp_addr in temporary {
+            if let Ok(Status::Stopped(..)) = status {
+                self.remove_break_point(temp_addr)?;
+            } else {
+                // The process is gone, so there's no memory to restore
+                self.breakpoints.remove(&temp_addr);
+            }
         }
+        status
+    }
 
-        // Now, continue normal execution.
-        ptrace::cont(self.pid(), None)?;
-        self.wait(None)
+    /// Continues until the inferior stops somewhere other than at one of the `temporary`
+    /// breakpoints, or at `addr` with the frame `frame_base` still active, or at `return_addr`
+    /// after that frame has been popped.
+    fn cont_until(
+        &mut self,
+        addr: usize,
+        return_addr: usize,
+        frame_base: usize,
+        temporary: &[usize],
+    ) -> Result<Status, nix::Error> {
+        loop {
+            let status = self.cont()?;
+            let ip = match status {
+                Status::Stopped(signal::Signal::SIGTRAP, ip) if temporary.contains(&ip) => ip,
+                _ => return Ok(status),
+            };
+            let regs = ptrace::getregs(self.pid())?;
+            if (ip == addr && regs.rbp as usize == frame_base)
+                || (ip == return_addr && regs.rsp as usize > frame_base)
+            {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// If rip is at an installed breakpoint, temporarily restores the original instruction byte,
+    /// single-steps over it, and writes 0xcc back. Returns the status after the step, or None if
+    /// rip wasn't at a breakpoint.
+    fn step_over_breakpoint(&mut self) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(self.pid())?.rip as usize;
+        // Take the breakpoint out of the map while stepping so that wait() doesn't mistake
+        // stepping over a one-byte instruction for hitting the breakpoint again.
+        let bp = match self.breakpoints.remove(&rip) {
+            Some(bp) => bp,
+            None => return Ok(None),
+        };
+        self.write_byte(bp.addr, bp.orig_byte)?;
+        let status = self.resume(true, None);
+        if let Ok(Status::Stopped(..)) = status {
+            self.write_byte(bp.addr, 0xcc)?;
+        }
+        self.breakpoints.insert(bp.addr, bp);
+        status.map(Some)
     }
 
     /// Terminates the running inferior process.
     ///
     /// This method uses `Child::kill` to send a kill signal to the inferior process and then reaps
-    /// the process to prevent a zombie process.
+    /// the process to prevent a zombie process. A process we attached to isn't our child, so it's
+    /// sent SIGKILL directly, and waited for only until it's gone from under ptrace.
     ///
     /// # Returns
     /// A `Result` indicating success or the encountered error.
     pub fn kill(&mut self) -> Result<(), std::io::Error> {
-        // Send kill signal to the child process.
-        self.child.kill()?;
-        // Wait for the process to exit, reaping it.
-        self.child.wait()?;
+        if self.is_core() {
+            return Ok(());
+        }
+        match self.child.as_mut() {
+            // Unless we followed a fork away from it
+            Some(child) if child.id() as i32 == self.pid.as_raw() => {
+                // Send kill signal to the child process.
+                child.kill()?;
+                // Wait for the process to exit, reaping it.
+                child.wait()?;
+            }
+            _ => {
+                signal::kill(self.pid, signal::SIGKILL).map_err(std::io::Error::other)?;
+                waitpid(self.pid, None).map_err(std::io::Error::other)?;
+            }
+        }
         Ok(())
     }
 
+    /// Removes all breakpoints, restoring the original instruction bytes, and lets the inferior
+    /// carry on running without being traced.
+    pub fn detach(&mut self) -> Result<(), nix::Error> {
+        if self.is_core() {
+            return Ok(());
+        }
+        self.clear_break_points(None)?;
+        ptrace::detach(self.pid, None)
+    }
+
+    /// Takes a snapshot of the stopped inferior's registers and readable memory, for saving as a
+    /// core file. Breakpoints are left out of the memory, so that it holds the original code.
+    /// Mappings that can't be read (e.g. [vvar]) are skipped.
+    pub fn snapshot(&self) -> Result<Core, std::io::Error> {
+        use std::os::unix::fs::FileExt;
+        let regs = self.regs().map_err(std::io::Error::other)?;
+        let signal = ptrace::getsiginfo(self.pid())
+            .ok()
+            .and_then(|info| signal::Signal::try_from(info.si_signo).ok());
+        let cmdline = fs::read(format!("/proc/{}/cmdline", self.pid()))?;
+        let command_line = String::from_utf8_lossy(&cmdline)
+            .trim_end_matches('\0')
+            .replace('\0', " ");
+        let mem = File::open(format!("/proc/{}/mem", self.pid()))?;
+        let mut segments = Vec::new();
+        for mapping in self.mappings().unwrap_or_default() {
+            let perms = mapping.perms.as_bytes();
+            if perms.first() != Some(&b'r') || mapping.path == "[vvar]" {
+                continue;
+            }
+            let mut data = vec![0; mapping.end - mapping.start];
+            if mem.read_exact_at(&mut data, mapping.start as u64).is_err() {
+                continue;
+            }
+            for bp in self.breakpoints.values() {
+                if mapping.start <= bp.addr && bp.addr < mapping.end {
+                    data[bp.addr - mapping.start] = bp.orig_byte;
+                }
+            }
+            let flag =
+                |idx: usize, c: u8, flag: u32| if perms.get(idx) == Some(&c) { flag } else { 0 };
+            segments.push(Segment {
+                start: mapping.start,
+                flags: flag(0, b'r', corefile::PF_R)
+                    | flag(1, b'w', corefile::PF_W)
+                    | flag(2, b'x', corefile::PF_X),
+                data,
+            });
+        }
+        Ok(Core {
+            pid: self.pid().as_raw(),
+            signal,
+            regs,
+            command_line,
+            segments,
+        })
+    }
+
     /// Prints the backtrace of the inferior process using DWARF debugging data.
     ///
     /// This method retrieves and displays the call stack of the inferior process by walking the stack
@@ -189,8 +788,9 @@ impl Inferior {
     /// provided `DwarfData`. The backtrace continues until it reaches the `main` function or an error
     /// occurs.
     ///
-    /// For each stack frame, it prints the function name, source file, and line number if available.
-    /// If the function or line information cannot be resolved, it skips that frame and continues.
+    /// For each stack frame, it prints the function name, its arguments' values, source file, and line
+    /// number if available. If the function or line information cannot be resolved, the frame is
+    /// printed as a raw address instead.
     ///
     /// # Parameters
     /// - `self`: A reference to the `Inferior` instance.
@@ -204,104 +804,1500 @@ impl Inferior {
     /// This function does not explicitly panic, but underlying `ptrace` calls may panic if the process
     /// is in an invalid state.
     pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let mut instruction_ptr = ptrace::getregs(self.pid())?.rip as usize;
-        let mut base_ptr = ptrace::getregs(self.pid())?.rbp as usize;
-        loop {
-            if (self.print_current_frame(instruction_ptr, debug_data)) {
-                break;
+        let mut events = Vec::new();
+        for frame in self.frames(debug_data)? {
+            let func = debug_data.get_function_containing(frame.instruction_ptr);
+            let args = func.map_or_else(Vec::new, |func| self.arguments(func, &frame));
+            match (func, debug_data.get_line_from_addr(frame.instruction_ptr)) {
+                (Some(func), Some(line)) => say!(
+                    "{}({}) ({}:{})",
+                    func.name,
+                    Inferior::format_arguments(&args),
+                    line.file,
+                    line.number
+                ),
+                _ => {
+                    self.print_current_frame(frame.instruction_ptr, debug_data);
+                }
             }
-            instruction_ptr = ptrace::read(self.pid(), (base_ptr + 8) as AddressType)? as usize;
-            base_ptr = ptrace::read(self.pid(), base_ptr as AddressType)? as usize;
+            let mut event = output::location(frame.instruction_ptr, debug_data);
+            let args = args
+                .into_iter()
+                .map(|(name, value)| {
+                    Json::object(vec![
+                        ("name", Json::String(name)),
+                        ("value", Json::String(value)),
+                    ])
+                })
+                .collect();
+            event.push(("args", Json::Array(args)));
+            events.push(Json::object(event));
         }
+        output().event("backtrace", vec![("frames", Json::Array(events))]);
         Ok(())
     }
 
+    /// Returns the names and values of the arguments `func` was called with in `frame`.
+    fn arguments(&self, func: &Function, frame: &Frame) -> Vec<(String, String)> {
+        func.variables
+            .iter()
+            .filter(|var| var.is_parameter)
+            .map(|var| {
+                let value = self.format_variable(var, frame).unwrap_or_else(|err| err);
+                (var.name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Formats arguments as `name=value` pairs, the way a call would be written.
+    fn format_arguments(args: &[(String, String)]) -> String {
+        let args: Vec<String> = args
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        args.join(", ")
+    }
+
+    /// Returns the call stack, innermost frame first, by walking saved rbp values up to main (or
+    /// until the chain of frame pointers ends or leads somewhere unreadable, which happens when
+    /// there's no debugging information to tell us where main is).
+    pub fn frames(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.regs()?;
+        let mut frame = Frame {
+            instruction_ptr: regs.rip as usize,
+            base_ptr: regs.rbp as usize,
+        };
+        let mut frames = Vec::new();
+        // On a function's first instruction (e.g. at a breakpoint on a library function, where
+        // there's no prologue to skip), rbp hasn't been pushed yet: it's still the caller's, and
+        // the return address is on top of the stack
+        if debug_data.is_function_start(frame.instruction_ptr)
+            && debug_data
+                .get_function_from_addr(frame.instruction_ptr)
+                .as_deref()
+                != Some("main")
+        {
+            if let Ok(return_addr) = self.peek(regs.rsp as usize) {
+                frames.push(frame);
+                frame = Frame {
+                    instruction_ptr: return_addr as usize,
+                    base_ptr: regs.rbp as usize,
+                };
+            }
+        }
+        loop {
+            frames.push(frame);
+            let func = debug_data.get_function_from_addr(frame.instruction_ptr);
+            if func.as_deref() == Some("main")
+                || frame.base_ptr == 0
+                || frames.len() >= MAX_BACKTRACE_DEPTH
+            {
+                return Ok(frames);
+            }
+            let caller = self.peek(frame.base_ptr + 8).and_then(|instruction_ptr| {
+                Ok(Frame {
+                    instruction_ptr: instruction_ptr as usize,
+                    base_ptr: self.peek(frame.base_ptr)? as usize,
+                })
+            });
+            frame = match caller {
+                Ok(caller) => caller,
+                Err(_) => return Ok(frames),
+            };
+        }
+    }
+
+    /// Prints the function and source line that `instruction_ptr` is in. If the debugging
+    /// information doesn't cover it (e.g. the binary was stripped or this is library code), prints
+    /// the raw address instead, with `<unknown>` for whatever couldn't be looked up. Returns true
+    /// if this is main's frame, where a backtrace ends.
     pub fn print_current_frame(&self, instruction_ptr: usize, debug_data: &DwarfData) -> bool {
         let line = debug_data.get_line_from_addr(instruction_ptr);
         let func = debug_data.get_function_from_addr(instruction_ptr);
-        if let Some(line) = line {
-            if let Some(func) = func {
-                println!("{} ({}:{})", func, line.file, line.number);
-                if (func == *"main") {
-                    return true;
+        match (&func, line) {
+            (Some(func), Some(line)) => say!("{} ({}:{})", func, line.file, line.number),
+            (func, line) => say!(
+                "{:#x} in {} ({})",
+                instruction_ptr,
+                func.as_deref().unwrap_or("<unknown>"),
+                line.map_or_else(|| "<unknown>".to_string(), |line| line.to_string())
+            ),
+        }
+        func.as_deref() == Some("main")
+    }
+
+    /// Reads `len` bytes of the inferior's memory starting at `addr`. Breakpoints installed in
+    /// that range are masked out, so callers see the original instruction bytes instead of 0xcc.
+    /// If the end of the range runs off the end of a mapping, the bytes read so far are returned.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < addr + len {
+            let word = match self.peek(word_addr) {
+                Ok(word) => word as u64,
+                Err(err) if bytes.is_empty() => return Err(err),
+                Err(_) => break,
+            };
+            for i in 0..size_of::<usize>() {
+                let byte_addr = word_addr + i;
+                if byte_addr >= addr && byte_addr < addr + len {
+                    bytes.push((word >> (8 * i)) as u8);
+                }
+            }
+            word_addr += size_of::<usize>();
+        }
+        for bp in self.breakpoints.values() {
+            if bp.addr >= addr && bp.addr - addr < bytes.len() {
+                bytes[bp.addr - addr] = bp.orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Reads the NUL-terminated string at `addr`, returning at most `max_len` bytes of it (not
+    /// including the NUL).
+    pub fn read_c_string(&self, addr: usize, max_len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut string = Vec::new();
+        while string.len() < max_len {
+            let chunk = self.read_memory(addr + string.len(), size_of::<usize>())?;
+            if let Some(end) = chunk.iter().position(|&byte| byte == 0) {
+                string.extend_from_slice(&chunk[..end]);
+                return Ok(string);
+            }
+            string.extend_from_slice(&chunk);
+        }
+        string.truncate(max_len);
+        Ok(string)
+    }
+
+    /// Evaluates `expr` as seen from `frame` and formats its value for `print`. Returns a message
+    /// saying what went wrong if the expression doesn't make sense or the memory can't be read.
+    pub fn format_expression(
+        &self,
+        expr: &Expr,
+        frame: &Frame,
+        debug_data: &DwarfData,
+    ) -> Result<String, String> {
+        let (addr, value_type) = self.evaluate(expr, frame, debug_data)?;
+        let bytes = self
+            .read_memory(addr, value_type.size)
+            .map_err(|err| format!("Cannot access memory at address {:#x}: {}", addr, err))?;
+        Ok(self.format_value(&value_type, &bytes))
+    }
+
+    /// Reads the value of `var` as seen from `frame` and formats it. Returns a message saying what
+    /// went wrong if the memory can't be read.
+    pub fn format_variable(&self, var: &Variable, frame: &Frame) -> Result<String, String> {
+        let addr = variable_address(var, frame);
+        let bytes = self
+            .read_memory(addr, var.entity_type.size)
+            .map_err(|err| format!("<cannot access memory at address {:#x}: {}>", addr, err))?;
+        Ok(self.format_value(&var.entity_type, &bytes))
+    }
+
+    /// Formats a value of type `value_type` stored in `bytes`, reading the strings char pointers
+    /// point to from the inferior.
+    pub fn format_value(&self, value_type: &Type, bytes: &[u8]) -> String {
+        value::format_value(value_type, bytes, &|addr| {
+            self.read_c_string(addr, value::MAX_STRING_LEN).ok()
+        })
+    }
+
+    /// Evaluates a breakpoint condition as seen from `frame`. Returns a message saying what went
+    /// wrong if either side isn't an integer (or character, bool or pointer) that can be read.
+    pub fn evaluate_condition(
+        &self,
+        condition: &Condition,
+        frame: &Frame,
+        debug_data: &DwarfData,
+    ) -> Result<bool, String> {
+        let lhs = self.evaluate_operand(&condition.lhs, frame, debug_data)?;
+        let rhs = self.evaluate_operand(&condition.rhs, frame, debug_data)?;
+        Ok(condition.op.apply(lhs, rhs))
+    }
+
+    fn evaluate_operand(
+        &self,
+        operand: &Operand,
+        frame: &Frame,
+        debug_data: &DwarfData,
+    ) -> Result<i64, String> {
+        let expr = match operand {
+            Operand::Int(value) => return Ok(*value),
+            Operand::Expr(expr) => expr,
+        };
+        let (addr, value_type) = self.evaluate(expr, frame, debug_data)?;
+        let bytes = self
+            .read_memory(addr, value_type.size)
+            .map_err(|err| format!("Cannot access memory at address {:#x}: {}", addr, err))?;
+        value::to_integer(&value_type, &bytes).ok_or_else(|| {
+            format!(
+                "Cannot compare {}, a value of type {}",
+                expr, value_type.name
+            )
+        })
+    }
+
+    /// Works out where the value `expr` refers to is stored, and its type.
+    pub fn evaluate(
+        &self,
+        expr: &Expr,
+        frame: &Frame,
+        debug_data: &DwarfData,
+    ) -> Result<(usize, Type), String> {
+        match expr {
+            Expr::Variable(name) => {
+                let var = debug_data
+                    .get_variable(frame.instruction_ptr, name)
+                    .ok_or_else(|| format!("No symbol \"{}\" in current context.", name))?;
+                Ok((variable_address(var, frame), var.entity_type.clone()))
+            }
+            Expr::Deref(inner) => {
+                let (addr, pointer_type) = self.evaluate(inner, frame, debug_data)?;
+                self.follow_pointer(addr, &pointer_type, debug_data)
+            }
+            Expr::Index(inner, index) => {
+                let (addr, container) = self.evaluate(inner, frame, debug_data)?;
+                let (start, element) = match &container.kind {
+                    TypeKind::Array(element, length) => {
+                        // A length of 0 means the array was declared without one
+                        if *length > 0 && index >= length {
+                            return Err(format!(
+                                "Index {} is out of bounds for {}",
+                                index, container.name
+                            ));
+                        }
+                        (addr, (**element).clone())
+                    }
+                    TypeKind::Pointer(_) => self.follow_pointer(addr, &container, debug_data)?,
+                    _ => return Err(format!("Cannot index a value of type {}", container.name)),
+                };
+                Ok((start + index * element.size, element))
+            }
+            Expr::Member(inner, name) => {
+                let (mut addr, mut value_type) = self.evaluate(inner, frame, debug_data)?;
+                // Accessing a member through a pointer follows the pointer first
+                if let TypeKind::Pointer(_) = value_type.kind {
+                    let (target, pointee) = self.follow_pointer(addr, &value_type, debug_data)?;
+                    addr = target;
+                    value_type = pointee;
+                }
+                let members = match &value_type.kind {
+                    TypeKind::Struct(members) => members,
+                    _ => return Err(format!("{} is not a struct or union", value_type.name)),
+                };
+                let member = members
+                    .iter()
+                    .find(|member| member.name == *name)
+                    .ok_or_else(|| {
+                        format!("There is no member named {} in {}", name, value_type.name)
+                    })?;
+                Ok((addr + member.offset, member.member_type.clone()))
+            }
+        }
+    }
+
+    /// Reads the pointer of type `pointer_type` stored at `addr`, returning the address it points
+    /// to and the type of the value there.
+    fn follow_pointer(
+        &self,
+        addr: usize,
+        pointer_type: &Type,
+        debug_data: &DwarfData,
+    ) -> Result<(usize, Type), String> {
+        let pointee = match &pointer_type.kind {
+            TypeKind::Pointer(Some(pointee)) => pointee,
+            TypeKind::Pointer(None) => {
+                return Err(format!("Cannot dereference a {}", pointer_type.name))
+            }
+            _ => {
+                return Err(format!(
+                    "Cannot dereference a value of type {}",
+                    pointer_type.name
+                ))
+            }
+        };
+        let bytes = self
+            .read_memory(addr, pointer_type.size)
+            .map_err(|err| format!("Cannot access memory at address {:#x}: {}", addr, err))?;
+        let target = value::read_uint(&bytes) as usize;
+        if target == 0 {
+            return Err("Cannot dereference a null pointer".to_string());
+        }
+        // Structs behind pointers are only known by reference, so look up their members
+        let pointee = match pointee.kind {
+            TypeKind::StructRef(offset) => {
+                let mut definition = debug_data
+                    .get_struct(offset)
+                    .ok_or_else(|| format!("{} is an incomplete type", pointee.name))?
+                    .clone();
+                definition.name = pointee.name.clone();
+                definition
+            }
+            _ => (**pointee).clone(),
+        };
+        Ok((target, pointee))
+    }
+
+    /// Decodes the instructions in `code`, which was read from the inferior starting at `start`.
+    fn decode_instructions(code: &[u8], start: usize) -> Vec<Instruction> {
+        let mut decoder = Decoder::with_ip(64, code, start as u64, DecoderOptions::NONE);
+        let mut instructions = Vec::new();
+        let mut instruction = Instruction::default();
+        while decoder.can_decode() {
+            decoder.decode_out(&mut instruction);
+            if instruction.is_invalid() {
+                break;
+            }
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    /// Returns the number of instructions in the inferior's code from `start` up to `end`. `start`
+    /// must be on an instruction boundary.
+    pub fn count_instructions(&self, start: usize, end: usize) -> Result<usize, nix::Error> {
+        let code = self.read_memory(start, end.saturating_sub(start))?;
+        Ok(Inferior::decode_instructions(&code, start)
+            .iter()
+            .filter(|instr| (instr.ip() as usize) < end)
+            .count())
+    }
+
+    /// Prints `count` instructions around the current instruction pointer, marking the current
+    /// one with `=>`. Branch and call targets are annotated with the name of the function they
+    /// land in, if the DWARF data knows about it.
+    ///
+    /// x86 can't reliably be decoded backwards, so to show the instructions leading up to rip we
+    /// decode forward from the start of the enclosing function. If rip isn't inside a known
+    /// function, disassembly simply starts at rip.
+    pub fn print_disassembly(
+        &self,
+        debug_data: &DwarfData,
+        count: usize,
+    ) -> Result<(), nix::Error> {
+        let rip = self.instruction_ptr()?;
+
+        // Decode from the start of the enclosing function, stopping at its end so we don't
+        // disassemble the padding between functions.
+        let (func_start, func_end) = match debug_data.get_function_containing(rip) {
+            Some(func) => (func.address, func.address + func.text_length),
+            None => (rip, usize::MAX),
+        };
+        let code = self.read_memory(func_start, rip - func_start + count * MAX_INSTRUCTION_LEN)?;
+        let mut instructions = Inferior::decode_instructions(&code, func_start);
+        instructions.retain(|instr| (instr.ip() as usize) < func_end);
+        let mut current = instructions
+            .iter()
+            .position(|instr| instr.ip() as usize == rip);
+        if current.is_none() {
+            // rip wasn't on an instruction boundary relative to the function start (e.g. the
+            // function contains data); fall back to decoding from rip itself.
+            let code = self.read_memory(rip, count * MAX_INSTRUCTION_LEN)?;
+            instructions = Inferior::decode_instructions(&code, rip);
+            current = Some(0);
+        }
+        let first = current.unwrap().saturating_sub(DISAS_CONTEXT_BEFORE);
+        let last = (first + count).min(instructions.len());
+        self.print_instructions(&instructions[first..last], rip, debug_data);
+        Ok(())
+    }
+
+    /// Prints every instruction of `func`, marking the one rip is at with `=>` and those with a
+    /// breakpoint on them with `b`.
+    pub fn print_function_disassembly(
+        &self,
+        func: &Function,
+        debug_data: &DwarfData,
+    ) -> Result<(), nix::Error> {
+        let rip = self.instruction_ptr()?;
+        let code = self.read_memory(func.address, func.text_length)?;
+        let instructions = Inferior::decode_instructions(&code, func.address);
+        self.print_instructions(&instructions, rip, debug_data);
+        Ok(())
+    }
+
+    fn print_instructions(&self, instructions: &[Instruction], rip: usize, debug_data: &DwarfData) {
+        let mut formatter = GasFormatter::new();
+        formatter.options_mut().set_uppercase_hex(false);
+        formatter.options_mut().set_branch_leading_zeros(false);
+        for instr in instructions {
+            let addr = instr.ip() as usize;
+            let mut text = String::new();
+            formatter.format(instr, &mut text);
+            if instr.op_count() > 0 && instr.op0_kind() == OpKind::NearBranch64 {
+                if let Some(symbol) =
+                    Inferior::format_symbol(instr.near_branch_target() as usize, debug_data)
+                {
+                    text = format!("{} {}", text, symbol);
                 }
             }
+            say!(
+                "{}{} {:#x}{}:\t{}",
+                if addr == rip { "=>" } else { "  " },
+                if self.breakpoints.contains_key(&addr) {
+                    "b"
+                } else {
+                    " "
+                },
+                addr,
+                Inferior::format_symbol(addr, debug_data)
+                    .map(|symbol| format!(" {}", symbol))
+                    .unwrap_or_default(),
+                text
+            );
         }
-        false
+    }
+
+    /// Prints the general-purpose registers, one per line, in hex and either decimal or (for rip)
+    /// as a location in the code.
+    pub fn print_registers(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        let regs = self.regs()?;
+        for name in registers::NAMES.iter() {
+            let value = registers::get(&regs, name).unwrap();
+            let natural = match *name {
+                "rip" => match Inferior::format_symbol(value as usize, debug_data) {
+                    Some(symbol) => format!("{:#x} {}", value, symbol),
+                    None => format!("{:#x}", value),
+                },
+                "rsp" | "rbp" => format!("{:#x}", value),
+                _ => (value as i64).to_string(),
+            };
+            say!("{:<15}{:<19}{}", name, format!("{:#x}", value), natural);
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the register called `name`, or None if there's no such register.
+    pub fn register(&self, name: &str) -> Result<Option<u64>, nix::Error> {
+        Ok(registers::get(&self.regs()?, name))
+    }
+
+    /// Sets the register called `name` to `value`. Returns false if there's no such register.
+    pub fn set_register(&mut self, name: &str, value: u64) -> Result<bool, nix::Error> {
+        let mut regs = ptrace::getregs(self.pid())?;
+        match registers::get_mut(&mut regs, name) {
+            Some(register) => *register = value,
+            None => return Ok(false),
+        }
+        ptrace::setregs(self.pid(), regs)?;
+        Ok(true)
+    }
+
+    /// Formats `addr` as `<function+offset>`, or returns None if the address isn't inside a known
+    /// function.
+    fn format_symbol(addr: usize, debug_data: &DwarfData) -> Option<String> {
+        let func = debug_data.get_function_containing(addr)?;
+        Some(format!("<{}+{}>", func.name, addr - func.address))
     }
 
     fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> (8 * byte_offset)) & 0xff;
-        let masked_word = word & !(0xff << (8 * byte_offset));
-        let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
-        ptrace::write(
-            self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_byte as u8)
+        write_byte_in(self.pid(), addr, val)
+    }
+
+    fn read_debug_register(&self, n: usize) -> Result<u64, nix::Error> {
+        let value = unsafe {
+            Errno::clear();
+            libc::ptrace(
+                libc::PTRACE_PEEKUSER,
+                self.pid().as_raw(),
+                DEBUG_REGS_OFFSET + n * size_of::<u64>(),
+                std::ptr::null_mut::<libc::c_void>(),
+            )
+        };
+        // The register's value is returned, so -1 only means failure if errno was set
+        if value == -1 && Errno::last() != Errno::UnknownErrno {
+            return Err(nix::Error::Sys(Errno::last()));
+        }
+        Ok(value as u64)
+    }
+
+    fn write_debug_register(&mut self, n: usize, value: u64) -> Result<(), nix::Error> {
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_POKEUSER,
+                self.pid().as_raw(),
+                DEBUG_REGS_OFFSET + n * size_of::<u64>(),
+                value as *mut libc::c_void,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+
+    /// Programs hardware watchpoint `slot` (0 to `WATCHPOINT_SLOTS - 1`) to stop the inferior
+    /// right after it writes to any of the `len` bytes at `addr`. `len` must be 1, 2, 4 or 8, and
+    /// `addr` a multiple of it; otherwise this fails with EINVAL.
+    pub fn set_watchpoint(
+        &mut self,
+        slot: usize,
+        addr: usize,
+        len: usize,
+    ) -> Result<(), nix::Error> {
+        let len_bits = match len {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b11,
+            8 => 0b10,
+            _ => return Err(nix::Error::Sys(Errno::EINVAL)),
+        };
+        if slot >= WATCHPOINT_SLOTS || !addr.is_multiple_of(len) {
+            return Err(nix::Error::Sys(Errno::EINVAL));
+        }
+        self.write_debug_register(slot, addr as u64)?;
+        let mut control = self.read_debug_register(DEBUG_CONTROL_REG)?;
+        // Each slot has a 4-bit field from bit 16 up: two bits for when to trigger (01 is on
+        // writes) and two for the length. The local enable bits are the even bits from 0.
+        control &= !(0b1111 << (16 + 4 * slot));
+        control |= (0b01 | len_bits << 2) << (16 + 4 * slot);
+        control |= 1 << (2 * slot);
+        self.write_debug_register(DEBUG_CONTROL_REG, control)
+    }
+
+    /// Returns the slots of the watchpoints that have been triggered since this was last called.
+    pub fn take_triggered_watchpoints(&mut self) -> Result<Vec<usize>, nix::Error> {
+        let status = self.read_debug_register(DEBUG_STATUS_REG)?;
+        // The processor never clears DR6 itself
+        self.write_debug_register(DEBUG_STATUS_REG, 0)?;
+        Ok((0..WATCHPOINT_SLOTS)
+            .filter(|slot| status & (1 << slot) != 0)
+            .collect())
+    }
+
+    /// Returns the inferior's memory mappings, as listed in /proc/<pid>/maps (or as saved in the
+    /// core file), or None if that can't be read (e.g. the inferior has exited).
+    pub fn mappings(&self) -> Option<Vec<maps::Mapping>> {
+        if let Some(core) = &self.core {
+            return Some(core.mappings());
+        }
+        let text = fs::read_to_string(format!("/proc/{}/maps", self.pid())).ok()?;
+        Some(maps::parse(&text))
+    }
+
+    /// Returns true if `addr` lies within an executable mapping of the inferior, according to
+    /// /proc/<pid>/maps.
+    pub fn is_executable_address(&self, addr: usize) -> bool {
+        self.mappings()
+            .unwrap_or_default()
+            .iter()
+            .any(|mapping| mapping.start <= addr && addr < mapping.end && mapping.is_executable())
     }
 
+    /// Installs a breakpoint at `addr` by writing 0xcc there. Installing a breakpoint at an
+    /// address that already has one is a no-op: reading the byte back would give us 0xcc rather
+    /// than the original instruction byte, and restoring that later would corrupt the program.
+    ///
+    /// Fails with EFAULT, without touching memory, if `addr` isn't in executable memory; writing
+    /// 0xcc into data would silently corrupt the inferior.
     pub fn install_break_points(&mut self, addr: usize) -> Result<(), nix::Error> {
+        // A core file's code can't be changed, and will never run into a breakpoint anyway
+        if self.breakpoints.contains_key(&addr) || self.is_core() {
+            return Ok(());
+        }
+        if !self.is_executable_address(addr) {
+            return Err(nix::Error::Sys(Errno::EFAULT));
+        }
         let orig_byte = self.write_byte(addr, 0xcc)?;
-        self.breakpoints.push(Breakpoint { addr, orig_byte });
+        self.breakpoints
+            .insert(addr, Breakpoint { addr, orig_byte });
         Ok(())
     }
 
-    /// Performs a single instruction step while handling any breakpoint hit.
-    pub fn step_once(&mut self) -> Result<Status, nix::Error> {
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        // Check if we stopped at a breakpoint (rip is one byte past breakpoint address).
-        if let Some(bp) = self
+    /// Removes the breakpoint at `addr`, restoring the original instruction byte. Returns false
+    /// if there was no breakpoint installed there.
+    pub fn remove_break_point(&mut self, addr: usize) -> Result<bool, nix::Error> {
+        match self.breakpoints.remove(&addr) {
+            Some(bp) => {
+                self.write_byte(bp.addr, bp.orig_byte)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Removes every breakpoint installed in `range` (or all of them, if `range` is None),
+    /// restoring the original instruction bytes. Returns how many were removed.
+    pub fn clear_break_points(
+        &mut self,
+        range: Option<&Range<usize>>,
+    ) -> Result<usize, nix::Error> {
+        let addrs: Vec<usize> = self
             .breakpoints
-            .iter()
-            .find(|bp| bp.addr == rip - 1)
-            .cloned()
-        {
-            // Restore the original instruction byte.
-            self.write_byte(bp.addr, bp.orig_byte)?;
-            // Rewind instruction pointer.
-            regs.rip = bp.addr as u64;
-            ptrace::setregs(self.pid(), regs)?;
-            // Single-step the process.
-            ptrace::step(self.pid(), None)?;
-            let status = self.wait(None)?;
-            // Reinstall the breakpoint.
-            self.write_byte(bp.addr, 0xcc)?;
+            .keys()
+            .copied()
+            .filter(|addr| range.is_none_or(|range| range.contains(addr)))
+            .collect();
+        for addr in &addrs {
+            self.remove_break_point(*addr)?;
+        }
+        Ok(addrs.len())
+    }
+
+    /// Performs a single instruction step, stepping over the breakpoint at rip if there is one.
+    pub fn step_once(&mut self) -> Result<Status, nix::Error> {
+        if let Some(status) = self.step_over_breakpoint()? {
             return Ok(status);
         }
-        // No breakpoint interference: simply step.
-        ptrace::step(self.pid(), None)?;
-        self.wait(None)
+        self.resume(true, None)
     }
 
-    /// Steps the inferior until the source line changes.
+    /// Steps the inferior until the source line changes, stepping over function calls: a called
+    /// function is run until it returns, as if the call were a single instruction.
     ///
     /// Uses DWARF data to compare the current source line before and after each single step.
+    /// Breakpoints are disarmed while stepping, so that any 0xcc bytes in the region being stepped
+    /// through don't trap in the middle of the line; they are re-armed while a called function
+    /// runs, so that hitting one in there still stops us, and once we've stopped.
     pub fn next_line(&mut self, debug_data: &DwarfData) -> Result<Status, nix::Error> {
-        // Get the current instruction pointer and its associated source line.
-        let regs = ptrace::getregs(self.pid())?;
-        let initial_ip = regs.rip as usize;
-        let initial_line = debug_data.get_line_from_addr(initial_ip);
+        let initial_line = Inferior::line_at(debug_data, self.instruction_ptr()?);
+        loop {
+            let disarmed = self.disarm_breakpoints()?;
+            let stepped = self.step_until_line_changes_or_call(debug_data, &initial_line);
+            let alive = matches!(
+                stepped,
+                Ok(Stepped::EnteredCall(_) | Stepped::Done(Status::Stopped(..)))
+            );
+            self.rearm_breakpoints(disarmed, alive)?;
+            match stepped? {
+                Stepped::Done(status) => return Ok(status),
+                Stepped::EnteredCall(status) => {
+                    // A breakpoint at the start of the callee is hit like any other
+                    if let Status::Stopped(_, ip) = status {
+                        if self.breakpoints.contains_key(&ip) {
+                            return Ok(status);
+                        }
+                    }
+                    match self.finish_call()? {
+                        // Back in the caller, which may still have more of the line to run
+                        Status::Stopped(_, ip)
+                            if Inferior::line_at(debug_data, ip) == initial_line => {}
+                        status => return Ok(status),
+                    }
+                }
+            }
+        }
+    }
 
+    /// Returns the file and line number `ip` is in. (Line also records the address looked up,
+    /// which would make lines compare unequal.)
+    fn line_at(debug_data: &DwarfData, ip: usize) -> Option<(String, usize)> {
+        debug_data
+            .get_line_from_addr(ip)
+            .map(|line| (line.file, line.number))
+    }
+
+    /// Single-steps until execution leaves `initial_line`, or until a call instruction has just
+    /// been executed.
+    fn step_until_line_changes_or_call(
+        &mut self,
+        debug_data: &DwarfData,
+        initial_line: &Option<(String, usize)>,
+    ) -> Result<Stepped, nix::Error> {
         loop {
+            let calling = self.at_call_instruction()?;
             let status = self.step_once()?;
             match status {
+                Status::Stopped(..) if calling => return Ok(Stepped::EnteredCall(status)),
                 Status::Stopped(_, ip) => {
-                    let new_line = debug_data.get_line_from_addr(ip);
-                    // If the source line changed, return.
-                    if new_line != initial_line {
-                        return Ok(status);
+                    // If the source line changed, return. Otherwise, continue stepping.
+                    if Inferior::line_at(debug_data, ip) != *initial_line {
+                        return Ok(Stepped::Done(status));
                     }
-                    // Otherwise, continue stepping.
                 }
                 // If the process terminated, return the status.
+                _ => return Ok(Stepped::Done(status)),
+            }
+        }
+    }
+
+    /// Steps the inferior until the source line changes, like `next_line`, except that it stops
+    /// at the first instruction of any function with debug info that gets called on the way, like
+    /// GDB's `step`. Calls into code without line information (e.g. library functions called
+    /// through the PLT) are run to completion rather than stepped through.
+    pub fn step_into(&mut self, debug_data: &DwarfData) -> Result<Status, nix::Error> {
+        let disarmed = self.disarm_breakpoints()?;
+        let status = self.step_until_line_or_function_changes(debug_data);
+        self.rearm_breakpoints(disarmed, matches!(status, Ok(Status::Stopped(..))))?;
+        status
+    }
+
+    fn step_until_line_or_function_changes(
+        &mut self,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        let line_at = |ip| Inferior::line_at(debug_data, ip);
+        let initial_line = line_at(self.instruction_ptr()?);
+
+        loop {
+            let calling = self.at_call_instruction()?;
+            let mut status = self.step_once()?;
+            if let Status::Stopped(_, ip) = status {
+                if calling {
+                    if debug_data
+                        .get_function_containing(ip)
+                        .is_some_and(|func| func.address == ip)
+                    {
+                        return Ok(status);
+                    }
+                    if line_at(ip).is_none() {
+                        status = self.finish_call()?;
+                    }
+                }
+            }
+            match status {
+                Status::Stopped(_, ip) => {
+                    if line_at(ip) != initial_line {
+                        return Ok(status);
+                    }
+                }
                 _ => return Ok(status),
             }
         }
     }
+
+    /// Returns true if the inferior is stopped at a call instruction.
+    fn at_call_instruction(&self) -> Result<bool, nix::Error> {
+        let rip = self.instruction_ptr()?;
+        let code = self.read_memory(rip, MAX_INSTRUCTION_LEN)?;
+        Ok(Inferior::decode_instructions(&code, rip)
+            .first()
+            .is_some_and(|instr| instr.mnemonic() == Mnemonic::Call))
+    }
+
+    /// Runs until the function that was just called returns to its caller. Must be used right
+    /// after the call instruction, while the return address is on top of the stack.
+    fn finish_call(&mut self) -> Result<Status, nix::Error> {
+        let rsp = ptrace::getregs(self.pid())?.rsp as usize;
+        let return_addr = ptrace::read(self.pid(), rsp as AddressType)? as usize;
+        let installed = !self.breakpoints.contains_key(&return_addr);
+        if installed {
+            self.install_break_points(return_addr)?;
+        }
+        let status = loop {
+            let status = self.cont()?;
+            match status {
+                // The return address can also be hit by a recursive call made from within the
+                // callee; only stop once the callee's frame has been popped
+                Status::Stopped(signal::Signal::SIGTRAP, ip)
+                    if ip == return_addr && (ptrace::getregs(self.pid())?.rsp as usize) <= rsp => {}
+                _ => break status,
+            }
+        };
+        if installed {
+            if let Status::Stopped(..) = status {
+                self.remove_break_point(return_addr)?;
+            } else {
+                self.breakpoints.remove(&return_addr);
+            }
+        }
+        Ok(status)
+    }
+
+    /// Restores the original byte of every installed breakpoint and takes them out of the
+    /// breakpoint map. The returned breakpoints should be passed to `rearm_breakpoints`.
+    fn disarm_breakpoints(&mut self) -> Result<HashMap<usize, Breakpoint>, nix::Error> {
+        let disarmed = std::mem::take(&mut self.breakpoints);
+        for bp in disarmed.values() {
+            self.write_byte(bp.addr, bp.orig_byte)?;
+        }
+        Ok(disarmed)
+    }
+
+    /// Puts breakpoints taken out by `disarm_breakpoints` back. The 0xcc bytes are only rewritten
+    /// if the inferior is still `alive` after what ran while they were disarmed.
+    fn rearm_breakpoints(
+        &mut self,
+        disarmed: HashMap<usize, Breakpoint>,
+        alive: bool,
+    ) -> Result<(), nix::Error> {
+        if alive {
+            for bp in disarmed.values() {
+                self.write_byte(bp.addr, 0xcc)?;
+            }
+        }
+        self.breakpoints.extend(disarmed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn start_sample(program: &str) -> (Inferior, DwarfData) {
+        let debug_data = DwarfData::from_file(program)
+            .unwrap_or_else(|_| panic!("Could not load {}. Have you run make?", program));
+        let inferior = Inferior::spawn(program, &Vec::new(), &Redirections::default(), &Vec::new())
+            .expect("Could not start inferior");
+        (inferior, debug_data)
+    }
+
+    #[test]
+    fn test_install_break_point_twice() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let addr = debug_data.get_addr_for_function(None, "func2").unwrap();
+        let orig_byte = inferior.read_memory(addr, 1).unwrap()[0];
+        assert_ne!(orig_byte, 0xcc);
+
+        inferior.install_break_points(addr).unwrap();
+        inferior.install_break_points(addr).unwrap();
+        assert_eq!(inferior.breakpoints.len(), 1);
+        assert_eq!(inferior.breakpoints[&addr].orig_byte, orig_byte);
+
+        assert!(inferior.remove_break_point(addr).unwrap());
+        assert_eq!(inferior.read_memory(addr, 1).unwrap()[0], orig_byte);
+        assert!(!inferior.remove_break_point(addr).unwrap());
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_clear_break_points() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let funcs: Vec<usize> = ["func1", "func2", "func3"]
+            .iter()
+            .map(|name| debug_data.get_addr_for_function(None, name).unwrap())
+            .collect();
+        let func2 = debug_data.get_function_containing(funcs[1]).unwrap();
+        let func2_range = func2.address..func2.address + func2.text_length;
+        let body = debug_data.get_addr_after_prologue(funcs[1]).unwrap();
+        let orig_bytes: Vec<u8> = funcs
+            .iter()
+            .chain(std::iter::once(&body))
+            .map(|&addr| inferior.read_memory(addr, 1).unwrap()[0])
+            .collect();
+        for &addr in funcs.iter().chain(std::iter::once(&body)) {
+            inferior.install_break_points(addr).unwrap();
+        }
+
+        // Only the two breakpoints in func2 go
+        assert_eq!(inferior.clear_break_points(Some(&func2_range)).unwrap(), 2);
+        assert_eq!(inferior.breakpoints.len(), 2);
+        assert!(!inferior.breakpoints.contains_key(&body));
+
+        assert_eq!(inferior.clear_break_points(None).unwrap(), 2);
+        assert!(inferior.breakpoints.is_empty());
+        // With no breakpoints left to mask, this reads what's really in memory
+        for (&addr, &orig_byte) in funcs.iter().chain(std::iter::once(&body)).zip(&orig_bytes) {
+            assert_eq!(inferior.read_memory(addr, 1).unwrap()[0], orig_byte);
+        }
+        assert_eq!(inferior.clear_break_points(None).unwrap(), 0);
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_install_break_point_invalid_address() {
+        let (mut inferior, _) = start_sample("samples/function_calls");
+        // Unmapped memory
+        assert!(inferior.install_break_points(0x1).is_err());
+        // The stack is mapped and writable, so without the check this would have been corrupted
+        let rsp = ptrace::getregs(inferior.pid()).unwrap().rsp as usize;
+        let orig_bytes = inferior.read_memory(rsp, 8).unwrap();
+        assert!(inferior.install_break_points(rsp).is_err());
+        assert_eq!(inferior.read_memory(rsp, 8).unwrap(), orig_bytes);
+        assert!(inferior.breakpoints.is_empty());
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_breakpoint_survives_restart() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let func2 = debug_data.get_addr_for_function(None, "func2").unwrap();
+        let orig_byte = inferior.read_memory(func2, 1).unwrap()[0];
+        inferior.kill().unwrap();
+
+        // The first run is killed while stopped at the breakpoint, with 0xcc still in place, like
+        // `run` does when restarting. The second run must still capture the program's own byte.
+        for run in 0..2 {
+            let mut inferior = Inferior::spawn(
+                "samples/function_calls",
+                &Vec::new(),
+                &Redirections::default(),
+                &vec![func2],
+            )
+            .expect("Could not start inferior");
+            assert_eq!(inferior.breakpoints[&func2].orig_byte, orig_byte);
+            match inferior.cont().unwrap() {
+                Status::Stopped(signal::Signal::SIGTRAP, ip) => assert_eq!(ip, func2),
+                _ => panic!("Expected to stop at the breakpoint on run {}", run),
+            }
+            assert_eq!(inferior.read_memory(func2, 1).unwrap()[0], orig_byte);
+            if run == 0 {
+                inferior.kill().unwrap();
+            } else {
+                match inferior.cont().unwrap() {
+                    Status::Exited(code) => assert_eq!(code, 0),
+                    _ => panic!("Expected the inferior to exit normally"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_next_line_over_breakpoint() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let line11 = debug_data.get_addr_for_line(None, 11).unwrap();
+        let line12 = debug_data.get_addr_for_line(None, 12).unwrap();
+        inferior.install_break_points(line11).unwrap();
+        inferior.install_break_points(line12).unwrap();
+
+        match inferior.cont().unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, line11),
+            _ => panic!("Expected to stop at the breakpoint on line 11"),
+        }
+        // Step from line 11 onto line 12, whose first instruction holds a breakpoint
+        match inferior.next_line(&debug_data).unwrap() {
+            Status::Stopped(_, ip) => {
+                assert_eq!(ip, line12);
+                assert_eq!(debug_data.get_line_from_addr(ip).unwrap().number, 12);
+            }
+            _ => panic!("Expected next to stop on line 12"),
+        }
+        // The breakpoints must have been re-armed with their original bytes intact, so the
+        // program should run to completion normally
+        assert_eq!(inferior.breakpoints.len(), 2);
+        match inferior.cont().unwrap() {
+            Status::Exited(code) => assert_eq!(code, 0),
+            _ => panic!("Expected the inferior to exit normally"),
+        }
+    }
+
+    #[test]
+    fn test_step_into() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let line17 = debug_data.get_addr_for_line(None, 17).unwrap();
+        let func2 = debug_data.get_addr_for_function(None, "func2").unwrap();
+        inferior.install_break_points(line17).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, line17),
+            _ => panic!("Expected to stop at the breakpoint on line 17"),
+        }
+        // printf has no line information, so it's run through rather than stepped into
+        let line = |status| match status {
+            Status::Stopped(_, ip) => (ip, debug_data.get_line_from_addr(ip).unwrap().number),
+            _ => panic!("Expected the inferior to stop"),
+        };
+        assert_eq!(line(inferior.step_into(&debug_data).unwrap()).1, 18);
+        // func2 is stepped into, stopping at its very first instruction
+        assert_eq!(line(inferior.step_into(&debug_data).unwrap()), (func2, 9));
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_frames() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        let func3 = debug_data.get_addr_after_prologue(func3).unwrap();
+        inferior.install_break_points(func3).unwrap();
+        inferior.cont().unwrap();
+
+        let frames = inferior.frames(&debug_data).unwrap();
+        let names: Vec<String> = frames
+            .iter()
+            .map(|frame| {
+                debug_data
+                    .get_function_from_addr(frame.instruction_ptr)
+                    .unwrap()
+            })
+            .collect();
+        // func3 is first called from func2
+        assert_eq!(names, vec!["func3", "func2", "func1", "main"]);
+        assert_eq!(frames[0].instruction_ptr, func3);
+        // Each caller's frame is further up the stack
+        assert!(frames
+            .windows(2)
+            .all(|pair| pair[0].base_ptr < pair[1].base_ptr));
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_frames_without_debug_info() {
+        let (mut inferior, debug_data) = start_sample("samples/segfault_nodebug");
+        let rip = match inferior.cont().unwrap() {
+            Status::Stopped(signal::Signal::SIGSEGV, rip) => rip,
+            _ => panic!("Expected the inferior to segfault"),
+        };
+        assert!(debug_data.get_function_from_addr(rip).is_none());
+        assert!(debug_data.get_line_from_addr(rip).is_none());
+
+        // Without symbols there's no main to stop at, so the walk has to end some other way
+        let frames = inferior.frames(&debug_data).unwrap();
+        assert_eq!(frames[0].instruction_ptr, rip);
+        // func2, func1 and main at least
+        assert!(frames.len() >= 3);
+        assert!(frames.len() <= MAX_BACKTRACE_DEPTH);
+        assert!(!inferior.print_current_frame(rip, &debug_data));
+        inferior.print_backtrace(&debug_data).unwrap();
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_breakpoint_hit_repeatedly() {
+        let (mut inferior, debug_data) = start_sample("samples/loop");
+        let loop_body = debug_data.get_addr_for_line(None, 6).unwrap();
+        inferior.install_break_points(loop_body).unwrap();
+
+        // sum_to(10) runs the loop body ten times, and each iteration stops at the breakpoint
+        for _ in 0..10 {
+            match inferior.cont().unwrap() {
+                Status::Stopped(_, ip) => assert_eq!(ip, loop_body),
+                _ => panic!("Expected to stop at the breakpoint inside the loop"),
+            }
+            let rip = ptrace::getregs(inferior.pid()).unwrap().rip as usize;
+            assert_eq!(rip, loop_body);
+        }
+        match inferior.cont().unwrap() {
+            Status::Exited(code) => assert_eq!(code, 0),
+            _ => panic!("Expected the inferior to exit normally"),
+        }
+    }
+
+    #[test]
+    fn test_run_until_past_loop() {
+        let (mut inferior, debug_data) = start_sample("samples/loop");
+        let loop_body = debug_data.get_addr_for_line(None, 6).unwrap();
+        let after_loop = debug_data.get_addr_for_line(None, 8).unwrap();
+        let orig_byte = inferior.read_memory(after_loop, 1).unwrap()[0];
+        inferior.install_break_points(loop_body).unwrap();
+        inferior.cont().unwrap();
+
+        // The breakpoint inside the loop is hit again before the loop is done
+        match inferior.run_until(after_loop).unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, loop_body),
+            _ => panic!("Expected to stop at the breakpoint inside the loop"),
+        }
+        assert_eq!(inferior.breakpoints.len(), 1);
+        assert_eq!(inferior.read_memory(after_loop, 1).unwrap()[0], orig_byte);
+
+        // Without it, we run out of the loop
+        inferior.remove_break_point(loop_body).unwrap();
+        match inferior.run_until(after_loop).unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, after_loop),
+            _ => panic!("Expected to stop after the loop"),
+        }
+        assert!(inferior.breakpoints.is_empty());
+
+        // The loop header is never reached again, so we end up back in main once sum_to returns
+        let loop_header = debug_data.get_addr_for_line(None, 5).unwrap();
+        match inferior.run_until(loop_header).unwrap() {
+            Status::Stopped(_, ip) => {
+                assert_eq!(debug_data.get_function_from_addr(ip).unwrap(), "main");
+                assert_eq!(debug_data.get_line_from_addr(ip).unwrap().number, 13);
+            }
+            _ => panic!("Expected to stop in main after sum_to returned"),
+        }
+        assert!(inferior.breakpoints.is_empty());
+        match inferior.cont().unwrap() {
+            Status::Exited(code) => assert_eq!(code, 0),
+            _ => panic!("Expected the inferior to exit normally"),
+        }
+    }
+
+    #[test]
+    fn test_format_variables() {
+        let (mut inferior, debug_data) = start_sample("samples/vars");
+        let printf_line = debug_data.get_addr_for_line(None, 20).unwrap();
+        inferior.install_break_points(printf_line).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, printf_line),
+            _ => panic!("Expected to stop at the breakpoint on line 20"),
+        }
+        let frame = inferior.frames(&debug_data).unwrap()[0];
+        let print = |name: &str| {
+            let var = debug_data
+                .get_variable(frame.instruction_ptr, name)
+                .unwrap_or_else(|| panic!("No variable {}", name));
+            (
+                var.entity_type.name.clone(),
+                inferior
+                    .format_expression(&Expr::Variable(name.to_string()), &frame, &debug_data)
+                    .unwrap(),
+            )
+        };
+
+        let (greeting_type, greeting) = print("greeting");
+        assert_eq!(greeting_type, "const char *");
+        assert!(greeting.ends_with(" \"hello, world\""), "{}", greeting);
+        assert_eq!(
+            print("primes"),
+            ("int [5]".to_string(), "{2, 3, 5, 7, 11}".to_string())
+        );
+        assert_eq!(
+            print("name"),
+            ("char [8]".to_string(), "\"deet\"".to_string())
+        );
+        assert_eq!(print("ratio").1, "0.5");
+        assert!(print("message")
+            .1
+            .ends_with(" \"a \\\"quoted\\\" message\""));
+        assert_eq!(
+            print("squares"),
+            ("int [4]".to_string(), "{0, 1, 4, 9}".to_string())
+        );
+        assert_eq!(print("total").1, "14");
+        assert!(debug_data
+            .get_variable(frame.instruction_ptr, "values")
+            .is_none());
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_format_locals() {
+        let (mut inferior, debug_data) = start_sample("samples/vars");
+        let sum_loop = debug_data.get_addr_for_line(None, 9).unwrap();
+        inferior.install_break_points(sum_loop).unwrap();
+        inferior.cont().unwrap();
+        let frame = inferior.frames(&debug_data).unwrap()[0];
+        let func = debug_data
+            .get_function_containing(frame.instruction_ptr)
+            .unwrap();
+        let locals: Vec<(&str, String)> = func
+            .variables
+            .iter()
+            .filter(|var| !var.is_parameter)
+            .map(|var| {
+                (
+                    var.name.as_str(),
+                    inferior.format_variable(var, &frame).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            locals,
+            vec![("total", "0".to_string()), ("i", "0".to_string())]
+        );
+        let params: Vec<&str> = func
+            .variables
+            .iter()
+            .filter(|var| var.is_parameter)
+            .map(|var| var.name.as_str())
+            .collect();
+        assert_eq!(params, vec!["values", "count"]);
+        let args = Inferior::format_arguments(&inferior.arguments(func, &frame));
+        assert!(args.starts_with("values=0x"), "{}", args);
+        assert!(args.ends_with(", count=4"), "{}", args);
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_format_expressions() {
+        let (mut inferior, debug_data) = start_sample("samples/structs");
+        let printf_line = debug_data.get_addr_for_line(None, 28).unwrap();
+        inferior.install_break_points(printf_line).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, printf_line),
+            _ => panic!("Expected to stop at the breakpoint on line 28"),
+        }
+        let frame = inferior.frames(&debug_data).unwrap()[0];
+        let print = |text: &str| {
+            let expr = crate::expr::parse(text).unwrap();
+            inferior.format_expression(&expr, &frame, &debug_data)
+        };
+
+        assert_eq!(print("s.x"), Ok("3".to_string()));
+        assert_eq!(print("s"), Ok("{x = 3, y = -4}".to_string()));
+        assert_eq!(print("arr[2]"), Ok("30".to_string()));
+        assert_eq!(print("*p"), Ok("20".to_string()));
+        assert_eq!(print("p[2]"), Ok("40".to_string()));
+        assert_eq!(print("list->next->value"), Ok("2".to_string()));
+        assert_eq!(print("list.next.next.value"), Ok("3".to_string()));
+        assert_eq!(print("(*list).value"), Ok("1".to_string()));
+        assert!(print("*list").unwrap().starts_with("{value = 1, next = 0x"));
+        assert_eq!(print("box.corners[1].y"), Ok("6".to_string()));
+        assert!(print("box.label").unwrap().ends_with(" \"box\""));
+
+        assert_eq!(
+            print("s.z"),
+            Err("There is no member named z in struct point".to_string())
+        );
+        assert_eq!(
+            print("arr[4]"),
+            Err("Index 4 is out of bounds for int [4]".to_string())
+        );
+        assert_eq!(
+            print("*s"),
+            Err("Cannot dereference a value of type struct point".to_string())
+        );
+        assert_eq!(
+            print("arr.x"),
+            Err("int [4] is not a struct or union".to_string())
+        );
+        assert_eq!(
+            print("list->next->next->next->value"),
+            Err("Cannot dereference a null pointer".to_string())
+        );
+        assert!(print("nothing").is_err());
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_evaluate_condition() {
+        let (mut inferior, debug_data) = start_sample("samples/structs");
+        let printf_line = debug_data.get_addr_for_line(None, 28).unwrap();
+        inferior.install_break_points(printf_line).unwrap();
+        inferior.cont().unwrap();
+        let frame = inferior.frames(&debug_data).unwrap()[0];
+        let holds = |text: &str| {
+            let condition = crate::expr::parse_condition(text).unwrap();
+            inferior.evaluate_condition(&condition, &frame, &debug_data)
+        };
+
+        assert_eq!(holds("s.x == 3"), Ok(true));
+        assert_eq!(holds("s.y < -4"), Ok(false));
+        assert_eq!(holds("arr[2] >= *p"), Ok(true));
+        assert_eq!(holds("list->next"), Ok(true));
+        assert_eq!(holds("list->next->next->next"), Ok(false));
+        assert_eq!(
+            holds("s > 1"),
+            Err("Cannot compare s, a value of type struct point".to_string())
+        );
+        assert!(holds("nothing == 1").is_err());
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let (mut inferior, debug_data) = start_sample("samples/loop");
+        let loop_body = debug_data.get_addr_for_line(None, 6).unwrap();
+        inferior.install_break_points(loop_body).unwrap();
+        inferior.cont().unwrap();
+        inferior.remove_break_point(loop_body).unwrap();
+        let frame = inferior.frames(&debug_data).unwrap()[0];
+        let sum = crate::expr::parse("sum").unwrap();
+        let (addr, sum_type) = inferior.evaluate(&sum, &frame, &debug_data).unwrap();
+        assert!(inferior.set_watchpoint(0, addr + 1, 4).is_err());
+        assert!(inferior.set_watchpoint(0, addr, 3).is_err());
+        inferior.set_watchpoint(0, addr, sum_type.size).unwrap();
+
+        // Each iteration writes sum once
+        for expected in &[1, 3, 6] {
+            match inferior.cont().unwrap() {
+                Status::Stopped(signal::SIGTRAP, ip) => {
+                    assert_eq!(debug_data.get_function_from_addr(ip).unwrap(), "sum_to")
+                }
+                _ => panic!("Expected the watchpoint to stop the inferior"),
+            }
+            assert_eq!(inferior.take_triggered_watchpoints().unwrap(), vec![0]);
+            let bytes = inferior.read_memory(addr, 4).unwrap();
+            assert_eq!(crate::value::read_uint(&bytes), *expected);
+        }
+
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_set_register() {
+        let (mut inferior, _) = start_sample("samples/exit");
+        let rip = inferior.instruction_ptr().unwrap();
+        assert!(inferior.set_register("rax", 0x10).unwrap());
+        assert!(!inferior.set_register("eax", 0x10).unwrap());
+        let regs = ptrace::getregs(inferior.pid()).unwrap();
+        assert_eq!(regs.rax, 0x10);
+        assert_eq!(regs.rip as usize, rip);
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_attach_and_detach() {
+        let debug_data = DwarfData::from_file("samples/sleepy_print").unwrap();
+        let print_line = debug_data.get_addr_for_line(None, 12).unwrap();
+        let mut child = Command::new("samples/sleepy_print")
+            .arg("30")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+
+        let mut inferior = Inferior::attach(pid, &vec![print_line]).unwrap();
+        assert!(inferior.is_attached());
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::SIGTRAP, ip) => assert_eq!(ip, print_line),
+            _ => panic!("Expected to stop at the breakpoint in the loop"),
+        }
+        inferior.detach().unwrap();
+
+        // The process carries on by itself. Had the breakpoint been left in, the next iteration
+        // would have killed it with SIGTRAP.
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_cont_with_signal() {
+        let (mut inferior, debug_data) = start_sample("samples/segfault");
+        let fault_line = debug_data.get_addr_for_line(None, 5).unwrap();
+        let ip = match inferior.cont().unwrap() {
+            Status::Stopped(signal::SIGSEGV, ip) => ip,
+            _ => panic!("Expected the inferior to segfault"),
+        };
+        assert_eq!(debug_data.get_line_from_addr(ip).unwrap().number, 5);
+        assert!(ip >= fault_line);
+        // Passing the signal on kills the inferior, as it would have without the debugger
+        match inferior.cont_with_signal(Some(signal::SIGSEGV)).unwrap() {
+            Status::Signaled(signal::SIGSEGV) => {}
+            _ => panic!("Expected the inferior to be killed by SIGSEGV"),
+        }
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let (mut inferior, debug_data) = start_sample("samples/vars");
+        let loop_body = debug_data.get_addr_for_line(None, 9).unwrap();
+        let return_line = debug_data.get_addr_for_line(None, 11).unwrap();
+        inferior.install_break_points(loop_body).unwrap();
+        inferior.cont().unwrap();
+        inferior.start_recording();
+        inferior.remove_break_point(loop_body).unwrap();
+        inferior.install_break_points(return_line).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::SIGTRAP, ip) => assert_eq!(ip, return_line),
+            _ => panic!("Expected the recorded inferior to stop at the breakpoint"),
+        }
+        assert!(inferior.recorded_instructions().unwrap() > 0);
+
+        let sum = debug_data.get_function_containing(loop_body).unwrap();
+        let total = sum
+            .variables
+            .iter()
+            .find(|var| var.name == "total")
+            .unwrap();
+        let i = sum.variables.iter().find(|var| var.name == "i").unwrap();
+        let locals = |inferior: &Inferior| {
+            let frame = inferior.frames(&debug_data).unwrap()[0];
+            (
+                inferior.format_variable(total, &frame).unwrap(),
+                inferior.format_variable(i, &frame).unwrap(),
+            )
+        };
+        assert_eq!(locals(&inferior), ("14".to_string(), "4".to_string()));
+
+        // Going back stops at the loop body each time round, latest first, with the values the
+        // variables had then
+        inferior.install_break_points(loop_body).unwrap();
+        assert!(inferior.reverse_cont().unwrap());
+        assert_eq!(inferior.instruction_ptr().unwrap(), loop_body);
+        assert_eq!(locals(&inferior), ("5".to_string(), "3".to_string()));
+        assert!(inferior.reverse_cont().unwrap());
+        assert_eq!(locals(&inferior), ("1".to_string(), "2".to_string()));
+        assert!(inferior.reverse_step_once().unwrap());
+        assert_ne!(inferior.instruction_ptr().unwrap(), loop_body);
+
+        // Running forward again from the restored state gets the same results
+        inferior.remove_break_point(loop_body).unwrap();
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::SIGTRAP, ip) => assert_eq!(ip, return_line),
+            _ => panic!("Expected the inferior to stop at the breakpoint again"),
+        }
+        assert_eq!(locals(&inferior), ("14".to_string(), "4".to_string()));
+        inferior.stop_recording();
+        assert!(!inferior.reverse_step_once().unwrap());
+        inferior.kill().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_core() {
+        let (mut inferior, debug_data) = start_sample("samples/segfault");
+        inferior.cont().unwrap();
+        let core = inferior.snapshot().unwrap();
+        assert_eq!(core.signal, Some(signal::SIGSEGV));
+        assert_eq!(core.command_line, "samples/segfault");
+        let live_frames = inferior.frames(&debug_data).unwrap();
+        inferior.kill().unwrap();
+
+        // The core has everything a backtrace needs after the process is gone
+        let inferior = Inferior::from_core(core);
+        assert!(inferior.is_core() && !inferior.is_attached());
+        let frames = inferior.frames(&debug_data).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames, live_frames);
+        let func2 = debug_data
+            .get_function_containing(frames[0].instruction_ptr)
+            .unwrap();
+        assert_eq!(func2.name, "func2");
+        let a = func2.variables.iter().find(|var| var.name == "a").unwrap();
+        assert_eq!(inferior.format_variable(a, &frames[0]).unwrap(), "2");
+    }
+
+    #[test]
+    fn test_follow_fork() {
+        let debug_data = DwarfData::from_file("samples/fork").unwrap();
+        let work = debug_data.get_addr_for_function(None, "work").unwrap();
+        let argument = |inferior: &Inferior| ptrace::getregs(inferior.pid()).unwrap().rdi;
+
+        // The parent's call comes after the child has exited, which it would have done with
+        // SIGTRAP had it kept its copy of the breakpoint
+        let mut inferior = Inferior::spawn(
+            "samples/fork",
+            &Vec::new(),
+            &Redirections::default(),
+            &vec![work],
+        )
+        .unwrap();
+        let mut status = inferior.cont().unwrap();
+        // The child exiting sends the parent SIGCHLD first
+        while let Status::Stopped(signal::SIGCHLD, _) = status {
+            status = inferior.cont_with_signal(Some(signal::SIGCHLD)).unwrap();
+        }
+        match status {
+            Status::Stopped(signal::SIGTRAP, ip) => assert_eq!(ip, work),
+            _ => panic!("Expected the parent to stop in work"),
+        }
+        assert_eq!(argument(&inferior), 1);
+        inferior.kill().unwrap();
+
+        let mut inferior = Inferior::spawn(
+            "samples/fork",
+            &Vec::new(),
+            &Redirections::default(),
+            &vec![work],
+        )
+        .unwrap();
+        let parent = inferior.pid();
+        inferior.set_follow_fork(FollowFork::Child);
+        match inferior.cont().unwrap() {
+            Status::Stopped(signal::SIGTRAP, ip) => assert_eq!(ip, work),
+            _ => panic!("Expected the child to stop in work"),
+        }
+        assert_ne!(inferior.pid(), parent);
+        assert_eq!(argument(&inferior), 21);
+        assert!(matches!(inferior.cont().unwrap(), Status::Exited(0)));
+    }
+
+    #[test]
+    fn test_exec() {
+        let args = vec!["samples/hello".to_string()];
+        let mut inferior =
+            Inferior::spawn("samples/fork", &args, &Redirections::default(), &Vec::new()).unwrap();
+        inferior.set_follow_fork(FollowFork::Child);
+        assert!(matches!(inferior.cont().unwrap(), Status::Execed(_)));
+        let exe = fs::read_link(format!("/proc/{}/exe", inferior.pid())).unwrap();
+        assert!(exe.ends_with("samples/hello"));
+        assert!(matches!(inferior.cont().unwrap(), Status::Exited(0)));
+    }
+
+    #[test]
+    fn test_next_line_over_call() {
+        let (mut inferior, debug_data) = start_sample("samples/function_calls");
+        let line18 = debug_data.get_addr_for_line(None, 18).unwrap();
+        inferior.install_break_points(line18).unwrap();
+        inferior.cont().unwrap();
+
+        // func2(a, global) runs to completion rather than being stepped into
+        match inferior.next_line(&debug_data).unwrap() {
+            Status::Stopped(_, ip) => {
+                assert_eq!(debug_data.get_function_from_addr(ip).unwrap(), "func1");
+                assert_eq!(debug_data.get_line_from_addr(ip).unwrap().number, 19);
+            }
+            _ => panic!("Expected next to stop on line 19"),
+        }
+
+        // A breakpoint inside the called function still stops next
+        let func3 = debug_data.get_addr_for_function(None, "func3").unwrap();
+        inferior.install_break_points(func3).unwrap();
+        match inferior.next_line(&debug_data).unwrap() {
+            Status::Stopped(_, ip) => assert_eq!(ip, func3),
+            _ => panic!("Expected to stop at the breakpoint in func3"),
+        }
+        inferior.kill().unwrap();
+    }
 }
@@ -0,0 +1,351 @@
+//! Just enough JSON for `--json` output and the DAP server: building values, writing them out and
+//! parsing the requests a client sends.
+
+use std::fmt;
+
+/// A JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys are kept in the order given, so that output always reads the same way
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from its fields, in order.
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Builds a JSON string, or null if there's no text.
+    pub fn string_or_null<S: ToString>(text: Option<S>) -> Json {
+        text.map_or(Json::Null, |text| Json::String(text.to_string()))
+    }
+
+    /// Returns the field called `key`, if this is an object that has one.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns this number, if it's a whole one.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(value) if value.fract() == 0.0 => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Json {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Json {
+        Json::String(text)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(text) => {
+                write!(f, "\"")?;
+                for c in text.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::String(key.clone()), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Parses a JSON document. Returns an error saying what's wrong and where if it isn't valid.
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, what: &str) -> String {
+        format!("Invalid JSON at character {}: {}", self.pos, what)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.next() == Some(c) {
+            Ok(())
+        } else {
+            self.pos -= 1;
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for c in word.chars() {
+            if self.next() != Some(c) {
+                return Err(self.error("unknown keyword"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Json::Array(values)),
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => {}
+                        Some('}') => return Ok(Json::Object(fields)),
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+                {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse::<f64>()
+                    .map(Json::Number)
+                    .map_err(|_| self.error("invalid number"))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.next() != Some('"') {
+            self.pos -= 1;
+            return Err(self.error("expected a string"));
+        }
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => match self.next() {
+                    Some('n') => text.push('\n'),
+                    Some('r') => text.push('\r'),
+                    Some('t') => text.push('\t'),
+                    Some('b') => text.push('\u{8}'),
+                    Some('f') => text.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = self.chars.iter().skip(self.pos).take(4).collect();
+                        let code = u32::from_str_radix(&hex, 16)
+                            .map_err(|_| self.error("invalid \\u escape"))?;
+                        self.pos += 4;
+                        // Characters outside the BMP come as surrogate pairs, which we don't need
+                        text.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    Some(c) => text.push(c),
+                    None => return Err(self.error("unterminated string")),
+                },
+                Some(c) => text.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let value = Json::object(vec![
+            ("event", "stopped".into()),
+            ("line", 12usize.into()),
+            ("file", Json::Null),
+            (
+                "args",
+                Json::Array(vec!["say \"hi\"\n\\".into(), true.into()]),
+            ),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            r#"{"event":"stopped","line":12,"file":null,"args":["say \"hi\"\n\\",true]}"#
+        );
+        assert_eq!(Json::String("\u{1}".to_string()).to_string(), r#""\u0001""#);
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = r#" {"seq": 3, "command":"setBreakpoints", "arguments": {"source": {"path":
+            "a \"b\".c"}, "breakpoints": [{"line": 10}, {"line": -2.5e1}], "x": [true, false, null]}}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(value.get("seq").and_then(Json::as_i64), Some(3));
+        assert_eq!(
+            value.get("command").and_then(Json::as_str),
+            Some("setBreakpoints")
+        );
+        let arguments = value.get("arguments").unwrap();
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"));
+        assert_eq!(path.and_then(Json::as_str), Some("a \"b\".c"));
+        let lines: Vec<Option<i64>> = arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .map(|bp| bp.get("line").and_then(Json::as_i64))
+            .collect();
+        assert_eq!(lines, vec![Some(10), Some(-25)]);
+        // What's written out parses back the same
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+        assert_eq!(parse(r#""é""#).unwrap(), Json::String("é".to_string()));
+        assert!(parse("{\"a\": }").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("{} x").is_err());
+    }
+}
@@ -1,24 +1,145 @@
+#[macro_use]
+mod output;
+
+mod corefile;
+mod dap;
 mod debugger;
 mod debugger_command;
 mod dwarf_data;
+mod examine;
+mod expr;
 mod gimli_wrapper;
 mod inferior;
+mod json;
+mod maps;
+mod record;
+mod registers;
+mod signals;
+mod source;
+mod value;
 
 use crate::debugger::Debugger;
-use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::unistd::Pid;
 use std::env;
+use std::fs;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    let mut args: Vec<String> = env::args().collect();
+    // --json prints everything as JSON events, one per line, for other programs to read
+    if let Some(idx) = args.iter().position(|arg| arg == "--json") {
+        args.remove(idx);
+        output::set_mode(output::Mode::Json);
     }
-    let target = &args[1];
+    // --no-confirm turns off the prompt before killing a running inferior
+    let confirm = match args.iter().position(|arg| arg == "--no-confirm") {
+        Some(idx) => {
+            args.remove(idx);
+            false
+        }
+        None => true,
+    };
+    // --ignore-errors keeps a script going past commands that fail
+    let ignore_errors = match args.iter().position(|arg| arg == "--ignore-errors") {
+        Some(idx) => {
+            args.remove(idx);
+            true
+        }
+        None => false,
+    };
+    // --source <file>, or -x <file> as in gdb, runs the commands in a file before the first prompt
+    let script = match args.iter().position(|arg| arg == "--source" || arg == "-x") {
+        Some(idx) if idx + 1 < args.len() => {
+            args.remove(idx);
+            Some(args.remove(idx))
+        }
+        _ => None,
+    };
+    // --attach <pid> debugs a process that's already running, instead of a program we start
+    let attach = match args.iter().position(|arg| arg == "--attach") {
+        Some(idx) if idx + 1 < args.len() => {
+            args.remove(idx);
+            match args.remove(idx).parse::<i32>() {
+                Ok(pid) if pid > 0 => Some(Pid::from_raw(pid)),
+                _ => {
+                    say!("Invalid process ID");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    // --core <file> looks at a process after its death, from a core file
+    let core = match args.iter().position(|arg| arg == "--core") {
+        Some(idx) if idx + 1 < args.len() && attach.is_none() => {
+            args.remove(idx);
+            Some(args.remove(idx))
+        }
+        _ => None,
+    };
+    // --dap <port> serves the Debug Adapter Protocol to an editor instead of reading commands
+    let dap = match args.iter().position(|arg| arg == "--dap") {
+        Some(idx) if idx + 1 < args.len() => {
+            args.remove(idx);
+            match args.remove(idx).parse::<u16>() {
+                Ok(port) => Some(port),
+                _ => {
+                    say!("Invalid port");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    let target = match (attach, args.len()) {
+        (_, 2) => args[1].clone(),
+        (Some(pid), 1) => match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe.to_string_lossy().into_owned(),
+            Err(e) => {
+                say!("Cannot find the program process {} is running: {}", pid, e);
+                std::process::exit(1);
+            }
+        },
+        _ => {
+            say!(
+                "Usage: {} [--no-confirm] [--json] [-x|--source <file> [--ignore-errors]] <target program>",
+                args[0]
+            );
+            say!(
+                "       {} [--no-confirm] [--json] [-x|--source <file> [--ignore-errors]] --attach <pid> [<target program>]",
+                args[0]
+            );
+            say!("       {} --dap <port> <target program>", args[0]);
+            say!(
+                "       {} [--json] [-x|--source <file> [--ignore-errors]] --core <core file> <target program>",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
 
-    // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
-    // processes)
-    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+    // Make ctrl+c interrupt the inferior instead of this process
+    inferior::forward_interrupts().expect("Error installing SIGINT handler");
 
-    Debugger::new(target).run();
+    if let Some(port) = dap {
+        // The client decides what to do, so there's nobody to ask before killing the inferior
+        let mut debugger = Debugger::new(&target, false, ignore_errors);
+        if let Err(e) = dap::serve(&mut debugger, port) {
+            say!("Error serving DAP: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut debugger = Debugger::new(&target, confirm, ignore_errors);
+    if let Some(pid) = attach {
+        debugger.attach(pid);
+    }
+    if let Some(core) = core {
+        debugger.load_core(&core);
+    }
+    if let Some(script) = script {
+        if !debugger.source(&script) {
+            return;
+        }
+    }
+    debugger.run();
 }
@@ -0,0 +1,128 @@
+//! Reading the inferior's memory mappings from /proc/<pid>/maps.
+
+/// One line of /proc/<pid>/maps, e.g.
+/// "00400000-00401000 r-xp 00000000 08:01 1234   /path/to/binary".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub start: usize,
+    /// One past the last mapped address
+    pub end: usize,
+    /// Permissions as the kernel writes them, e.g. "r-xp"
+    pub perms: String,
+    /// The mapped file, a pseudo-path like "[stack]", or empty for anonymous memory
+    pub path: String,
+}
+
+impl Mapping {
+    pub fn is_executable(&self) -> bool {
+        self.perms.as_bytes().get(2) == Some(&b'x')
+    }
+}
+
+/// Parses the contents of /proc/<pid>/maps, skipping any lines that don't look right.
+pub fn parse(maps: &str) -> Vec<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            // The path is the sixth field, but may itself contain spaces
+            let mut fields = line.splitn(6, char::is_whitespace);
+            let (start, end) = fields.next()?.split_once('-')?;
+            let perms = fields.next()?.to_string();
+            let path = fields.nth(3).unwrap_or("").trim().to_string();
+            Some(Mapping {
+                start: usize::from_str_radix(start, 16).ok()?,
+                end: usize::from_str_radix(end, 16).ok()?,
+                perms,
+                path,
+            })
+        })
+        .collect()
+}
+
+/// A run of adjacent mappings of the same file, e.g. the text, rodata and data segments of a
+/// shared library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedObject {
+    pub start: usize,
+    pub end: usize,
+    /// The permissions of each of the mappings, in address order
+    pub perms: Vec<String>,
+    pub path: String,
+}
+
+/// Groups the file-backed mappings (the loaded object files) into runs of adjacent mappings of the
+/// same file. Anonymous memory and pseudo-paths like "[heap]" are left out.
+pub fn loaded_objects(mappings: &[Mapping]) -> Vec<MappedObject> {
+    let mut objects: Vec<MappedObject> = Vec::new();
+    for mapping in mappings
+        .iter()
+        .filter(|mapping| mapping.path.starts_with('/'))
+    {
+        match objects.last_mut() {
+            Some(last) if last.path == mapping.path && last.end == mapping.start => {
+                last.end = mapping.end;
+                last.perms.push(mapping.perms.clone());
+            }
+            _ => objects.push(MappedObject {
+                start: mapping.start,
+                end: mapping.end,
+                perms: vec![mapping.perms.clone()],
+                path: mapping.path.clone(),
+            }),
+        }
+    }
+    objects
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAPS: &str = "\
+00400000-00401000 r--p 00000000 08:01 1234                               /home/user/a.out
+00401000-00402000 r-xp 00001000 08:01 1234                               /home/user/a.out
+00403000-00404000 rw-p 00002000 08:01 1234                               /home/user/a.out
+01e5c000-01e7d000 rw-p 00000000 00:00 0                                  [heap]
+7f0d6a600000-7f0d6a628000 r--p 00000000 08:01 5678                       /usr/lib/libc.so.6
+7f0d6a628000-7f0d6a7bd000 r-xp 00028000 08:01 5678                       /usr/lib/libc.so.6
+7f0d6a800000-7f0d6a801000 rw-p 00000000 00:00 0
+7ffc3b1e6000-7ffc3b207000 rw-p 00000000 00:00 0                          [stack]
+";
+
+    #[test]
+    fn test_parse() {
+        let mappings = parse(MAPS);
+        assert_eq!(mappings.len(), 8);
+        assert_eq!(
+            mappings[1],
+            Mapping {
+                start: 0x401000,
+                end: 0x402000,
+                perms: "r-xp".to_string(),
+                path: "/home/user/a.out".to_string(),
+            }
+        );
+        assert!(mappings[1].is_executable());
+        assert!(!mappings[0].is_executable());
+        assert_eq!(mappings[6].path, "");
+        assert!(parse("garbage\n").is_empty());
+    }
+
+    #[test]
+    fn test_loaded_objects() {
+        let objects = loaded_objects(&parse(MAPS));
+        let summary: Vec<(usize, usize, &str)> = objects
+            .iter()
+            .map(|object| (object.start, object.end, object.path.as_str()))
+            .collect();
+        // a.out has a gap before its data segment, so that is listed on its own
+        assert_eq!(
+            summary,
+            vec![
+                (0x400000, 0x402000, "/home/user/a.out"),
+                (0x403000, 0x404000, "/home/user/a.out"),
+                (0x7f0d6a600000, 0x7f0d6a7bd000, "/usr/lib/libc.so.6"),
+            ]
+        );
+        assert_eq!(objects[0].perms, vec!["r--p", "r-xp"]);
+    }
+}
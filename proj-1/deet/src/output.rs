@@ -0,0 +1,141 @@
+//! Where the debugger's output goes. Normally that's text on stdout for a person to read; with
+//! `--json` every line is a JSON object instead, so that an editor plugin can drive deet the way
+//! gdb's MI is used.
+//!
+//! In JSON mode, text that would have been printed becomes a `console` event, and commands also
+//! report what they found (where the inferior stopped, the backtrace, a value...) as events of
+//! their own, which are silent in text mode. The inferior's own output isn't ours to wrap, so it
+//! should be redirected (`run > file`) if it would get in the way.
+
+use crate::dwarf_data::DwarfData;
+use crate::json::Json;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// Prints a line of text meant for a person, like `println!`.
+macro_rules! say {
+    () => {
+        $crate::output::output().console("")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::output().console(&format!($($arg)*))
+    };
+}
+
+/// How output is presented.
+pub trait Output: Sync {
+    /// Shows a line of text meant for a person.
+    fn console(&self, text: &str);
+    /// Reports the outcome of a command, e.g. `("stopped", [("reason", ...), ...])`.
+    fn event(&self, name: &'static str, fields: Vec<(&'static str, Json)>);
+    /// The prompt to show when reading a command.
+    fn prompt<'a>(&self, prompt: &'a str) -> &'a str;
+}
+
+/// Plain text, as deet normally prints it. Events are left out, since the same information has
+/// already been printed as text.
+pub struct Text;
+
+impl Output for Text {
+    fn console(&self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn event(&self, _name: &'static str, _fields: Vec<(&'static str, Json)>) {}
+
+    fn prompt<'a>(&self, prompt: &'a str) -> &'a str {
+        prompt
+    }
+}
+
+/// One JSON object per line, with no prompts.
+pub struct JsonLines;
+
+impl Output for JsonLines {
+    fn console(&self, text: &str) {
+        self.event("console", vec![("text", Json::String(text.to_string()))]);
+    }
+
+    fn event(&self, name: &'static str, fields: Vec<(&'static str, Json)>) {
+        println!("{}", event(name, fields));
+    }
+
+    fn prompt<'a>(&self, _prompt: &'a str) -> &'a str {
+        ""
+    }
+}
+
+/// Describes the code at `addr` for an event: the address, and the function and source line if
+/// the debugging information covers it.
+pub fn location(addr: usize, debug_data: &DwarfData) -> Vec<(&'static str, Json)> {
+    let line = debug_data.get_line_from_addr(addr);
+    vec![
+        ("addr", Json::String(format!("{:#x}", addr))),
+        (
+            "function",
+            Json::string_or_null(debug_data.get_function_from_addr(addr)),
+        ),
+        (
+            "file",
+            Json::string_or_null(line.as_ref().map(|line| &line.file)),
+        ),
+        (
+            "line",
+            line.map_or(Json::Null, |line| Json::from(line.number)),
+        ),
+    ]
+}
+
+/// Builds an event as it's written in JSON: an object whose `event` field is the name.
+pub fn event(name: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("event", name.into()));
+    Json::object(fields)
+}
+
+/// Keeps events and text for the program to pick up with `take_captured`, instead of printing
+/// them, for the DAP server.
+pub struct Captured;
+
+static CAPTURED: Mutex<Vec<Json>> = Mutex::new(Vec::new());
+
+impl Output for Captured {
+    fn console(&self, text: &str) {
+        self.event("console", vec![("text", text.into())]);
+    }
+
+    fn event(&self, name: &'static str, fields: Vec<(&'static str, Json)>) {
+        CAPTURED.lock().unwrap().push(event(name, fields));
+    }
+
+    fn prompt<'a>(&self, _prompt: &'a str) -> &'a str {
+        ""
+    }
+}
+
+/// Returns the events captured since this was last called, oldest first.
+pub fn take_captured() -> Vec<Json> {
+    std::mem::take(&mut *CAPTURED.lock().unwrap())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Text = 0,
+    Json = 1,
+    Capture = 2,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(Mode::Text as u8);
+
+/// Chooses how output is presented.
+pub fn set_mode(mode: Mode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the output in use.
+pub fn output() -> &'static dyn Output {
+    match MODE.load(Ordering::Relaxed) {
+        1 => &JsonLines,
+        2 => &Captured,
+        _ => &Text,
+    }
+}
@@ -0,0 +1,167 @@
+//! Recording the inferior's execution one instruction at a time, so that it can be run backwards
+//! with `reverse-stepi`, `reverse-step` and `reverse-continue`.
+//!
+//! Before each instruction runs, the general-purpose registers are saved along with whatever
+//! memory the instruction is about to write, so that undoing it is a matter of putting them back.
+//! Memory written by the kernel (e.g. the buffer filled in by read(2)) and the floating-point and
+//! vector registers aren't recorded, so going back over those doesn't restore them.
+
+use crate::registers;
+use iced_x86::{Instruction, InstructionInfoFactory, OpAccess, Register};
+use libc::user_regs_struct;
+use std::collections::VecDeque;
+
+/// How many instructions are remembered; once there are this many, the oldest are forgotten.
+/// This is gdb's default for `record full`.
+pub const RECORD_LIMIT: usize = 200_000;
+
+/// What it takes to undo one instruction.
+pub struct Entry {
+    /// The registers as they were before the instruction ran
+    pub regs: user_regs_struct,
+    /// The memory the instruction wrote to, as it was before: (address, original bytes) pairs
+    pub memory: Vec<(usize, Vec<u8>)>,
+}
+
+/// The most recently executed instructions, oldest first.
+pub struct History {
+    entries: VecDeque<Entry>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History {
+            entries: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Remembers an instruction that's about to run, forgetting the oldest one if need be.
+    pub fn push(&mut self, entry: Entry) {
+        if self.entries.len() == self.limit {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Takes the entry for the most recently executed instruction.
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    /// Returns the entry for the most recently executed instruction.
+    pub fn last(&self) -> Option<&Entry> {
+        self.entries.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Returns the value of `reg` for working out a memory address, or None for a register we don't
+/// have (e.g. a vector register used as a VSIB index).
+fn register_value(regs: &user_regs_struct, reg: Register) -> Option<u64> {
+    match reg {
+        Register::FS => return Some(regs.fs_base),
+        Register::GS => return Some(regs.gs_base),
+        Register::ES | Register::CS | Register::SS | Register::DS => return Some(0),
+        _ => {}
+    }
+    let value = registers::get(regs, &format!("{:?}", reg.full_register()).to_lowercase())?;
+    Some(match reg.size() {
+        8 => value,
+        size => value & ((1 << (size * 8)) - 1),
+    })
+}
+
+/// Works out which memory `instr` is about to write, given the registers it's about to run with,
+/// as (address, length) pairs. A repeated string instruction (e.g. `rep stos`) is single-stepped
+/// one repetition at a time, so only the element written by the next repetition is included.
+pub fn memory_written(instr: &Instruction, regs: &user_regs_struct) -> Vec<(usize, usize)> {
+    let mut factory = InstructionInfoFactory::new();
+    let info = factory.info(instr);
+    info.used_memory()
+        .iter()
+        .filter(|mem| {
+            matches!(
+                mem.access(),
+                OpAccess::Write
+                    | OpAccess::CondWrite
+                    | OpAccess::ReadWrite
+                    | OpAccess::ReadCondWrite
+            )
+        })
+        .filter_map(|mem| {
+            let addr = mem.virtual_address(0, |reg, _, _| register_value(regs, reg))? as usize;
+            // The size of a repeated string instruction's memory operand is given as unknown
+            let size = match mem.memory_size().size() {
+                0 => instr.memory_size().size(),
+                size => size,
+            };
+            if size == 0 {
+                None
+            } else {
+                Some((addr, size))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use iced_x86::{Decoder, DecoderOptions};
+
+    fn decode(code: &[u8]) -> Instruction {
+        Decoder::with_ip(64, code, 0x401000, DecoderOptions::NONE).decode()
+    }
+
+    #[test]
+    fn test_memory_written() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rsp = 0x7ffc1000;
+        regs.rdx = 0x601000;
+        regs.rdi = 0x602000;
+        // push %rbp
+        assert_eq!(
+            memory_written(&decode(&[0x55]), &regs),
+            vec![(0x7ffc0ff8, 8)]
+        );
+        // mov %eax,(%rdx)
+        assert_eq!(
+            memory_written(&decode(&[0x89, 0x02]), &regs),
+            vec![(0x601000, 4)]
+        );
+        // mov -0x4(%rbp),%eax only reads
+        assert!(memory_written(&decode(&[0x8b, 0x45, 0xfc]), &regs).is_empty());
+        // rep stos %rax,%es:(%rdi)
+        assert_eq!(
+            memory_written(&decode(&[0xf3, 0x48, 0xab]), &regs),
+            vec![(0x602000, 8)]
+        );
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut history = History::new(2);
+        for rip in 1..=3 {
+            let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+            regs.rip = rip;
+            history.push(Entry {
+                regs,
+                memory: Vec::new(),
+            });
+        }
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.pop().unwrap().regs.rip, 3);
+        assert_eq!(history.last().unwrap().regs.rip, 2);
+        assert_eq!(history.pop().unwrap().regs.rip, 2);
+        assert!(history.pop().is_none());
+    }
+}
@@ -0,0 +1,89 @@
+//! Looking up the inferior's general-purpose registers by name, for `info registers` and
+//! `set $reg = value`.
+
+use libc::user_regs_struct;
+
+/// The registers `info registers` shows, in the order gdb lists them.
+pub const NAMES: [&str; 24] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs",
+];
+
+/// Returns the register called `name` (without the leading `$`), or None if there's no such
+/// register.
+pub fn get_mut<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" => &mut regs.rbp,
+        "rsp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        _ => return None,
+    })
+}
+
+/// Returns the value of the register called `name`, or None if there's no such register.
+pub fn get(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    get_mut(&mut regs, name).map(|value| *value)
+}
+
+/// Parses a value to put in a register: hexadecimal with a `0x` prefix, or decimal, possibly
+/// negative (stored in two's complement).
+pub fn parse_value(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text
+            .parse::<u64>()
+            .ok()
+            .or_else(|| text.parse::<i64>().ok().map(|value| value as u64)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_and_set() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        for (idx, name) in NAMES.iter().enumerate() {
+            *get_mut(&mut regs, name).unwrap() = idx as u64;
+        }
+        assert_eq!(regs.rax, 0);
+        assert_eq!(regs.r8, 8);
+        assert_eq!(regs.rip, 16);
+        assert_eq!(regs.gs, 23);
+        assert_eq!(get(&regs, "pc"), Some(16));
+        assert_eq!(get(&regs, "eax"), None);
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("0x10"), Some(16));
+        assert_eq!(parse_value("16"), Some(16));
+        assert_eq!(parse_value("-1"), Some(u64::MAX));
+        assert_eq!(parse_value("0xffffffffffffffff"), Some(u64::MAX));
+        assert_eq!(parse_value("0x"), None);
+        assert_eq!(parse_value("ten"), None);
+    }
+}
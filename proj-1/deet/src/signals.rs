@@ -0,0 +1,132 @@
+//! What to do when the inferior receives a signal, set with `handle SIGNAL action...` as in gdb.
+
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// How the debugger treats a signal the inferior receives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handling {
+    /// Whether to stop and give the user the prompt
+    pub stop: bool,
+    /// Whether to say that the signal was received
+    pub print: bool,
+    /// Whether to deliver the signal to the inferior when it resumes
+    pub pass: bool,
+}
+
+/// One of the keywords given to `handle`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Stop,
+    NoStop,
+    Print,
+    NoPrint,
+    Pass,
+    NoPass,
+}
+
+impl Handling {
+    /// Applies `action`. As in gdb, stopping implies printing and not printing implies not
+    /// stopping.
+    pub fn apply(&mut self, action: Action) {
+        match action {
+            Action::Stop => {
+                self.stop = true;
+                self.print = true;
+            }
+            Action::NoStop => self.stop = false,
+            Action::Print => self.print = true,
+            Action::NoPrint => {
+                self.print = false;
+                self.stop = false;
+            }
+            Action::Pass => self.pass = true,
+            Action::NoPass => self.pass = false,
+        }
+    }
+}
+
+/// How a signal is handled until the user says otherwise: signals that programs routinely use
+/// for timers and job control are passed along silently, SIGINT (which is normally meant for the
+/// debugger) stops without being passed, and everything else stops and is passed.
+pub fn default_handling(signal: Signal) -> Handling {
+    match signal {
+        Signal::SIGALRM
+        | Signal::SIGCHLD
+        | Signal::SIGURG
+        | Signal::SIGWINCH
+        | Signal::SIGPROF
+        | Signal::SIGVTALRM
+        | Signal::SIGIO => Handling {
+            stop: false,
+            print: false,
+            pass: true,
+        },
+        Signal::SIGINT => Handling {
+            stop: true,
+            print: true,
+            pass: false,
+        },
+        _ => Handling {
+            stop: true,
+            print: true,
+            pass: true,
+        },
+    }
+}
+
+/// Parses a signal given by name, with or without the `SIG` prefix, or by number.
+pub fn parse_signal(text: &str) -> Option<Signal> {
+    if let Ok(number) = text.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+    let name = text.to_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name).ok()
+    } else {
+        Signal::from_str(&format!("SIG{}", name)).ok()
+    }
+}
+
+/// Parses a `handle` keyword. `ignore` and `noignore` are gdb's other names for `nopass` and
+/// `pass`.
+pub fn parse_action(text: &str) -> Option<Action> {
+    Some(match text {
+        "stop" => Action::Stop,
+        "nostop" => Action::NoStop,
+        "print" => Action::Print,
+        "noprint" => Action::NoPrint,
+        "pass" | "noignore" => Action::Pass,
+        "nopass" | "ignore" => Action::NoPass,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGSEGV"), Some(Signal::SIGSEGV));
+        assert_eq!(parse_signal("usr1"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("2"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("SIGFOO"), None);
+        assert_eq!(parse_signal("0"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut handling = default_handling(Signal::SIGALRM);
+        assert!(!handling.stop && !handling.print && handling.pass);
+        handling.apply(Action::Stop);
+        assert!(handling.stop && handling.print);
+        handling.apply(Action::NoPrint);
+        assert!(!handling.stop && !handling.print);
+        handling.apply(Action::NoPass);
+        assert!(!handling.pass);
+        assert_eq!(parse_action("ignore"), Some(Action::NoPass));
+        assert_eq!(parse_action("stopp"), None);
+    }
+}
@@ -0,0 +1,61 @@
+//! Reading lines of the target's source files, for showing where the inferior stopped.
+
+use std::collections::HashMap;
+use std::fs;
+
+/// Source files that have been read, so that each is only read from disk once. Files that couldn't
+/// be read are remembered too, so we don't keep trying.
+#[derive(Default)]
+pub struct SourceCache {
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl SourceCache {
+    pub fn new() -> SourceCache {
+        SourceCache::default()
+    }
+
+    /// Returns the text of line `number` (counting from 1) of the file at `path`, or None if the
+    /// file can't be read or doesn't have that many lines.
+    pub fn line(&mut self, path: &str, number: usize) -> Option<&str> {
+        let lines = self.files.entry(path.to_string()).or_insert_with(|| {
+            let bytes = fs::read(path).ok()?;
+            Some(
+                String::from_utf8_lossy(&bytes)
+                    .lines()
+                    .map(|line| line.to_string())
+                    .collect(),
+            )
+        });
+        lines
+            .as_ref()?
+            .get(number.checked_sub(1)?)
+            .map(|line| line.as_str())
+    }
+
+    /// Forgets every file that has been read, e.g. because the target was rebuilt and its source
+    /// may have changed.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_cache() {
+        let mut cache = SourceCache::new();
+        assert_eq!(cache.line("samples/count.c", 1), Some("#include <stdio.h>"));
+        assert_eq!(cache.line("samples/count.c", 0), None);
+        assert_eq!(cache.line("samples/count.c", 100000), None);
+        assert_eq!(cache.line("samples/no_such_file.c", 1), None);
+        assert_eq!(cache.files.len(), 2);
+        // Files are only read once, even if they can't be read
+        assert_eq!(cache.line("samples/count.c", 1), Some("#include <stdio.h>"));
+        assert_eq!(cache.files.len(), 2);
+        cache.clear();
+        assert!(cache.files.is_empty());
+    }
+}
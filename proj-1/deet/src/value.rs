@@ -0,0 +1,327 @@
+//! Formatting of variables' values for `print`, based on their DWARF types.
+
+use crate::dwarf_data::{Encoding, Type, TypeKind};
+use std::convert::TryInto;
+
+/// Strings are cut off after this many bytes.
+pub const MAX_STRING_LEN: usize = 200;
+
+/// Formats a value of type `ty` stored in `bytes`. Char pointers are shown along with the string
+/// they point to, which is read with `read_string` (given the address of the string; it should
+/// return the bytes before the NUL, or None if the memory can't be read).
+pub fn format_value(
+    ty: &Type,
+    bytes: &[u8],
+    read_string: &dyn Fn(usize) -> Option<Vec<u8>>,
+) -> String {
+    if bytes.len() < ty.size {
+        return "<incomplete value>".to_string();
+    }
+    match &ty.kind {
+        TypeKind::Base(encoding) => format_base(*encoding, &bytes[..ty.size]),
+        TypeKind::Pointer(pointee) => {
+            let addr = read_uint(&bytes[..ty.size]) as usize;
+            match pointee {
+                Some(pointee) if is_char(pointee) && addr != 0 => match read_string(addr) {
+                    Some(string) => format!("{:#x} {}", addr, quote_string(&string)),
+                    None => format!("{:#x} <error reading string>", addr),
+                },
+                _ => format!("{:#x}", addr),
+            }
+        }
+        TypeKind::Array(element, length) => {
+            let array = &bytes[..element.size * length];
+            if is_char(element) {
+                // Show char arrays as the string they hold
+                let end = array.iter().position(|&b| b == 0).unwrap_or(array.len());
+                return quote_string(&array[..end]);
+            }
+            let elements: Vec<String> = (0..*length)
+                .map(|idx| {
+                    let start = idx * element.size;
+                    format_value(element, &array[start..start + element.size], read_string)
+                })
+                .collect();
+            format!("{{{}}}", elements.join(", "))
+        }
+        TypeKind::Struct(members) => {
+            let fields: Vec<String> = members
+                .iter()
+                .map(|member| {
+                    let start = member.offset;
+                    let end = start + member.member_type.size;
+                    let value = match bytes.get(start..end) {
+                        Some(member_bytes) => {
+                            format_value(&member.member_type, member_bytes, read_string)
+                        }
+                        None => "<incomplete value>".to_string(),
+                    };
+                    format!("{} = {}", member.name, value)
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        // The members of a struct behind a pointer aren't known here
+        TypeKind::StructRef(_) => "{...}".to_string(),
+    }
+}
+
+fn is_char(ty: &Type) -> bool {
+    match ty.kind {
+        TypeKind::Base(Encoding::SignedChar) | TypeKind::Base(Encoding::UnsignedChar) => {
+            ty.size == 1
+        }
+        _ => false,
+    }
+}
+
+/// Reads a little-endian unsigned integer of up to 8 bytes.
+pub fn read_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+/// Reads a little-endian integer of up to 8 bytes, sign-extending it from however many bytes it
+/// has.
+pub fn read_int(bytes: &[u8]) -> i64 {
+    let shift = 64 - 8 * bytes.len() as u32;
+    if shift == 64 {
+        0
+    } else {
+        ((read_uint(bytes) << shift) as i64) >> shift
+    }
+}
+
+/// Converts a value of type `ty` stored in `bytes` to an integer, for comparing it in a breakpoint
+/// condition. Returns None unless it's an integer, character, bool or pointer.
+pub fn to_integer(ty: &Type, bytes: &[u8]) -> Option<i64> {
+    if bytes.len() < ty.size || ty.size > 8 {
+        return None;
+    }
+    let bytes = &bytes[..ty.size];
+    match ty.kind {
+        TypeKind::Base(Encoding::Signed) | TypeKind::Base(Encoding::SignedChar) => {
+            Some(read_int(bytes))
+        }
+        TypeKind::Base(Encoding::Unsigned)
+        | TypeKind::Base(Encoding::UnsignedChar)
+        | TypeKind::Base(Encoding::Boolean)
+        | TypeKind::Pointer(_) => Some(read_uint(bytes) as i64),
+        _ => None,
+    }
+}
+
+fn format_base(encoding: Encoding, bytes: &[u8]) -> String {
+    if bytes.len() > 8 {
+        return format!("<{}-byte value>", bytes.len());
+    }
+    let unsigned = read_uint(bytes);
+    let signed = read_int(bytes);
+    match encoding {
+        Encoding::Signed => signed.to_string(),
+        Encoding::Unsigned => unsigned.to_string(),
+        Encoding::SignedChar => format!("{} {}", signed, quote_char(unsigned as u8)),
+        Encoding::UnsignedChar => format!("{} {}", unsigned, quote_char(unsigned as u8)),
+        Encoding::Boolean => (unsigned != 0).to_string(),
+        Encoding::Float => match bytes.len() {
+            4 => f32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            8 => f64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            len => format!("<{}-byte float>", len),
+        },
+    }
+}
+
+/// Escapes a byte the way C would write it inside quotes.
+fn escape_byte(byte: u8, quote: char) -> String {
+    match byte {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        b'\\' => "\\\\".to_string(),
+        byte if byte as char == quote => format!("\\{}", quote),
+        byte if byte.is_ascii_graphic() || byte == b' ' => (byte as char).to_string(),
+        byte => format!("\\{:03o}", byte),
+    }
+}
+
+pub fn quote_char(byte: u8) -> String {
+    format!("'{}'", escape_byte(byte, '\''))
+}
+
+fn quote_string(bytes: &[u8]) -> String {
+    let quoted: String = bytes.iter().map(|&byte| escape_byte(byte, '"')).collect();
+    if bytes.len() >= MAX_STRING_LEN {
+        format!("\"{}\"...", quoted)
+    } else {
+        format!("\"{}\"", quoted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::Member;
+
+    fn base(name: &str, size: usize, encoding: Encoding) -> Type {
+        Type::new(name.to_string(), size, TypeKind::Base(encoding))
+    }
+
+    fn no_memory(_: usize) -> Option<Vec<u8>> {
+        None
+    }
+
+    #[test]
+    fn test_format_base() {
+        let int = base("int", 4, Encoding::Signed);
+        assert_eq!(
+            format_value(&int, &(-42i32).to_le_bytes(), &no_memory),
+            "-42"
+        );
+        let unsigned = base("unsigned int", 4, Encoding::Unsigned);
+        assert_eq!(
+            format_value(&unsigned, &u32::MAX.to_le_bytes(), &no_memory),
+            "4294967295"
+        );
+        let long = base("long", 8, Encoding::Signed);
+        assert_eq!(
+            format_value(&long, &i64::MIN.to_le_bytes(), &no_memory),
+            i64::MIN.to_string()
+        );
+        let double = base("double", 8, Encoding::Float);
+        assert_eq!(
+            format_value(&double, &0.5f64.to_le_bytes(), &no_memory),
+            "0.5"
+        );
+        let c = base("char", 1, Encoding::SignedChar);
+        assert_eq!(format_value(&c, b"A", &no_memory), "65 'A'");
+        assert_eq!(format_value(&c, b"\n", &no_memory), "10 '\\n'");
+        let b = base("_Bool", 1, Encoding::Boolean);
+        assert_eq!(format_value(&b, &[1], &no_memory), "true");
+    }
+
+    #[test]
+    fn test_to_integer() {
+        let int = base("int", 4, Encoding::Signed);
+        assert_eq!(to_integer(&int, &(-42i32).to_le_bytes()), Some(-42));
+        let unsigned = base("unsigned int", 4, Encoding::Unsigned);
+        assert_eq!(
+            to_integer(&unsigned, &u32::MAX.to_le_bytes()),
+            Some(u32::MAX as i64)
+        );
+        let c = base("char", 1, Encoding::SignedChar);
+        assert_eq!(to_integer(&c, b"A"), Some(65));
+        let double = base("double", 8, Encoding::Float);
+        assert_eq!(to_integer(&double, &0.5f64.to_le_bytes()), None);
+        assert_eq!(to_integer(&int, &[1, 2]), None);
+    }
+
+    #[test]
+    fn test_format_pointers_and_arrays() {
+        let c = base("char", 1, Encoding::SignedChar);
+        let int = base("int", 4, Encoding::Signed);
+        let string = Type::new(
+            "char *".to_string(),
+            8,
+            TypeKind::Pointer(Some(Box::new(c.clone()))),
+        );
+        let memory = |addr: usize| {
+            if addr == 0x1000 {
+                Some(b"say \"hi\"".to_vec())
+            } else {
+                None
+            }
+        };
+        assert_eq!(
+            format_value(&string, &0x1000u64.to_le_bytes(), &memory),
+            "0x1000 \"say \\\"hi\\\"\""
+        );
+        assert_eq!(
+            format_value(&string, &0x2000u64.to_le_bytes(), &memory),
+            "0x2000 <error reading string>"
+        );
+        assert_eq!(format_value(&string, &[0; 8], &memory), "0x0");
+        let int_ptr = Type::new(
+            "int *".to_string(),
+            8,
+            TypeKind::Pointer(Some(Box::new(int.clone()))),
+        );
+        assert_eq!(
+            format_value(&int_ptr, &0x1000u64.to_le_bytes(), &memory),
+            "0x1000"
+        );
+
+        let squares = Type::new(
+            "int [4]".to_string(),
+            16,
+            TypeKind::Array(Box::new(int.clone()), 4),
+        );
+        let bytes: Vec<u8> = [0i32, 1, 4, 9]
+            .iter()
+            .flat_map(|n| n.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(format_value(&squares, &bytes, &no_memory), "{0, 1, 4, 9}");
+        let grid = Type::new(
+            "int [2][2]".to_string(),
+            16,
+            TypeKind::Array(
+                Box::new(Type::new(
+                    "int [2]".to_string(),
+                    8,
+                    TypeKind::Array(Box::new(int), 2),
+                )),
+                2,
+            ),
+        );
+        assert_eq!(format_value(&grid, &bytes, &no_memory), "{{0, 1}, {4, 9}}");
+
+        let name = Type::new("char [8]".to_string(), 8, TypeKind::Array(Box::new(c), 8));
+        assert_eq!(format_value(&name, b"deet\0\0\0\0", &no_memory), "\"deet\"");
+        assert_eq!(
+            format_value(&name, b"deet", &no_memory),
+            "<incomplete value>"
+        );
+    }
+
+    #[test]
+    fn test_format_structs() {
+        let int = base("int", 4, Encoding::Signed);
+        let member = |name: &str, offset, member_type: &Type| Member {
+            name: name.to_string(),
+            offset,
+            member_type: member_type.clone(),
+        };
+        let point = Type::new(
+            "struct point".to_string(),
+            8,
+            TypeKind::Struct(vec![member("x", 0, &int), member("y", 4, &int)]),
+        );
+        let bytes: Vec<u8> = [3i32, -4]
+            .iter()
+            .flat_map(|n| n.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(format_value(&point, &bytes, &no_memory), "{x = 3, y = -4}");
+
+        let next = Type::new(
+            "struct node *".to_string(),
+            8,
+            TypeKind::Pointer(Some(Box::new(Type::new(
+                "struct node".to_string(),
+                16,
+                TypeKind::StructRef(0x2d),
+            )))),
+        );
+        let node = Type::new(
+            "struct node".to_string(),
+            16,
+            TypeKind::Struct(vec![member("where", 0, &point), member("next", 8, &next)]),
+        );
+        let mut bytes = bytes;
+        bytes.extend_from_slice(&0x4010u64.to_le_bytes());
+        assert_eq!(
+            format_value(&node, &bytes, &no_memory),
+            "{where = {x = 3, y = -4}, next = 0x4010}"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The header carrying the ID that ties a request's log entries at balancebeam and the upstreams
+/// together.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Makes sure the request carries an X-Request-Id header, keeping the client's if it sent one and
+/// otherwise adding a new random ID. Returns the ID.
+pub fn ensure_request_id(request: &mut http::Request<Vec<u8>>) -> String {
+    if let Some(id) = request.headers().get(REQUEST_ID_HEADER) {
+        if let Ok(id) = id.to_str() {
+            if !id.is_empty() {
+                return id.to_string();
+            }
+        }
+    }
+    let id = generate_request_id();
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, http::HeaderValue::from_str(&id).unwrap());
+    id
+}
+
+/// Returns a random (version 4) UUID.
+pub fn generate_request_id() -> String {
+    let mut bits: u128 = rand::random();
+    bits = (bits & !(0xf << 76)) | (0x4 << 76); // version 4
+    bits = (bits & !(0x3 << 62)) | (0x2 << 62); // RFC 4122 variant
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Everything recorded about one request in the access log.
+pub struct AccessLogEntry<'a> {
+    pub request_id: &'a str,
+    pub client_ip: &'a str,
+    pub method: &'a str,
+    pub uri: String,
+    /// The upstream the request was sent to, or None if it never reached one (e.g. it was served
+    /// from the cache)
+    pub upstream: Option<&'a str>,
+    pub status: u16,
+    pub request_body_bytes: usize,
+    pub response_body_bytes: usize,
+    /// When the request was received, and how long it took to respond
+    pub start: SystemTime,
+    pub duration: Duration,
+}
+
+impl AccessLogEntry<'_> {
+    /// Formats the entry as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let timestamp_ms = self
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        format!(
+            "{{\"request_id\":{},\"timestamp_ms\":{},\"duration_ms\":{:.3},\"client\":{},\
+             \"method\":{},\"uri\":{},\"upstream\":{},\"status\":{},\"request_bytes\":{},\
+             \"response_bytes\":{}}}",
+            json_string(self.request_id),
+            timestamp_ms,
+            self.duration.as_secs_f64() * 1000.0,
+            json_string(self.client_ip),
+            json_string(self.method),
+            json_string(&self.uri),
+            self.upstream.map_or("null".to_string(), json_string),
+            self.status,
+            self.request_body_bytes,
+            self.response_body_bytes
+        )
+    }
+}
+
+/// Quotes a string for JSON, escaping the characters that need it.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate_request_id() {
+        let id = generate_request_id();
+        assert_eq!(id.len(), 36);
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('4'));
+        assert!("89ab".contains(&groups[3][..1]));
+        assert_ne!(id, generate_request_id());
+    }
+
+    #[test]
+    fn test_ensure_request_id() {
+        let mut request = http::Request::new(Vec::new());
+        let id = ensure_request_id(&mut request);
+        assert_eq!(request.headers()[REQUEST_ID_HEADER], id.as_str());
+        // A request that already has an ID keeps it
+        assert_eq!(ensure_request_id(&mut request), id);
+        assert_eq!(
+            request.headers().get_all(REQUEST_ID_HEADER).iter().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_access_log_json() {
+        let entry = AccessLogEntry {
+            request_id: "abc",
+            client_ip: "127.0.0.1",
+            method: "GET",
+            uri: "/say?q=\"hi\"\\".to_string(),
+            upstream: Some("127.0.0.1:8080"),
+            status: 200,
+            request_body_bytes: 0,
+            response_body_bytes: 5,
+            start: UNIX_EPOCH + Duration::from_millis(1500),
+            duration: Duration::from_micros(2500),
+        };
+        assert_eq!(
+            entry.to_json(),
+            "{\"request_id\":\"abc\",\"timestamp_ms\":1500,\"duration_ms\":2.500,\
+             \"client\":\"127.0.0.1\",\"method\":\"GET\",\"uri\":\"/say?q=\\\"hi\\\"\\\\\",\
+             \"upstream\":\"127.0.0.1:8080\",\"status\":200,\"request_bytes\":0,\
+             \"response_bytes\":5}"
+        );
+        let cached = AccessLogEntry {
+            upstream: None,
+            ..entry
+        };
+        assert!(cached.to_json().contains("\"upstream\":null"));
+        assert_eq!(json_string("a\u{1}\nb"), "\"a\\u0001\\nb\"");
+    }
+}
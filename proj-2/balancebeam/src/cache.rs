@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+struct CacheEntry {
+    response: http::Response<Vec<u8>>,
+    /// When this entry goes stale, or None if the upstream didn't give it a max-age
+    expires_at: Option<Instant>,
+    /// Value of the cache's clock the last time this entry was stored or served, used to pick the
+    /// least recently used entry for eviction
+    last_used: u64,
+}
+
+/// An in-memory cache of upstream responses to GET requests, keyed by request URI. Holds at most
+/// `max_entries` responses, evicting the least recently used one when full.
+pub struct ResponseCache {
+    max_entries: usize,
+    entries: HashMap<String, CacheEntry>,
+    clock: u64,
+}
+
+/// Returns the value of the given Cache-Control directive if it is present (an empty string for
+/// directives without a value, like no-store), or None if it is absent.
+fn cache_control_directive<'a>(headers: &'a http::HeaderMap, directive: &str) -> Option<&'a str> {
+    headers
+        .get_all("cache-control")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|item| {
+            let mut parts = item.trim().splitn(2, '=');
+            if parts.next()?.trim().eq_ignore_ascii_case(directive) {
+                Some(parts.next().unwrap_or("").trim().trim_matches('"'))
+            } else {
+                None
+            }
+        })
+}
+
+/// Decides whether a response to the given request may be stored. Returns Some(lifetime) if so,
+/// where a lifetime of None means the response stays fresh until it is evicted, or None if it
+/// must not be cached.
+///
+/// This cache is shared by every client and keyed only by URI, so it follows the rules for shared
+/// caches in RFC 9111 §3.5: a response to a request with credentials is only stored if the
+/// upstream says it's public, and nothing that differs between clients is stored at all.
+fn cacheable_lifetime(
+    request: &http::Request<Vec<u8>>,
+    response: &http::Response<Vec<u8>>,
+) -> Option<Option<Duration>> {
+    if response.status() != http::StatusCode::OK {
+        return None;
+    }
+    let headers = response.headers();
+    if cache_control_directive(headers, "no-store").is_some()
+        || cache_control_directive(headers, "no-cache").is_some()
+        || cache_control_directive(headers, "private").is_some()
+    {
+        return None;
+    }
+    // A session cookie or a response chosen by request headers we don't key on would be handed
+    // to the wrong clients
+    if headers.contains_key("set-cookie") || headers.contains_key("vary") {
+        return None;
+    }
+    if request.headers().contains_key("authorization")
+        && cache_control_directive(headers, "public").is_none()
+        && cache_control_directive(headers, "s-maxage").is_none()
+    {
+        return None;
+    }
+    // s-maxage is meant for shared caches like this one, and overrides max-age
+    let max_age = cache_control_directive(headers, "s-maxage")
+        .or_else(|| cache_control_directive(headers, "max-age"));
+    match max_age {
+        // An unparseable max-age must be treated as stale
+        Some(max_age) => match max_age.parse::<u64>() {
+            Ok(0) | Err(_) => None,
+            Ok(seconds) => Some(Some(Duration::from_secs(seconds))),
+        },
+        None => Some(None),
+    }
+}
+
+impl ResponseCache {
+    pub fn new(max_entries: usize) -> ResponseCache {
+        ResponseCache {
+            max_entries,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns true if the cache might be able to answer this request.
+    pub fn handles(request: &http::Request<Vec<u8>>) -> bool {
+        request.method() == http::Method::GET
+            && cache_control_directive(request.headers(), "no-store").is_none()
+            && cache_control_directive(request.headers(), "no-cache").is_none()
+    }
+
+    /// Looks up a fresh response for the given request. The returned response is a copy of the
+    /// stored one, marked with an `X-Cache: HIT` header.
+    pub fn get(&mut self, request: &http::Request<Vec<u8>>) -> Option<http::Response<Vec<u8>>> {
+        if !ResponseCache::handles(request) {
+            return None;
+        }
+        let key = request.uri().to_string();
+        let entry = self.entries.get_mut(&key)?;
+        if entry
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+        {
+            self.entries.remove(&key);
+            return None;
+        }
+        self.clock += 1;
+        entry.last_used = self.clock;
+        let mut response = entry.response.clone();
+        response
+            .headers_mut()
+            .insert("x-cache", http::HeaderValue::from_static("HIT"));
+        Some(response)
+    }
+
+    /// Stores the upstream's response to the given request, if both of them allow it.
+    pub fn insert(&mut self, request: &http::Request<Vec<u8>>, response: &http::Response<Vec<u8>>) {
+        if self.max_entries == 0 || !ResponseCache::handles(request) {
+            return;
+        }
+        let key = request.uri().to_string();
+        let lifetime = match cacheable_lifetime(request, response) {
+            Some(lifetime) => lifetime,
+            None => {
+                // The resource told us not to keep it, so don't keep serving an older copy either
+                self.entries.remove(&key);
+                return;
+            }
+        };
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.evict_least_recently_used();
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                response: response.clone(),
+                expires_at: lifetime.map(|lifetime| Instant::now() + lifetime),
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest_key = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest_key {
+            log::debug!("Evicting {} from the response cache", key);
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_request(authorization: Option<&str>) -> http::Request<Vec<u8>> {
+        let mut request = http::Request::builder().uri("/account");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.body(Vec::new()).unwrap()
+    }
+
+    fn ok_response(headers: &[(&str, &str)]) -> http::Response<Vec<u8>> {
+        let mut response = http::Response::builder();
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        response.body(b"hello".to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_authorized_responses_need_public() {
+        let mut cache = ResponseCache::new(10);
+        let request = get_request(Some("Bearer secret"));
+        cache.insert(&request, &ok_response(&[("cache-control", "max-age=60")]));
+        assert!(cache.get(&request).is_none());
+        // Nor is it served to a client without credentials
+        assert!(cache.get(&get_request(None)).is_none());
+
+        cache.insert(
+            &request,
+            &ok_response(&[("cache-control", "public, max-age=60")]),
+        );
+        assert!(cache.get(&get_request(None)).is_some());
+        let mut cache = ResponseCache::new(10);
+        cache.insert(&request, &ok_response(&[("cache-control", "s-maxage=60")]));
+        assert!(cache.get(&get_request(None)).is_some());
+    }
+
+    #[test]
+    fn test_per_client_responses_not_stored() {
+        let mut cache = ResponseCache::new(10);
+        let request = get_request(None);
+        cache.insert(&request, &ok_response(&[("set-cookie", "session=abc")]));
+        assert!(cache.get(&request).is_none());
+        cache.insert(&request, &ok_response(&[("vary", "accept-language")]));
+        assert!(cache.get(&request).is_none());
+        cache.insert(&request, &ok_response(&[]));
+        assert!(cache.get(&request).is_some());
+    }
+
+    #[test]
+    fn test_s_maxage_overrides_max_age() {
+        let request = get_request(None);
+        assert_eq!(
+            cacheable_lifetime(
+                &request,
+                &ok_response(&[("cache-control", "max-age=0, s-maxage=30")])
+            ),
+            Some(Some(Duration::from_secs(30)))
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Requests flow normally. Contains the number of failures in a row so far.
+    Closed(usize),
+    /// The upstream failed too many times in a row and is skipped until the cool-down ends.
+    Open { until: Instant },
+    /// The cool-down has ended and a single probe request was let through to find out whether
+    /// the upstream has recovered. If the probe never reports back (e.g. the client hung up before
+    /// sending a request), another one is allowed after a further cool-down.
+    HalfOpen { probe_started: Instant },
+}
+
+/// Tracks the health of a single upstream. After `failure_threshold` consecutive failures, the
+/// breaker trips open and the upstream is skipped for `cooldown`; then one probe request decides
+/// whether it closes again or re-opens for another cool-down.
+pub struct CircuitBreaker {
+    upstream: String,
+    state: State,
+    failure_threshold: usize,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed breaker for the given upstream address. A `failure_threshold` of 0 means
+    /// the breaker never trips.
+    pub fn new(upstream: String, failure_threshold: usize, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            upstream,
+            state: State::Closed(0),
+            failure_threshold,
+            cooldown,
+        }
+    }
+
+    /// Returns true if a request may be sent to this upstream right now.
+    pub fn is_available(&self, now: Instant) -> bool {
+        match self.state {
+            State::Closed(_) => true,
+            State::Open { until } => now >= until,
+            State::HalfOpen { probe_started } => now >= probe_started + self.cooldown,
+        }
+    }
+
+    /// Called when a request is about to be sent to this upstream. If the cool-down has ended,
+    /// this request becomes the half-open probe, and true is returned. The result must be passed
+    /// back to `record_success` or `record_failure` once the request is done.
+    pub fn begin_request(&mut self, now: Instant) -> bool {
+        if let State::Open { .. } | State::HalfOpen { .. } = self.state {
+            if self.is_available(now) {
+                log::info!(
+                    "Circuit for upstream {} is half-open, sending a probe request",
+                    self.upstream
+                );
+                self.state = State::HalfOpen { probe_started: now };
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Records that a request succeeded. Only the half-open probe can close an open circuit;
+    /// requests that were already in flight when the circuit opened don't count.
+    pub fn record_success(&mut self, probe: bool) {
+        self.state = match self.state {
+            State::Closed(_) => State::Closed(0),
+            State::HalfOpen { .. } if probe => {
+                log::info!(
+                    "Circuit for upstream {} closed: probe request succeeded",
+                    self.upstream
+                );
+                State::Closed(0)
+            }
+            state => state,
+        };
+    }
+
+    /// Records that a request failed, opening the circuit once there have been too many failures
+    /// in a row or the half-open probe failed.
+    pub fn record_failure(&mut self, now: Instant, probe: bool) {
+        self.state = match self.state {
+            State::Closed(failures)
+                if self.failure_threshold > 0 && failures + 1 >= self.failure_threshold =>
+            {
+                log::warn!(
+                    "Circuit for upstream {} opened after {} consecutive failures",
+                    self.upstream,
+                    failures + 1
+                );
+                State::Open {
+                    until: now + self.cooldown,
+                }
+            }
+            State::Closed(failures) => State::Closed(failures + 1),
+            State::HalfOpen { .. } if probe => {
+                log::warn!(
+                    "Circuit for upstream {} re-opened: probe request failed",
+                    self.upstream
+                );
+                State::Open {
+                    until: now + self.cooldown,
+                }
+            }
+            // A request that was already in flight when the circuit opened
+            state => state,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_trips_and_recovers() {
+        let cooldown = Duration::from_secs(10);
+        let mut breaker = CircuitBreaker::new("127.0.0.1:8001".to_string(), 2, cooldown);
+        let start = Instant::now();
+        assert!(!breaker.begin_request(start));
+        breaker.record_failure(start, false);
+        assert!(breaker.is_available(start));
+        breaker.record_failure(start, false);
+        assert!(!breaker.is_available(start));
+
+        // Once the cool-down ends, a failed probe re-opens the circuit and a successful one
+        // closes it
+        let later = start + cooldown;
+        assert!(breaker.begin_request(later));
+        breaker.record_failure(later, true);
+        assert!(!breaker.is_available(later));
+        let much_later = later + cooldown;
+        assert!(breaker.begin_request(much_later));
+        breaker.record_success(true);
+        assert!(breaker.is_available(much_later));
+    }
+
+    #[test]
+    fn test_in_flight_requests_dont_close_circuit() {
+        let cooldown = Duration::from_secs(10);
+        let mut breaker = CircuitBreaker::new("127.0.0.1:8001".to_string(), 1, cooldown);
+        let start = Instant::now();
+        // Sent before the circuit opened
+        assert!(!breaker.begin_request(start));
+        breaker.record_failure(start, false);
+        breaker.record_success(false);
+        assert!(!breaker.is_available(start));
+
+        let later = start + cooldown;
+        assert!(breaker.begin_request(later));
+        breaker.record_success(false);
+        breaker.record_failure(later, false);
+        // Still waiting on the probe
+        assert!(!breaker.is_available(later));
+        breaker.record_success(true);
+        assert!(breaker.is_available(later));
+    }
+}
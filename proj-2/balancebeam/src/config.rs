@@ -0,0 +1,179 @@
+use crate::upstream_config;
+use std::fs;
+use std::time::Duration;
+
+/// The settings that can be changed while balancebeam is running, by editing the file given with
+/// --config and sending balancebeam SIGHUP. They start out with the values from the command line,
+/// and anything set in the file overrides those.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub upstreams: Vec<String>,
+    pub upstream_weights: Vec<usize>,
+    pub max_upstreams: usize,
+    /// In seconds, 0 = no limit
+    pub upstream_response_timeout: u64,
+    pub circuit_breaker_threshold: usize,
+    /// In seconds
+    pub circuit_breaker_cooldown: u64,
+    /// Requests each upstream may be handling at once, 0 = no limit
+    pub max_upstream_requests: usize,
+    /// How long a request waits for an upstream to have room for it, in seconds
+    pub upstream_queue_timeout: u64,
+}
+
+impl Settings {
+    /// Reads the config file at `path` and returns these settings with the ones in the file
+    /// applied on top, as long as the result is valid.
+    pub fn load(&self, path: &str) -> Result<Settings, String> {
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("Can't read {}: {}", path, err))?;
+        let settings = self
+            .with_file(&text)
+            .map_err(|err| format!("{}: {}", path, err))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Applies a config file to these settings. Each non-blank line that isn't a `#` comment is a
+    /// `name = value` pair, where the names are those of the command-line options. `upstream` and
+    /// `upstream-weight` may be repeated; if the file has any, they replace the ones given on the
+    /// command line rather than adding to them. Weights always describe the upstream list they
+    /// came with, so a file that replaces the upstreams also drops the old weights.
+    pub fn with_file(&self, text: &str) -> Result<Settings, String> {
+        let mut settings = self.clone();
+        let mut upstreams = Vec::new();
+        let mut upstream_weights = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .ok_or(format!("line {}: expected \"name = value\"", idx + 1))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("line {}: {} must be a number", idx + 1, name))
+            };
+            match name {
+                "upstream" => upstreams.push(value.to_string()),
+                "upstream-weight" => upstream_weights.push(number()? as usize),
+                "max-upstreams" => settings.max_upstreams = number()? as usize,
+                "upstream-response-timeout" => settings.upstream_response_timeout = number()?,
+                "circuit-breaker-threshold" => {
+                    settings.circuit_breaker_threshold = number()? as usize
+                }
+                "circuit-breaker-cooldown" => settings.circuit_breaker_cooldown = number()?,
+                "max-upstream-requests" => settings.max_upstream_requests = number()? as usize,
+                "upstream-queue-timeout" => settings.upstream_queue_timeout = number()?,
+                _ => return Err(format!("line {}: unknown setting {:?}", idx + 1, name)),
+            }
+        }
+        if !upstreams.is_empty() {
+            settings.upstreams = upstreams;
+            settings.upstream_weights = upstream_weights;
+        } else if !upstream_weights.is_empty() {
+            settings.upstream_weights = upstream_weights;
+        }
+        Ok(settings)
+    }
+
+    /// Checks the settings the same way whether they come from the command line or a config file.
+    pub fn validate(&self) -> Result<(), String> {
+        upstream_config::validate_upstreams(&self.upstreams, self.max_upstreams)
+            .map_err(|err| err.to_string())?;
+        if self.upstream_weights.len() > self.upstreams.len() {
+            return Err("More upstream weights were given than upstreams".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the weight of each upstream; upstreams without one get a weight of 1.
+    pub fn weights(&self) -> Vec<usize> {
+        (0..self.upstreams.len())
+            .map(|idx| self.upstream_weights.get(idx).copied().unwrap_or(1))
+            .collect()
+    }
+
+    pub fn upstream_response_timeout(&self) -> Option<Duration> {
+        if self.upstream_response_timeout > 0 {
+            Some(Duration::from_secs(self.upstream_response_timeout))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            upstreams: vec!["127.0.0.1:8001".to_string()],
+            upstream_weights: Vec::new(),
+            max_upstreams: 0,
+            upstream_response_timeout: 0,
+            circuit_breaker_threshold: 0,
+            circuit_breaker_cooldown: 10,
+            max_upstream_requests: 0,
+            upstream_queue_timeout: 1,
+        }
+    }
+
+    #[test]
+    fn test_with_file() {
+        let text = "# Moved to the new servers\n\
+                    upstream = 127.0.0.1:8002\n\
+                    upstream = 127.0.0.1:8003\n\
+                    \n\
+                    upstream-weight = 3\n\
+                    upstream-response-timeout = 5\n\
+                    max-upstream-requests = 4\n";
+        let updated = settings().with_file(text).unwrap();
+        assert_eq!(updated.upstreams, vec!["127.0.0.1:8002", "127.0.0.1:8003"]);
+        assert_eq!(updated.weights(), vec![3, 1]);
+        assert_eq!(
+            updated.upstream_response_timeout(),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(updated.max_upstream_requests, 4);
+        // Settings missing from the file keep their old values
+        assert_eq!(updated.circuit_breaker_cooldown, 10);
+        assert_eq!(settings().with_file("").unwrap(), settings());
+    }
+
+    #[test]
+    fn test_with_file_replaces_weights_with_upstreams() {
+        let mut weighted = settings();
+        weighted.upstream_weights = vec![5];
+        let text = "upstream = 127.0.0.1:8002\n\
+                    upstream = 127.0.0.1:8003\n";
+        let updated = weighted.with_file(text).unwrap();
+        assert_eq!(updated.weights(), vec![1, 1]);
+        assert_eq!(
+            weighted.with_file("upstream-weight = 2").unwrap().weights(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_with_file_errors() {
+        assert!(settings().with_file("upstream 127.0.0.1:8002").is_err());
+        assert!(settings().with_file("upstream-weight = heavy").is_err());
+        assert!(settings().with_file("upstreams = 127.0.0.1:8002").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(settings().validate(), Ok(()));
+        let mut invalid = settings();
+        invalid.upstream_weights = vec![1, 2];
+        assert!(invalid.validate().is_err());
+        invalid.upstreams.clear();
+        invalid.upstream_weights.clear();
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::upstream_conn::{self, TlsSettings, UpstreamConn};
+use crate::{request, response};
+use std::time::{Duration, Instant};
+
+/// How long an upstream gets to send its whole response to a health check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What an upstream's response to a health check request must look like for it to count as
+/// healthy.
+#[derive(Debug, Clone)]
+pub struct HealthCriteria {
+    /// The status the response must have, or None to accept any 2xx status
+    pub status: Option<http::StatusCode>,
+    /// Text the response body must contain, if any
+    pub body_contains: Option<String>,
+}
+
+impl HealthCriteria {
+    /// Builds the criteria from the command-line options. A status of 0 means any 2xx status.
+    pub fn parse(status: u16, body_contains: Option<String>) -> Result<HealthCriteria, String> {
+        let status = match status {
+            0 => None,
+            code => Some(
+                http::StatusCode::from_u16(code)
+                    .map_err(|_| format!("{} is not a valid HTTP status code", code))?,
+            ),
+        };
+        Ok(HealthCriteria {
+            status,
+            body_contains,
+        })
+    }
+
+    /// Returns true if the response shows a healthy upstream.
+    pub fn accepts(&self, response: &http::Response<Vec<u8>>) -> bool {
+        let status_ok = match self.status {
+            Some(status) => response.status() == status,
+            None => response.status().is_success(),
+        };
+        let body_ok = match &self.body_contains {
+            Some(text) => String::from_utf8_lossy(response.body()).contains(text.as_str()),
+            None => true,
+        };
+        status_ok && body_ok
+    }
+}
+
+/// Sends a GET request for `path` to the upstream and returns whether it answered in a way the
+/// criteria accept. Failing to connect or to get a complete response in time counts as unhealthy.
+/// TLS upstreams are checked over TLS, set up with `tls`.
+pub fn check_upstream(
+    address: &str,
+    path: &str,
+    criteria: &HealthCriteria,
+    tls: Option<&TlsSettings>,
+) -> bool {
+    let request = match http::Request::builder()
+        .method(http::Method::GET)
+        .uri(path)
+        .header("Host", upstream_conn::host_port(address))
+        .header("Connection", "close")
+        .body(Vec::new())
+    {
+        Ok(request) => request,
+        Err(err) => {
+            log::error!("Invalid active health check path {:?}: {}", path, err);
+            return false;
+        }
+    };
+    let deadline = Instant::now() + CHECK_TIMEOUT;
+    let mut conn = match UpstreamConn::connect(address, tls) {
+        Ok(conn) => conn,
+        Err(err) => {
+            log::warn!("Health check: could not connect to {}: {}", address, err);
+            return false;
+        }
+    };
+    if let Err(err) = request::write_to_stream(&request, &mut conn) {
+        log::warn!(
+            "Health check: failed to send request to {}: {}",
+            address,
+            err
+        );
+        return false;
+    }
+    match response::read_from_stream(&mut conn, request.method(), Some(deadline)) {
+        Ok(response) => {
+            let healthy = criteria.accepts(&response);
+            if !healthy {
+                log::warn!(
+                    "Health check: upstream {} answered {} {}",
+                    address,
+                    path,
+                    response::format_response_line(&response)
+                );
+            }
+            healthy
+        }
+        Err(err) => {
+            log::warn!("Health check: bad response from {}: {:?}", address, err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(status: u16, body: &str) -> http::Response<Vec<u8>> {
+        http::Response::builder()
+            .status(status)
+            .body(body.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_health_criteria() {
+        let any_success = HealthCriteria::parse(0, None).unwrap();
+        assert!(any_success.accepts(&response(200, "")));
+        assert!(any_success.accepts(&response(204, "")));
+        assert!(!any_success.accepts(&response(301, "")));
+        assert!(!any_success.accepts(&response(500, "")));
+        assert!(!any_success.accepts(&response(503, "")));
+
+        let exact = HealthCriteria::parse(204, None).unwrap();
+        assert!(exact.accepts(&response(204, "")));
+        assert!(!exact.accepts(&response(200, "")));
+
+        let with_body = HealthCriteria::parse(0, Some("\"status\":\"up\"".to_string())).unwrap();
+        assert!(with_body.accepts(&response(200, "{\"status\":\"up\"}")));
+        assert!(!with_body.accepts(&response(200, "{\"status\":\"down\"}")));
+        assert!(!with_body.accepts(&response(500, "{\"status\":\"up\"}")));
+
+        assert!(HealthCriteria::parse(1000, None).is_err());
+    }
+}
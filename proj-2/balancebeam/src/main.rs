@@ -1,30 +1,120 @@
+mod access_log;
+mod cache;
+mod circuit_breaker;
+mod config;
+mod health;
 mod request;
 mod response;
+mod selector;
+mod upstream_config;
+mod upstream_conn;
 
+use cache::ResponseCache;
+use circuit_breaker::CircuitBreaker;
 use clap::Parser;
-use rand::{Rng, SeedableRng};
-use std::net::{TcpListener, TcpStream};
+use config::Settings;
+use health::HealthCriteria;
+use parking_lot::{Condvar, Mutex, RwLock};
+use selector::UpstreamSelector;
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use upstream_conn::{TlsSettings, UpstreamConn};
 
 /// Contains information parsed from the command-line invocation of balancebeam. The Clap macros
 /// provide a fancy way to automatically construct a command-line argument parser.
 #[derive(Parser, Debug)]
 #[command(about = "Fun with load balancing")]
 struct CmdOptions {
-    /// "IP/port to bind to"
+    /// "IP/port to bind to (may be repeated to listen on several addresses)"
     #[arg(short, long, default_value = "0.0.0.0:1100")]
-    bind: String,
-    /// "Upstream host to forward requests to"
+    bind: Vec<String>,
+    /// "Maximum number of connections waiting to be accepted on each listening socket"
+    #[arg(long, default_value = "128")]
+    listen_backlog: i32,
+    /// "Handle at most this many client connections at once; further connections wait to be
+    /// accepted until one closes"
+    #[arg(long, default_value = "1024", value_parser = clap::value_parser!(u64).range(1..))]
+    max_connections: u64,
+    /// "Close a client connection once it has sent nothing for this long (in seconds, 0 = never)"
+    #[arg(long, default_value = "60")]
+    client_idle_timeout: u64,
+    /// "Upstream host to forward requests to (written as https://host:port to connect over TLS)"
     #[arg(short, long)]
     upstream: Vec<String>,
-    /// "Perform active health checks on this interval (in seconds)"
+    /// "PEM file with CA certificates to trust for TLS upstreams (may be repeated)"
+    #[arg(long)]
+    upstream_ca: Vec<String>,
+    /// "Name to send as SNI to TLS upstreams and to check their certificates against (defaults to
+    /// the host in each upstream's address)"
+    #[arg(long)]
+    upstream_sni: Option<String>,
+    /// "Refuse to start with more than this many upstreams (0 = no limit)"
+    #[arg(long, default_value = "0")]
+    max_upstreams: usize,
+    /// "How to pick the upstream for each request: random, round-robin, least-connections or
+    /// weighted"
+    #[arg(long, default_value = "round-robin")]
+    balancing_strategy: String,
+    /// "Weight of each upstream for the weighted strategy, in the same order as --upstream
+    /// (may be repeated; upstreams without one get a weight of 1)"
+    #[arg(long)]
+    upstream_weight: Vec<usize>,
+    /// "Perform active health checks on this interval (in seconds, 0 = no active health checks)"
     #[arg(long, default_value = "10")]
     active_health_check_interval: usize,
     /// "Path to send request to for active health checks"
     #[arg(long, default_value = "/")]
     active_health_check_path: String,
+    /// "Status an upstream must answer health checks with to count as healthy (0 = any 2xx)"
+    #[arg(long, default_value = "0")]
+    active_health_check_status: u16,
+    /// "Text an upstream's answer to health checks must contain to count as healthy"
+    #[arg(long)]
+    active_health_check_body: Option<String>,
     /// "Maximum number of requests to accept per IP per minute (0 = unlimited)"
     #[arg(long, default_value = "0")]
     max_requests_per_minute: usize,
+    /// "Cache up to this many responses to GET requests (0 = caching disabled)"
+    #[arg(long, default_value = "0")]
+    cache_max_entries: usize,
+    /// "Skip an upstream after this many consecutive failed requests (0 = never)"
+    #[arg(long, default_value = "0")]
+    circuit_breaker_threshold: usize,
+    /// "How long to skip a failing upstream before probing it again (in seconds)"
+    #[arg(long, default_value = "10")]
+    circuit_breaker_cooldown: u64,
+    /// "Give up on an upstream whose complete response (headers and body) takes longer than this
+    /// to arrive, and reply 504 instead (in seconds, 0 = no limit)"
+    #[arg(long, default_value = "0")]
+    upstream_response_timeout: u64,
+    /// "Send each upstream at most this many requests at once (0 = no limit)"
+    #[arg(long, default_value = "0")]
+    max_upstream_requests: usize,
+    /// "How long a request waits for an upstream to have room for it when every upstream already
+    /// has --max-upstream-requests requests in flight, before replying 503 (in seconds)"
+    #[arg(long, default_value = "1")]
+    upstream_queue_timeout: u64,
+    /// "Remove this header from upstream responses (may be repeated)"
+    #[arg(long)]
+    remove_response_header: Vec<String>,
+    /// "Set a header on upstream responses, formatted as \"Name: value\" (may be repeated)"
+    #[arg(long)]
+    add_response_header: Vec<String>,
+    /// "Print an access log entry for each request to stdout, as a JSON object"
+    #[arg(long)]
+    json_access_log: bool,
+    /// "File to send clients as the body of 502, 503 and 504 responses (served as HTML if it ends in
+    /// .html or .htm, otherwise as plain text)"
+    #[arg(long)]
+    error_page: Option<String>,
+    /// "File with settings that override the options above; it is read again when balancebeam
+    /// receives SIGHUP"
+    #[arg(long)]
+    config: Option<String>,
 }
 
 /// Contains information about the state of balancebeam (e.g. what servers we are currently proxying
@@ -33,16 +123,166 @@ struct CmdOptions {
 /// You should add fields to this struct in later milestones.
 struct ProxyState {
     /// How frequently we check whether upstream servers are alive (Milestone 4)
-    #[allow(dead_code)]
     active_health_check_interval: usize,
     /// Where we should send requests when doing active health checks (Milestone 4)
-    #[allow(dead_code)]
     active_health_check_path: String,
+    /// What an upstream's answer to a health check must look like for it to count as healthy
+    health_criteria: HealthCriteria,
     /// Maximum number of requests an individual IP can make in a minute (Milestone 5)
     #[allow(dead_code)]
     max_requests_per_minute: usize,
+    /// The servers we are proxying to. Swapped out as a whole when the config file is reloaded
+    upstreams: RwLock<Arc<UpstreamPool>>,
+    /// Picks the upstream each request is sent to
+    selector: Box<dyn UpstreamSelector>,
+    /// Responses to GET requests that can be served without contacting an upstream, or None if
+    /// caching is disabled
+    response_cache: Option<Mutex<ResponseCache>>,
+    /// Header changes applied to every upstream response
+    response_header_rewrites: response::HeaderRewrites,
+    /// Whether to print a JSON access log entry for each request
+    json_access_log: bool,
+    /// Page to send when upstreams can't be reached, or None to send make_http_error's body
+    error_page: Option<response::ErrorPage>,
+    /// How to connect to TLS upstreams, or None if no CA certificates were given for them
+    upstream_tls: Option<TlsSettings>,
+    /// How long a client connection may go without sending anything, or None to wait forever
+    client_idle_timeout: Option<Duration>,
+}
+
+impl ProxyState {
+    /// Returns the current set of upstreams. A request should stick to the set it started with,
+    /// even if the config is reloaded while it is underway.
+    fn upstreams(&self) -> Arc<UpstreamPool> {
+        self.upstreams.read().clone()
+    }
+
+    /// Makes an error response to send the client, using the custom error page if there is one
+    /// for this status.
+    fn make_http_error(&self, status: http::StatusCode) -> http::Response<Vec<u8>> {
+        match &self.error_page {
+            Some(page) if response::ErrorPage::covers(status) => page.make_response(status),
+            _ => response::make_http_error(status),
+        }
+    }
+}
+
+/// The servers we are proxying to, along with the settings and state that go with each of them.
+/// All the Vecs are in the same order as `addresses`.
+struct UpstreamPool {
     /// Addresses of servers that we are proxying to
-    upstream_addresses: Vec<String>,
+    addresses: Vec<String>,
+    /// Weight of each upstream
+    weights: Vec<usize>,
+    /// Whether each upstream passed its last active health check
+    healthy: Vec<AtomicBool>,
+    /// Number of requests each upstream is handling right now. Only goes up while
+    /// `circuit_breakers` is locked, so that the cap on requests in flight can't be overshot
+    active_requests: Vec<AtomicUsize>,
+    /// One circuit breaker per upstream
+    circuit_breakers: Mutex<Vec<CircuitBreaker>>,
+    /// Signalled (with `circuit_breakers` locked) whenever an upstream finishes a request, for
+    /// requests waiting for an upstream to have room
+    request_finished: Condvar,
+    /// How long to wait for an upstream's complete response, or None to wait forever
+    response_timeout: Option<Duration>,
+    /// Requests each upstream may be handling at once, or None for no limit
+    max_requests: Option<usize>,
+    /// How long a request waits for an upstream to have room for it
+    queue_timeout: Duration,
+}
+
+impl UpstreamPool {
+    /// Creates a pool for the given (already validated) settings, where every upstream starts out
+    /// healthy, idle and with a closed circuit.
+    fn new(settings: &Settings) -> UpstreamPool {
+        UpstreamPool {
+            addresses: settings.upstreams.clone(),
+            weights: settings.weights(),
+            healthy: settings
+                .upstreams
+                .iter()
+                .map(|_| AtomicBool::new(true))
+                .collect(),
+            active_requests: settings
+                .upstreams
+                .iter()
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            circuit_breakers: Mutex::new(
+                settings
+                    .upstreams
+                    .iter()
+                    .map(|upstream| {
+                        CircuitBreaker::new(
+                            upstream.clone(),
+                            settings.circuit_breaker_threshold,
+                            Duration::from_secs(settings.circuit_breaker_cooldown),
+                        )
+                    })
+                    .collect(),
+            ),
+            request_finished: Condvar::new(),
+            response_timeout: settings.upstream_response_timeout(),
+            max_requests: Some(settings.max_upstream_requests).filter(|max| *max > 0),
+            queue_timeout: Duration::from_secs(settings.upstream_queue_timeout),
+        }
+    }
+
+    /// Returns true if the upstream has as many requests in flight as it is allowed.
+    fn is_full(&self, upstream_idx: usize) -> bool {
+        self.max_requests
+            .is_some_and(|max| self.active_requests[upstream_idx].load(Ordering::SeqCst) >= max)
+    }
+
+    /// Records that the upstream is done with a request, waking up any requests waiting for room.
+    fn finish_request(&self, upstream_idx: usize) {
+        let _circuit_breakers = self.circuit_breakers.lock();
+        self.active_requests[upstream_idx].fetch_sub(1, Ordering::SeqCst);
+        self.request_finished.notify_all();
+    }
+}
+
+/// Caps how many client connections are handled at once, each on its own thread.
+struct ConnectionSlots {
+    /// Number of connections being handled right now
+    open: Mutex<usize>,
+    /// Signalled whenever a connection closes, for listeners waiting for room to accept another
+    slot_freed: Condvar,
+    max: usize,
+}
+
+impl ConnectionSlots {
+    fn new(max: usize) -> ConnectionSlots {
+        ConnectionSlots {
+            open: Mutex::new(0),
+            slot_freed: Condvar::new(),
+            max,
+        }
+    }
+
+    /// Waits until fewer than `max` connections are open, then claims a slot for another one. The
+    /// slot is given back when the returned guard is dropped.
+    fn acquire(&self) -> ConnectionSlot<'_> {
+        let mut open = self.open.lock();
+        while *open >= self.max {
+            self.slot_freed.wait(&mut open);
+        }
+        *open += 1;
+        ConnectionSlot { slots: self }
+    }
+}
+
+/// A claim on one of the `ConnectionSlots`, held for as long as the connection is open.
+struct ConnectionSlot<'a> {
+    slots: &'a ConnectionSlots,
+}
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        *self.slots.open.lock() -= 1;
+        self.slots.slot_freed.notify_one();
+    }
 }
 
 fn main() {
@@ -56,45 +296,376 @@ fn main() {
 
     // Parse the command line arguments passed to this program
     let options = CmdOptions::parse();
-    if options.upstream.len() < 1 {
-        log::error!("At least one upstream server must be specified using the --upstream option.");
+    let command_line_settings = Settings {
+        upstreams: options.upstream,
+        upstream_weights: options.upstream_weight,
+        max_upstreams: options.max_upstreams,
+        upstream_response_timeout: options.upstream_response_timeout,
+        circuit_breaker_threshold: options.circuit_breaker_threshold,
+        circuit_breaker_cooldown: options.circuit_breaker_cooldown,
+        max_upstream_requests: options.max_upstream_requests,
+        upstream_queue_timeout: options.upstream_queue_timeout,
+    };
+    let settings = match &options.config {
+        Some(path) => command_line_settings.load(path),
+        None => command_line_settings
+            .validate()
+            .map(|()| command_line_settings.clone()),
+    };
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(err) => {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let response_header_rewrites = match response::HeaderRewrites::parse(
+        &options.remove_response_header,
+        &options.add_response_header,
+    ) {
+        Ok(rewrites) => rewrites,
+        Err(err) => {
+            log::error!("Invalid response header option: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let error_page = match &options.error_page {
+        Some(path) => match response::ErrorPage::load(path) {
+            Ok(page) => Some(page),
+            Err(err) => {
+                log::error!("Could not read error page {}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let upstream_tls = if options.upstream_ca.is_empty() {
+        None
+    } else {
+        match TlsSettings::new(&options.upstream_ca, options.upstream_sni) {
+            Ok(tls) => Some(tls),
+            Err(err) => {
+                log::error!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    };
+    if upstream_tls.is_none()
+        && settings
+            .upstreams
+            .iter()
+            .any(|address| upstream_conn::is_tls(address))
+    {
+        log::error!("TLS upstreams need CA certificates to trust, given with --upstream-ca");
         std::process::exit(1);
     }
 
-    // Start listening for connections
-    let listener = match TcpListener::bind(&options.bind) {
-        Ok(listener) => listener,
+    let health_criteria = match HealthCriteria::parse(
+        options.active_health_check_status,
+        options.active_health_check_body,
+    ) {
+        Ok(criteria) => criteria,
         Err(err) => {
-            log::error!("Could not bind to {}: {}", options.bind, err);
+            log::error!("Invalid --active-health-check-status: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let selector = match selector::from_name(&options.balancing_strategy) {
+        Some(selector) => selector,
+        None => {
+            log::error!(
+                "Unknown balancing strategy {:?}; expected one of {}",
+                options.balancing_strategy,
+                selector::STRATEGY_NAMES.join(", ")
+            );
             std::process::exit(1);
         }
     };
-    log::info!("Listening for requests on {}", options.bind);
+
+    // Catch SIGHUP (which would otherwise kill us) so that it reloads the config file instead
+    let reload_signals = match options.config {
+        Some(path) => match ReloadSignals::new(path, command_line_settings) {
+            Ok(signals) => Some(signals),
+            Err(err) => {
+                log::error!("Could not listen for SIGHUP: {}", err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Start listening for connections
+    let mut listeners = Vec::new();
+    for address in &options.bind {
+        match bind_listener(address, options.listen_backlog) {
+            Ok(listener) => listeners.push(listener),
+            Err(err) => {
+                log::error!("Could not bind to {}: {}", address, err);
+                std::process::exit(1);
+            }
+        }
+        log::info!("Listening for requests on {}", address);
+    }
 
     // Handle incoming connections
     let state = ProxyState {
-        upstream_addresses: options.upstream,
+        upstreams: RwLock::new(Arc::new(UpstreamPool::new(&settings))),
+        selector,
         active_health_check_interval: options.active_health_check_interval,
         active_health_check_path: options.active_health_check_path,
+        health_criteria,
         max_requests_per_minute: options.max_requests_per_minute,
+        response_cache: if options.cache_max_entries > 0 {
+            Some(Mutex::new(ResponseCache::new(options.cache_max_entries)))
+        } else {
+            None
+        },
+        response_header_rewrites,
+        json_access_log: options.json_access_log,
+        error_page,
+        upstream_tls,
+        client_idle_timeout: Some(Duration::from_secs(options.client_idle_timeout))
+            .filter(|timeout| !timeout.is_zero()),
     };
-    for stream in listener.incoming() {
-        if let Ok(stream) = stream {
-            // Handle the connection!
-            handle_connection(stream, &state);
+    let connection_slots = ConnectionSlots::new(options.max_connections as usize);
+    thread::scope(|scope| {
+        if state.active_health_check_interval > 0 {
+            scope.spawn(|| run_active_health_checks(&state));
+        }
+        if let Some(reload_signals) = reload_signals {
+            scope.spawn(|| reload_signals.run(&state));
+        }
+        // Every listener feeds the same connection handling. Each connection gets its own thread,
+        // so that a slow client, an idle keep-alive connection or a long-lived tunnel doesn't hold
+        // up everyone else on the same listener. Once --max-connections are open, the listeners
+        // stop accepting until one closes, and new connections wait in the accept backlog.
+        for listener in &listeners {
+            let state = &state;
+            let connection_slots = &connection_slots;
+            scope.spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let slot = connection_slots.acquire();
+                    // Handle the connection!
+                    let spawned = thread::Builder::new().spawn_scoped(scope, move || {
+                        handle_connection(stream, state);
+                        drop(slot);
+                    });
+                    // The connection (and its slot) is dropped along with the closure
+                    if let Err(err) = spawned {
+                        log::error!("Failed to start a thread for a new connection: {}", err);
+                    }
+                }
+            });
         }
+    });
+}
+
+/// Binds a listening socket to `address` with the given accept backlog. IPv6 sockets only accept
+/// IPv6 connections, so that `[::]` and `0.0.0.0` can both be bound on the same port.
+fn bind_listener(address: &str, backlog: i32) -> io::Result<TcpListener> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other("address resolved to nothing"))?;
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    // Like TcpListener::bind, allow rebinding a port with connections still in TIME_WAIT
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
     }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
 }
 
-fn connect_to_upstream(state: &ProxyState) -> Result<TcpStream, std::io::Error> {
-    let mut rng = rand::rngs::StdRng::from_os_rng();
-    let upstream_idx = rng.random_range(0..state.upstream_addresses.len());
-    let upstream_ip = &state.upstream_addresses[upstream_idx];
-    TcpStream::connect(upstream_ip).or_else(|err| {
-        log::error!("Failed to connect to upstream {}: {}", upstream_ip, err);
-        Err(err)
-    })
-    // TODO: implement failover (milestone 3)
+/// Checks every upstream on the active health check interval, forever. Upstreams that fail a
+/// check aren't sent requests until they pass one again.
+fn run_active_health_checks(state: &ProxyState) {
+    let interval = Duration::from_secs(state.active_health_check_interval as u64);
+    loop {
+        thread::sleep(interval);
+        let upstreams = state.upstreams();
+        for (idx, address) in upstreams.addresses.iter().enumerate() {
+            let healthy = health::check_upstream(
+                address,
+                &state.active_health_check_path,
+                &state.health_criteria,
+                state.upstream_tls.as_ref(),
+            );
+            let was_healthy = upstreams.healthy[idx].swap(healthy, Ordering::SeqCst);
+            if healthy != was_healthy {
+                log::info!(
+                    "Upstream {} is now {}",
+                    address,
+                    if healthy { "healthy" } else { "unhealthy" }
+                );
+            }
+        }
+    }
+}
+
+/// Waits for SIGHUP, which asks us to re-read the config file. The signal handler is tokio's,
+/// driven by a runtime of its own that does nothing else.
+struct ReloadSignals {
+    runtime: tokio::runtime::Runtime,
+    hangups: tokio::signal::unix::Signal,
+    /// The config file to re-read
+    path: String,
+    /// The settings given on the command line, which the config file is applied on top of
+    command_line_settings: Settings,
+}
+
+impl ReloadSignals {
+    fn new(path: String, command_line_settings: Settings) -> io::Result<ReloadSignals> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        let hangups = {
+            let _guard = runtime.enter();
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?
+        };
+        Ok(ReloadSignals {
+            runtime,
+            hangups,
+            path,
+            command_line_settings,
+        })
+    }
+
+    /// Reloads the config file every time SIGHUP arrives, forever. If the file is valid, the
+    /// upstreams and settings in it are swapped in for new requests, while requests already
+    /// underway finish with the ones they started with. Otherwise the current config stays.
+    fn run(self, state: &ProxyState) {
+        let ReloadSignals {
+            runtime,
+            mut hangups,
+            path,
+            command_line_settings,
+        } = self;
+        runtime.block_on(async {
+            while hangups.recv().await.is_some() {
+                match command_line_settings.load(&path) {
+                    Ok(settings) => {
+                        *state.upstreams.write() = Arc::new(UpstreamPool::new(&settings));
+                        log::info!(
+                            "Reloaded {}; now proxying to {}",
+                            path,
+                            settings.upstreams.join(", ")
+                        );
+                    }
+                    Err(err) => log::error!("Keeping the current config: {}", err),
+                }
+            }
+        });
+    }
+}
+
+/// Why a request couldn't be sent to any upstream.
+enum ConnectError {
+    /// Every upstream is unhealthy or has an open circuit
+    NoUpstreams,
+    /// The upstreams that could take the request were all at their limit of requests in flight
+    /// for the whole queue timeout
+    AtCapacity,
+    /// Connecting to the picked upstream failed
+    ConnectFailed,
+}
+
+/// The upstream that `connect_to_upstream` picked for a request.
+#[derive(Debug, Clone, Copy)]
+struct PickedUpstream {
+    idx: usize,
+    /// True if the request is the probe for the upstream's half-open circuit
+    probe: bool,
+}
+
+/// Connects to the upstream that the balancing strategy picks for this request among those whose
+/// circuit isn't open and that have room for another request, returning the upstream along with
+/// the connection. If the only upstreams that could take the request are full, waits up
+/// to the queue timeout for one of them to finish a request.
+///
+/// On success, the request counts towards the upstream's active requests, and the caller must call
+/// `finish_request` once it is done.
+fn connect_to_upstream(
+    state: &ProxyState,
+    upstreams: &UpstreamPool,
+    request: &http::Request<Vec<u8>>,
+) -> Result<(PickedUpstream, UpstreamConn), ConnectError> {
+    let queue_deadline = Instant::now() + upstreams.queue_timeout;
+    let upstream = {
+        let mut circuit_breakers = upstreams.circuit_breakers.lock();
+        loop {
+            let now = Instant::now();
+            let usable: Vec<bool> = (0..upstreams.addresses.len())
+                .map(|idx| {
+                    circuit_breakers[idx].is_available(now)
+                        && upstreams.healthy[idx].load(Ordering::SeqCst)
+                })
+                .collect();
+            let candidates: Vec<selector::Upstream> = (0..upstreams.addresses.len())
+                .map(|idx| selector::Upstream {
+                    available: usable[idx] && !upstreams.is_full(idx),
+                    active_requests: upstreams.active_requests[idx].load(Ordering::SeqCst),
+                    weight: upstreams.weights[idx],
+                })
+                .collect();
+            if let Some(upstream_idx) = state.selector.select(&candidates, request) {
+                let probe = circuit_breakers[upstream_idx].begin_request(now);
+                upstreams.active_requests[upstream_idx].fetch_add(1, Ordering::SeqCst);
+                break PickedUpstream {
+                    idx: upstream_idx,
+                    probe,
+                };
+            }
+            if !usable.contains(&true) {
+                log::error!(
+                    "Every upstream is unhealthy or has an open circuit; not forwarding the request"
+                );
+                return Err(ConnectError::NoUpstreams);
+            }
+            if upstreams
+                .request_finished
+                .wait_until(&mut circuit_breakers, queue_deadline)
+                .timed_out()
+            {
+                log::error!(
+                    "Every upstream still had {} requests in flight after {:?}; not forwarding the \
+                     request",
+                    upstreams.max_requests.unwrap_or(0),
+                    upstreams.queue_timeout
+                );
+                return Err(ConnectError::AtCapacity);
+            }
+        }
+    };
+    let upstream_ip = &upstreams.addresses[upstream.idx];
+    match UpstreamConn::connect(upstream_ip, state.upstream_tls.as_ref()) {
+        Ok(stream) => Ok((upstream, stream)),
+        Err(err) => {
+            log::error!("Failed to connect to upstream {}: {}", upstream_ip, err);
+            upstreams.finish_request(upstream.idx);
+            record_upstream_result(upstreams, upstream, false);
+            Err(ConnectError::ConnectFailed)
+        }
+    }
+}
+
+/// Tells the upstream's circuit breaker whether a request to it succeeded.
+fn record_upstream_result(upstreams: &UpstreamPool, upstream: PickedUpstream, succeeded: bool) {
+    let circuit_breaker = &mut upstreams.circuit_breakers.lock()[upstream.idx];
+    if succeeded {
+        circuit_breaker.record_success(upstream.probe);
+    } else {
+        circuit_breaker.record_failure(Instant::now(), upstream.probe);
+    }
 }
 
 fn send_response(client_conn: &mut TcpStream, response: &http::Response<Vec<u8>>) {
@@ -113,23 +684,19 @@ fn send_response(client_conn: &mut TcpStream, response: &http::Response<Vec<u8>>
 fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
     let client_ip = client_conn.peer_addr().unwrap().ip().to_string();
     log::info!("Connection received from {}", client_ip);
-
-    // Open a connection to a random destination server
-    let mut upstream_conn = match connect_to_upstream(state) {
-        Ok(stream) => stream,
-        Err(_error) => {
-            let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
-            send_response(&mut client_conn, &response);
-            return;
-        }
-    };
-    let upstream_ip = upstream_conn.peer_addr().unwrap().ip().to_string();
+    // A client that leaves the connection idle (or stalls partway through a request) gets
+    // disconnected, rather than holding on to its thread forever
+    if let Err(err) = client_conn.set_read_timeout(state.client_idle_timeout) {
+        log::error!("Failed to set a read timeout for {}: {}", client_ip, err);
+        return;
+    }
 
     // The client may now send us one or more requests. Keep trying to read requests until the
-    // client hangs up or we get an error.
+    // client hangs up, either side asks to close the connection, or we get an error. Each request
+    // is routed on its own, so requests sharing a connection still get spread across upstreams.
     loop {
         // Read a request from the client
-        let mut request = match request::read_from_stream(&mut client_conn) {
+        let (mut request, unread_body) = match request::read_from_stream(&mut client_conn) {
             Ok(request) => request,
             // Handle case where client closed connection and is no longer sending requests
             Err(request::Error::IncompleteRequest(0)) => {
@@ -143,7 +710,7 @@ fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
             }
             Err(error) => {
                 log::debug!("Error parsing request: {:?}", error);
-                let response = response::make_http_error(match error {
+                let response = state.make_http_error(match error {
                     request::Error::IncompleteRequest(_)
                     | request::Error::MalformedRequest(_)
                     | request::Error::InvalidContentLength
@@ -155,43 +722,235 @@ fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
                 continue;
             }
         };
-        log::info!(
-            "{} -> {}: {}",
-            client_ip,
-            upstream_ip,
-            request::format_request_line(&request)
-        );
 
         // Add X-Forwarded-For header so that the upstream server knows the client's IP address.
         // (We're the ones connecting directly to the upstream server, so without this header, the
         // upstream server will only know our IP, not the client's.)
         request::extend_header_value(&mut request, "x-forwarded-for", &client_ip);
+        // Tag the request with an ID (or keep the one it came with) so it can be traced through
+        // the upstream's logs
+        let request_id = access_log::ensure_request_id(&mut request);
+        let start = (SystemTime::now(), Instant::now());
+
+        // Large bodies are still (mostly) waiting to be read, and get passed on to the upstream as
+        // they arrive
+        let mut body = request::BodyStream::new(&mut client_conn, unread_body);
+        let upstreams = state.upstreams();
+        let (response, upstream_idx, upstream_conn) =
+            proxy_request(state, &upstreams, &client_ip, &request, &mut body);
+        let body_finished = body.is_finished();
+        // Forward the response to the client
+        send_response(&mut client_conn, &response);
+        log::debug!("Forwarded response to client");
+        if state.json_access_log {
+            let entry = access_log::AccessLogEntry {
+                request_id: &request_id,
+                client_ip: &client_ip,
+                method: request.method().as_str(),
+                uri: request.uri().to_string(),
+                upstream: upstream_idx.map(|idx| upstreams.addresses[idx].as_str()),
+                status: response.status().as_u16(),
+                request_body_bytes: request.body().len()
+                    + if body_finished { unread_body } else { 0 },
+                response_body_bytes: response.body().len(),
+                start: start.0,
+                duration: start.1.elapsed(),
+            };
+            println!("{}", entry.to_json());
+        }
 
-        // Forward the request to the server
-        if let Err(error) = request::write_to_stream(&request, &mut upstream_conn) {
+        // If the upstream agreed to switch protocols (e.g. a WebSocket handshake) or to open a
+        // CONNECT tunnel, the connection no longer carries HTTP. Just shuttle bytes between the
+        // client and upstream until one of them hangs up.
+        if let Some(upstream_conn) = upstream_conn {
+            if request::is_upgrade_request(&request)
+                && response::is_upgrade_accepted(&response, request.method())
+            {
+                match upstream_conn.into_plain() {
+                    Some(upstream_conn) => {
+                        log::info!("{} <-> upstream: switching to raw tunnel", client_ip);
+                        // Tunnels (e.g. WebSockets) may legitimately go quiet for a long time
+                        if let Err(err) = client_conn.set_read_timeout(None) {
+                            log::error!(
+                                "Failed to clear the read timeout for {}: {}",
+                                client_ip,
+                                err
+                            );
+                            return;
+                        }
+                        tunnel(client_conn, upstream_conn);
+                        log::debug!("Tunnel for {} closed", client_ip);
+                    }
+                    None => log::error!(
+                        "Can't tunnel {}'s connection to a TLS upstream; closing it",
+                        client_ip
+                    ),
+                }
+                return;
+            }
+        }
+
+        // If the rest of the body was never read (e.g. no upstream could be reached), we can't
+        // tell where the client's next request starts
+        if !body_finished
+            || !request::wants_keep_alive(&request)
+            || request::connection_has_token(response.headers(), "close")
+        {
+            log::debug!("Not keeping the connection alive. Shutting down connection");
+            return;
+        }
+    }
+}
+
+/// Gets a response to the client's request, either from the cache or by sending the request to
+/// an upstream. Failures are turned into error responses. Along with the response, returns the
+/// index of the upstream the request was sent to (if any) and the upstream connection the response
+/// came from (if any), which is needed if the connection is being upgraded.
+fn proxy_request(
+    state: &ProxyState,
+    upstreams: &UpstreamPool,
+    client_ip: &str,
+    request: &http::Request<Vec<u8>>,
+    body: &mut request::BodyStream,
+) -> (http::Response<Vec<u8>>, Option<usize>, Option<UpstreamConn>) {
+    // Serve the response from our cache if we have a fresh copy of it
+    if let Some(cache) = &state.response_cache {
+        if let Some(response) = cache.lock().get(request) {
+            log::debug!("Serving {} from cache", request.uri());
+            return (response, None, None);
+        }
+    }
+
+    // Open a connection to the destination server picked by the balancing strategy
+    let (upstream, upstream_conn) = match connect_to_upstream(state, upstreams, request) {
+        Ok(upstream) => upstream,
+        Err(error) => {
+            let status = match error {
+                ConnectError::AtCapacity => http::StatusCode::SERVICE_UNAVAILABLE,
+                ConnectError::NoUpstreams | ConnectError::ConnectFailed => {
+                    http::StatusCode::BAD_GATEWAY
+                }
+            };
+            return (state.make_http_error(status), None, None);
+        }
+    };
+    let (response, upstream_conn) = forward_request(
+        state,
+        upstreams,
+        client_ip,
+        request,
+        body,
+        upstream,
+        upstream_conn,
+    );
+    upstreams.finish_request(upstream.idx);
+    (response, Some(upstream.idx), upstream_conn)
+}
+
+/// Sends the request (followed by the rest of its body, if that is still being streamed from the
+/// client) to the given upstream and reads its response, which is also stored in the cache if
+/// possible.
+fn forward_request(
+    state: &ProxyState,
+    upstreams: &UpstreamPool,
+    client_ip: &str,
+    request: &http::Request<Vec<u8>>,
+    body: &mut request::BodyStream,
+    upstream: PickedUpstream,
+    mut upstream_conn: UpstreamConn,
+) -> (http::Response<Vec<u8>>, Option<UpstreamConn>) {
+    let upstream_ip = upstream_conn.tcp().peer_addr().unwrap().ip().to_string();
+    log::info!(
+        "{} -> {}: {}",
+        client_ip,
+        upstream_ip,
+        request::format_request_line(request)
+    );
+
+    // Forward the request to the server
+    if let Err(error) = request::write_to_stream(request, &mut upstream_conn) {
+        log::error!(
+            "Failed to send request to upstream {}: {}",
+            upstream_ip,
+            error
+        );
+        record_upstream_result(upstreams, upstream, false);
+        return (state.make_http_error(http::StatusCode::BAD_GATEWAY), None);
+    }
+    match body.copy_to(&mut upstream_conn) {
+        Ok(()) => {}
+        Err(request::StreamError::Client(error)) => {
+            log::info!("Error reading request body from client: {:?}", error);
+            return (state.make_http_error(http::StatusCode::BAD_REQUEST), None);
+        }
+        Err(request::StreamError::Upstream(error)) => {
             log::error!(
-                "Failed to send request to upstream {}: {}",
+                "Failed to send request body to upstream {}: {}",
                 upstream_ip,
                 error
             );
-            let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
-            send_response(&mut client_conn, &response);
-            return;
+            record_upstream_result(upstreams, upstream, false);
+            return (state.make_http_error(http::StatusCode::BAD_GATEWAY), None);
         }
-        log::debug!("Forwarded request to server");
+    }
+    log::debug!("Forwarded request to server");
 
-        // Read the server's response
-        let response = match response::read_from_stream(&mut upstream_conn, request.method()) {
+    // Read the server's response
+    let deadline = upstreams
+        .response_timeout
+        .map(|timeout| Instant::now() + timeout);
+    let mut response =
+        match response::read_from_stream(&mut upstream_conn, request.method(), deadline) {
             Ok(response) => response,
+            Err(response::Error::Timeout) => {
+                log::error!(
+                    "Upstream {} did not respond within {:?}",
+                    upstream_ip,
+                    upstreams.response_timeout.unwrap()
+                );
+                record_upstream_result(upstreams, upstream, false);
+                return (
+                    state.make_http_error(http::StatusCode::GATEWAY_TIMEOUT),
+                    None,
+                );
+            }
             Err(error) => {
                 log::error!("Error reading response from server: {:?}", error);
-                let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
-                send_response(&mut client_conn, &response);
+                record_upstream_result(upstreams, upstream, false);
+                return (state.make_http_error(http::StatusCode::BAD_GATEWAY), None);
+            }
+        };
+    record_upstream_result(upstreams, upstream, !response.status().is_server_error());
+    state.response_header_rewrites.apply(&mut response);
+    if let Some(cache) = &state.response_cache {
+        cache.lock().insert(request, &response);
+    }
+    (response, Some(upstream_conn))
+}
+
+/// Copies bytes in both directions between the client and the upstream until both sides have
+/// hung up. When one side finishes sending, we shut down the write half of the other connection
+/// so that the EOF is passed along.
+fn tunnel(mut client_conn: TcpStream, mut upstream_conn: TcpStream) {
+    let (mut client_reader, mut upstream_writer) =
+        match (client_conn.try_clone(), upstream_conn.try_clone()) {
+            (Ok(client_reader), Ok(upstream_writer)) => (client_reader, upstream_writer),
+            (Err(err), _) | (_, Err(err)) => {
+                log::error!("Failed to set up tunnel: {}", err);
                 return;
             }
         };
-        // Forward the response to the client
-        send_response(&mut client_conn, &response);
-        log::debug!("Forwarded response to client");
+    let client_to_upstream = thread::spawn(move || {
+        if let Err(err) = io::copy(&mut client_reader, &mut upstream_writer) {
+            log::debug!("Error copying from client to upstream: {}", err);
+        }
+        let _ = upstream_writer.shutdown(Shutdown::Write);
+    });
+    if let Err(err) = io::copy(&mut upstream_conn, &mut client_conn) {
+        log::debug!("Error copying from upstream to client: {}", err);
+    }
+    let _ = client_conn.shutdown(Shutdown::Write);
+    if client_to_upstream.join().is_err() {
+        log::error!("Tunnel thread panicked");
     }
 }
@@ -4,6 +4,14 @@ use std::net::TcpStream;
 
 const MAX_HEADERS_SIZE: usize = 8000;
 const MAX_BODY_SIZE: usize = 10000000;
+/// Request bodies up to this size are read into memory in full before the request is forwarded, so
+/// a client that sends its body slowly doesn't tie up an upstream meanwhile, and a body that doesn't
+/// match its Content-Length is rejected before anything reaches the upstream. Larger bodies are
+/// streamed to the upstream as they arrive instead, BODY_CHUNK_SIZE bytes at a time, which keeps
+/// memory use bounded.
+pub const MAX_BUFFERED_BODY_SIZE: usize = 64 * 1024;
+/// How many bytes of a streamed request body are read from the client at a time
+const BODY_CHUNK_SIZE: usize = 8 * 1024;
 const MAX_NUM_HEADERS: usize = 32;
 
 #[derive(Debug)]
@@ -66,6 +74,41 @@ pub fn extend_header_value(
         .insert(name, http::HeaderValue::from_bytes(&new_value).unwrap());
 }
 
+/// Returns true if the given token (e.g. "close") is listed in the Connection header.
+pub fn connection_has_token(headers: &http::HeaderMap, token: &str) -> bool {
+    headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Returns true if the client wants to keep its connection open after this request. HTTP/1.1
+/// connections are persistent unless the client sends `Connection: close`, while HTTP/1.0
+/// connections are closed unless the client sends `Connection: keep-alive`.
+pub fn wants_keep_alive(request: &http::Request<Vec<u8>>) -> bool {
+    if connection_has_token(request.headers(), "close") {
+        return false;
+    }
+    match request.version() {
+        http::Version::HTTP_09 | http::Version::HTTP_10 => {
+            connection_has_token(request.headers(), "keep-alive")
+        }
+        _ => true,
+    }
+}
+
+/// Returns true if the client is asking to stop speaking HTTP on this connection: either a
+/// protocol upgrade (e.g. a WebSocket handshake, which carries `Connection: Upgrade` and an
+/// `Upgrade` header) or a CONNECT request asking for a raw tunnel.
+pub fn is_upgrade_request(request: &http::Request<Vec<u8>>) -> bool {
+    if request.method() == http::Method::CONNECT {
+        return true;
+    }
+    connection_has_token(request.headers(), "upgrade") && request.headers().contains_key("upgrade")
+}
+
 /// Attempts to parse the data in the supplied buffer as an HTTP request. Returns one of the
 /// following:
 ///
@@ -85,7 +128,11 @@ fn parse_request(buffer: &[u8]) -> Result<Option<(http::Request<Vec<u8>>, usize)
         let mut request = http::Request::builder()
             .method(req.method.unwrap())
             .uri(req.path.unwrap())
-            .version(http::Version::HTTP_11);
+            .version(if req.version == Some(0) {
+                http::Version::HTTP_10
+            } else {
+                http::Version::HTTP_11
+            });
         for header in req.headers {
             request = request.header(header.name, header.value);
         }
@@ -181,19 +228,77 @@ fn read_body(
 /// This function reads and returns an HTTP request from a stream, returning an Error if the client
 /// closes the connection prematurely or sends an invalid request.
 ///
-/// You will need to modify this function in Milestone 2.
-pub fn read_from_stream(stream: &mut TcpStream) -> Result<http::Request<Vec<u8>>, Error> {
+/// Bodies larger than MAX_BUFFERED_BODY_SIZE are not read in full: the request only holds whatever
+/// part of the body arrived along with the headers, and the number of body bytes still waiting in
+/// the stream is returned alongside it, to be passed on with a `BodyStream`.
+pub fn read_from_stream(stream: &mut TcpStream) -> Result<(http::Request<Vec<u8>>, usize), Error> {
     // Read headers
     let mut request = read_headers(stream)?;
     // Read body if the client supplied the Content-Length header (which it does for POST requests)
     if let Some(content_length) = get_content_length(&request)? {
         if content_length > MAX_BODY_SIZE {
             return Err(Error::RequestBodyTooLarge);
+        } else if request.body().len() > content_length {
+            return Err(Error::ContentLengthMismatch);
+        } else if content_length > MAX_BUFFERED_BODY_SIZE {
+            let unread = content_length - request.body().len();
+            return Ok((request, unread));
         } else {
             read_body(stream, &mut request, content_length)?;
         }
     }
-    Ok(request)
+    Ok((request, 0))
+}
+
+/// Where passing a request body on from the client to the upstream failed
+#[derive(Debug)]
+pub enum StreamError {
+    /// Reading the rest of the body from the client failed
+    Client(Error),
+    /// Writing to the upstream failed
+    Upstream(std::io::Error),
+}
+
+/// The rest of a request body that hasn't been read from the client yet (see read_from_stream).
+pub struct BodyStream<'a> {
+    client: &'a mut TcpStream,
+    unread: usize,
+}
+
+impl<'a> BodyStream<'a> {
+    pub fn new(client: &'a mut TcpStream, unread: usize) -> BodyStream<'a> {
+        BodyStream { client, unread }
+    }
+
+    /// Returns true once the whole body has been read from the client. Until then, the client's
+    /// connection can't be used for another request.
+    pub fn is_finished(&self) -> bool {
+        self.unread == 0
+    }
+
+    /// Copies the rest of the body from the client to `upstream`, holding at most BODY_CHUNK_SIZE
+    /// bytes in memory at once.
+    pub fn copy_to(&mut self, upstream: &mut impl Write) -> Result<(), StreamError> {
+        let mut buffer = [0_u8; BODY_CHUNK_SIZE];
+        while self.unread > 0 {
+            let bytes_read = self
+                .client
+                .read(&mut buffer[..min(BODY_CHUNK_SIZE, self.unread)])
+                .map_err(|err| StreamError::Client(Error::ConnectionError(err)))?;
+            if bytes_read == 0 {
+                log::debug!(
+                    "Client hung up with {} bytes of the request body left to send",
+                    self.unread
+                );
+                return Err(StreamError::Client(Error::ContentLengthMismatch));
+            }
+            self.unread -= bytes_read;
+            upstream
+                .write_all(&buffer[..bytes_read])
+                .map_err(StreamError::Upstream)?;
+        }
+        Ok(())
+    }
 }
 
 /// This function serializes a request to bytes and writes those bytes to the provided stream.
@@ -201,7 +306,7 @@ pub fn read_from_stream(stream: &mut TcpStream) -> Result<http::Request<Vec<u8>>
 /// You will need to modify this function in Milestone 2.
 pub fn write_to_stream(
     request: &http::Request<Vec<u8>>,
-    stream: &mut TcpStream,
+    stream: &mut impl Write,
 ) -> Result<(), std::io::Error> {
     stream.write(&format_request_line(request).into_bytes())?;
     stream.write(&['\r' as u8, '\n' as u8])?; // \r\n
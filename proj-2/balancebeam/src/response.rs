@@ -1,5 +1,7 @@
-use std::io::{Read, Write};
+use crate::upstream_conn::UpstreamConn;
+use std::io::{self, Read, Write};
 use std::net::TcpStream;
+use std::time::Instant;
 
 const MAX_HEADERS_SIZE: usize = 8000;
 const MAX_BODY_SIZE: usize = 10000000;
@@ -19,6 +21,8 @@ pub enum Error {
     ResponseBodyTooLarge,
     /// Encountered an I/O error when reading/writing a TcpStream
     ConnectionError(std::io::Error),
+    /// The server did not finish sending its response before the deadline
+    Timeout,
 }
 
 /// Extracts the Content-Length header value from the provided response. Returns Ok(Some(usize)) if
@@ -80,7 +84,10 @@ fn parse_response(buffer: &[u8]) -> Result<Option<(http::Response<Vec<u8>>, usiz
 /// Returns Ok(http::Response) if a valid response is received, or Error if not.
 ///
 /// You will need to modify this function in Milestone 2.
-fn read_headers(stream: &mut TcpStream) -> Result<http::Response<Vec<u8>>, Error> {
+fn read_headers(
+    stream: &mut UpstreamConn,
+    deadline: Option<Instant>,
+) -> Result<http::Response<Vec<u8>>, Error> {
     // Try reading the headers from the response. We may not receive all the headers in one shot
     // (e.g. we might receive the first few bytes of a response, and then the rest follows later).
     // Try parsing repeatedly until we read a valid HTTP response
@@ -88,9 +95,7 @@ fn read_headers(stream: &mut TcpStream) -> Result<http::Response<Vec<u8>>, Error
     let mut bytes_read = 0;
     loop {
         // Read bytes from the connection into the buffer, starting at position bytes_read
-        let new_bytes = stream
-            .read(&mut response_buffer[bytes_read..])
-            .or_else(|err| Err(Error::ConnectionError(err)))?;
+        let new_bytes = read_before(stream, &mut response_buffer[bytes_read..], deadline)?;
         if new_bytes == 0 {
             // We didn't manage to read a complete response
             return Err(Error::IncompleteResponse);
@@ -114,7 +119,11 @@ fn read_headers(stream: &mut TcpStream) -> Result<http::Response<Vec<u8>>, Error
 /// present, it reads that many bytes; otherwise, it reads bytes until the connection is closed.
 ///
 /// You will need to modify this function in Milestone 2.
-fn read_body(stream: &mut TcpStream, response: &mut http::Response<Vec<u8>>) -> Result<(), Error> {
+fn read_body(
+    stream: &mut UpstreamConn,
+    response: &mut http::Response<Vec<u8>>,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
     // The response may or may not supply a Content-Length header. If it provides the header, then
     // we want to read that number of bytes; if it does not, we want to keep reading bytes until
     // the connection is closed.
@@ -122,9 +131,7 @@ fn read_body(stream: &mut TcpStream, response: &mut http::Response<Vec<u8>>) ->
 
     while content_length.is_none() || response.body().len() < content_length.unwrap() {
         let mut buffer = [0_u8; 512];
-        let bytes_read = stream
-            .read(&mut buffer)
-            .or_else(|err| Err(Error::ConnectionError(err)))?;
+        let bytes_read = read_before(stream, &mut buffer, deadline)?;
         if bytes_read == 0 {
             // The server has hung up!
             if content_length.is_none() {
@@ -154,27 +161,134 @@ fn read_body(stream: &mut TcpStream, response: &mut http::Response<Vec<u8>>) ->
     Ok(())
 }
 
+/// Reads from the stream like Read::read, but gives up with Error::Timeout if nothing arrives
+/// before the deadline (if there is one).
+fn read_before(
+    stream: &mut UpstreamConn,
+    buffer: &mut [u8],
+    deadline: Option<Instant>,
+) -> Result<usize, Error> {
+    if let Some(deadline) = deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::Timeout);
+        }
+        stream
+            .set_read_timeout(Some(remaining))
+            .or_else(|err| Err(Error::ConnectionError(err)))?;
+    }
+    match stream.read(buffer) {
+        Ok(bytes_read) => Ok(bytes_read),
+        // Depending on the platform, a read timeout shows up as either of these
+        Err(err)
+            if deadline.is_some()
+                && (err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut) =>
+        {
+            Err(Error::Timeout)
+        }
+        Err(err) => Err(Error::ConnectionError(err)),
+    }
+}
+
 /// This function reads and returns an HTTP response from a stream, returning an Error if the server
-/// closes the connection prematurely or sends an invalid response.
+/// closes the connection prematurely or sends an invalid response. If a deadline is given, the
+/// whole response (headers and body) must arrive before then, or Error::Timeout is returned.
 ///
 /// You will need to modify this function in Milestone 2.
 pub fn read_from_stream(
-    stream: &mut TcpStream,
+    stream: &mut UpstreamConn,
     request_method: &http::Method,
+    deadline: Option<Instant>,
 ) -> Result<http::Response<Vec<u8>>, Error> {
-    let mut response = read_headers(stream)?;
+    let response = read_response(stream, request_method, deadline);
+    if deadline.is_some() {
+        // Don't leave a timeout behind for later reads (e.g. if the connection becomes a tunnel)
+        stream
+            .set_read_timeout(None)
+            .or_else(|err| Err(Error::ConnectionError(err)))?;
+    }
+    response
+}
+
+fn read_response(
+    stream: &mut UpstreamConn,
+    request_method: &http::Method,
+    deadline: Option<Instant>,
+) -> Result<http::Response<Vec<u8>>, Error> {
+    let mut response = read_headers(stream, deadline)?;
     // A response may have a body as long as it is not responding to a HEAD request and as long as
-    // the response status code is not 1xx, 204 (no content), or 304 (not modified).
+    // the response status code is not 1xx, 204 (no content), or 304 (not modified). A successful
+    // response to CONNECT has no body either; the connection becomes a tunnel instead.
     if !(request_method == http::Method::HEAD
+        || is_upgrade_accepted(&response, request_method)
         || response.status().as_u16() < 200
         || response.status() == http::StatusCode::NO_CONTENT
         || response.status() == http::StatusCode::NOT_MODIFIED)
     {
-        read_body(stream, &mut response)?;
+        read_body(stream, &mut response, deadline)?;
     }
     Ok(response)
 }
 
+/// Returns true if the upstream agreed to switch protocols: 101 Switching Protocols for an
+/// upgrade request, or any 2xx response to CONNECT. After such a response, the connection no
+/// longer carries HTTP messages.
+pub fn is_upgrade_accepted(
+    response: &http::Response<Vec<u8>>,
+    request_method: &http::Method,
+) -> bool {
+    response.status() == http::StatusCode::SWITCHING_PROTOCOLS
+        || (request_method == http::Method::CONNECT && response.status().is_success())
+}
+
+/// Header changes applied to every response from an upstream before it is sent to the client,
+/// e.g. to hide the upstream's `Server` header or to add `Strict-Transport-Security`.
+#[derive(Debug, Default)]
+pub struct HeaderRewrites {
+    /// Headers to strip from responses
+    pub remove: Vec<http::HeaderName>,
+    /// Headers to set on responses, replacing any value the upstream sent
+    pub add: Vec<(http::HeaderName, http::HeaderValue)>,
+}
+
+impl HeaderRewrites {
+    /// Parses header names to remove and "Name: value" pairs to add. Returns a description of the
+    /// problem if any of them isn't a valid header.
+    pub fn parse(remove: &[String], add: &[String]) -> Result<HeaderRewrites, String> {
+        let mut rewrites = HeaderRewrites::default();
+        for name in remove {
+            rewrites.remove.push(
+                http::HeaderName::from_bytes(name.trim().as_bytes())
+                    .or(Err(format!("invalid header name {:?}", name)))?,
+            );
+        }
+        for header in add {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or(format!("expected \"Name: value\", got {:?}", header))?;
+            rewrites.add.push((
+                http::HeaderName::from_bytes(name.trim().as_bytes())
+                    .or(Err(format!("invalid header name in {:?}", header)))?,
+                http::HeaderValue::from_str(value.trim())
+                    .or(Err(format!("invalid header value in {:?}", header)))?,
+            ));
+        }
+        Ok(rewrites)
+    }
+
+    /// Applies the removals, then the additions, to the given response.
+    pub fn apply(&self, response: &mut http::Response<Vec<u8>>) {
+        let headers = response.headers_mut();
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.add {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
 /// This function serializes a response to bytes and writes those bytes to the provided stream.
 ///
 /// You will need to modify this function in Milestone 2.
@@ -214,11 +328,57 @@ pub fn make_http_error(status: http::StatusCode) -> http::Response<Vec<u8>> {
         status.canonical_reason().unwrap_or("")
     )
     .into_bytes();
+    make_response(status, "text/plain", body)
+}
+
+fn make_response(
+    status: http::StatusCode,
+    content_type: &str,
+    body: Vec<u8>,
+) -> http::Response<Vec<u8>> {
     http::Response::builder()
         .status(status)
-        .header("Content-Type", "text/plain")
+        .header("Content-Type", content_type)
         .header("Content-Length", body.len().to_string())
         .version(http::Version::HTTP_11)
         .body(body)
         .unwrap()
 }
+
+/// A page to send clients instead of the plain make_http_error body when we can't get a response
+/// from an upstream (502, 503 and 504).
+pub struct ErrorPage {
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl ErrorPage {
+    /// Reads the page from a file. Files ending in .html or .htm are served as HTML, anything else
+    /// as plain text.
+    pub fn load(path: &str) -> io::Result<ErrorPage> {
+        let body = std::fs::read(path)?;
+        let is_html = path.ends_with(".html") || path.ends_with(".htm");
+        Ok(ErrorPage {
+            content_type: if is_html {
+                "text/html; charset=utf-8"
+            } else {
+                "text/plain; charset=utf-8"
+            },
+            body,
+        })
+    }
+
+    /// Returns true if this page should be used for the given status.
+    pub fn covers(status: http::StatusCode) -> bool {
+        matches!(
+            status,
+            http::StatusCode::BAD_GATEWAY
+                | http::StatusCode::SERVICE_UNAVAILABLE
+                | http::StatusCode::GATEWAY_TIMEOUT
+        )
+    }
+
+    pub fn make_response(&self, status: http::StatusCode) -> http::Response<Vec<u8>> {
+        make_response(status, self.content_type, self.body.clone())
+    }
+}
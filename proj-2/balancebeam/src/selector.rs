@@ -0,0 +1,206 @@
+use rand::Rng;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What a selector gets to know about an upstream when picking where to send a request.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    /// False if the upstream must not be picked (e.g. its circuit is open)
+    pub available: bool,
+    /// Number of requests currently being handled by this upstream
+    pub active_requests: usize,
+    /// Relative share of requests this upstream should get, for weighted selection
+    pub weight: usize,
+}
+
+/// A load balancing strategy. Given the upstreams (in the order they were configured), picks the
+/// index of the one the request should go to, or returns None if none of them is available.
+pub trait UpstreamSelector: Send + Sync {
+    fn select(&self, upstreams: &[Upstream], request: &http::Request<Vec<u8>>) -> Option<usize>;
+}
+
+/// The names accepted by `from_name`, for the --balancing-strategy help text and error messages.
+pub const STRATEGY_NAMES: [&str; 4] = ["random", "round-robin", "least-connections", "weighted"];
+
+/// Returns the selector for the given strategy name, or None if there is no such strategy.
+pub fn from_name(name: &str) -> Option<Box<dyn UpstreamSelector>> {
+    match name {
+        "random" => Some(Box::new(Random)),
+        "round-robin" => Some(Box::new(RoundRobin::default())),
+        "least-connections" => Some(Box::new(LeastConnections::default())),
+        "weighted" => Some(Box::new(Weighted::default())),
+        _ => None,
+    }
+}
+
+/// Returns the indices of the available upstreams, starting from `start` (modulo the number of
+/// upstreams) and wrapping around.
+fn available_from<'a>(upstreams: &'a [Upstream], start: usize) -> impl Iterator<Item = usize> + 'a {
+    (0..upstreams.len())
+        .map(move |offset| (start + offset) % upstreams.len())
+        .filter(move |idx| upstreams[*idx].available)
+}
+
+/// Picks an available upstream uniformly at random.
+pub struct Random;
+
+impl UpstreamSelector for Random {
+    fn select(&self, upstreams: &[Upstream], _request: &http::Request<Vec<u8>>) -> Option<usize> {
+        let available: Vec<usize> = available_from(upstreams, 0).collect();
+        if available.is_empty() {
+            return None;
+        }
+        Some(available[rand::rng().random_range(0..available.len())])
+    }
+}
+
+/// Takes turns between the upstreams, skipping unavailable ones.
+#[derive(Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl UpstreamSelector for RoundRobin {
+    fn select(&self, upstreams: &[Upstream], _request: &http::Request<Vec<u8>>) -> Option<usize> {
+        if upstreams.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        available_from(upstreams, start).next()
+    }
+}
+
+/// Picks the available upstream with the fewest requests in flight. Ties are broken round-robin,
+/// so that idle upstreams still share the load evenly.
+#[derive(Default)]
+pub struct LeastConnections {
+    next: AtomicUsize,
+}
+
+impl UpstreamSelector for LeastConnections {
+    fn select(&self, upstreams: &[Upstream], _request: &http::Request<Vec<u8>>) -> Option<usize> {
+        if upstreams.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        // min_by_key returns the first of several equal minimums, i.e. the one closest to start
+        available_from(upstreams, start).min_by_key(|idx| upstreams[*idx].active_requests)
+    }
+}
+
+/// Takes turns between the available upstreams, giving each a number of consecutive requests
+/// proportional to its weight. Upstreams with a weight of 0 only get requests when nothing else is
+/// available.
+#[derive(Default)]
+pub struct Weighted {
+    next: AtomicUsize,
+}
+
+impl UpstreamSelector for Weighted {
+    fn select(&self, upstreams: &[Upstream], _request: &http::Request<Vec<u8>>) -> Option<usize> {
+        let total_weight: usize = available_from(upstreams, 0)
+            .map(|idx| upstreams[idx].weight)
+            .sum();
+        if total_weight == 0 {
+            return available_from(upstreams, 0).next();
+        }
+        let mut position = self.next.fetch_add(1, Ordering::Relaxed) % total_weight;
+        available_from(upstreams, 0).find(|idx| {
+            if position < upstreams[*idx].weight {
+                true
+            } else {
+                position -= upstreams[*idx].weight;
+                false
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn upstreams() -> Vec<Upstream> {
+        vec![
+            Upstream {
+                available: true,
+                active_requests: 0,
+                weight: 1,
+            };
+            3
+        ]
+    }
+
+    fn request() -> http::Request<Vec<u8>> {
+        http::Request::builder().uri("/").body(Vec::new()).unwrap()
+    }
+
+    /// Runs `count` selections and returns the picked indices.
+    fn picks(selector: &dyn UpstreamSelector, upstreams: &[Upstream], count: usize) -> Vec<usize> {
+        (0..count)
+            .map(|_| selector.select(upstreams, &request()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_random() {
+        let mut upstreams = upstreams();
+        upstreams[1].available = false;
+        let picked = picks(&Random, &upstreams, 100);
+        assert!(!picked.contains(&1));
+        assert!(picked.contains(&0) && picked.contains(&2));
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut upstreams = upstreams();
+        let selector = RoundRobin::default();
+        assert_eq!(picks(&selector, &upstreams, 6), vec![0, 1, 2, 0, 1, 2]);
+        upstreams[1].available = false;
+        assert_eq!(picks(&selector, &upstreams, 3), vec![0, 2, 2]);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let mut upstreams = upstreams();
+        upstreams[0].active_requests = 2;
+        upstreams[1].active_requests = 1;
+        upstreams[2].active_requests = 3;
+        let selector = LeastConnections::default();
+        assert_eq!(picks(&selector, &upstreams, 3), vec![1, 1, 1]);
+        upstreams[1].available = false;
+        assert_eq!(picks(&selector, &upstreams, 1), vec![0]);
+
+        // Idle upstreams share the load
+        let idle = self::upstreams();
+        let picked = picks(&selector, &idle, 3);
+        assert!(picked.contains(&0) && picked.contains(&1) && picked.contains(&2));
+    }
+
+    #[test]
+    fn test_weighted() {
+        let mut upstreams = upstreams();
+        upstreams[0].weight = 3;
+        upstreams[2].weight = 0;
+        let selector = Weighted::default();
+        assert_eq!(
+            picks(&selector, &upstreams, 8),
+            vec![0, 0, 0, 1, 0, 0, 0, 1]
+        );
+        upstreams[0].available = false;
+        upstreams[1].available = false;
+        assert_eq!(picks(&selector, &upstreams, 1), vec![2]);
+    }
+
+    #[test]
+    fn test_no_upstreams_available() {
+        let mut upstreams = upstreams();
+        for upstream in upstreams.iter_mut() {
+            upstream.available = false;
+        }
+        for name in STRATEGY_NAMES.iter() {
+            let selector = from_name(name).unwrap();
+            assert_eq!(selector.select(&upstreams, &request()), None, "{}", name);
+        }
+        assert!(from_name("fastest").is_none());
+    }
+}
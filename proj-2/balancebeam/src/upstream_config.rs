@@ -0,0 +1,151 @@
+use crate::upstream_conn;
+use std::fmt;
+use std::net::ToSocketAddrs;
+
+/// A problem with the --upstream options that keeps balancebeam from starting.
+#[derive(Debug, PartialEq)]
+pub enum UpstreamConfigError {
+    /// No upstreams were given
+    NoUpstreams,
+    /// More upstreams were given than --max-upstreams allows
+    TooManyUpstreams { count: usize, max: usize },
+    /// The same address was given more than once
+    DuplicateUpstream(String),
+    /// An address isn't a valid host:port (or the host couldn't be resolved). The second field
+    /// describes what's wrong with it
+    InvalidAddress(String, String),
+}
+
+impl fmt::Display for UpstreamConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpstreamConfigError::NoUpstreams => write!(
+                f,
+                "At least one upstream server must be specified using the --upstream option"
+            ),
+            UpstreamConfigError::TooManyUpstreams { count, max } => write!(
+                f,
+                "{} upstreams were given, but --max-upstreams is {}",
+                count, max
+            ),
+            UpstreamConfigError::DuplicateUpstream(address) => {
+                write!(f, "Upstream {} was given more than once", address)
+            }
+            UpstreamConfigError::InvalidAddress(address, reason) => write!(
+                f,
+                "Upstream {:?} is not a valid host:port address: {}",
+                address, reason
+            ),
+        }
+    }
+}
+
+/// Checks the upstream addresses given on the command line: there must be at least one and (unless
+/// `max_upstreams` is 0) at most `max_upstreams`, no address may appear twice, and each must be a
+/// host:port that resolves to a socket address. Returns the first problem found.
+pub fn validate_upstreams(
+    upstreams: &[String],
+    max_upstreams: usize,
+) -> Result<(), UpstreamConfigError> {
+    if upstreams.is_empty() {
+        return Err(UpstreamConfigError::NoUpstreams);
+    }
+    if max_upstreams > 0 && upstreams.len() > max_upstreams {
+        return Err(UpstreamConfigError::TooManyUpstreams {
+            count: upstreams.len(),
+            max: max_upstreams,
+        });
+    }
+    for (idx, address) in upstreams.iter().enumerate() {
+        if upstreams[..idx].contains(address) {
+            return Err(UpstreamConfigError::DuplicateUpstream(address.clone()));
+        }
+        match upstream_conn::host_port(address)
+            .to_socket_addrs()
+            .map(|mut addrs| addrs.next())
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Err(UpstreamConfigError::InvalidAddress(
+                    address.clone(),
+                    "host has no addresses".to_string(),
+                ))
+            }
+            Err(err) => {
+                return Err(UpstreamConfigError::InvalidAddress(
+                    address.clone(),
+                    err.to_string(),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn upstreams(addresses: &[&str]) -> Vec<String> {
+        addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_upstreams() {
+        assert_eq!(
+            validate_upstreams(&upstreams(&["127.0.0.1:8080", "localhost:8081"]), 0),
+            Ok(())
+        );
+        assert_eq!(
+            validate_upstreams(&upstreams(&["127.0.0.1:8080", "[::1]:8080"]), 2),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_no_upstreams() {
+        assert_eq!(
+            validate_upstreams(&[], 0),
+            Err(UpstreamConfigError::NoUpstreams)
+        );
+    }
+
+    #[test]
+    fn test_too_many_upstreams() {
+        assert_eq!(
+            validate_upstreams(
+                &upstreams(&["127.0.0.1:8080", "127.0.0.1:8081", "127.0.0.1:8082"]),
+                2
+            ),
+            Err(UpstreamConfigError::TooManyUpstreams { count: 3, max: 2 })
+        );
+    }
+
+    #[test]
+    fn test_duplicate_upstream() {
+        assert_eq!(
+            validate_upstreams(
+                &upstreams(&["127.0.0.1:8080", "127.0.0.1:8081", "127.0.0.1:8080"]),
+                0
+            ),
+            Err(UpstreamConfigError::DuplicateUpstream(
+                "127.0.0.1:8080".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        for address in ["127.0.0.1", "127.0.0.1:http", "127.0.0.1:70000", ":8080"] {
+            match validate_upstreams(&upstreams(&["127.0.0.1:8080", address]), 0) {
+                Err(UpstreamConfigError::InvalidAddress(invalid, _)) => {
+                    assert_eq!(invalid, address)
+                }
+                other => panic!("Expected {:?} to be rejected, got {:?}", address, other),
+            }
+        }
+    }
+}
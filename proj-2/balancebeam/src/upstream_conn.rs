@@ -0,0 +1,160 @@
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upstream addresses starting with this are spoken to over TLS.
+const TLS_PREFIX: &str = "https://";
+
+/// Returns the host:port part of an upstream address, without the https:// marking TLS upstreams.
+pub fn host_port(address: &str) -> &str {
+    address.strip_prefix(TLS_PREFIX).unwrap_or(address)
+}
+
+/// Returns true if the upstream is to be spoken to over TLS.
+pub fn is_tls(address: &str) -> bool {
+    address.starts_with(TLS_PREFIX)
+}
+
+/// How to set up TLS connections to upstreams.
+pub struct TlsSettings {
+    config: Arc<rustls::ClientConfig>,
+    /// Name to send as SNI and to check the upstream's certificate against, instead of the host
+    /// in its address
+    server_name: Option<String>,
+}
+
+impl TlsSettings {
+    /// Trusts the CA certificates in the given PEM files (and nothing else) to sign upstream
+    /// certificates.
+    pub fn new(ca_files: &[String], server_name: Option<String>) -> Result<TlsSettings, String> {
+        let mut roots = rustls::RootCertStore::empty();
+        for path in ca_files {
+            let certs = CertificateDer::pem_file_iter(path)
+                .map_err(|err| format!("Can't read {}: {}", path, err))?;
+            for cert in certs {
+                let cert = cert.map_err(|err| format!("Can't read {}: {}", path, err))?;
+                roots
+                    .add(cert)
+                    .map_err(|err| format!("Bad certificate in {}: {}", path, err))?;
+            }
+        }
+        if roots.is_empty() {
+            return Err("No CA certificates were given for TLS upstreams".to_string());
+        }
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        Ok(TlsSettings {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+}
+
+/// A connection to an upstream, in plain text or over TLS depending on how its address is written.
+pub enum UpstreamConn {
+    Plain(TcpStream),
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl UpstreamConn {
+    /// Connects to the upstream. TLS upstreams need `tls`; the handshake happens along with the
+    /// first write.
+    pub fn connect(address: &str, tls: Option<&TlsSettings>) -> io::Result<UpstreamConn> {
+        let stream = TcpStream::connect(host_port(address))?;
+        if !is_tls(address) {
+            return Ok(UpstreamConn::Plain(stream));
+        }
+        let tls =
+            tls.ok_or_else(|| io::Error::other("no --upstream-ca given for TLS upstreams"))?;
+        let name = match &tls.server_name {
+            Some(name) => name.clone(),
+            None => host_port(address)
+                .rsplit_once(':')
+                .map_or(host_port(address), |(host, _)| host)
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+        };
+        let server_name = ServerName::try_from(name).map_err(io::Error::other)?;
+        let conn = rustls::ClientConnection::new(tls.config.clone(), server_name)
+            .map_err(io::Error::other)?;
+        Ok(UpstreamConn::Tls(Box::new(rustls::StreamOwned::new(
+            conn, stream,
+        ))))
+    }
+
+    /// Returns the underlying TCP connection.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            UpstreamConn::Plain(stream) => stream,
+            UpstreamConn::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Sets a read timeout on the underlying TCP connection, which applies to TLS reads as well.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+
+    /// Returns the TCP connection if this isn't a TLS connection. Raw tunnels need to split the
+    /// connection into a reading and a writing half, which we can only do for plain TCP.
+    pub fn into_plain(self) -> Option<TcpStream> {
+        match self {
+            UpstreamConn::Plain(stream) => Some(stream),
+            UpstreamConn::Tls(_) => None,
+        }
+    }
+}
+
+impl Read for UpstreamConn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            UpstreamConn::Plain(stream) => stream.read(buf),
+            UpstreamConn::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for UpstreamConn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            UpstreamConn::Plain(stream) => stream.write(buf),
+            UpstreamConn::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            UpstreamConn::Plain(stream) => stream.flush(),
+            UpstreamConn::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_addresses() {
+        assert!(is_tls("https://backend:8443"));
+        assert!(!is_tls("backend:8080"));
+        assert_eq!(host_port("https://backend:8443"), "backend:8443");
+        assert_eq!(host_port("backend:8080"), "backend:8080");
+    }
+
+    #[test]
+    fn test_tls_settings_need_a_ca() {
+        assert!(TlsSettings::new(&[], None).is_err());
+        assert!(TlsSettings::new(&["/nonexistent.pem".to_string()], None).is_err());
+        assert!(TlsSettings::new(&["tests/fixtures/tls/ca.pem".to_string()], None).is_ok());
+    }
+}
@@ -1,7 +1,13 @@
 mod common;
 
-use common::{init_logging, BalanceBeam, EchoServer, Server};
+use common::{
+    init_logging, BalanceBeam, EchoServer, HeaderServer, Server, SlowServer, TlsServer,
+    UpgradeServer, TLS_CA_PATH,
+};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 
 async fn setup() -> (BalanceBeam, EchoServer) {
     init_logging();
@@ -99,3 +105,455 @@ async fn test_multiple_requests_per_connection() {
 
     log::info!("All done :)");
 }
+
+/// Test that protocol upgrades (e.g. WebSockets) are passed through: once the upstream accepts the
+/// handshake with 101 Switching Protocols, raw bytes should flow in both directions.
+#[tokio::test]
+async fn test_upgrade_pass_through() {
+    init_logging();
+    let upstream = UpgradeServer::new().await;
+    let balancebeam = BalanceBeam::new(&[&upstream.address], None, None).await;
+
+    log::info!("Sending an upgrade request");
+    let mut conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    conn.write_all(
+        b"GET /socket HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+        Upgrade: websocket\r\n\r\n",
+    )
+    .await
+    .expect("Failed to send upgrade request");
+
+    let mut response_head = Vec::new();
+    while !response_head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0_u8; 1];
+        conn.read_exact(&mut byte)
+            .await
+            .expect("Balancebeam hung up before finishing the handshake response");
+        response_head.push(byte[0]);
+    }
+    let response_head = String::from_utf8_lossy(&response_head);
+    log::info!("Handshake response: {:?}", response_head);
+    assert!(response_head.starts_with("HTTP/1.1 101"));
+
+    log::info!("Sending raw bytes through the upgraded connection");
+    for message in &[&b"hello"[..], &b"\x00\xffnot http at all\r\n\r\n"[..]] {
+        conn.write_all(message)
+            .await
+            .expect("Failed to write to upgraded connection");
+        let mut echoed = vec![0_u8; message.len()];
+        conn.read_exact(&mut echoed)
+            .await
+            .expect("Failed to read from upgraded connection");
+        assert_eq!(&echoed[..], *message);
+    }
+
+    log::info!("Closing the connection");
+    drop(conn);
+    let num_connections = Box::new(upstream).stop().await;
+    assert_eq!(
+        num_connections, 1,
+        "Upstream server did not receive the expected number of connections"
+    );
+
+    log::info!("All done :)");
+}
+
+/// Test that with caching enabled, a repeated GET is answered from balancebeam's cache without
+/// contacting the upstream server again.
+#[tokio::test]
+async fn test_response_caching() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &["--cache-max-entries", "10"],
+    )
+    .await;
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/cached_url", balancebeam.address);
+
+    log::info!("Sending the first GET request");
+    let first_response = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(first_response.headers().get("x-cache").is_none());
+    let first_text = first_response
+        .text()
+        .await
+        .expect("Balancebeam replied with a malformed response");
+    assert!(first_text.contains("GET /cached_url HTTP/1.1"));
+
+    log::info!("Sending the same GET request again");
+    let second_response = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(
+        second_response
+            .headers()
+            .get("x-cache")
+            .map(|value| value.as_bytes()),
+        Some(&b"HIT"[..])
+    );
+    let second_text = second_response
+        .text()
+        .await
+        .expect("Balancebeam replied with a malformed response");
+    assert_eq!(first_text, second_text);
+
+    log::info!("Checking that the origin server received only 1 request");
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(
+        num_requests_received, 1,
+        "Upstream server should not have been contacted for the cached response"
+    );
+
+    log::info!("All done :)");
+}
+
+/// Test that every request reaching the upstream carries an X-Request-Id: a fresh one for each
+/// request, or the client's own if it sent one.
+#[tokio::test]
+async fn test_request_id_propagation() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&upstream.address], None, None, &["--json-access-log"]).await;
+
+    let request_id_seen = |response_text: &str| -> String {
+        response_text
+            .lines()
+            .find_map(|line| line.strip_prefix("x-request-id: "))
+            .expect("The upstream didn't receive an X-Request-Id header")
+            .to_string()
+    };
+
+    log::info!("Sending requests without a request ID");
+    let first = request_id_seen(&balancebeam.get("/first").await.unwrap());
+    let second = request_id_seen(&balancebeam.get("/second").await.unwrap());
+    assert_eq!(first.len(), 36, "Unexpected request ID {:?}", first);
+    assert_ne!(first, second, "Each request should get its own ID");
+
+    log::info!("Sending a request with a request ID");
+    let response_text = reqwest::Client::new()
+        .get(format!("http://{}/traced", balancebeam.address))
+        .header("x-request-id", "trace-me-123")
+        .send()
+        .await
+        .expect("Error sending request to balancebeam")
+        .text()
+        .await
+        .expect("Balancebeam replied with a malformed response");
+    assert_eq!(request_id_seen(&response_text), "trace-me-123");
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 3);
+
+    log::info!("All done :)");
+}
+
+/// Test that configured response header rewrites are applied to upstream responses: removed
+/// headers are stripped, added headers are set, and everything else passes through untouched.
+#[tokio::test]
+async fn test_response_header_rewriting() {
+    init_logging();
+    let upstream = HeaderServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &[
+            "--remove-response-header",
+            "Server",
+            "--add-response-header",
+            "Strict-Transport-Security: max-age=31536000",
+        ],
+    )
+    .await;
+
+    log::info!("Sending a GET request");
+    let response = reqwest::Client::new()
+        .get(format!("http://{}/", balancebeam.address))
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    let headers = response.headers();
+    assert!(
+        headers.get("server").is_none(),
+        "The Server header should have been removed"
+    );
+    assert_eq!(
+        headers
+            .get("strict-transport-security")
+            .map(|value| value.as_bytes()),
+        Some(&b"max-age=31536000"[..])
+    );
+    assert_eq!(
+        headers.get("x-upstream-id").map(|value| value.as_bytes()),
+        Some(&b"header-server"[..])
+    );
+    assert_eq!(
+        response
+            .text()
+            .await
+            .expect("Balancebeam replied with a malformed response"),
+        "hello"
+    );
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+
+    log::info!("All done :)");
+}
+
+/// Sends two requests at once through a balancebeam that lets the upstream handle one request at a
+/// time. Returns the statuses of the responses and how long it took for both of them to arrive.
+async fn concurrent_requests_with_cap(
+    upstream: &SlowServer,
+    queue_timeout: &str,
+) -> (Vec<reqwest::StatusCode>, Duration) {
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &[
+            "--max-upstream-requests",
+            "1",
+            "--upstream-queue-timeout",
+            queue_timeout,
+        ],
+    )
+    .await;
+    let start = Instant::now();
+    let (first, second) = tokio::join!(
+        reqwest::get(format!("http://{}/", balancebeam.address)),
+        reqwest::get(format!("http://{}/", balancebeam.address))
+    );
+    let elapsed = start.elapsed();
+    let mut statuses: Vec<reqwest::StatusCode> = [first, second]
+        .into_iter()
+        .map(|response| {
+            response
+                .expect("Error sending request to balancebeam")
+                .status()
+        })
+        .collect();
+    statuses.sort();
+    (statuses, elapsed)
+}
+
+/// Test that with --max-upstream-requests 1, a second request waits for the upstream to finish the
+/// first one instead of being sent alongside it, and gets a 503 if it can't wait that long.
+#[tokio::test]
+async fn test_max_upstream_requests() {
+    init_logging();
+    let delay = Duration::from_millis(500);
+    let upstream = SlowServer::new(delay).await;
+
+    log::info!("Sending two requests that are allowed to wait");
+    let (statuses, elapsed) = concurrent_requests_with_cap(&upstream, "5").await;
+    assert_eq!(
+        statuses,
+        vec![reqwest::StatusCode::OK, reqwest::StatusCode::OK]
+    );
+    assert!(
+        elapsed >= delay * 2,
+        "The second request should have waited for the first, but both were done in {:?}",
+        elapsed
+    );
+
+    log::info!("Sending two requests that aren't allowed to wait");
+    let (statuses, _) = concurrent_requests_with_cap(&upstream, "0").await;
+    assert_eq!(
+        statuses,
+        vec![
+            reqwest::StatusCode::OK,
+            reqwest::StatusCode::SERVICE_UNAVAILABLE
+        ]
+    );
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 3);
+
+    log::info!("All done :)");
+}
+
+/// Sends a request through a balancebeam with the given upstream response timeout to an upstream
+/// that takes `delay` to respond, returning the response status and how long it took to arrive.
+async fn request_with_timeout(delay: Duration, timeout: &str) -> (reqwest::StatusCode, Duration) {
+    let upstream = SlowServer::new(delay).await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &["--upstream-response-timeout", timeout],
+    )
+    .await;
+    let start = Instant::now();
+    let response = reqwest::Client::new()
+        .get(format!("http://{}/", balancebeam.address))
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    let elapsed = start.elapsed();
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+    (response.status(), elapsed)
+}
+
+#[tokio::test]
+async fn test_upstream_response_timeout() {
+    init_logging();
+
+    log::info!("Sending a request to an upstream that responds within the timeout");
+    let (status, _) = request_with_timeout(Duration::from_millis(200), "2").await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+
+    log::info!("Sending a request to an upstream that responds too slowly");
+    let (status, elapsed) = request_with_timeout(Duration::from_secs(4), "1").await;
+    assert_eq!(status, reqwest::StatusCode::GATEWAY_TIMEOUT);
+    assert!(
+        elapsed < Duration::from_secs(3),
+        "Balancebeam should have given up on the upstream after 1 second, but took {:?}",
+        elapsed
+    );
+
+    log::info!("All done :)");
+}
+
+/// Test that balancebeam accepts connections on every address it's told to bind to.
+#[tokio::test]
+async fn test_multiple_listen_addresses() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let second_address = format!("127.0.0.1:{}", rand::random_range(1024..65535));
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &["--bind", &second_address, "--listen-backlog", "16"],
+    )
+    .await;
+
+    for address in [&balancebeam.address, &second_address] {
+        log::info!("Sending a request to {}", address);
+        let response_text = reqwest::Client::new()
+            .get(format!("http://{}/via_{}", address, address))
+            .send()
+            .await
+            .expect("Error sending request to balancebeam")
+            .text()
+            .await
+            .expect("Balancebeam replied with a malformed response");
+        assert!(response_text.contains(&format!("GET /via_{} HTTP/1.1", address)));
+    }
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 2);
+    log::info!("All done :)");
+}
+
+/// Test that a request body too large to buffer is streamed through to the upstream intact, and
+/// that balancebeam doesn't hold onto a copy of all of it on the way.
+#[tokio::test]
+async fn test_large_request_body() {
+    let (balancebeam, upstream) = setup().await;
+    let body: Vec<u8> = (0..8_000_000_u32).map(|i| b'a' + (i % 26) as u8).collect();
+    let memory_before = balancebeam.peak_memory_kb();
+
+    log::info!("Sending a POST request with a {} byte body", body.len());
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/upload", balancebeam.address))
+        .body(body.clone())
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    let response_body = response
+        .bytes()
+        .await
+        .expect("Balancebeam replied with a malformed response");
+    assert!(response_body.starts_with(b"POST /upload HTTP/1.1"));
+    assert!(response_body.ends_with(&body));
+
+    // The echoed response is buffered in full, so the request body must not have been as well
+    let memory_used = (balancebeam.peak_memory_kb() - memory_before) * 1024;
+    log::info!(
+        "Balancebeam's peak memory use grew by {} bytes",
+        memory_used
+    );
+    assert!(
+        memory_used < body.len() * 3 / 2,
+        "Balancebeam used {} bytes of memory for a {} byte request",
+        memory_used,
+        body.len()
+    );
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+
+    log::info!("All done :)");
+}
+
+/// Forward requests to an upstream over TLS, checking its certificate against the test CA. With an
+/// SNI name the certificate doesn't cover, the handshake must fail and the client gets a 502.
+#[tokio::test]
+async fn test_tls_upstream() {
+    init_logging();
+    let upstream = TlsServer::new().await;
+    let tls_address = format!("https://{}", upstream.address);
+    let balancebeam =
+        BalanceBeam::new_with_args(&[&tls_address], None, None, &["--upstream-ca", TLS_CA_PATH])
+            .await;
+    for path in ["/first", "/second"] {
+        let response_text = balancebeam
+            .get(path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert_eq!(
+            response_text,
+            format!("served over TLS: GET {} HTTP/1.1", path)
+        );
+    }
+
+    log::info!("Connecting with an SNI name the certificate is for");
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&tls_address],
+        None,
+        None,
+        &[
+            "--upstream-ca",
+            TLS_CA_PATH,
+            "--upstream-sni",
+            "upstream.test",
+        ],
+    )
+    .await;
+    let response_text = balancebeam
+        .get("/named")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response_text, "served over TLS: GET /named HTTP/1.1");
+
+    log::info!("Connecting with an SNI name the certificate isn't for");
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&tls_address],
+        None,
+        None,
+        &["--upstream-ca", TLS_CA_PATH, "--upstream-sni", "other.test"],
+    )
+    .await;
+    let response = reqwest::get(format!("http://{}/mismatch", balancebeam.address))
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 502);
+
+    assert_eq!(Box::new(upstream).stop().await, 3);
+    log::info!("All done :)");
+}
@@ -1,8 +1,13 @@
 mod common;
 
-use common::{init_logging, BalanceBeam, EchoServer, ErrorServer, Server};
+use common::{
+    init_logging, BalanceBeam, EchoServer, ErrorServer, FixedResponseServer, Server, SlowServer,
+    UpgradeServer, HEALTH_PATH,
+};
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tokio::time::sleep;
 
 async fn setup_with_params(
@@ -240,6 +245,77 @@ async fn test_active_health_checks_restore_failed_upstream() {
     log::info!("All done :)");
 }
 
+/// Starts balancebeam in front of a healthy and an unhealthy upstream, with active health checks
+/// judged by `health_check_args`. Once the checks have run, every request should go to the
+/// healthy upstream.
+async fn check_unhealthy_upstream_excluded(
+    healthy: FixedResponseServer,
+    unhealthy: FixedResponseServer,
+    health_check_args: &[&str],
+) {
+    init_logging();
+    let mut args = vec!["--active-health-check-path", HEALTH_PATH];
+    args.extend_from_slice(health_check_args);
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&healthy.address, &unhealthy.address],
+        Some(1),
+        None,
+        &args,
+    )
+    .await;
+
+    log::info!("Waiting for the active health checks to run...");
+    sleep(Duration::from_secs(3)).await;
+
+    for i in 0..6 {
+        let response_text = balancebeam
+            .get(&format!("/request-{}", i))
+            .await
+            .expect("Error sending request to balancebeam");
+        assert_eq!(
+            response_text,
+            format!("served by {}", healthy.address),
+            "Request was routed to an upstream that failed its health checks"
+        );
+    }
+
+    let unhealthy_requests = Box::new(unhealthy).stop().await;
+    let healthy_requests = Box::new(healthy).stop().await;
+    assert_eq!(unhealthy_requests, 0);
+    assert_eq!(healthy_requests, 6);
+}
+
+/// An upstream that accepts connections but answers health checks with an error status must not
+/// be sent requests
+#[tokio::test]
+async fn test_active_health_check_status() {
+    check_unhealthy_upstream_excluded(
+        FixedResponseServer::new(200, "").await,
+        FixedResponseServer::new(503, "").await,
+        &[],
+    )
+    .await;
+    // With an exact status configured, other successful statuses don't count
+    check_unhealthy_upstream_excluded(
+        FixedResponseServer::new(204, "").await,
+        FixedResponseServer::new(200, "").await,
+        &["--active-health-check-status", "204"],
+    )
+    .await;
+}
+
+/// With an expected body configured, an upstream has to include it in its answer to count as
+/// healthy
+#[tokio::test]
+async fn test_active_health_check_body() {
+    check_unhealthy_upstream_excluded(
+        FixedResponseServer::new(200, "{\"status\": \"up\"}").await,
+        FixedResponseServer::new(200, "{\"status\": \"degraded\"}").await,
+        &["--active-health-check-body", "\"up\""],
+    )
+    .await;
+}
+
 /// Enable rate limiting and ensure that requests fail after sending more than the threshold
 #[tokio::test]
 async fn test_rate_limiting() {
@@ -294,3 +370,529 @@ async fn test_rate_limiting() {
 
     log::info!("All done :)");
 }
+
+/// Make sure the circuit breaker stops sending requests to an upstream that keeps failing, then
+/// lets it back in once it recovers:
+///
+/// * Start with one working upstream and one that only returns HTTP error 500s
+/// * Send requests until the failing upstream has tripped its circuit, then make sure no more
+///   requests reach it
+/// * Replace it with a working server and wait out the cool-down
+/// * Ensure the recovered upstream gets requests again
+#[tokio::test]
+async fn test_circuit_breaker() {
+    let failure_threshold = 3;
+    init_logging();
+    let mut upstreams: Vec<Box<dyn Server>> = vec![
+        Box::new(EchoServer::new().await),
+        Box::new(ErrorServer::new().await),
+    ];
+    let failing_ip = upstreams[1].address();
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstreams[0].address(), &failing_ip],
+        None,
+        None,
+        &[
+            "--circuit-breaker-threshold",
+            &failure_threshold.to_string(),
+            "--circuit-breaker-cooldown",
+            "2",
+        ],
+    )
+    .await;
+
+    log::info!("Sending requests until the failing upstream trips its circuit");
+    let mut num_errors = 0;
+    for i in 0..100 {
+        let client = reqwest::Client::new();
+        let response = client
+            .get(&format!("http://{}/request-{}", balancebeam.address, i))
+            .send()
+            .await
+            .expect("Error sending request to balancebeam");
+        if response.status().is_server_error() {
+            num_errors += 1;
+            if num_errors == failure_threshold {
+                break;
+            }
+        }
+    }
+    assert_eq!(
+        num_errors, failure_threshold,
+        "The failing upstream never received enough requests to trip its circuit"
+    );
+
+    log::info!("Sending more requests. The failing upstream should be skipped");
+    for i in 0..10 {
+        let path = format!("/after-trip-{}", i);
+        let response_text = balancebeam
+            .get(&path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(
+            response_text.contains(&format!("GET {} HTTP/1.1", path)),
+            "A request was sent to an upstream whose circuit should be open"
+        );
+    }
+
+    log::info!("Replacing the failing upstream with a working server");
+    let failing_req_count = upstreams.pop().unwrap().stop().await;
+    assert_eq!(
+        failing_req_count, failure_threshold,
+        "The failing upstream received requests after its circuit opened"
+    );
+    upstreams.push(Box::new(EchoServer::new_at_address(failing_ip).await));
+
+    log::info!("Waiting for the cool-down to end...");
+    sleep(Duration::from_secs(3)).await;
+
+    log::info!("Sending some more requests");
+    for i in 0..20 {
+        let path = format!("/after-recovery-{}", i);
+        let response_text = balancebeam
+            .get(&path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(response_text.contains(&format!("GET {} HTTP/1.1", path)));
+    }
+
+    log::info!("Verifying that the recovered upstream got some requests");
+    let recovered_req_count = upstreams.pop().unwrap().stop().await;
+    assert!(
+        recovered_req_count > 1,
+        "The upstream recovered, but its circuit never closed again"
+    );
+    while let Some(upstream) = upstreams.pop() {
+        upstream.stop().await;
+    }
+
+    log::info!("All done :)");
+}
+
+/// Reads one HTTP response from the connection and returns it as text. Relies on the response
+/// having a Content-Length header, which EchoServer's responses do.
+async fn read_response(conn: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0_u8; 1];
+        conn.read_exact(&mut byte)
+            .await
+            .expect("Balancebeam hung up before sending a complete response");
+        response.push(byte[0]);
+    }
+    let content_length: usize = String::from_utf8_lossy(&response)
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.eq_ignore_ascii_case("content-length") {
+                value.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .expect("Response has no Content-Length");
+    let mut body = vec![0_u8; content_length];
+    conn.read_exact(&mut body)
+        .await
+        .expect("Balancebeam hung up before sending the whole response body");
+    response.extend_from_slice(&body);
+    String::from_utf8_lossy(&response).to_string()
+}
+
+/// Asserts that balancebeam closes the connection without sending anything else.
+async fn assert_closed(conn: &mut TcpStream) {
+    let mut buf = [0_u8; 1];
+    assert_eq!(
+        conn.read(&mut buf)
+            .await
+            .expect("Error reading from balancebeam"),
+        0,
+        "Balancebeam should have closed the connection"
+    );
+}
+
+/// Send several requests over one client connection and make sure that:
+/// * Each request is load balanced on its own, rather than the whole connection going to one
+///   upstream
+/// * The connection is closed after a request with `Connection: close`
+/// * HTTP/1.0 connections are closed after one request unless the client asks for keep-alive
+#[tokio::test]
+async fn test_client_keep_alive() {
+    let (balancebeam, mut upstreams) = setup(2).await;
+
+    log::info!("Sending two requests on one connection");
+    let mut conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    for path in ["/first", "/second"] {
+        conn.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await
+            .expect("Failed to send request");
+        let response = read_response(&mut conn).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains(&format!("GET {} HTTP/1.1", path)));
+    }
+
+    log::info!("Sending a request with Connection: close");
+    conn.write_all(b"GET /last HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .expect("Failed to send request");
+    assert!(read_response(&mut conn)
+        .await
+        .contains("GET /last HTTP/1.1"));
+    assert_closed(&mut conn).await;
+
+    log::info!("Sending an HTTP/1.0 request without keep-alive");
+    let mut conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    conn.write_all(b"GET /old HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Failed to send request");
+    read_response(&mut conn).await;
+    assert_closed(&mut conn).await;
+
+    // Requests are handed out round-robin, so each upstream should have gotten two of them
+    let mut request_counters = Vec::new();
+    while let Some(upstream) = upstreams.pop() {
+        request_counters.insert(0, upstream.stop().await);
+    }
+    assert_eq!(request_counters, vec![2, 2]);
+
+    log::info!("All done :)");
+}
+
+/// Leave a keep-alive connection idle after one request, and make sure other clients of the same
+/// listener don't have to wait for it to close.
+#[tokio::test]
+async fn test_idle_keep_alive_does_not_block_listener() {
+    let (balancebeam, mut upstreams) = setup(1).await;
+
+    log::info!("Sending a request and leaving the connection open");
+    let mut idle_conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    idle_conn
+        .write_all(b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Failed to send request");
+    assert!(read_response(&mut idle_conn)
+        .await
+        .contains("GET /first HTTP/1.1"));
+
+    log::info!("Sending a request on another connection");
+    let response_text = tokio::time::timeout(Duration::from_secs(5), balancebeam.get("/second"))
+        .await
+        .expect("Balancebeam didn't answer while another connection was idle")
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("GET /second HTTP/1.1"));
+
+    drop(idle_conn);
+    assert_eq!(upstreams.pop().unwrap().stop().await, 2);
+
+    log::info!("All done :)");
+}
+
+/// Leave a keep-alive connection idle for longer than --client-idle-timeout, and make sure
+/// balancebeam closes it.
+#[tokio::test]
+async fn test_idle_keep_alive_times_out() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &["--client-idle-timeout", "1"],
+    )
+    .await;
+
+    log::info!("Sending a request and leaving the connection open");
+    let mut idle_conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    idle_conn
+        .write_all(b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Failed to send request");
+    assert!(read_response(&mut idle_conn)
+        .await
+        .contains("GET /first HTTP/1.1"));
+
+    log::info!("Waiting for balancebeam to close the idle connection");
+    let mut buf = [0_u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), idle_conn.read(&mut buf))
+        .await
+        .expect("Balancebeam didn't close the idle connection");
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    assert_eq!(Box::new(upstream).stop().await, 1);
+
+    log::info!("All done :)");
+}
+
+/// With --max-connections 1, a second client has to wait until the first one hangs up.
+#[tokio::test]
+async fn test_max_connections() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&upstream.address],
+        None,
+        None,
+        &["--max-connections", "1"],
+    )
+    .await;
+
+    log::info!("Sending a request and leaving the connection open");
+    let mut first_conn = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    first_conn
+        .write_all(b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("Failed to send request");
+    assert!(read_response(&mut first_conn)
+        .await
+        .contains("GET /first HTTP/1.1"));
+
+    log::info!("Sending a request on another connection while the first is still open");
+    let second = tokio::spawn(async move {
+        let mut second_conn = TcpStream::connect(&balancebeam.address)
+            .await
+            .expect("Failed to connect to balancebeam");
+        second_conn
+            .write_all(b"GET /second HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .expect("Failed to send request");
+        let response = read_response(&mut second_conn).await;
+        (balancebeam, response)
+    });
+    sleep(Duration::from_millis(500)).await;
+    assert!(
+        !second.is_finished(),
+        "Balancebeam handled a second connection over --max-connections"
+    );
+
+    log::info!("Closing the first connection");
+    drop(first_conn);
+    let (_balancebeam, response) = tokio::time::timeout(Duration::from_secs(5), second)
+        .await
+        .expect("Balancebeam didn't handle the waiting connection once the first one closed")
+        .unwrap();
+    assert!(response.contains("GET /second HTTP/1.1"));
+
+    assert_eq!(Box::new(upstream).stop().await, 2);
+
+    log::info!("All done :)");
+}
+
+/// Open a tunnel through balancebeam, then make sure other clients of the same listener are still
+/// served while it is open. Requests are handed out round-robin, so the upgrade goes to
+/// UpgradeServer and the normal request that follows goes to EchoServer.
+#[tokio::test]
+async fn test_tunnel_does_not_block_listener() {
+    init_logging();
+    let tunnel_upstream = UpgradeServer::new().await;
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::new(&[&tunnel_upstream.address, &upstream.address], None, None).await;
+
+    log::info!("Opening a tunnel");
+    let mut tunnel = TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Failed to connect to balancebeam");
+    tunnel
+        .write_all(
+            b"GET /socket HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+            Upgrade: websocket\r\n\r\n",
+        )
+        .await
+        .expect("Failed to send upgrade request");
+    let mut response_head = Vec::new();
+    while !response_head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0_u8; 1];
+        tunnel
+            .read_exact(&mut byte)
+            .await
+            .expect("Balancebeam hung up before finishing the handshake response");
+        response_head.push(byte[0]);
+    }
+    assert!(response_head.starts_with(b"HTTP/1.1 101"));
+
+    log::info!("Sending a normal request while the tunnel is open");
+    let response_text = tokio::time::timeout(Duration::from_secs(5), balancebeam.get("/normal"))
+        .await
+        .expect("Balancebeam didn't answer while the tunnel was open")
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("GET /normal HTTP/1.1"));
+
+    log::info!("Making sure the tunnel still works");
+    tunnel
+        .write_all(b"still here")
+        .await
+        .expect("Failed to write to the tunnel");
+    let mut echoed = [0_u8; 10];
+    tunnel
+        .read_exact(&mut echoed)
+        .await
+        .expect("Failed to read from the tunnel");
+    assert_eq!(&echoed, b"still here");
+
+    drop(tunnel);
+    assert_eq!(Box::new(tunnel_upstream).stop().await, 1);
+    assert_eq!(Box::new(upstream).stop().await, 1);
+
+    log::info!("All done :)");
+}
+
+/// Start balancebeam with a config file pointing at one upstream, then point the file at another
+/// upstream and send SIGHUP. Requests after the reload should go to the new upstream, and a reload
+/// with an invalid file should leave the config alone.
+#[tokio::test]
+async fn test_reload_config() {
+    init_logging();
+    let old_upstream = EchoServer::new().await;
+    let new_upstream = EchoServer::new().await;
+    let config_path = std::env::temp_dir().join(format!(
+        "balancebeam-test-reload-{}.conf",
+        old_upstream.address.replace(':', "-")
+    ));
+    let config_arg = config_path.to_str().unwrap().to_string();
+    std::fs::write(
+        &config_path,
+        format!("upstream = {}\n", old_upstream.address),
+    )
+    .expect("Failed to write config file");
+    let balancebeam = BalanceBeam::new_with_args(&[], None, None, &["--config", &config_arg]).await;
+
+    log::info!("Sending requests to the original upstream");
+    for _ in 0..3 {
+        balancebeam
+            .get("/before")
+            .await
+            .expect("Error sending request to balancebeam");
+    }
+
+    log::info!("Switching to the new upstream");
+    std::fs::write(
+        &config_path,
+        format!("upstream = {}\n", new_upstream.address),
+    )
+    .expect("Failed to write config file");
+    balancebeam.reload_config().await;
+    for _ in 0..4 {
+        balancebeam
+            .get("/after")
+            .await
+            .expect("Error sending request to balancebeam");
+    }
+
+    log::info!("Reloading an invalid config file");
+    std::fs::write(&config_path, "upstream = not an address\n")
+        .expect("Failed to write config file");
+    balancebeam.reload_config().await;
+    let response_text = balancebeam
+        .get("/still-working")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("GET /still-working HTTP/1.1"));
+    std::fs::remove_file(&config_path).ok();
+
+    assert_eq!(Box::new(old_upstream).stop().await, 3);
+    assert_eq!(Box::new(new_upstream).stop().await, 5);
+
+    log::info!("All done :)");
+}
+
+/// Point balancebeam at upstreams that refuse connections and make sure the client gets the
+/// configured error page, with the right status and Content-Type.
+#[tokio::test]
+async fn test_error_page() {
+    init_logging();
+    // Nothing is listening on these ports once the listeners are dropped
+    let upstreams: Vec<String> = (0..2)
+        .map(|_| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        })
+        .collect();
+    let page = "<h1>We'll be right back</h1>\n";
+    let page_path = std::env::temp_dir().join(format!(
+        "balancebeam-test-error-page-{}.html",
+        upstreams[0].replace(':', "-")
+    ));
+    std::fs::write(&page_path, page).expect("Failed to write error page");
+    let upstream_refs: Vec<&str> = upstreams.iter().map(|addr| addr.as_str()).collect();
+    let balancebeam = BalanceBeam::new_with_args(
+        &upstream_refs,
+        None,
+        None,
+        &["--error-page", page_path.to_str().unwrap()],
+    )
+    .await;
+
+    for _ in 0..2 {
+        let response = reqwest::get(format!("http://{}/", balancebeam.address))
+            .await
+            .expect("Error sending request to balancebeam");
+        assert_eq!(response.status().as_u16(), 502);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(response.text().await.unwrap(), page);
+    }
+    std::fs::remove_file(&page_path).ok();
+
+    log::info!("All done :)");
+}
+
+/// Give the first upstream all of the weight, but only let it handle one request at a time. Of two
+/// requests sent at once, the second should go to the other upstream rather than wait.
+#[tokio::test]
+async fn test_max_upstream_requests_routes_elsewhere() {
+    init_logging();
+    let delay = Duration::from_secs(1);
+    let preferred = SlowServer::new(delay).await;
+    let spare = SlowServer::new(delay).await;
+    let balancebeam = BalanceBeam::new_with_args(
+        &[&preferred.address, &spare.address],
+        None,
+        None,
+        &[
+            "--balancing-strategy",
+            "weighted",
+            "--upstream-weight",
+            "1",
+            "--upstream-weight",
+            "0",
+            "--max-upstream-requests",
+            "1",
+            "--upstream-queue-timeout",
+            "5",
+        ],
+    )
+    .await;
+
+    let start = Instant::now();
+    let (first, second) = tokio::join!(
+        reqwest::get(format!("http://{}/", balancebeam.address)),
+        reqwest::get(format!("http://{}/", balancebeam.address))
+    );
+    let elapsed = start.elapsed();
+    for response in [first, second] {
+        let response = response.expect("Error sending request to balancebeam");
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    assert!(
+        elapsed < delay * 2,
+        "The second request should have gone to the spare upstream, but took {:?}",
+        elapsed
+    );
+
+    assert_eq!(Box::new(preferred).stop().await, 1);
+    assert_eq!(Box::new(spare).stop().await, 1);
+
+    log::info!("All done :)");
+}
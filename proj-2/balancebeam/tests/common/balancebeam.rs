@@ -6,7 +6,6 @@ use tokio::process::{Child, Command};
 use tokio::time::sleep;
 
 pub struct BalanceBeam {
-    #[allow(dead_code)]
     child: Child, // process is killed when dropped (Command::kill_on_drop)
     pub address: String,
 }
@@ -24,6 +23,22 @@ impl BalanceBeam {
         upstreams: &[&str],
         active_health_check_interval: Option<usize>,
         max_requests_per_minute: Option<usize>,
+    ) -> BalanceBeam {
+        BalanceBeam::new_with_args(
+            upstreams,
+            active_health_check_interval,
+            max_requests_per_minute,
+            &[],
+        )
+        .await
+    }
+
+    /// Like `new`, but passes `extra_args` through to balancebeam as well.
+    pub async fn new_with_args(
+        upstreams: &[&str],
+        active_health_check_interval: Option<usize>,
+        max_requests_per_minute: Option<usize>,
+        extra_args: &[&str],
     ) -> BalanceBeam {
         let mut rng = rand::rng();
         let address = format!("127.0.0.1:{}", rng.random_range(1024..65535));
@@ -40,6 +55,7 @@ impl BalanceBeam {
             cmd.arg("--max-requests-per-minute")
                 .arg(max_requests_per_minute.to_string());
         }
+        cmd.args(extra_args);
         cmd.kill_on_drop(true);
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
@@ -85,6 +101,33 @@ impl BalanceBeam {
         BalanceBeam { child, address }
     }
 
+    /// Returns the most memory balancebeam has had resident at any one time so far, in kB (the
+    /// VmHWM line of /proc/<pid>/status).
+    #[allow(dead_code)]
+    pub fn peak_memory_kb(&self) -> usize {
+        let pid = self.child.id().expect("Balancebeam has already exited");
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid))
+            .expect("Could not read balancebeam's /proc status");
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .expect("No VmHWM line in balancebeam's /proc status")
+    }
+
+    /// Sends balancebeam SIGHUP, asking it to reload its config file, and gives it a moment to do
+    /// so.
+    #[allow(dead_code)]
+    pub async fn reload_config(&self) {
+        let pid = self.child.id().expect("Balancebeam has already exited");
+        nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(pid as i32),
+            nix::sys::signal::Signal::SIGHUP,
+        )
+        .expect("Failed to send SIGHUP to balancebeam");
+        sleep(Duration::from_millis(500)).await;
+    }
+
     #[allow(dead_code)]
     pub async fn get(&self, path: &str) -> Result<String, reqwest::Error> {
         let client = reqwest::Client::new();
@@ -0,0 +1,114 @@
+use crate::common::server::Server;
+use async_trait::async_trait;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response};
+use rand::Rng;
+use std::sync::{atomic, Arc};
+use tokio::sync::oneshot;
+
+/// The path that FixedResponseServer answers with its fixed response
+pub const HEALTH_PATH: &str = "/health";
+
+#[derive(Debug)]
+struct ServerState {
+    pub requests_received: atomic::AtomicUsize,
+    /// Status and body of the response to HEALTH_PATH
+    health_status: u16,
+    health_body: &'static str,
+    address: String,
+}
+
+/// Requests to HEALTH_PATH get the fixed response. Anything else gets a 200 naming this server, so
+/// that tests can tell which upstream a request was routed to.
+async fn respond(
+    state: Arc<ServerState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.uri().path() == HEALTH_PATH {
+        Ok(Response::builder()
+            .status(state.health_status)
+            .body(Body::from(state.health_body))
+            .unwrap())
+    } else {
+        state
+            .requests_received
+            .fetch_add(1, atomic::Ordering::SeqCst);
+        Ok(Response::new(Body::from(format!(
+            "served by {}",
+            state.address
+        ))))
+    }
+}
+
+/// A server whose health check answer is fixed. Unlike the other servers, the request count it
+/// reports leaves out requests to HEALTH_PATH, so it only counts requests routed to it.
+pub struct FixedResponseServer {
+    shutdown_signal_sender: oneshot::Sender<()>,
+    server_task: tokio::task::JoinHandle<()>,
+    pub address: String,
+    state: Arc<ServerState>,
+}
+
+impl FixedResponseServer {
+    #[allow(dead_code)]
+    pub async fn new(health_status: u16, health_body: &'static str) -> FixedResponseServer {
+        let mut rng = rand::rng();
+        let bind_addr_string = format!("127.0.0.1:{}", rng.random_range(1024..65535));
+        let bind_addr = bind_addr_string.parse().unwrap();
+        // Create a one-shot channel that can be used to tell the server to shut down
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        // Start a separate server task
+        let server_state = Arc::new(ServerState {
+            requests_received: atomic::AtomicUsize::new(0),
+            health_status,
+            health_body,
+            address: bind_addr_string.clone(),
+        });
+        let server_task_state = server_state.clone();
+        let server_task = tokio::spawn(async move {
+            let service = make_service_fn(|_| {
+                let server_task_state = server_task_state.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |req| {
+                        respond(server_task_state.clone(), req)
+                    }))
+                }
+            });
+            let server = hyper::Server::bind(&bind_addr)
+                .serve(service)
+                .with_graceful_shutdown(async {
+                    shutdown_rx.await.ok();
+                });
+            // Start serving and wait for the server to exit
+            if let Err(e) = server.await {
+                log::error!("Error in FixedResponseServer: {}", e);
+            }
+        });
+
+        FixedResponseServer {
+            shutdown_signal_sender: shutdown_tx,
+            server_task,
+            state: server_state,
+            address: bind_addr_string,
+        }
+    }
+}
+
+#[async_trait]
+impl Server for FixedResponseServer {
+    async fn stop(self: Box<Self>) -> usize {
+        // Tell the hyper server to stop
+        let _ = self.shutdown_signal_sender.send(());
+        // Wait for it to stop
+        self.server_task
+            .await
+            .expect("FixedResponseServer server task panicked");
+
+        self.state.requests_received.load(atomic::Ordering::SeqCst)
+    }
+
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+}
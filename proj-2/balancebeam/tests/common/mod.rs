@@ -1,13 +1,30 @@
 mod balancebeam;
 mod echo_server;
 mod error_server;
+mod fixed_response_server;
+mod header_server;
 mod server;
+mod slow_server;
+mod tls_server;
+mod upgrade_server;
 
 use std::sync;
 
 pub use balancebeam::BalanceBeam;
 pub use echo_server::EchoServer;
+#[allow(unused_imports)]
+pub use error_server::ErrorServer;
+#[allow(unused_imports)]
+pub use fixed_response_server::{FixedResponseServer, HEALTH_PATH};
+#[allow(unused_imports)]
+pub use header_server::HeaderServer;
 pub use server::Server;
+#[allow(unused_imports)]
+pub use slow_server::SlowServer;
+#[allow(unused_imports)]
+pub use tls_server::{TlsServer, TLS_CA_PATH};
+#[allow(unused_imports)]
+pub use upgrade_server::UpgradeServer;
 
 static INIT_TESTS: sync::Once = sync::Once::new();
 
@@ -0,0 +1,118 @@
+use crate::common::server::Server;
+use async_trait::async_trait;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// CA certificate that signed TlsServer's certificate, for balancebeam's --upstream-ca
+#[allow(dead_code)]
+pub const TLS_CA_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls/ca.pem");
+const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls/server.pem");
+const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls/server.key");
+
+/// Reads one request (headers only) and answers it with a body containing the request line, then
+/// closes the connection.
+fn respond(mut conn: impl Read + Write) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0_u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let bytes_read = conn.read(&mut buffer)?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..bytes_read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let body = format!(
+        "served over TLS: {}",
+        request.lines().next().unwrap_or_default()
+    );
+    write!(
+        conn,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    conn.flush()
+}
+
+/// An HTTPS server with a certificate for 127.0.0.1, localhost and upstream.test, signed by the CA
+/// at TLS_CA_PATH. It answers each request with the request line, one request per connection.
+pub struct TlsServer {
+    pub address: String,
+    requests_received: Arc<AtomicUsize>,
+    stopping: Arc<AtomicBool>,
+    accept_thread: thread::JoinHandle<()>,
+}
+
+impl TlsServer {
+    #[allow(dead_code)]
+    pub async fn new() -> TlsServer {
+        let certs = CertificateDer::pem_file_iter(CERT_PATH)
+            .expect("Could not read test certificate")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Could not parse test certificate");
+        let key = PrivateKeyDer::from_pem_file(KEY_PATH).expect("Could not read test key");
+        let config = Arc::new(
+            rustls::ServerConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .expect("Invalid test certificate"),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let requests_received = Arc::new(AtomicUsize::new(0));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let (thread_requests, thread_stopping) = (requests_received.clone(), stopping.clone());
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let conn = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut tls = rustls::StreamOwned::new(conn, stream);
+                match respond(&mut tls) {
+                    Ok(()) => {
+                        thread_requests.fetch_add(1, Ordering::SeqCst);
+                        tls.conn.send_close_notify();
+                        let _ = tls.flush();
+                    }
+                    Err(err) => log::info!("TlsServer: {}", err),
+                }
+            }
+        });
+        TlsServer {
+            address,
+            requests_received,
+            stopping,
+            accept_thread,
+        }
+    }
+}
+
+#[async_trait]
+impl Server for TlsServer {
+    async fn stop(self: Box<Self>) -> usize {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so it notices
+        let _ = TcpStream::connect(&self.address);
+        self.accept_thread
+            .join()
+            .expect("TlsServer's thread panicked");
+        self.requests_received.load(Ordering::SeqCst)
+    }
+
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+}
@@ -0,0 +1,118 @@
+use crate::common::server::Server;
+use async_trait::async_trait;
+use rand::Rng;
+use std::sync::{atomic, Arc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+#[derive(Debug)]
+struct ServerState {
+    pub requests_received: atomic::AtomicUsize,
+}
+
+/// Accepts whatever handshake the client sends with a 101 Switching Protocols response, then echoes
+/// back any bytes it receives until the client hangs up.
+async fn accept_upgrade(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0_u8; 512];
+    let headers_len = loop {
+        if let Some(pos) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let bytes_read = stream.read(&mut chunk).await?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..bytes_read]);
+    };
+    stream
+        .write_all(
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n",
+        )
+        .await?;
+    // Anything the client sent after the handshake is already tunneled data
+    stream.write_all(&buffer[headers_len..]).await?;
+    loop {
+        let bytes_read = stream.read(&mut chunk).await?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        stream.write_all(&chunk[..bytes_read]).await?;
+    }
+}
+
+pub struct UpgradeServer {
+    shutdown_signal_sender: oneshot::Sender<()>,
+    server_task: tokio::task::JoinHandle<()>,
+    pub address: String,
+    state: Arc<ServerState>,
+}
+
+impl UpgradeServer {
+    #[allow(dead_code)]
+    pub async fn new() -> UpgradeServer {
+        let mut rng = rand::rng();
+        UpgradeServer::new_at_address(format!("127.0.0.1:{}", rng.random_range(1024..65535))).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn new_at_address(bind_addr_string: String) -> UpgradeServer {
+        let listener = TcpListener::bind(&bind_addr_string)
+            .await
+            .expect("UpgradeServer could not bind to address");
+        // Create a one-shot channel that can be used to tell the server to shut down
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+
+        // Start a separate server task
+        let server_state = Arc::new(ServerState {
+            requests_received: atomic::AtomicUsize::new(0),
+        });
+        let server_task_state = server_state.clone();
+        let server_task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            server_task_state
+                                .requests_received
+                                .fetch_add(1, atomic::Ordering::SeqCst);
+                            tokio::spawn(async move {
+                                if let Err(e) = accept_upgrade(stream).await {
+                                    log::error!("Error in UpgradeServer connection: {}", e);
+                                }
+                            });
+                        }
+                        Err(e) => log::error!("Error in UpgradeServer: {}", e),
+                    },
+                }
+            }
+        });
+
+        UpgradeServer {
+            shutdown_signal_sender: shutdown_tx,
+            server_task,
+            state: server_state,
+            address: bind_addr_string,
+        }
+    }
+}
+
+#[async_trait]
+impl Server for UpgradeServer {
+    async fn stop(self: Box<Self>) -> usize {
+        // Tell the accept loop to stop
+        let _ = self.shutdown_signal_sender.send(());
+        // Wait for it to stop
+        self.server_task
+            .await
+            .expect("UpgradeServer server task panicked");
+
+        self.state.requests_received.load(atomic::Ordering::SeqCst)
+    }
+
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+}
@@ -1,6 +1,7 @@
 /* The following exercises were borrowed from Will Crichton's CS 242 Rust lab. */
 
 use std::collections::HashSet;
+use std::ops::Add;
 
 fn main() {
     println!("Hi! Try running \"cargo test\" to run tests.");
@@ -42,6 +43,52 @@ fn dedup(v: &mut Vec<i32>) {
     }
 }
 
+/// Takes two slices of numbers and returns a new vector whose elements are the sums of the
+/// corresponding elements of a and b. Returns an error if a and b have different lengths, rather
+/// than silently dropping the extra elements like zip would.
+fn zip_add(a: &[i32], b: &[i32]) -> Result<Vec<i32>, String> {
+    zip_add_generic(a, b)
+}
+
+/// Does the same thing as zip_add, but works for any type that can be added together.
+fn zip_add_generic<T: Add<Output = T> + Copy>(a: &[T], b: &[T]) -> Result<Vec<T>, String> {
+    if a.len() != b.len() {
+        return Err(format!("length mismatch: {} vs {}", a.len(), b.len()));
+    }
+    Ok(a.iter().zip(b.iter()).map(|(&x, &y)| x + y).collect())
+}
+
+/// Does the same thing as zip_add, but adds b into a directly (in place). On a length mismatch, a
+/// is left untouched.
+fn zip_add_inplace(a: &mut [i32], b: &[i32]) -> Result<(), String> {
+    if a.len() != b.len() {
+        return Err(format!("length mismatch: {} vs {}", a.len(), b.len()));
+    }
+    for (x, y) in a.iter_mut().zip(b.iter()) {
+        *x += y;
+    }
+    Ok(())
+}
+
+/// Returns the running total of v: element i is the sum of v[0] through v[i].
+fn running_total(v: &[i32]) -> Vec<i32> {
+    v.iter()
+        .scan(0, |total, &x| {
+            *total += x;
+            Some(*total)
+        })
+        .collect()
+}
+
+/// Returns the sum of each run of k consecutive elements of v, from the front. There are no such
+/// runs (so the result is empty) if k is 0 or bigger than v.
+fn window_sums(v: &[i32], k: usize) -> Vec<i32> {
+    if k == 0 {
+        return Vec::new();
+    }
+    v.windows(k).map(|window| window.iter().sum()).collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -64,4 +111,48 @@ mod test {
         dedup(&mut v);
         assert_eq!(v, vec![3, 1, 0, 4]);
     }
+
+    #[test]
+    fn test_zip_add() {
+        assert_eq!(zip_add(&[1, 2, 3], &[10, 20, 30]), Ok(vec![11, 22, 33]));
+        assert_eq!(zip_add(&[], &[]), Ok(vec![]));
+        assert_eq!(
+            zip_add_generic(&[0.5, 1.5], &[1.0, 2.0]),
+            Ok(vec![1.5, 3.5])
+        );
+    }
+
+    #[test]
+    fn test_zip_add_mismatched_lengths() {
+        assert!(zip_add(&[1, 2, 3], &[1, 2]).is_err());
+        assert!(zip_add_generic(&[1_u8], &[]).is_err());
+        let mut v = vec![1, 2];
+        assert!(zip_add_inplace(&mut v, &[1, 2, 3]).is_err());
+        assert_eq!(v, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_zip_add_inplace() {
+        let mut v = vec![1, 2, 3];
+        assert_eq!(zip_add_inplace(&mut v, &[10, 20, 30]), Ok(()));
+        assert_eq!(v, vec![11, 22, 33]);
+    }
+
+    #[test]
+    fn test_running_total() {
+        assert_eq!(running_total(&[1, 2, 3, 4]), vec![1, 3, 6, 10]);
+        assert_eq!(running_total(&[5, -5, 2]), vec![5, 0, 2]);
+        assert_eq!(running_total(&[]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_window_sums() {
+        let v = [1, 2, 3, 4];
+        assert_eq!(window_sums(&v, 1), vec![1, 2, 3, 4]);
+        assert_eq!(window_sums(&v, 2), vec![3, 5, 7]);
+        assert_eq!(window_sums(&v, v.len()), vec![10]);
+        assert!(window_sums(&v, v.len() + 1).is_empty());
+        assert!(window_sums(&v, 0).is_empty());
+        assert!(window_sums(&[], 1).is_empty());
+    }
 }
@@ -0,0 +1,68 @@
+//! Deciding which lines count as the same when diffing, for the options that make the comparison
+//! less strict than exact equality.
+
+use regex::Regex;
+
+/// How lines are compared. The default compares them exactly.
+#[derive(Debug, Default)]
+pub struct LineComparison {
+    /// Ignore changes that only insert or delete blank lines (`-B`)
+    pub ignore_blank_lines: bool,
+    /// Ignore changes whose lines all match one of these patterns (`-I`)
+    pub ignore_matching_lines: Vec<Regex>,
+}
+
+impl LineComparison {
+    /// Returns true if changes to `line` alone shouldn't count as differences.
+    pub fn is_ignorable(&self, line: &str) -> bool {
+        (self.ignore_blank_lines && line.trim().is_empty())
+            || self
+                .ignore_matching_lines
+                .iter()
+                .any(|pattern| pattern.is_match(line))
+    }
+
+    /// Returns true if `line1` and `line2` should be treated as the same line. Two lines that are
+    /// both ignorable are the same, so e.g. a timestamp line that changed between the files pairs
+    /// up with its counterpart.
+    pub fn equivalent(&self, line1: &str, line2: &str) -> bool {
+        line1 == line2 || (self.is_ignorable(line1) && self.is_ignorable(line2))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exact_comparison() {
+        let exact = LineComparison::default();
+        assert!(exact.equivalent("a", "a"));
+        assert!(!exact.equivalent("", " "));
+        assert!(!exact.is_ignorable(""));
+    }
+
+    #[test]
+    fn test_ignore_blank_lines() {
+        let blank = LineComparison {
+            ignore_blank_lines: true,
+            ..LineComparison::default()
+        };
+        assert!(blank.is_ignorable(""));
+        assert!(blank.is_ignorable(" \t"));
+        assert!(!blank.is_ignorable(" x "));
+        assert!(blank.equivalent("", "   "));
+        assert!(!blank.equivalent("", "x"));
+    }
+
+    #[test]
+    fn test_ignore_matching_lines() {
+        let years = LineComparison {
+            ignore_matching_lines: vec![Regex::new(r"^// Copyright \d{4}").unwrap()],
+            ..LineComparison::default()
+        };
+        assert!(years.equivalent("// Copyright 2019 me", "// Copyright 2024 you"));
+        assert!(!years.equivalent("// Copyright 2019", "fn main() {}"));
+        assert!(!years.is_ignorable(""));
+    }
+}
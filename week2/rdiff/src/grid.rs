@@ -9,8 +9,8 @@ impl Grid {
     /// Returns a Grid of the specified size, with all elements pre-initialized to zero.
     pub fn new(num_rows: usize, num_cols: usize) -> Grid {
         Grid {
-            num_rows: num_rows,
-            num_cols: num_cols,
+            num_rows,
+            num_cols,
             // This syntax uses the vec! macro to create a vector of zeros, initialized to a
             // specific length
             // https://stackoverflow.com/a/29530932
@@ -18,6 +18,15 @@ impl Grid {
         }
     }
 
+    /// Returns how many bytes the elements of a Grid of the specified size take up, saturating at
+    /// usize::MAX if that's more than can be addressed.
+    pub fn bytes_needed(num_rows: usize, num_cols: usize) -> usize {
+        num_rows
+            .checked_mul(num_cols)
+            .and_then(|elems| elems.checked_mul(std::mem::size_of::<usize>()))
+            .unwrap_or(usize::MAX)
+    }
+
     pub fn size(&self) -> (usize, usize) {
         (self.num_rows, self.num_cols)
     }
@@ -103,4 +112,11 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_bytes_needed() {
+        assert_eq!(Grid::bytes_needed(4, 3), 12 * std::mem::size_of::<usize>());
+        assert_eq!(Grid::bytes_needed(0, 3), 0);
+        assert_eq!(Grid::bytes_needed(usize::MAX / 2, 3), usize::MAX);
+    }
 }
@@ -1,14 +1,20 @@
+extern crate regex;
+
+use compare::LineComparison;
 use grid::Grid;
+use regex::Regex;
 use std::cmp::max;
 // For lcs()
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 // For read_file_lines()
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read};
 // For read_file_lines()
 use std::process;
 
+mod compare;
 pub mod grid;
+mod unified;
 
 /// Reads the file at the supplied path, and returns a vector of strings.
 fn read_file_lines(filename: &String) -> Result<Vec<String>, io::Error> {
@@ -21,7 +27,71 @@ fn read_file_lines(filename: &String) -> Result<Vec<String>, io::Error> {
     Ok(res)
 }
 
-fn lcs(seq1: &Vec<String>, seq2: &Vec<String>) -> Grid {
+/// How much of a file is checked for NUL bytes when deciding whether it's binary. GNU diff also
+/// only looks at the start of each file.
+const BINARY_SNIFF_LEN: usize = 8192;
+
+/// Returns true if `contents` looks like a binary file rather than text, i.e. there's a NUL byte
+/// near the start.
+fn looks_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+/// Returns true if the file at the supplied path looks binary, reading only as much of it as
+/// `looks_binary` checks.
+fn is_binary_file(filename: &String) -> Result<bool, io::Error> {
+    let mut start = Vec::with_capacity(BINARY_SNIFF_LEN);
+    File::open(filename)?
+        .take(BINARY_SNIFF_LEN as u64)
+        .read_to_end(&mut start)?;
+    Ok(looks_binary(&start))
+}
+
+/// Returns true if the files at the supplied paths have the same contents, reading them a chunk at
+/// a time rather than whole.
+fn same_contents(filename1: &String, filename2: &String) -> Result<bool, io::Error> {
+    if fs::metadata(filename1)?.len() != fs::metadata(filename2)?.len() {
+        return Ok(false);
+    }
+    let mut reader1 = io::BufReader::new(File::open(filename1)?);
+    let mut reader2 = io::BufReader::new(File::open(filename2)?);
+    loop {
+        let chunk1 = reader1.fill_buf()?;
+        if chunk1.is_empty() {
+            // Same length, so the second file has run out too
+            return Ok(true);
+        }
+        let chunk2 = reader2.fill_buf()?;
+        let len = chunk1.len().min(chunk2.len());
+        if len == 0 || chunk1[..len] != chunk2[..len] {
+            return Ok(false);
+        }
+        reader1.consume(len);
+        reader2.consume(len);
+    }
+}
+
+/// Returns the number of lines shared at the start and at the end of both sequences. The prefix
+/// and suffix never overlap, so `seq1[prefix..seq1.len() - suffix]` and
+/// `seq2[prefix..seq2.len() - suffix]` are the only parts that actually need to be diffed. Real
+/// diff implementations do this first because files being compared usually differ in only a few
+/// places, and trimming shrinks the LCS grid dramatically.
+fn common_affixes(seq1: &[String], seq2: &[String], cmp: &LineComparison) -> (usize, usize) {
+    let prefix = seq1
+        .iter()
+        .zip(seq2.iter())
+        .take_while(|(line1, line2)| cmp.equivalent(line1, line2))
+        .count();
+    let suffix = seq1[prefix..]
+        .iter()
+        .rev()
+        .zip(seq2[prefix..].iter().rev())
+        .take_while(|(line1, line2)| cmp.equivalent(line1, line2))
+        .count();
+    (prefix, suffix)
+}
+
+fn lcs(seq1: &[String], seq2: &[String], cmp: &LineComparison) -> Grid {
     // Note: Feel free to use unwrap() in this code, as long as you're basically certain it'll
     // never happen. Conceptually, unwrap() is justified here, because there's not really any error
     // condition you're watching out for (i.e. as long as your code is written correctly, nothing
@@ -36,9 +106,9 @@ fn lcs(seq1: &Vec<String>, seq2: &Vec<String>) -> Grid {
     for j in 0..n + 1 {
         c.set(0, j, 0).unwrap();
     }
-    for i in 0..m {
-        for j in 0..n {
-            if seq1[i] == seq2[j] {
+    for (i, line1) in seq1.iter().enumerate() {
+        for (j, line2) in seq2.iter().enumerate() {
+            if cmp.equivalent(line1, line2) {
                 c.set(i + 1, j + 1, c.get(i, j).unwrap() + 1).unwrap();
             } else {
                 c.set(
@@ -53,34 +123,394 @@ fn lcs(seq1: &Vec<String>, seq2: &Vec<String>) -> Grid {
     c
 }
 
-fn print_diff(lcs_table: &Grid, lines1: &Vec<String>, lines2: &Vec<String>, i: usize, j: usize) {
-    if i > 0 && j > 0 && lines1[i - 1] == lines2[j - 1] {
-        print_diff(lcs_table, lines1, lines2, i - 1, j - 1);
-        println!("  {}", lines1[i - 1]);
+/// A single line of a diff: a line present in both files, only in the second file, or only in
+/// the first file. Added and deleted lines that the comparison ignores (with `-B` or `-I`) are
+/// kept apart from real changes, so they can be shown without counting as differences.
+#[derive(Debug, PartialEq)]
+enum Edit<'a> {
+    Unchanged(&'a str),
+    Added(&'a str),
+    Deleted(&'a str),
+    IgnoredAdded(&'a str),
+    IgnoredDeleted(&'a str),
+}
+
+impl Edit<'_> {
+    /// Returns true for lines that count as a difference between the files.
+    fn is_change(&self) -> bool {
+        matches!(self, Edit::Added(_) | Edit::Deleted(_))
+    }
+}
+
+impl std::fmt::Display for Edit<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edit::Unchanged(line) | Edit::IgnoredAdded(line) | Edit::IgnoredDeleted(line) => {
+                write!(f, "  {}", line)
+            }
+            Edit::Added(line) => write!(f, "> {}", line),
+            Edit::Deleted(line) => write!(f, "< {}", line),
+        }
+    }
+}
+
+/// Walks the LCS table back from (i, j), appending the edits that turn `lines1[..i]` into
+/// `lines2[..j]` to `edits` in order.
+fn collect_edits<'a>(
+    lcs_table: &Grid,
+    lines1: &'a [String],
+    lines2: &'a [String],
+    cmp: &LineComparison,
+    i: usize,
+    j: usize,
+    edits: &mut Vec<Edit<'a>>,
+) {
+    if i > 0 && j > 0 && cmp.equivalent(&lines1[i - 1], &lines2[j - 1]) {
+        collect_edits(lcs_table, lines1, lines2, cmp, i - 1, j - 1, edits);
+        edits.push(Edit::Unchanged(&lines1[i - 1]));
     } else if j > 0 && (i == 0 || lcs_table.get(i, j - 1) >= lcs_table.get(i - 1, j)) {
-        print_diff(lcs_table, lines1, lines2, i, j - 1);
-        println!("> {}", lines2[j - 1]);
+        collect_edits(lcs_table, lines1, lines2, cmp, i, j - 1, edits);
+        edits.push(Edit::Added(&lines2[j - 1]));
     } else if i > 0 && (j == 0 || lcs_table.get(i, j - 1) < lcs_table.get(i - 1, j)) {
-        print_diff(lcs_table, lines1, lines2, i - 1, j);
-        println!("< {}", lines1[i - 1]);
+        collect_edits(lcs_table, lines1, lines2, cmp, i - 1, j, edits);
+        edits.push(Edit::Deleted(&lines1[i - 1]));
+    }
+}
+
+/// Marks the groups of changes that consist only of ignorable lines (e.g. a run of inserted blank
+/// lines with `-B`) as ignored. Groups that mix ignorable lines with real changes are left alone.
+fn ignore_changes(edits: &mut [Edit], cmp: &LineComparison) {
+    let mut start = 0;
+    while start < edits.len() {
+        if !edits[start].is_change() {
+            start += 1;
+            continue;
+        }
+        let end = start
+            + edits[start..]
+                .iter()
+                .take_while(|edit| edit.is_change())
+                .count();
+        let ignorable = edits[start..end].iter().all(|edit| match edit {
+            Edit::Added(line) | Edit::Deleted(line) => cmp.is_ignorable(line),
+            _ => false,
+        });
+        if ignorable {
+            for edit in &mut edits[start..end] {
+                *edit = match *edit {
+                    Edit::Added(line) => Edit::IgnoredAdded(line),
+                    Edit::Deleted(line) => Edit::IgnoredDeleted(line),
+                    _ => unreachable!(),
+                };
+            }
+        }
+        start = end;
+    }
+}
+
+/// Returns the edits that turn `a` into `b`, from the first line to the last.
+#[cfg(test)]
+fn diff<'a>(a: &'a [String], b: &'a [String]) -> Vec<Edit<'a>> {
+    diff_with(a, b, &LineComparison::default())
+}
+
+/// Like `diff`, but compares lines with `cmp`. Lines that `cmp` treats as the same but that aren't
+/// identical are shown with their text from `a`.
+#[cfg(test)]
+fn diff_with<'a>(a: &'a [String], b: &'a [String], cmp: &LineComparison) -> Vec<Edit<'a>> {
+    diff_within(a, b, cmp, usize::MAX).unwrap()
+}
+
+/// Like `diff_with`, but refuses to allocate an LCS grid bigger than `max_memory` bytes. If the
+/// grid would be too big, returns Err with the number of bytes it would need instead.
+fn diff_within<'a>(
+    a: &'a [String],
+    b: &'a [String],
+    cmp: &LineComparison,
+    max_memory: usize,
+) -> Result<Vec<Edit<'a>>, usize> {
+    // Only run LCS on the lines between the common prefix and suffix
+    let (prefix, suffix) = common_affixes(a, b, cmp);
+    let a_middle = &a[prefix..a.len() - suffix];
+    let b_middle = &b[prefix..b.len() - suffix];
+    let needed = Grid::bytes_needed(a_middle.len() + 1, b_middle.len() + 1);
+    if needed > max_memory {
+        return Err(needed);
+    }
+    let mut edits: Vec<Edit> = a[..prefix]
+        .iter()
+        .map(|line| Edit::Unchanged(line))
+        .collect();
+    let grid = lcs(a_middle, b_middle, cmp);
+    collect_edits(
+        &grid,
+        a_middle,
+        b_middle,
+        cmp,
+        a_middle.len(),
+        b_middle.len(),
+        &mut edits,
+    );
+    edits.extend(
+        a[a.len() - suffix..]
+            .iter()
+            .map(|line| Edit::Unchanged(line)),
+    );
+    ignore_changes(&mut edits, cmp);
+    Ok(edits)
+}
+
+/// Summarizes a diff as a single line: how many lines were added, deleted, and left unchanged,
+/// and what percentage of all lines were common to both files. Ignored changes count as unchanged.
+fn format_stats(edits: &[Edit]) -> String {
+    let (mut added, mut deleted, mut unchanged) = (0, 0, 0);
+    for edit in edits {
+        match edit {
+            Edit::Unchanged(_) | Edit::IgnoredAdded(_) | Edit::IgnoredDeleted(_) => unchanged += 1,
+            Edit::Added(_) => added += 1,
+            Edit::Deleted(_) => deleted += 1,
+        }
+    }
+    // Two empty files are identical
+    let similarity = if edits.is_empty() {
+        100.0
+    } else {
+        100.0 * unchanged as f64 / edits.len() as f64
+    };
+    format!(
+        "added: {}, deleted: {}, unchanged: {}, similarity: {:.1}%",
+        added, deleted, unchanged, similarity
+    )
+}
+
+/// Default limit on the memory used for the LCS grid, in bytes. Two files that differ in 10,000
+/// lines each fit comfortably.
+const DEFAULT_MAX_MEMORY: usize = 1 << 30;
+
+/// Parses a size in bytes, optionally followed by K, M or G (powers of 1024).
+fn parse_size(text: &str) -> Option<usize> {
+    let (digits, unit) = match text.char_indices().last()? {
+        (idx, 'K') | (idx, 'k') => (&text[..idx], 1 << 10),
+        (idx, 'M') | (idx, 'm') => (&text[..idx], 1 << 20),
+        (idx, 'G') | (idx, 'g') => (&text[..idx], 1 << 30),
+        _ => (text, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// Default total width of side-by-side output, in characters.
+const DEFAULT_SIDE_BY_SIDE_WIDTH: usize = 80;
+
+/// Pads or truncates `line` to exactly `width` characters. Truncated lines end with an ellipsis so
+/// it's clear something was cut off.
+fn fit_to_column(line: &str, width: usize) -> String {
+    if line.chars().count() > width {
+        let mut truncated: String = line.chars().take(width.saturating_sub(1)).collect();
+        truncated.push('…');
+        truncated
     } else {
-        println!();
+        format!("{:<width$}", line, width = width)
+    }
+}
+
+/// Renders a diff in two columns, like `diff -y`: the first file on the left, the second on the
+/// right, and a gutter marker between them (` ` unchanged, `|` changed, `<` deleted, `>` added).
+/// Deleted and added lines from the same hunk are paired up as changes. Ignored lines are shown in
+/// their own column with no marker.
+fn format_side_by_side(edits: &[Edit], width: usize) -> Vec<String> {
+    // Each column gets half of what's left after the 3-character gutter
+    let column = width.saturating_sub(3) / 2;
+    let row = |left: &str, marker: char, right: &str| {
+        format!(
+            "{} {} {}",
+            fit_to_column(left, column),
+            marker,
+            fit_to_column(right, column)
+        )
+        .trim_end()
+        .to_string()
+    };
+
+    let mut rows = Vec::new();
+    let mut i = 0;
+    while i < edits.len() {
+        match edits[i] {
+            Edit::Unchanged(line) => {
+                rows.push(row(line, ' ', line));
+                i += 1;
+                continue;
+            }
+            Edit::IgnoredDeleted(line) => {
+                rows.push(row(line, ' ', ""));
+                i += 1;
+                continue;
+            }
+            Edit::IgnoredAdded(line) => {
+                rows.push(row("", ' ', line));
+                i += 1;
+                continue;
+            }
+            Edit::Added(_) | Edit::Deleted(_) => {}
+        }
+        // Gather the whole hunk of changed lines
+        let (mut deleted, mut added) = (Vec::new(), Vec::new());
+        while i < edits.len() {
+            match edits[i] {
+                Edit::Deleted(line) => deleted.push(line),
+                Edit::Added(line) => added.push(line),
+                _ => break,
+            }
+            i += 1;
+        }
+        for j in 0..deleted.len().max(added.len()) {
+            rows.push(match (deleted.get(j), added.get(j)) {
+                (Some(left), Some(right)) => row(left, '|', right),
+                (Some(left), None) => row(left, '<', ""),
+                (None, Some(right)) => row("", '>', right),
+                (None, None) => unreachable!(),
+            });
+        }
+    }
+    rows
+}
+
+/// Applies the unified diff in `patch_file` to `filename` and prints the result. Exits with status 1
+/// if the patch doesn't match the file.
+fn apply(patch_file: &String, filename: &String) {
+    let patch = read_file_lines(patch_file).unwrap();
+    let original = read_file_lines(filename).unwrap();
+    match unified::apply_patch(&original, &patch) {
+        Ok(patched) => {
+            for line in patched {
+                println!("{}", line);
+            }
+        }
+        Err(err) => {
+            eprintln!("rdiff: {}: {}", patch_file, err);
+            process::exit(1);
+        }
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
+    let mut stats_only = false;
+    let mut side_by_side = false;
+    let mut unified = false;
+    let mut patch_file = None;
+    let mut width = DEFAULT_SIDE_BY_SIDE_WIDTH;
+    let mut max_memory = DEFAULT_MAX_MEMORY;
+    let mut cmp = LineComparison::default();
+    let mut filenames = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Print a one-line summary instead of the full diff
+            "--stats" => stats_only = true,
+            "-y" | "--side-by-side" => side_by_side = true,
+            "-u" | "--unified" => unified = true,
+            // Apply the given unified diff to a file instead of diffing two files
+            "--apply" => match args.next() {
+                Some(path) => patch_file = Some(path),
+                None => {
+                    println!("--apply expects a patch file.");
+                    process::exit(1);
+                }
+            },
+            "-B" | "--ignore-blank-lines" => cmp.ignore_blank_lines = true,
+            "-I" | "--ignore-matching-lines" => match args.next().map(|p| Regex::new(&p)) {
+                Some(Ok(pattern)) => cmp.ignore_matching_lines.push(pattern),
+                Some(Err(err)) => {
+                    println!("Invalid pattern for {}: {}", arg, err);
+                    process::exit(1);
+                }
+                None => {
+                    println!("{} expects a regular expression.", arg);
+                    process::exit(1);
+                }
+            },
+            "-W" | "--width" => match args.next().and_then(|w| w.parse().ok()) {
+                Some(w) => width = w,
+                None => {
+                    println!("{} expects a number of columns.", arg);
+                    process::exit(1);
+                }
+            },
+            // Give up on inputs whose LCS grid would need more than this many bytes
+            "--max-memory" => match args.next().and_then(|m| parse_size(&m)) {
+                Some(m) => max_memory = m,
+                None => {
+                    println!("--max-memory expects a size in bytes, e.g. 512M.");
+                    process::exit(1);
+                }
+            },
+            _ => filenames.push(arg),
+        }
+    }
+    if let Some(patch_file) = patch_file {
+        if filenames.len() != 1 {
+            println!("Usage: rdiff --apply <patch file> <file to patch>");
+            process::exit(1);
+        }
+        apply(&patch_file, &filenames[0]);
+        return;
+    }
+    if filenames.len() < 2 {
         println!("Too few arguments.");
         process::exit(1);
     }
-    let filename1 = &args[1];
-    let filename2 = &args[2];
+    let filename1 = &filenames[0];
+    let filename2 = &filenames[1];
+
+    // Line-based diffing makes no sense for binary files, so just say whether they're the same
+    let or_exit = |result: Result<bool, io::Error>, what: &str| {
+        result.unwrap_or_else(|e| {
+            eprintln!("rdiff: {}: {}", what, e);
+            process::exit(2);
+        })
+    };
+    let binary1 = or_exit(is_binary_file(filename1), filename1);
+    let binary2 = or_exit(is_binary_file(filename2), filename2);
+    if binary1 || binary2 {
+        let both = format!("{} and {}", filename1, filename2);
+        if or_exit(same_contents(filename1, filename2), &both) {
+            println!("Binary files {} and {} are identical", filename1, filename2);
+            process::exit(0);
+        }
+        println!("Binary files {} and {} differ", filename1, filename2);
+        process::exit(1);
+    }
 
     let a = read_file_lines(filename1).unwrap();
     let b = read_file_lines(filename2).unwrap();
-    let grid = lcs(&a, &b);
-    print_diff(&grid, &a, &b, a.len(), b.len());
+
+    let edits = match diff_within(&a, &b, &cmp, max_memory) {
+        Ok(edits) => edits,
+        Err(needed) => {
+            eprintln!(
+                "rdiff: inputs too large: diffing {} and {} needs {} bytes, more than the \
+                 --max-memory limit of {}. Raise --max-memory, or the files differ too much to \
+                 diff.",
+                filename1, filename2, needed, max_memory
+            );
+            process::exit(2);
+        }
+    };
+    if stats_only {
+        println!("{}", format_stats(&edits));
+    } else if unified {
+        for line in unified::format_unified(&edits, filename1, filename2, unified::DEFAULT_CONTEXT)
+        {
+            println!("{}", line);
+        }
+    } else if side_by_side {
+        for row in format_side_by_side(&edits, width) {
+            println!("{}", row);
+        }
+    } else {
+        for edit in &edits {
+            println!("{}", edit);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,6 +529,36 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary(b"plain text\nwith lines\n"));
+        assert!(!looks_binary(b""));
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00"));
+        // Only the start of the file is checked
+        let mut late_nul = vec![b'a'; BINARY_SNIFF_LEN];
+        late_nul.push(0);
+        assert!(!looks_binary(&late_nul));
+    }
+
+    #[test]
+    fn test_binary_files() {
+        let dir = std::env::temp_dir().join(format!("rdiff-binary-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("a.bin"), b"\x7fELF\x02\x01\x01\x00").unwrap();
+        fs::write(path("b.bin"), b"\x7fELF\x02\x01\x01\x00").unwrap();
+        fs::write(path("c.bin"), b"\x7fELF\x02\x01\x01\x01").unwrap();
+
+        assert!(is_binary_file(&path("a.bin")).unwrap());
+        assert!(!is_binary_file(&String::from("handout-a.txt")).unwrap());
+        assert!(is_binary_file(&path("missing")).is_err());
+        assert!(same_contents(&path("a.bin"), &path("b.bin")).unwrap());
+        assert!(!same_contents(&path("a.bin"), &path("c.bin")).unwrap());
+        assert!(!same_contents(&path("a.bin"), &String::from("handout-a.txt")).unwrap());
+        assert!(same_contents(&path("a.bin"), &path("missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_lcs() {
         let mut expected = Grid::new(5, 4);
@@ -118,8 +578,9 @@ mod test {
         println!("Expected:");
         expected.display();
         let result = lcs(
-            &"abcd".chars().map(|c| c.to_string()).collect(),
-            &"adb".chars().map(|c| c.to_string()).collect(),
+            &"abcd".chars().map(|c| c.to_string()).collect::<Vec<_>>(),
+            &"adb".chars().map(|c| c.to_string()).collect::<Vec<_>>(),
+            &LineComparison::default(),
         );
         println!("Got:");
         result.display();
@@ -130,4 +591,179 @@ mod test {
             }
         }
     }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.chars().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_common_affixes() {
+        let exact = LineComparison::default();
+        assert_eq!(
+            common_affixes(&lines("abXcd"), &lines("abYYcd"), &exact),
+            (2, 2)
+        );
+        assert_eq!(common_affixes(&lines("abc"), &lines("xyz"), &exact), (0, 0));
+        assert_eq!(common_affixes(&lines(""), &lines("abc"), &exact), (0, 0));
+        // Identical inputs are all prefix; the suffix must not count the same lines again
+        assert_eq!(common_affixes(&lines("abc"), &lines("abc"), &exact), (3, 0));
+        // When one input extends the other, the prefix and suffix can't overlap
+        assert_eq!(common_affixes(&lines("aa"), &lines("aaa"), &exact), (2, 0));
+        assert_eq!(common_affixes(&lines("ab"), &lines("aXb"), &exact), (1, 1));
+    }
+
+    #[test]
+    fn test_diff() {
+        let (a, b) = (lines("abXcd"), lines("abYcd"));
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                Edit::Unchanged("a"),
+                Edit::Unchanged("b"),
+                Edit::Deleted("X"),
+                Edit::Added("Y"),
+                Edit::Unchanged("c"),
+                Edit::Unchanged("d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let (a, b) = (lines("abcdefgh"), lines("hgfedcba"));
+        // A 9x9 grid is needed
+        let needed = Grid::bytes_needed(9, 9);
+        assert_eq!(
+            diff_within(&a, &b, &LineComparison::default(), needed - 1),
+            Err(needed)
+        );
+        assert_eq!(
+            diff_within(&a, &b, &LineComparison::default(), needed),
+            Ok(diff(&a, &b))
+        );
+        // Only the lines between the common prefix and suffix count
+        let (c, d) = (lines("xxxxxxxxaxxxxxxxx"), lines("xxxxxxxxbxxxxxxxx"));
+        assert!(diff_within(&c, &d, &LineComparison::default(), Grid::bytes_needed(2, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("8K"), Some(8192));
+        assert_eq!(parse_size("512m"), Some(512 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("G"), None);
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn test_format_side_by_side() {
+        let a: Vec<String> = vec!["same", "old", "gone", "a line that is far too long"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let b: Vec<String> = vec!["same", "new", "a line that is far too long", "extra"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            format_side_by_side(&diff(&a, &b), 23),
+            vec![
+                "same         same",
+                "old        | new",
+                "gone       <",
+                "a line th…   a line th…",
+                "           > extra",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_stats() {
+        let (a, b) = (lines("abXcd"), lines("abYYcd"));
+        assert_eq!(
+            format_stats(&diff(&a, &b)),
+            "added: 2, deleted: 1, unchanged: 4, similarity: 57.1%"
+        );
+        assert_eq!(
+            format_stats(&diff(&lines(""), &lines(""))),
+            "added: 0, deleted: 0, unchanged: 0, similarity: 100.0%"
+        );
+        assert_eq!(
+            format_stats(&diff(&lines("ab"), &lines("cd"))),
+            "added: 2, deleted: 2, unchanged: 0, similarity: 0.0%"
+        );
+    }
+
+    fn strings(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_ignore_blank_lines() {
+        let a = strings(&["fn main() {", "    a();", "    b();", "}"]);
+        let b = strings(&["fn main() {", "    a();", "", "    b();", "}", "  "]);
+        let blank = LineComparison {
+            ignore_blank_lines: true,
+            ..LineComparison::default()
+        };
+        assert!(diff(&a, &b).iter().any(|edit| edit.is_change()));
+        let edits = diff_with(&a, &b, &blank);
+        assert_eq!(
+            edits,
+            vec![
+                Edit::Unchanged("fn main() {"),
+                Edit::Unchanged("    a();"),
+                Edit::IgnoredAdded(""),
+                Edit::Unchanged("    b();"),
+                Edit::Unchanged("}"),
+                Edit::IgnoredAdded("  "),
+            ]
+        );
+        assert!(unified::format_unified(&edits, "a", "b", unified::DEFAULT_CONTEXT).is_empty());
+        assert_eq!(
+            format_stats(&edits),
+            "added: 0, deleted: 0, unchanged: 6, similarity: 100.0%"
+        );
+
+        // A blank line that's part of a real change still shows up as one
+        let c = strings(&["fn main() {", "    a();", "", "    c();", "}"]);
+        assert_eq!(
+            diff_with(&a, &c, &blank)
+                .iter()
+                .filter(|edit| edit.is_change())
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_ignore_matching_lines() {
+        let a = strings(&["// Generated 2019-01-01", "let x = 1;", "let y = 2;"]);
+        let b = strings(&[
+            "// Generated 2024-05-06",
+            "let x = 1;",
+            "// Generated by rdiff",
+            "let y = 2;",
+        ]);
+        let generated = LineComparison {
+            ignore_matching_lines: vec![Regex::new("^// Generated").unwrap()],
+            ..LineComparison::default()
+        };
+        assert_eq!(
+            diff_with(&a, &b, &generated),
+            vec![
+                // Lines treated as the same are shown as they are in the first file
+                Edit::Unchanged("// Generated 2019-01-01"),
+                Edit::Unchanged("let x = 1;"),
+                Edit::IgnoredAdded("// Generated by rdiff"),
+                Edit::Unchanged("let y = 2;"),
+            ]
+        );
+        assert_eq!(
+            format_stats(&diff(&a, &b)),
+            "added: 2, deleted: 1, unchanged: 2, similarity: 40.0%"
+        );
+    }
 }
@@ -0,0 +1,271 @@
+//! Unified diffs (the format of `diff -u`): writing them from a list of edits, and applying them
+//! to the original file to get the changed one back.
+
+use crate::Edit;
+
+/// Number of unchanged lines shown around each change in unified output.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// Formats a hunk header range starting at the 0-based line index `start`: the 1-based first line
+/// and the number of lines. An empty range gives the line *after which* it sits instead, as GNU
+/// diff does.
+fn format_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Renders a diff in unified format, with `context` unchanged lines around each group of changes.
+/// `from` and `to` are the names shown in the `---` and `+++` header lines. Identical inputs give
+/// no output at all.
+pub fn format_unified(edits: &[Edit], from: &str, to: &str, context: usize) -> Vec<String> {
+    // Where each edit sits in the two files, as 0-based line indices
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for edit in edits {
+        positions.push((old_line, new_line));
+        match edit {
+            Edit::Unchanged(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Edit::Deleted(_) | Edit::IgnoredDeleted(_) => old_line += 1,
+            Edit::Added(_) | Edit::IgnoredAdded(_) => new_line += 1,
+        }
+    }
+
+    // Group the changes into hunks: ranges of edits that include the surrounding context, merged
+    // when their contexts would touch or overlap. Ignored changes don't start a hunk, but are
+    // shown as changes if they fall inside one.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (idx, edit) in edits.iter().enumerate() {
+        if !edit.is_change() {
+            continue;
+        }
+        let start = idx.saturating_sub(context);
+        let end = (idx + 1 + context).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lines = vec![format!("--- {}", from), format!("+++ {}", to)];
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Added(_) | Edit::IgnoredAdded(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Deleted(_) | Edit::IgnoredDeleted(_)))
+            .count();
+        let (old_start, new_start) = positions[start];
+        lines.push(format!(
+            "@@ -{} +{} @@",
+            format_range(old_start, old_count),
+            format_range(new_start, new_count)
+        ));
+        for edit in hunk {
+            lines.push(match edit {
+                Edit::Unchanged(line) => format!(" {}", line),
+                Edit::Deleted(line) | Edit::IgnoredDeleted(line) => format!("-{}", line),
+                Edit::Added(line) | Edit::IgnoredAdded(line) => format!("+{}", line),
+            });
+        }
+    }
+    lines
+}
+
+/// Parses a hunk header range ("3,4", "3" or "2,0"), returning the 0-based index of the first line
+/// and the number of lines. This is the inverse of `format_range`.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (start, count) = match range.split_once(',') {
+        Some((start, count)) => (start.parse().ok()?, count.parse().ok()?),
+        None => (range.parse().ok()?, 1),
+    };
+    if count == 0 {
+        Some((start, 0))
+    } else {
+        Some((start.checked_sub(1)?, count))
+    }
+}
+
+/// Parses a hunk header like "@@ -1,3 +1,4 @@", returning the old and new ranges.
+fn parse_hunk_header(line: &str) -> Option<((usize, usize), (usize, usize))> {
+    let (ranges, _) = line.strip_prefix("@@ -")?.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    Some((parse_range(old)?, parse_range(new)?))
+}
+
+/// Applies a unified diff to the lines of the original file and returns the patched lines. Every
+/// context and deleted line in the patch has to match the original exactly, at the position the
+/// hunk header gives; otherwise the patch is rejected with a description of the first mismatch.
+/// Anything before the first hunk (such as the `---`/`+++` header) is ignored.
+pub fn apply_patch(original: &[String], patch: &[String]) -> Result<Vec<String>, String> {
+    let mut patched = Vec::new();
+    // Number of lines of the original that have been copied or replaced so far
+    let mut consumed = 0;
+    let mut hunk_number = 0;
+    let mut patch_lines = patch.iter().enumerate();
+    while let Some((line_idx, line)) = patch_lines.next() {
+        if !line.starts_with("@@") {
+            continue;
+        }
+        hunk_number += 1;
+        let ((old_start, old_count), (_, new_count)) = parse_hunk_header(line)
+            .ok_or_else(|| format!("line {}: malformed hunk header {:?}", line_idx + 1, line))?;
+        if old_start < consumed || old_start + old_count > original.len() {
+            return Err(format!(
+                "hunk {} refers to lines {}-{}, which are out of order or past the end of the file",
+                hunk_number,
+                old_start + 1,
+                old_start + old_count
+            ));
+        }
+        patched.extend_from_slice(&original[consumed..old_start]);
+        consumed = old_start;
+
+        let (mut old_seen, mut new_seen) = (0, 0);
+        while old_seen < old_count || new_seen < new_count {
+            let (line_idx, line) = patch_lines.next().ok_or_else(|| {
+                format!("hunk {} ends before all its lines were given", hunk_number)
+            })?;
+            // Some tools strip the trailing space of empty context lines
+            let (marker, text) = match line.chars().next() {
+                Some(marker) => (marker, &line[marker.len_utf8()..]),
+                None => (' ', ""),
+            };
+            let in_old = marker == ' ' || marker == '-';
+            let in_new = marker == ' ' || marker == '+';
+            if marker == '\\' {
+                // "\ No newline at end of file"
+                continue;
+            } else if !in_old && !in_new {
+                return Err(format!(
+                    "line {}: unexpected line {:?} in hunk {}",
+                    line_idx + 1,
+                    line,
+                    hunk_number
+                ));
+            } else if (in_old && old_seen == old_count) || (in_new && new_seen == new_count) {
+                return Err(format!(
+                    "hunk {} has more lines than its header says",
+                    hunk_number
+                ));
+            }
+            if in_old {
+                if original[consumed] != text {
+                    return Err(format!(
+                        "hunk {} doesn't apply: line {} is {:?}, but the patch expects {:?}",
+                        hunk_number,
+                        consumed + 1,
+                        original[consumed],
+                        text
+                    ));
+                }
+                consumed += 1;
+                old_seen += 1;
+            }
+            if in_new {
+                patched.push(text.to_string());
+                new_seen += 1;
+            }
+        }
+    }
+    patched.extend_from_slice(&original[consumed..]);
+    Ok(patched)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.chars().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_format_unified() {
+        let (a, b) = (lines("abcdefghij"), lines("abcXefghiYj"));
+        assert_eq!(
+            format_unified(&diff(&a, &b), "a.txt", "b.txt", 1),
+            vec![
+                "--- a.txt",
+                "+++ b.txt",
+                "@@ -3,3 +3,3 @@",
+                " c",
+                "-d",
+                "+X",
+                " e",
+                "@@ -9,2 +9,3 @@",
+                " i",
+                "+Y",
+                " j",
+            ]
+        );
+        // With more context the two hunks merge
+        assert_eq!(
+            format_unified(&diff(&a, &b), "a.txt", "b.txt", 3)[2],
+            "@@ -1,10 +1,11 @@"
+        );
+        // Empty ranges give the line before them, like GNU diff
+        assert_eq!(
+            format_unified(&diff(&lines(""), &lines("ab")), "a", "b", 3)[2],
+            "@@ -0,0 +1,2 @@"
+        );
+        assert_eq!(
+            format_unified(&diff(&lines("abc"), &lines("ac")), "a", "b", 0)[2],
+            "@@ -2 +1,0 @@"
+        );
+        assert!(format_unified(&diff(&a, &a), "a", "b", 3).is_empty());
+    }
+
+    #[test]
+    fn test_apply_patch_round_trip() {
+        let cases = [
+            ("abcdefghij", "abcXefghiYj"),
+            ("abc", "abc"),
+            ("", "xyz"),
+            ("xyz", ""),
+            ("abcabcabc", "abXabcaYc"),
+            ("aaaaaaaaaa", "aaaaaXaaaaa"),
+            ("abcdefghijklmnop", "bcdefghXjklmnopq"),
+        ];
+        for (a, b) in cases.iter() {
+            let (a, b) = (lines(a), lines(b));
+            for context in 0..=DEFAULT_CONTEXT {
+                let patch = format_unified(&diff(&a, &b), "a", "b", context);
+                assert_eq!(
+                    apply_patch(&a, &patch),
+                    Ok(b.clone()),
+                    "patch with {} lines of context: {:?}",
+                    context,
+                    patch
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply_patch_mismatch() {
+        let (a, b) = (lines("abcdef"), lines("abXdef"));
+        let patch = format_unified(&diff(&a, &b), "a", "b", 1);
+        assert_eq!(
+            apply_patch(&lines("abQdef"), &patch),
+            Err("hunk 1 doesn't apply: line 3 is \"Q\", but the patch expects \"c\"".to_string())
+        );
+        assert!(apply_patch(&lines("ab"), &patch).is_err());
+        let truncated = &patch[..patch.len() - 1];
+        assert!(apply_patch(&a, truncated).is_err());
+        let bad_header = vec!["@@ -x +1 @@".to_string()];
+        assert!(apply_patch(&a, &bad_header).is_err());
+    }
+}
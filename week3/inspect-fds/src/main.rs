@@ -1,27 +1,115 @@
-use std::env;
+use monitor::FdMonitor;
+use open_file::OpenFile;
+use process::FdComparison;
+use std::time::{Duration, Instant};
+use std::{env, thread};
 
+mod monitor;
 mod open_file;
 mod process;
 mod ps_utils;
 
+/// How often `--alert` checks the fd count unless `--interval` is given, in seconds
+const DEFAULT_ALERT_INTERVAL: f64 = 1.0;
+/// Exit status used when `--alert ... --exit` stops because the threshold was exceeded
+const ALERT_EXIT_STATUS: i32 = 2;
+
+/// Settings for `--alert` mode.
+struct AlertOptions {
+    threshold: usize,
+    interval: Duration,
+    exit_on_alert: bool,
+    target: String,
+}
+
+impl AlertOptions {
+    /// Parses the arguments following `--alert`: `<count> [--interval <seconds>] [--exit]
+    /// <target>`. Returns None if they're malformed.
+    fn parse(args: &[String]) -> Option<AlertOptions> {
+        let threshold = args.first()?.parse().ok()?;
+        let mut interval = Duration::from_secs_f64(DEFAULT_ALERT_INTERVAL);
+        let mut exit_on_alert = false;
+        let mut target = None;
+        let mut rest = args[1..].iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--interval" => {
+                    let secs: f64 = rest.next()?.parse().ok()?;
+                    if !(secs > 0.0 && secs.is_finite()) {
+                        return None;
+                    }
+                    interval = Duration::from_secs_f64(secs);
+                }
+                "--exit" => exit_on_alert = true,
+                _ if target.is_none() => target = Some(arg.clone()),
+                _ => return None,
+            }
+        }
+        Some(AlertOptions {
+            threshold,
+            interval,
+            exit_on_alert,
+            target: target?,
+        })
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <name or pid of target>", args[0]);
+    if args.len() == 4 && args[1] == "--compare" {
+        compare_processes(&args[2], &args[3]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "--alert" {
+        match AlertOptions::parse(&args[2..]) {
+            Some(options) => monitor_process(&options),
+            None => {
+                println!(
+                    "Usage: {} --alert <count> [--interval <seconds>] [--exit] <name or pid>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.len() == 3 && args[1] == "--lsof" {
+        print_lsof(&args[2]);
+        return;
+    }
+    let show_threads = args.len() == 3 && args[1] == "--threads";
+    if args.len() != 2 && !show_threads {
+        println!("Usage: {} [--threads] <name or pid of target>", args[0]);
+        println!("       {} --lsof <name or pid of target>", args[0]);
+        println!("       {} --compare <name or pid> <name or pid>", args[0]);
+        println!(
+            "       {} --alert <count> [--interval <seconds>] [--exit] <name or pid>",
+            args[0]
+        );
         std::process::exit(1);
     }
-    let target = &args[1];
+    let target = &args[args.len() - 1];
     if let Some(resin) = ps_utils::get_target(target).unwrap_or_else(|_| {
         panic!(
             "Target {} did not match any running PIDs or executables",
             target
         )
     }) {
-        println!("{}", resin);
+        let print_process = |process: &process::Process| {
+            if show_threads {
+                println!("{}", process.with_threads());
+            } else {
+                println!("{}", process);
+            }
+        };
+        print_process(&resin);
+        let mut total = resin.fd_summary().unwrap_or_default();
         let cp = ps_utils::get_child_processes(resin.pid).expect("Error running ps");
-        for p in cp {
-            println!("{}", p);
+        for p in &cp {
+            print_process(p);
+            total += p.fd_summary().unwrap_or_default();
         }
+        println!("Total: {}", total);
     } else {
         eprintln!(
             "Target {} did not match any running PIDs or executables",
@@ -31,6 +119,130 @@ fn main() {
     }
 }
 
+/// Prints the open files of the target and its children in lsof's column layout, for scripts that
+/// already know how to parse lsof. Processes whose fds can't be inspected are left out, like lsof
+/// does.
+fn print_lsof(target: &str) {
+    let process = match ps_utils::get_target(target) {
+        Ok(Some(process)) => process,
+        _ => {
+            eprintln!(
+                "Target {} did not match any running PIDs or executables",
+                target
+            );
+            std::process::exit(1);
+        }
+    };
+    println!("{}", process::lsof_header());
+    let children = ps_utils::get_child_processes(process.pid).expect("Error running ps");
+    for p in std::iter::once(&process).chain(&children) {
+        for line in p.lsof_lines().unwrap_or_default() {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Polls the target's fd count until it exits, printing the count and how fast it's changing, and
+/// a prominent warning whenever it goes above the threshold. With `--exit`, exits with
+/// ALERT_EXIT_STATUS on the first warning instead of continuing to watch.
+fn monitor_process(options: &AlertOptions) {
+    let process = match ps_utils::get_target(&options.target) {
+        Ok(Some(process)) => process,
+        _ => {
+            eprintln!(
+                "Target {} did not match any running PIDs or executables",
+                options.target
+            );
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Watching \"{}\" (pid {}) for more than {} open fds",
+        process.command, process.pid, options.threshold
+    );
+    let mut monitor = FdMonitor::new(options.threshold);
+    loop {
+        let count = match process.list_fds() {
+            Some(fds) => fds.len(),
+            None => {
+                println!("Process {} has exited; stopping", process.pid);
+                return;
+            }
+        };
+        let report = monitor.poll(count, Instant::now());
+        if report.alert {
+            println!(
+                "*** ALERT: \"{}\" (pid {}) has {} open fds, over the threshold of {} ({}) ***",
+                process.command,
+                process.pid,
+                report.count,
+                options.threshold,
+                report.format_growth()
+            );
+            if options.exit_on_alert {
+                std::process::exit(ALERT_EXIT_STATUS);
+            }
+        } else {
+            println!("{} open fds ({})", report.count, report.format_growth());
+        }
+        thread::sleep(options.interval);
+    }
+}
+
+/// Returns the open files of the given process. If it can't be inspected (most likely because it
+/// has exited), prints a warning and returns an empty list.
+fn open_files_of(target: &str) -> Vec<(usize, OpenFile)> {
+    match ps_utils::get_target(target) {
+        Ok(Some(process)) => match process.list_open_files() {
+            Some(open_files) => return open_files,
+            None => eprintln!(
+                "Warning: could not inspect file descriptors for {} (pid {}); it may have exited",
+                target, process.pid
+            ),
+        },
+        _ => eprintln!("Warning: {} did not match any running process", target),
+    }
+    eprintln!("Treating {} as having no open files", target);
+    Vec::new()
+}
+
+/// Prints the open files that two processes have in common (matched by name) and those that only
+/// one of them has open.
+fn compare_processes(first: &str, second: &str) {
+    let comparison = FdComparison::new(&open_files_of(first), &open_files_of(second));
+    for (target, only) in [
+        (first, &comparison.only_first),
+        (second, &comparison.only_second),
+    ] {
+        println!("Only in {}:", target);
+        for (fd, file) in only {
+            println!(
+                "  {:<4} {:<15} {}",
+                fd,
+                format!("({})", file.access_mode),
+                file.colorized_name()
+            );
+        }
+    }
+    println!("In both:");
+    for (name, first_fds, second_fds) in &comparison.common {
+        let fd_list = |fds: &Vec<usize>| {
+            fds.iter()
+                .map(|fd| fd.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        println!(
+            "  {} (fd {} in {}, fd {} in {})",
+            name,
+            fd_list(first_fds),
+            first,
+            fd_list(second_fds),
+            second
+        );
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::process::{Child, Command};
@@ -38,7 +250,7 @@ mod test {
     fn start_c_program(program: &str) -> Child {
         Command::new(program)
             .spawn()
-            .expect(&format!("Could not find {}. Have you run make?", program))
+            .unwrap_or_else(|_| panic!("Could not find {}. Have you run make?", program))
     }
 
     #[test]
@@ -46,7 +258,7 @@ mod test {
         let mut subprocess = start_c_program("./multi_pipe_test");
         assert_eq!(
             Command::new("./target/debug/inspect-fds")
-                .args(&[&subprocess.id().to_string()])
+                .args([&subprocess.id().to_string()])
                 .status()
                 .expect("Could not find target/debug/inspect-fds. Is the binary compiled?")
                 .code()
@@ -55,13 +267,14 @@ mod test {
             "We expected the program to exit normally, but it didn't."
         );
         let _ = subprocess.kill();
+        let _ = subprocess.wait();
     }
 
     #[test]
     fn test_exit_status_invalid_target() {
         assert_eq!(
             Command::new("./target/debug/inspect-fds")
-                .args(&["./nonexistent"])
+                .args(["./nonexistent"])
                 .status()
                 .expect("Could not find target/debug/inspect-fds. Is the binary compiled?")
                 .code()
@@ -0,0 +1,94 @@
+use std::time::Instant;
+
+/// Watches a process's fd count across polls, for `--alert`. It raises an alert when the count
+/// goes above the threshold, and again if it drops back down and then crosses it once more.
+pub struct FdMonitor {
+    threshold: usize,
+    /// The count seen at the previous poll, and when
+    last_poll: Option<(usize, Instant)>,
+    /// Whether the count was above the threshold at the previous poll
+    over_threshold: bool,
+}
+
+/// The result of one poll.
+#[derive(Debug, PartialEq)]
+pub struct FdReport {
+    pub count: usize,
+    /// Change in the fd count per second since the previous poll (None for the first poll)
+    pub growth_per_sec: Option<f64>,
+    /// True if this poll pushed the count over the threshold
+    pub alert: bool,
+}
+
+impl FdReport {
+    /// Formats the growth rate, e.g. "+2.5 fds/s".
+    pub fn format_growth(&self) -> String {
+        match self.growth_per_sec {
+            Some(rate) => format!("{:+.1} fds/s", rate),
+            None => "growth unknown".to_string(),
+        }
+    }
+}
+
+impl FdMonitor {
+    pub fn new(threshold: usize) -> FdMonitor {
+        FdMonitor {
+            threshold,
+            last_poll: None,
+            over_threshold: false,
+        }
+    }
+
+    /// Records that the process had `count` open fds at time `now`.
+    pub fn poll(&mut self, count: usize, now: Instant) -> FdReport {
+        let growth_per_sec = self.last_poll.and_then(|(last_count, last_time)| {
+            let elapsed = now.duration_since(last_time).as_secs_f64();
+            if elapsed > 0.0 {
+                Some((count as f64 - last_count as f64) / elapsed)
+            } else {
+                None
+            }
+        });
+        let over_threshold = count > self.threshold;
+        let alert = over_threshold && !self.over_threshold;
+        self.over_threshold = over_threshold;
+        self.last_poll = Some((count, now));
+        FdReport {
+            count,
+            growth_per_sec,
+            alert,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fd_monitor() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut monitor = FdMonitor::new(10);
+
+        let report = monitor.poll(8, at(0));
+        assert_eq!(report.growth_per_sec, None);
+        assert!(!report.alert);
+        assert_eq!(report.format_growth(), "growth unknown");
+
+        let report = monitor.poll(12, at(2));
+        assert_eq!(report.growth_per_sec, Some(2.0));
+        assert!(report.alert);
+        assert_eq!(report.format_growth(), "+2.0 fds/s");
+
+        // Staying over the threshold doesn't alert again
+        assert!(!monitor.poll(15, at(3)).alert);
+        // Exactly at the threshold isn't over it
+        let report = monitor.poll(10, at(4));
+        assert!(!report.alert);
+        assert_eq!(report.format_growth(), "-5.0 fds/s");
+        // Crossing it again does
+        assert!(monitor.poll(11, at(5)).alert);
+    }
+}
@@ -1,10 +1,15 @@
 use regex::Regex;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
 use std::{fmt, fs};
 
-const O_WRONLY: usize = 00000001;
-const O_RDWR: usize = 00000002;
+const O_WRONLY: usize = 0o00000001;
+const O_RDWR: usize = 0o00000002;
+const O_APPEND: usize = 0o00002000;
+const O_NONBLOCK: usize = 0o00004000;
+const O_CLOEXEC: usize = 0o02000000;
 const COLORS: [&str; 6] = [
     "\x1B[38;5;9m",
     "\x1B[38;5;10m",
@@ -14,6 +19,14 @@ const COLORS: [&str; 6] = [
     "\x1B[38;5;14m",
 ];
 const CLEAR_COLOR: &str = "\x1B[0m";
+/// Files under /proc/{pid}/net listing the sockets in that process's network namespace, along with
+/// the protocol name we display for each
+const SOCKET_TABLES: [(&str, &str); 4] = [
+    ("tcp", "tcp"),
+    ("tcp6", "tcp"),
+    ("udp", "udp"),
+    ("udp6", "udp"),
+];
 
 /// This enum can be used to represent whether a file is read-only, write-only, or read/write. An
 /// enum is basically a value that can be one of some number of "things."
@@ -29,13 +42,71 @@ impl fmt::Display for AccessMode {
         // Match operators are very commonly used with enums in Rust. They function similar to
         // switch statements in other languages (but can be more expressive).
         match self {
-            AccessMode::Read => write!(f, "{}", "read"),
-            AccessMode::Write => write!(f, "{}", "write"),
-            AccessMode::ReadWrite => write!(f, "{}", "read/write"),
+            AccessMode::Read => write!(f, "read"),
+            AccessMode::Write => write!(f, "write"),
+            AccessMode::ReadWrite => write!(f, "read/write"),
+        }
+    }
+}
+
+/// The kind of thing a file descriptor points to, as reported by stat() on /proc/{pid}/fd/{fdnum}.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Regular,
+    Socket,
+    /// Anonymous pipes as well as named FIFOs
+    Pipe,
+    /// Terminals and other devices, directories, epoll/event fds, etc.
+    Other,
+}
+
+impl From<fs::FileType> for FileType {
+    fn from(file_type: fs::FileType) -> FileType {
+        if file_type.is_file() {
+            FileType::Regular
+        } else if file_type.is_socket() {
+            FileType::Socket
+        } else if file_type.is_fifo() {
+            FileType::Pipe
+        } else {
+            FileType::Other
         }
     }
 }
 
+/// The open flags of a file descriptor that are worth knowing about when debugging, decoded from
+/// the "flags:" field of /proc/{pid}/fdinfo/{fdnum}.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpenFlags {
+    /// The fd is closed when the process calls exec. Without it, the fd leaks into the new program.
+    pub cloexec: bool,
+    /// Writes always go to the end of the file
+    pub append: bool,
+    /// Reads and writes return EAGAIN instead of blocking
+    pub nonblock: bool,
+}
+
+impl OpenFlags {
+    pub fn is_empty(&self) -> bool {
+        *self == OpenFlags::default()
+    }
+}
+
+impl fmt::Display for OpenFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = [
+            (self.cloexec, "O_CLOEXEC"),
+            (self.append, "O_APPEND"),
+            (self.nonblock, "O_NONBLOCK"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+        write!(f, "{}", names.join("|"))
+    }
+}
+
 /// Stores information about an open file on the system. Since the Linux kernel doesn't really
 /// expose much information about the open file table to userspace (cplayground uses a modified
 /// kernel), this struct contains info from both the open file table and the vnode table.
@@ -44,14 +115,29 @@ pub struct OpenFile {
     pub name: String,
     pub cursor: usize,
     pub access_mode: AccessMode,
+    pub flags: OpenFlags,
+    pub file_type: FileType,
+    /// Inode number of the open file, from the "ino:" field of /proc/{pid}/fdinfo/{fdnum}, or None
+    /// on kernels too old to report it
+    pub inode: Option<u64>,
 }
 
 impl OpenFile {
-    pub fn new(name: String, cursor: usize, access_mode: AccessMode) -> OpenFile {
+    pub fn new(
+        name: String,
+        cursor: usize,
+        access_mode: AccessMode,
+        flags: OpenFlags,
+        file_type: FileType,
+        inode: Option<u64>,
+    ) -> OpenFile {
         OpenFile {
             name,
             cursor,
             access_mode,
+            flags,
+            file_type,
+            inode,
         }
     }
 
@@ -61,17 +147,75 @@ impl OpenFile {
     /// * For regular files, this will simply return the supplied path.
     /// * For terminals (files starting with /dev/pts), this will return "<terminal>".
     /// * For pipes (filenames formatted like pipe:[pipenum]), this will return "<pipe #pipenum>".
-    fn path_to_name(path: &str) -> String {
+    /// * For TCP/UDP sockets (filenames formatted like socket:[inode]), this will return
+    ///   "<tcp local_addr -> remote_addr>" (or just the local address if there is no peer). Other
+    ///   kinds of sockets (e.g. Unix domain sockets) are left as-is.
+    fn path_to_name(pid: usize, path: &str) -> String {
         if path.starts_with("/dev/pts/") {
             String::from("<terminal>")
         } else if path.starts_with("pipe:[") && path.ends_with("]") {
             let pipe_num = &path[path.find('[').unwrap() + 1..path.find(']').unwrap()];
             format!("<pipe #{}>", pipe_num)
+        } else if path.starts_with("socket:[") && path.ends_with("]") {
+            let inode = &path["socket:[".len()..path.len() - 1];
+            OpenFile::socket_to_name(pid, inode).unwrap_or_else(|| String::from(path))
         } else {
             String::from(path)
         }
     }
 
+    /// This function looks up the socket with the given inode number in the kernel's TCP and UDP
+    /// socket tables for the specified process, and returns a name describing its endpoints. It
+    /// returns None if the socket isn't a TCP/UDP socket (or if the tables can't be read).
+    fn socket_to_name(pid: usize, inode: &str) -> Option<String> {
+        for (table, protocol) in SOCKET_TABLES.iter() {
+            let contents = match fs::read_to_string(format!("/proc/{}/net/{}", pid, table)) {
+                Ok(contents) => contents,
+                Err(_) => continue,
+            };
+            // Each line after the header looks like:
+            //   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+            //   0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 43117 ...
+            for line in contents.lines().skip(1) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 10 || fields[9] != inode {
+                    continue;
+                }
+                let local = OpenFile::parse_socket_addr(fields[1])?;
+                let remote = OpenFile::parse_socket_addr(fields[2])?;
+                return Some(if remote.port() == 0 {
+                    format!("<{} {}>", protocol, local)
+                } else {
+                    format!("<{} {} -> {}>", protocol, local, remote)
+                });
+            }
+        }
+        None
+    }
+
+    /// This function parses an address from /proc/net/{tcp,udp}{,6}, formatted as "ADDR:PORT" in
+    /// hex. The port is written as a regular big-endian number, but the address is dumped as a
+    /// sequence of 32-bit words in host byte order (one word for IPv4, four for IPv6).
+    fn parse_socket_addr(hex: &str) -> Option<SocketAddr> {
+        let (addr_hex, port_hex) = hex.split_at(hex.find(':')?);
+        let port = u16::from_str_radix(&port_hex[1..], 16).ok()?;
+        let mut bytes = Vec::with_capacity(16);
+        for i in (0..addr_hex.len()).step_by(8) {
+            let word = u32::from_str_radix(addr_hex.get(i..i + 8)?, 16).ok()?;
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+        let ip = match bytes.len() {
+            4 => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+            16 => {
+                let mut octets = [0_u8; 16];
+                octets.copy_from_slice(&bytes);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+
     /// This file takes the contents of /proc/{pid}/fdinfo/{fdnum} for some file descriptor and
     /// extracts the cursor position of that file descriptor (technically, the position of the
     /// open file table entry that the fd points to) using a regex. It returns None if the cursor
@@ -82,13 +226,7 @@ impl OpenFile {
         // our code, but if this were code for a critical system that needs to not crash, then
         // we would want to return an Error instead.
         let re = Regex::new(r"pos:\s*(\d+)").unwrap();
-        Some(
-            re.captures(fdinfo)?
-                .get(1)?
-                .as_str()
-                .parse::<usize>()
-                .ok()?,
-        )
+        re.captures(fdinfo)?.get(1)?.as_str().parse::<usize>().ok()
     }
 
     /// This file takes the contents of /proc/{pid}/fdinfo/{fdnum} for some file descriptor and
@@ -111,6 +249,33 @@ impl OpenFile {
         }
     }
 
+    /// This file takes the contents of /proc/{pid}/fdinfo/{fdnum} for some file descriptor and
+    /// decodes the open flags we care about from the "flags:" field. If the field is missing or
+    /// can't be parsed, it returns the default (no flags set).
+    fn parse_flags(fdinfo: &str) -> OpenFlags {
+        let re = Regex::new(r"flags:\s*(\d+)").unwrap();
+        let flags = match re
+            .captures(fdinfo)
+            .and_then(|captures| usize::from_str_radix(captures.get(1)?.as_str(), 8).ok())
+        {
+            Some(flags) => flags,
+            None => return OpenFlags::default(),
+        };
+        OpenFlags {
+            cloexec: flags & O_CLOEXEC > 0,
+            append: flags & O_APPEND > 0,
+            nonblock: flags & O_NONBLOCK > 0,
+        }
+    }
+
+    /// This file takes the contents of /proc/{pid}/fdinfo/{fdnum} for some file descriptor and
+    /// extracts the inode number of the open file from the "ino:" field. It returns None if the
+    /// field is missing, which it is on kernels older than 5.14.
+    fn parse_inode(fdinfo: &str) -> Option<u64> {
+        let re = Regex::new(r"ino:\s*(\d+)").unwrap();
+        re.captures(fdinfo)?.get(1)?.as_str().parse::<u64>().ok()
+    }
+
     /// Given a specified process and fd number, this function reads /proc/{pid}/fd/{fdnum} and
     /// /proc/{pid}/fdinfo/{fdnum} to populate an OpenFile struct. It returns None if the pid or fd
     /// are invalid, or if necessary information is unavailable.
@@ -126,6 +291,7 @@ impl OpenFile {
     /// without making a big deal of it.)
     pub fn from_fd(pid: usize, fd: usize) -> Option<OpenFile> {
         let name = OpenFile::path_to_name(
+            pid,
             fs::read_link(format!("/proc/{}/fd/{}", pid, fd))
                 .ok()?
                 .to_str()?,
@@ -133,7 +299,46 @@ impl OpenFile {
         let r2str = fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd)).ok()?;
         let cursor = OpenFile::parse_cursor(&r2str)?;
         let access_mode = OpenFile::parse_access_mode(&r2str)?;
-        Some(OpenFile::new(name, cursor, access_mode))
+        let flags = OpenFile::parse_flags(&r2str);
+        let inode = OpenFile::parse_inode(&r2str);
+        // fs::metadata follows the /proc symlink, so this stats the open file itself (this works
+        // even for pipes and sockets, which don't have a real path)
+        let file_type = fs::metadata(format!("/proc/{}/fd/{}", pid, fd))
+            .ok()?
+            .file_type()
+            .into();
+        Some(OpenFile::new(
+            name,
+            cursor,
+            access_mode,
+            flags,
+            file_type,
+            inode,
+        ))
+    }
+
+    /// Returns the TYPE that lsof would show for this file. lsof can tell more kinds of files
+    /// apart than we do, so anything we don't classify is "CHR" if it's a device (terminals and
+    /// the like) and "unknown" otherwise.
+    pub fn lsof_type(&self) -> &'static str {
+        match self.file_type {
+            FileType::Regular => "REG",
+            FileType::Socket => "sock",
+            FileType::Pipe => "FIFO",
+            FileType::Other if self.name == "<terminal>" || self.name.starts_with("/dev/") => "CHR",
+            FileType::Other => "unknown",
+        }
+    }
+
+    /// Returns the FD column lsof would show for this file: the fd number followed by r, w or u
+    /// (read/write) for the access mode.
+    pub fn lsof_fd(&self, fd: usize) -> String {
+        let mode = match self.access_mode {
+            AccessMode::Read => 'r',
+            AccessMode::Write => 'w',
+            AccessMode::ReadWrite => 'u',
+        };
+        format!("{}{}", fd, mode)
     }
 
     /// This function returns the OpenFile's name with ANSI escape codes included to colorize
@@ -148,7 +353,7 @@ impl OpenFile {
             let color = COLORS[(hash_val % COLORS.len() as u64) as usize];
             format!("{}{}{}", color, self.name, CLEAR_COLOR)
         } else {
-            format!("{}", self.name)
+            self.name.to_string()
         }
     }
 }
@@ -157,12 +362,16 @@ impl OpenFile {
 mod test {
     use super::*;
     use crate::ps_utils;
+    use std::fs::OpenOptions;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
     use std::process::{Child, Command};
 
     fn start_c_program(program: &str) -> Child {
         Command::new(program)
             .spawn()
-            .expect(&format!("Could not find {}. Have you run make?", program))
+            .unwrap_or_else(|_| panic!("Could not find {}. Have you run make?", program))
     }
 
     #[test]
@@ -176,6 +385,7 @@ mod test {
         assert_eq!(open_file.cursor, 0);
         assert_eq!(open_file.access_mode, AccessMode::ReadWrite);
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
     }
 
     #[test]
@@ -188,5 +398,98 @@ mod test {
             "Expected None because file descriptor 30 is invalid"
         );
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let fdinfo = "pos:\t0\nflags:\t02004002\nmnt_id:\t25\n";
+        assert_eq!(
+            OpenFile::parse_flags(fdinfo),
+            OpenFlags {
+                cloexec: true,
+                append: false,
+                nonblock: true,
+            }
+        );
+        assert_eq!(
+            OpenFile::parse_flags(fdinfo).to_string(),
+            "O_CLOEXEC|O_NONBLOCK"
+        );
+        assert!(OpenFile::parse_flags("pos:\t0\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_inode() {
+        assert_eq!(
+            OpenFile::parse_inode("pos:\t0\nflags:\t02\nmnt_id:\t25\nino:\t3971\n"),
+            Some(3971)
+        );
+        assert_eq!(OpenFile::parse_inode("pos:\t0\nflags:\t02\n"), None);
+    }
+
+    #[test]
+    fn test_openfile_from_fd_flags() {
+        // Rust opens files with O_CLOEXEC
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open("target/flags_test.txt")
+            .unwrap();
+        let open_file = OpenFile::from_fd(std::process::id() as usize, file.as_raw_fd() as usize)
+            .expect("Expected to get open file data for our own file");
+        assert_eq!(
+            open_file.flags,
+            OpenFlags {
+                cloexec: true,
+                append: true,
+                nonblock: false,
+            }
+        );
+        assert_eq!(open_file.file_type, FileType::Regular);
+        assert_eq!(open_file.inode, Some(file.metadata().unwrap().ino()));
+    }
+
+    #[test]
+    fn test_parse_socket_addr() {
+        // Addresses are stored as host-order words, so these strings are only valid on
+        // little-endian machines
+        if cfg!(target_endian = "little") {
+            assert_eq!(
+                OpenFile::parse_socket_addr("0100007F:0016"),
+                Some("127.0.0.1:22".parse().unwrap())
+            );
+            assert_eq!(
+                OpenFile::parse_socket_addr("00000000000000000000000001000000:1F90"),
+                Some("[::1]:8080".parse().unwrap())
+            );
+        }
+        assert_eq!(OpenFile::parse_socket_addr("0100007F"), None);
+        assert_eq!(OpenFile::parse_socket_addr("0100:0016"), None);
+    }
+
+    #[test]
+    fn test_openfile_from_fd_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let pid = std::process::id() as usize;
+
+        let listening = OpenFile::from_fd(pid, listener.as_raw_fd() as usize)
+            .expect("Expected to get open file data for the listening socket");
+        assert_eq!(
+            listening.name,
+            format!("<tcp {}>", listener.local_addr().unwrap())
+        );
+        let connected = OpenFile::from_fd(pid, stream.as_raw_fd() as usize)
+            .expect("Expected to get open file data for the connected socket");
+        assert_eq!(
+            connected.name,
+            format!(
+                "<tcp {} -> {}>",
+                stream.local_addr().unwrap(),
+                stream.peer_addr().unwrap()
+            )
+        );
+        assert_eq!(connected.file_type, FileType::Socket);
     }
 }
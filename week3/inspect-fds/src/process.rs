@@ -1,7 +1,112 @@
-use crate::open_file::OpenFile;
+use crate::open_file::{FileType, OpenFile};
 use std::fmt::{Display, Formatter};
+use std::ops::AddAssign;
 use std::{fmt, fs};
 
+/// Counts of a process's open file descriptors, broken down by what they point to. Summaries can
+/// be added together to get totals across several processes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FdSummary {
+    pub total: usize,
+    pub regular: usize,
+    pub sockets: usize,
+    pub pipes: usize,
+    pub other: usize,
+}
+
+impl FdSummary {
+    fn count(&mut self, file_type: FileType) {
+        self.total += 1;
+        match file_type {
+            FileType::Regular => self.regular += 1,
+            FileType::Socket => self.sockets += 1,
+            FileType::Pipe => self.pipes += 1,
+            FileType::Other => self.other += 1,
+        }
+    }
+}
+
+impl AddAssign for FdSummary {
+    fn add_assign(&mut self, other: FdSummary) {
+        self.total += other.total;
+        self.regular += other.regular;
+        self.sockets += other.sockets;
+        self.pipes += other.pipes;
+        self.other += other.other;
+    }
+}
+
+impl Display for FdSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} open fds ({} regular files, {} sockets, {} pipes, {} other)",
+            self.total, self.regular, self.sockets, self.pipes, self.other
+        )
+    }
+}
+
+/// One thread (task) of a process, as listed under /proc/<pid>/task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub tid: usize,
+    /// The thread's name (its comm), which the kernel truncates to 15 characters
+    pub command: String,
+    /// The single-letter scheduler state from the task's stat file (R, S, D, Z, T, ...)
+    pub state: char,
+}
+
+impl Task {
+    /// Reads the name and state of the given task of process `pid`. Returns None if the task
+    /// can't be inspected, which usually means it has exited.
+    fn from_tid(pid: usize, tid: usize) -> Option<Task> {
+        let dir = format!("/proc/{}/task/{}", pid, tid);
+        let command = fs::read_to_string(format!("{}/comm", dir)).ok()?;
+        let stat = fs::read_to_string(format!("{}/stat", dir)).ok()?;
+        Some(Task {
+            tid,
+            command: command.trim_end_matches('\n').to_string(),
+            state: parse_stat_state(&stat)?,
+        })
+    }
+
+    /// Returns a description of the task's state, e.g. "sleeping" for S.
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            'R' => "running",
+            'S' => "sleeping",
+            'D' => "disk sleep",
+            'T' => "stopped",
+            't' => "tracing stop",
+            'Z' => "zombie",
+            'X' => "dead",
+            'I' => "idle",
+            'P' => "parked",
+            _ => "unknown",
+        }
+    }
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tid {:<7} {:<16} ({})",
+            self.tid,
+            format!("\"{}\"", self.command),
+            self.state_name()
+        )
+    }
+}
+
+/// Extracts the state field from the contents of a /proc stat file. The state follows the
+/// command, which is in parentheses and may itself contain spaces and parentheses, so we look
+/// for the last closing parenthesis.
+fn parse_stat_state(stat: &str) -> Option<char> {
+    let after_command = &stat[stat.rfind(')')? + 1..];
+    after_command.split_whitespace().next()?.chars().next()
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Process {
     pub pid: usize,
@@ -9,6 +114,51 @@ pub struct Process {
     pub command: String,
 }
 
+/// The open files of two processes, matched up by name. Useful for spotting which fds a process
+/// shares with a related one (e.g. its parent, or itself before forking off a worker).
+#[derive(Debug, Default, PartialEq)]
+pub struct FdComparison {
+    /// Files that only the first process has open
+    pub only_first: Vec<(usize, OpenFile)>,
+    /// Files that only the second process has open
+    pub only_second: Vec<(usize, OpenFile)>,
+    /// Names of files open in both processes, along with the fds each process has them open on
+    pub common: Vec<(String, Vec<usize>, Vec<usize>)>,
+}
+
+impl FdComparison {
+    /// Compares two lists of (fd, OpenFile) tuples, as returned by Process::list_open_files.
+    pub fn new(first: &[(usize, OpenFile)], second: &[(usize, OpenFile)]) -> FdComparison {
+        let mut comparison = FdComparison::default();
+        for (fd, file) in first {
+            if !second.iter().any(|(_, other)| other.name == file.name) {
+                comparison.only_first.push((*fd, file.clone()));
+            } else if let Some((_, first_fds, _)) = comparison
+                .common
+                .iter_mut()
+                .find(|(name, _, _)| *name == file.name)
+            {
+                first_fds.push(*fd);
+            } else {
+                let second_fds = second
+                    .iter()
+                    .filter(|(_, other)| other.name == file.name)
+                    .map(|(fd, _)| *fd)
+                    .collect();
+                comparison
+                    .common
+                    .push((file.name.clone(), vec![*fd], second_fds));
+            }
+        }
+        for (fd, file) in second {
+            if !first.iter().any(|(_, other)| other.name == file.name) {
+                comparison.only_second.push((*fd, file.clone()));
+            }
+        }
+        comparison
+    }
+}
+
 impl Process {
     pub fn new(pid: usize, ppid: usize, command: String) -> Process {
         Process { pid, ppid, command }
@@ -38,6 +188,92 @@ impl Process {
         }
         Some(open_files)
     }
+
+    /// This function returns the threads (tasks) of this Process, sorted by thread ID, if that
+    /// information is available. Like list_fds, it returns None if the process has exited. A
+    /// thread that exits while we're looking at it is left out.
+    pub fn list_tasks(&self) -> Option<Vec<Task>> {
+        let mut tasks = vec![];
+        for entry in fs::read_dir(format!("/proc/{}/task", self.pid)).ok()? {
+            let tid = match entry.ok()?.file_name().to_str()?.parse::<usize>() {
+                Ok(tid) => tid,
+                Err(_) => continue,
+            };
+            if let Some(task) = Task::from_tid(self.pid, tid) {
+                tasks.push(task);
+            }
+        }
+        if tasks.is_empty() {
+            return None;
+        }
+        tasks.sort_by_key(|task| task.tid);
+        Some(tasks)
+    }
+
+    /// Returns a wrapper that displays this Process like its Display implementation does, but with
+    /// its threads listed beneath the header.
+    pub fn with_threads(&self) -> WithThreads<'_> {
+        WithThreads(self)
+    }
+
+    /// This function counts this Process's open file descriptors by type. Like list_open_files,
+    /// it returns None if the fd table couldn't be inspected.
+    pub fn fd_summary(&self) -> Option<FdSummary> {
+        let mut summary = FdSummary::default();
+        for (_, file) in self.list_open_files()? {
+            summary.count(file.file_type);
+        }
+        Some(summary)
+    }
+}
+
+/// Lays out one line of `--lsof` output in the classic lsof columns (minus the ones we don't
+/// have, like USER and DEVICE). Like lsof, the command is cut off after 9 characters.
+fn lsof_columns(
+    command: &str,
+    pid: &str,
+    fd: &str,
+    file_type: &str,
+    node: &str,
+    name: &str,
+) -> String {
+    let command: String = command.chars().take(9).collect();
+    format!(
+        "{:<9} {:>7} {:>4} {:<7} {:>10} {}",
+        command, pid, fd, file_type, node, name
+    )
+}
+
+/// Returns the heading line for `--lsof` output.
+pub fn lsof_header() -> String {
+    lsof_columns("COMMAND", "PID", "FD", "TYPE", "NODE", "NAME")
+}
+
+impl Process {
+    /// Returns one lsof-style line per open file descriptor (see lsof_header), or None if the fd
+    /// table couldn't be inspected.
+    pub fn lsof_lines(&self) -> Option<Vec<String>> {
+        Some(
+            self.list_open_files()?
+                .iter()
+                .map(|(fd, file)| self.lsof_line(*fd, file))
+                .collect(),
+        )
+    }
+
+    fn lsof_line(&self, fd: usize, file: &OpenFile) -> String {
+        lsof_columns(
+            &self.command,
+            &self.pid.to_string(),
+            &file.lsof_fd(fd),
+            file.lsof_type(),
+            &file
+                .inode
+                .map(|inode| inode.to_string())
+                .unwrap_or_default(),
+            &file.name,
+        )
+    }
 }
 
 /// Implements the Display trait for the `Process` structure.
@@ -55,12 +291,21 @@ impl Display for Process {
     /// * `fmt::Result` indicating the success or failure of the formatting operation.
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         // Write the process header with its command, PID, and PPID.
+        self.write_header(f)?;
+        self.write_open_files(f)
+    }
+}
+
+impl Process {
+    fn write_header(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(
             f,
             "\"{}\" (pid {}, ppid {})",
             self.command, self.pid, self.ppid
-        )?;
+        )
+    }
 
+    fn write_open_files(&self, f: &mut Formatter<'_>) -> fmt::Result {
         // Match on the open file descriptors.
         match self.list_open_files() {
             // If the file descriptors could not be inspected, output a warning.
@@ -72,8 +317,10 @@ or it might have exited a while ago and is waiting for the parent to reap it."
             ),
             // Otherwise, iterate over each open file descriptor and format its details.
             Some(open_files) => {
+                let mut summary = FdSummary::default();
                 for (fd, file) in open_files {
-                    writeln!(
+                    summary.count(file.file_type);
+                    write!(
                         f,
                         "{:<4} {:<15} cursor: {:<4} {}",
                         fd,
@@ -81,22 +328,53 @@ or it might have exited a while ago and is waiting for the parent to reap it."
                         file.cursor,
                         file.colorized_name()
                     )?;
+                    if !file.flags.is_empty() {
+                        write!(f, " [{}]", file.flags)?;
+                    }
+                    writeln!(f)?;
+                }
+                writeln!(f, "Summary: {}", summary)
+            }
+        }
+    }
+}
+
+/// Displays a Process with its threads listed between the header and its open files. Returned by
+/// Process::with_threads.
+pub struct WithThreads<'a>(&'a Process);
+
+impl Display for WithThreads<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let process = self.0;
+        process.write_header(f)?;
+        match process.list_tasks() {
+            None => writeln!(
+                f,
+                "Warning: could not inspect the threads of this process! \
+It might have exited just as we were about to look at them."
+            )?,
+            Some(tasks) => {
+                writeln!(f, "Threads ({}):", tasks.len())?;
+                for task in tasks {
+                    writeln!(f, "  {}", task)?;
                 }
-                Ok(())
             }
         }
+        process.write_open_files(f)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::open_file::{AccessMode, OpenFlags};
     use crate::ps_utils;
     use std::process::{Child, Command};
 
     fn start_c_program(program: &str) -> Child {
         Command::new(program)
             .spawn()
-            .expect(&format!("Could not find {}. Have you run make?", program))
+            .unwrap_or_else(|_| panic!("Could not find {}. Have you run make?", program))
     }
 
     #[test]
@@ -110,6 +388,180 @@ mod test {
             vec![0, 1, 2, 4, 5]
         );
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+    }
+
+    #[test]
+    fn test_fd_summary() {
+        let mut test_subprocess = start_c_program("./multi_pipe_test");
+        let process = ps_utils::get_target("multi_pipe_test").unwrap().unwrap();
+        let summary = process
+            .fd_summary()
+            .expect("Expected fd_summary to find file descriptors, but it returned None");
+        // fds 4 and 5 are the parent's ends of the two pipes; 0-2 depend on how the tests are run
+        assert_eq!(summary.total, 5);
+        assert!(summary.pipes >= 2);
+        assert_eq!(
+            summary.regular + summary.sockets + summary.pipes + summary.other,
+            summary.total
+        );
+        let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+
+        let mut total = summary;
+        total += summary;
+        assert_eq!(total.total, 10);
+        assert_eq!(total.pipes, summary.pipes * 2);
+    }
+
+    #[test]
+    fn test_fd_comparison() {
+        let file = |name: &str| {
+            OpenFile::new(
+                name.to_string(),
+                0,
+                AccessMode::Read,
+                OpenFlags::default(),
+                FileType::Other,
+                None,
+            )
+        };
+        let parent = vec![
+            (0, file("<terminal>")),
+            (3, file("<pipe #10>")),
+            (4, file("<pipe #10>")),
+            (5, file("/tmp/log")),
+        ];
+        let child = vec![(0, file("<pipe #10>")), (1, file("<terminal>"))];
+        let comparison = FdComparison::new(&parent, &child);
+        assert_eq!(comparison.only_first, vec![(5, file("/tmp/log"))]);
+        assert!(comparison.only_second.is_empty());
+        assert_eq!(
+            comparison.common,
+            vec![
+                ("<terminal>".to_string(), vec![0], vec![1]),
+                ("<pipe #10>".to_string(), vec![3, 4], vec![0]),
+            ]
+        );
+
+        // A process that has exited has nothing in common with anything
+        let comparison = FdComparison::new(&parent, &[]);
+        assert_eq!(comparison.only_first.len(), 4);
+        assert!(comparison.common.is_empty());
+    }
+
+    #[test]
+    fn test_lsof_lines() {
+        let process = Process::new(4242, 1, "multi_pipe_test".to_string());
+        let files = [
+            (
+                0,
+                "<terminal>",
+                AccessMode::ReadWrite,
+                FileType::Other,
+                Some(3),
+            ),
+            (
+                1,
+                "/tmp/log",
+                AccessMode::Write,
+                FileType::Regular,
+                Some(131077),
+            ),
+            (
+                4,
+                "<pipe #9106>",
+                AccessMode::Read,
+                FileType::Pipe,
+                Some(9106),
+            ),
+            (
+                5,
+                "<tcp 127.0.0.1:80>",
+                AccessMode::ReadWrite,
+                FileType::Socket,
+                None,
+            ),
+        ];
+        let mut lines = vec![lsof_header()];
+        for (fd, name, access_mode, file_type, inode) in files {
+            let file = OpenFile::new(
+                name.to_string(),
+                0,
+                access_mode,
+                OpenFlags::default(),
+                file_type,
+                inode,
+            );
+            lines.push(process.lsof_line(fd, &file));
+        }
+        assert_eq!(
+            lines.join("\n"),
+            "\
+COMMAND       PID   FD TYPE          NODE NAME
+multi_pip    4242   0u CHR              3 <terminal>
+multi_pip    4242   1w REG         131077 /tmp/log
+multi_pip    4242   4r FIFO          9106 <pipe #9106>
+multi_pip    4242   5u sock               <tcp 127.0.0.1:80>"
+        );
+    }
+
+    #[test]
+    fn test_list_tasks() {
+        let mut test_subprocess = start_c_program("./multi_pipe_test");
+        // Other tests run multi_pipe_test concurrently, so look this one up by pid
+        let process = ps_utils::get_target(&test_subprocess.id().to_string())
+            .unwrap()
+            .unwrap();
+        let tasks = process
+            .list_tasks()
+            .expect("Expected list_tasks to find the process's threads, but it returned None");
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].tid, process.pid);
+        assert_eq!(tasks[0].command, "multi_pipe_test");
+        let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
+
+        // Once the process is gone, its threads can't be listed
+        assert!(process.list_tasks().is_none());
+    }
+
+    #[test]
+    fn test_list_tasks_multithreaded() {
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let worker = std::thread::Builder::new()
+            .name("fds-test-worker".to_string())
+            .spawn(move || {
+                // The thread names itself once it's running, so wait until then to look at it
+                started_tx.send(()).unwrap();
+                let _ = rx.recv();
+            })
+            .unwrap();
+        started_rx.recv().unwrap();
+        let process = Process::new(std::process::id() as usize, 0, "test".to_string());
+        let tasks = process.list_tasks().unwrap();
+        assert!(tasks.len() >= 2);
+        assert_eq!(tasks[0].tid, process.pid);
+        let task = tasks
+            .iter()
+            .find(|task| task.command == "fds-test-worker")
+            .expect("Expected to find the worker thread");
+        // It's blocked on the channel, or about to be
+        assert!(task.state == 'S' || task.state == 'R');
+        assert!(process
+            .with_threads()
+            .to_string()
+            .contains("\"fds-test-worker\""));
+        drop(tx);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn test_parse_stat_state() {
+        assert_eq!(parse_stat_state("42 (bash) S 1 42 42 0"), Some('S'));
+        assert_eq!(parse_stat_state("7 (a) b (c)) R 1 7"), Some('R'));
+        assert_eq!(parse_stat_state("garbage"), None);
     }
 
     #[test]
@@ -121,5 +573,6 @@ mod test {
             "Expected list_fds to return None for a zombie process"
         );
         let _ = test_subprocess.kill();
+        let _ = test_subprocess.wait();
     }
 }
@@ -77,14 +77,14 @@ fn get_process(pid: usize) -> Result<Option<Process>, Error> {
     // custom error type.)
     let output = String::from_utf8(
         Command::new("ps")
-            .args(&["--pid", &pid.to_string(), "-o", "pid= ppid= command="])
+            .args(["--pid", &pid.to_string(), "-o", "pid= ppid= command="])
             .output()?
             .stdout,
     )?;
     // Return Some if the process was found and output parsing succeeds, or None if ps produced no
     // output (indicating there is no matching process). Note the use of ? to propagate Error if an
     // error occured in parsing the output.
-    if output.trim().len() > 0 {
+    if !output.trim().is_empty() {
         Ok(Some(parse_ps_line(output.trim())?))
     } else {
         Ok(None)
@@ -96,7 +96,7 @@ fn get_process(pid: usize) -> Result<Option<Process>, Error> {
 /// produces unexpected output format.
 pub fn get_child_processes(pid: usize) -> Result<Vec<Process>, Error> {
     let ps_output = Command::new("ps")
-        .args(&["--ppid", &pid.to_string(), "-o", "pid= ppid= command="])
+        .args(["--ppid", &pid.to_string(), "-o", "pid= ppid= command="])
         .output()?;
     let mut output = Vec::new();
     for line in String::from_utf8(ps_output.stdout)?.lines() {
@@ -111,7 +111,7 @@ pub fn get_child_processes(pid: usize) -> Result<Vec<Process>, Error> {
 fn get_pid_by_command_name(name: &str) -> Result<Option<usize>, Error> {
     let output = String::from_utf8(
         Command::new("pgrep")
-            .args(&["-xU", getuid().to_string().as_str(), name])
+            .args(["-xU", getuid().to_string().as_str(), name])
             .output()?
             .stdout,
     )?;
@@ -126,14 +126,13 @@ fn get_pid_by_command_name(name: &str) -> Result<Option<usize>, Error> {
 /// Process struct if the specified process was found, None if no matching processes were found, or
 /// Error if an error was encountered in running ps or pgrep.
 pub fn get_target(query: &str) -> Result<Option<Process>, Error> {
-    let pid_by_command = get_pid_by_command_name(query)?;
-    if pid_by_command.is_some() {
-        return get_process(pid_by_command.unwrap());
+    if let Some(pid) = get_pid_by_command_name(query)? {
+        return get_process(pid);
     }
     // If searching for the query as a command name failed, let's see if it's a valid pid
     match query.parse() {
-        Ok(pid) => return get_process(pid),
-        Err(_) => return Ok(None),
+        Ok(pid) => get_process(pid),
+        Err(_) => Ok(None),
     }
 }
 
@@ -145,7 +144,7 @@ mod test {
     fn start_c_program(program: &str) -> Child {
         Command::new(program)
             .spawn()
-            .expect(&format!("Could not find {}. Have you run make?", program))
+            .unwrap_or_else(|_| panic!("Could not find {}. Have you run make?", program))
     }
 
     #[test]
@@ -156,6 +155,7 @@ mod test {
             .expect("Passed valid \"multi_pipe_test\" to get_target, but it returned None");
         assert_eq!(found.command, "./multi_pipe_test");
         let _ = subprocess.kill();
+        let _ = subprocess.wait();
     }
 
     #[test]
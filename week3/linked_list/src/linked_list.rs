@@ -1,4 +1,7 @@
+use std::collections::{LinkedList as StdLinkedList, VecDeque};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
 use std::option::Option;
 
 pub struct LinkedList<T> {
@@ -48,6 +51,348 @@ impl<T> LinkedList<T> {
         self.size -= 1;
         Some(node.value)
     }
+
+    /// Returns a reference to the front element without removing it.
+    pub fn peek_front(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    /// Returns a reference to the back element without removing it. This walks the whole list,
+    /// since we don't keep a tail pointer.
+    pub fn peek_back(&self) -> Option<&T> {
+        self.iter().last()
+    }
+
+    /// Returns a mutable reference to the front element, so it can be modified in place.
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        self.head.as_mut().map(|node| &mut node.value)
+    }
+
+    /// Returns a new list holding `f` applied to each element, in the same order.
+    pub fn map<U, F: Fn(&T) -> U>(&self, f: F) -> LinkedList<U> {
+        self.iter().map(f).collect()
+    }
+
+    /// Returns a new list holding clones of the elements for which `f` returns true, in the same
+    /// order.
+    pub fn filter<F: Fn(&T) -> bool>(&self, f: F) -> LinkedList<T>
+    where
+        T: Clone,
+    {
+        self.iter().filter(|value| f(value)).cloned().collect()
+    }
+
+    /// Removes the elements for which `f` returns false, in place and in a single pass. The
+    /// remaining elements keep their order.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        let mut cursor = &mut self.head;
+        while cursor.is_some() {
+            if f(&cursor.as_ref().unwrap().value) {
+                cursor = &mut cursor.as_mut().unwrap().next;
+            } else {
+                let mut removed = cursor.take().unwrap();
+                *cursor = removed.next.take();
+                self.size -= 1;
+            }
+        }
+    }
+
+    /// Collapses each run of equal adjacent elements into its first element, like `Vec::dedup`.
+    pub fn dedup_consecutive(&mut self)
+    where
+        T: PartialEq,
+    {
+        let mut cursor = &mut self.head;
+        while let Some(node) = cursor {
+            while node
+                .next
+                .as_ref()
+                .is_some_and(|next| next.value == node.value)
+            {
+                let mut duplicate = node.next.take().unwrap();
+                node.next = duplicate.next.take();
+                self.size -= 1;
+            }
+            cursor = &mut node.next;
+        }
+    }
+
+    /// Combines the elements from front to back into a single value, starting from `init`.
+    pub fn fold<B, F: Fn(B, &T) -> B>(&self, init: B, f: F) -> B {
+        self.iter().fold(init, f)
+    }
+
+    /// Splits the list in two at the given index: `self` keeps the first `at` elements and the rest
+    /// are returned as a new list. Panics if `at` is greater than the size of the list.
+    pub fn split_off(&mut self, at: usize) -> LinkedList<T> {
+        assert!(
+            at <= self.size,
+            "split_off index (is {}) should be <= size (is {})",
+            at,
+            self.size
+        );
+        let mut cursor = &mut self.head;
+        for _ in 0..at {
+            cursor = &mut cursor.as_mut().unwrap().next;
+        }
+        let rest = LinkedList {
+            head: cursor.take(),
+            size: self.size - at,
+        };
+        self.size = at;
+        rest
+    }
+
+    /// Swaps the values at indices `i` and `j`. Walks the list up to the later index, so this is
+    /// O(n). Panics if either index is out of bounds.
+    pub fn swap(&mut self, i: usize, j: usize) {
+        assert!(
+            i < self.size && j < self.size,
+            "swap indices (are {} and {}) should be < size (is {})",
+            i,
+            j,
+            self.size
+        );
+        let (first, second) = (i.min(j), i.max(j));
+        if first == second {
+            return;
+        }
+        let mut values = self.iter_mut();
+        let a = values.nth(first).unwrap();
+        let b = values.nth(second - first - 1).unwrap();
+        std::mem::swap(a, b);
+    }
+
+    /// Moves the first `n` elements to the back of the list, keeping their order, so that the
+    /// element at index `n` ends up at the front. `n` wraps around modulo the size of the list.
+    /// The nodes are relinked rather than their values moved, but finding the new front and the
+    /// old back still walks the list, so this is O(n).
+    pub fn rotate_left(&mut self, n: usize) {
+        let at = match n.checked_rem(self.size) {
+            Some(0) | None => return,
+            Some(at) => at,
+        };
+        let size = self.size;
+        let mut rest = self.split_off(at);
+        let mut tail = &mut rest.head;
+        while let Some(node) = tail {
+            tail = &mut node.next;
+        }
+        *tail = self.head.take();
+        self.head = rest.head.take();
+        self.size = size;
+    }
+
+    /// Moves the last `n` elements to the front of the list, keeping their order. `n` wraps around
+    /// modulo the size of the list. Like `rotate_left`, this is O(n).
+    pub fn rotate_right(&mut self, n: usize) {
+        if self.size == 0 {
+            return;
+        }
+        self.rotate_left(self.size - n % self.size);
+    }
+
+    /// Returns true if every element is less than or equal to the one after it.
+    pub fn is_sorted(&self) -> bool
+    where
+        T: PartialOrd,
+    {
+        self.iter().zip(self.iter().skip(1)).all(|(a, b)| a <= b)
+    }
+
+    /// Sorts the list in ascending order with a merge sort. The sort is stable (equal elements
+    /// keep their relative order), and nodes are relinked rather than values being copied.
+    pub fn sort(&mut self)
+    where
+        T: Ord,
+    {
+        if self.size < 2 {
+            return;
+        }
+        let mut right = self.split_off(self.size / 2);
+        self.sort();
+        right.sort();
+        self.size += right.size;
+        self.head = LinkedList::merge(self.head.take(), right.head.take());
+    }
+
+    /// Merges two sorted chains of nodes into one. On ties, nodes from `left` come first, which
+    /// is what keeps `sort` stable.
+    fn merge(
+        mut left: Option<Box<Node<T>>>,
+        mut right: Option<Box<Node<T>>>,
+    ) -> Option<Box<Node<T>>>
+    where
+        T: Ord,
+    {
+        let mut merged = None;
+        let mut tail = &mut merged;
+        loop {
+            let source = match (&left, &right) {
+                (Some(l), Some(r)) if r.value < l.value => &mut right,
+                (Some(_), Some(_)) => &mut left,
+                _ => break,
+            };
+            let mut node = source.take().unwrap();
+            *source = node.next.take();
+            tail = &mut tail.insert(node).next;
+        }
+        *tail = left.or(right);
+        merged
+    }
+
+    /// Returns an iterator over mutable references to the elements, front to back.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            current: self.head.as_deref_mut(),
+        }
+    }
+
+    /// Returns a cursor pointing at the front element (or past the end, if the list is empty),
+    /// for editing the list in place while walking it.
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            link: Some(&mut self.head),
+            size: &mut self.size,
+        }
+    }
+
+    /// Returns an iterator over references to the elements, front to back. Use `.cloned()` on it
+    /// to get copies of the elements instead.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            current: &self.head,
+        }
+    }
+}
+
+/// Iterates over references to the elements of a list, front to back. Returned by
+/// `LinkedList::iter`.
+pub struct Iter<'a, T> {
+    current: &'a Option<Box<Node<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.current.as_ref()?;
+        self.current = &node.next;
+        Some(&node.value)
+    }
+}
+
+/// Iterates over mutable references to the elements of a list, front to back. Returned by
+/// `LinkedList::iter_mut`.
+pub struct IterMut<'a, T> {
+    current: Option<&'a mut Node<T>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        let node = self.current.take()?;
+        self.current = node.next.as_deref_mut();
+        Some(&mut node.value)
+    }
+}
+
+/// A position in a list that can move towards the back and edit the list around it, like the
+/// standard library's `CursorMut` (but only forwards, since our nodes only link forwards). The
+/// cursor points either at an element or past the last one.
+pub struct CursorMut<'a, T> {
+    /// The link holding the current node; it's None once the cursor is past the end. This is only
+    /// ever None in between the steps of `move_next`, which has to take it out to move it.
+    link: Option<&'a mut Option<Box<Node<T>>>>,
+    size: &'a mut usize,
+}
+
+impl<T> CursorMut<'_, T> {
+    fn link(&mut self) -> &mut Option<Box<Node<T>>> {
+        self.link.as_mut().unwrap()
+    }
+
+    /// Returns the element the cursor points at, or None if it's past the end.
+    pub fn current(&mut self) -> Option<&mut T> {
+        self.link().as_mut().map(|node| &mut node.value)
+    }
+
+    /// Returns the element after the current one, without moving.
+    pub fn peek_next(&mut self) -> Option<&mut T> {
+        let next = &mut self.link().as_mut()?.next;
+        next.as_mut().map(|node| &mut node.value)
+    }
+
+    /// Moves to the next element. Once past the end, the cursor stays there.
+    pub fn move_next(&mut self) {
+        let link = self.link.take().unwrap();
+        self.link = Some(if link.is_some() {
+            &mut link.as_mut().unwrap().next
+        } else {
+            link
+        });
+    }
+
+    /// Inserts `value` after the current element, without moving. If the cursor is past the end,
+    /// `value` is appended to the list and becomes the current element.
+    pub fn insert_after(&mut self, value: T) {
+        let link = match self.link().as_mut() {
+            Some(node) => &mut node.next,
+            None => self.link(),
+        };
+        let next = link.take();
+        *link = Some(Box::new(Node::new(value, next)));
+        *self.size += 1;
+    }
+
+    /// Removes the current element and returns it, leaving the cursor pointing at the element
+    /// that followed it. Returns None (and changes nothing) if the cursor is past the end.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let link = self.link();
+        let mut node = link.take()?;
+        *link = node.next.take();
+        *self.size -= 1;
+        Some(node.value)
+    }
+}
+
+/// Builds a list with the elements in iteration order, so the first element ends up at the front.
+impl<T> FromIterator<T> for LinkedList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = LinkedList::new();
+        let mut tail = &mut list.head;
+        for value in iter {
+            let node = tail.insert(Box::new(Node::new(value, None)));
+            tail = &mut node.next;
+            list.size += 1;
+        }
+        list
+    }
+}
+
+impl<T> From<StdLinkedList<T>> for LinkedList<T> {
+    fn from(list: StdLinkedList<T>) -> Self {
+        list.into_iter().collect()
+    }
+}
+
+impl<T> From<VecDeque<T>> for LinkedList<T> {
+    fn from(deque: VecDeque<T>) -> Self {
+        deque.into_iter().collect()
+    }
+}
+
+/// Moves the elements into a standard library list, front to back.
+impl<T> From<LinkedList<T>> for StdLinkedList<T> {
+    fn from(mut list: LinkedList<T>) -> Self {
+        std::iter::from_fn(|| list.pop_front()).collect()
+    }
+}
+
+/// Moves the elements into a deque, so that the front of the list is the front of the deque.
+impl<T> From<LinkedList<T>> for VecDeque<T> {
+    fn from(mut list: LinkedList<T>) -> Self {
+        std::iter::from_fn(|| list.pop_front()).collect()
+    }
 }
 
 impl<T: std::fmt::Display> fmt::Display for LinkedList<T> {
@@ -97,7 +442,7 @@ impl<T: PartialEq> PartialEq for Node<T> {
     }
 }
 
-impl<T: PartialEq> PartialEq for &LinkedList<T> {
+impl<T: PartialEq> PartialEq for LinkedList<T> {
     fn eq(&self, other: &Self) -> bool {
         if self.size != other.size {
             return false;
@@ -120,30 +465,363 @@ impl<T: PartialEq> PartialEq for &LinkedList<T> {
     }
 }
 
-pub struct LinkedListIter<'a, T> {
-    current: &'a Option<Box<Node<T>>>,
+impl<T: Eq> Eq for LinkedList<T> {}
+
+/// Hashes the size followed by each element from front to back, so that lists that compare equal
+/// also hash equally.
+impl<T: Hash> Hash for LinkedList<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.size.hash(state);
+        let mut current = &self.head;
+        while let Some(node) = current {
+            node.value.hash(state);
+            current = &node.next;
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a LinkedList<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
 }
 
-impl<T: Clone> Iterator for LinkedListIter<'_, T> {
-    type Item = T;
-    fn next(&mut self) -> Option<T> {
-        match &self.current {
-            Some(node) => {
-                let v = node.value.clone();
-                self.current = &node.next;
-                Some(v)
+impl<'a, T> IntoIterator for &'a mut LinkedList<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn list_of(values: &[u32]) -> LinkedList<u32> {
+        let mut list = LinkedList::new();
+        for value in values.iter().rev() {
+            list.push_front(*value);
+        }
+        list
+    }
+
+    #[test]
+    fn test_equal_lists_share_a_set_entry() {
+        let mut set = HashSet::new();
+        set.insert(list_of(&[1, 2, 3]));
+        set.insert(list_of(&[1, 2, 3]));
+        assert_eq!(set.len(), 1);
+        set.insert(list_of(&[3, 2, 1]));
+        set.insert(list_of(&[1, 2]));
+        set.insert(LinkedList::new());
+        assert_eq!(set.len(), 4);
+        assert!(set.contains(&list_of(&[1, 2])));
+    }
+
+    #[test]
+    fn test_peek_empty() {
+        let mut list: LinkedList<u32> = LinkedList::new();
+        assert_eq!(list.peek_front(), None);
+        assert_eq!(list.peek_back(), None);
+        assert_eq!(list.front_mut(), None);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut list = list_of(&[1, 2, 3]);
+        assert_eq!(list.peek_front(), Some(&1));
+        assert_eq!(list.peek_back(), Some(&3));
+        // Peeking doesn't remove anything
+        assert_eq!(list.get_size(), 3);
+
+        *list.front_mut().unwrap() = 10;
+        assert!(list == list_of(&[10, 2, 3]));
+        assert_eq!(list.pop_front(), Some(10));
+
+        let single = list_of(&[7]);
+        assert_eq!(single.peek_front(), Some(&7));
+        assert_eq!(single.peek_back(), Some(&7));
+    }
+
+    #[test]
+    fn test_map_doubles_in_order() {
+        let list = list_of(&[1, 2, 3, 4]);
+        let doubled = list.map(|value| value * 2);
+        assert_eq!(doubled.get_size(), 4);
+        assert!(doubled == list_of(&[2, 4, 6, 8]));
+        assert_eq!(list.map(|value| value.to_string()).to_string(), " 1 2 3 4");
+        assert!(LinkedList::<u32>::new().map(|value| value * 2).is_empty());
+    }
+
+    #[test]
+    fn test_filter_keeps_evens_in_order() {
+        let list = list_of(&[1, 2, 3, 4, 5, 6]);
+        let evens = list.filter(|value| value % 2 == 0);
+        assert_eq!(evens.get_size(), 3);
+        assert!(evens == list_of(&[2, 4, 6]));
+        assert_eq!(list.get_size(), 6);
+        assert!(list.filter(|value| *value > 10).is_empty());
+    }
+
+    #[test]
+    fn test_retain_keeps_evens_in_order() {
+        let mut list = list_of(&[1, 2, 3, 4, 5, 6]);
+        list.retain(|value| value % 2 == 0);
+        assert!(list == list_of(&[2, 4, 6]));
+        assert_eq!(list.get_size(), 3);
+
+        // Removing from the front and back, and removing everything
+        let mut list = list_of(&[1, 1, 2, 1]);
+        list.retain(|value| *value == 2);
+        assert!(list == list_of(&[2]));
+        list.retain(|_| false);
+        assert!(list.is_empty());
+        assert_eq!(list.peek_front(), None);
+    }
+
+    #[test]
+    fn test_dedup_consecutive() {
+        let mut list = list_of(&[1, 1, 2, 2, 1]);
+        list.dedup_consecutive();
+        assert!(list == list_of(&[1, 2, 1]));
+        assert_eq!(list.get_size(), 3);
+
+        let mut list = list_of(&[5, 5, 5, 5]);
+        list.dedup_consecutive();
+        assert!(list == list_of(&[5]));
+        assert_eq!(list.get_size(), 1);
+
+        let mut empty: LinkedList<u32> = LinkedList::new();
+        empty.dedup_consecutive();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_iter_by_reference() {
+        let mut list: LinkedList<String> = LinkedList::new();
+        list.push_front("world".to_string());
+        list.push_front("hello".to_string());
+        let mut lengths = Vec::new();
+        for word in &list {
+            lengths.push(word.len());
+        }
+        assert_eq!(lengths, vec![5, 5]);
+        // The references point at the elements in the list, not at copies of them
+        assert!(std::ptr::eq(
+            list.iter().next().unwrap(),
+            list.peek_front().unwrap()
+        ));
+        let copies: Vec<String> = list.iter().cloned().collect();
+        assert_eq!(copies, vec!["hello", "world"]);
+
+        // Elements don't need to be Clone to be iterated over
+        struct Opaque(u32);
+        let mut opaque = LinkedList::new();
+        opaque.push_front(Opaque(2));
+        opaque.push_front(Opaque(1));
+        assert_eq!(opaque.iter().map(|o| o.0).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut list = list_of(&[1, 2, 3]);
+        for value in list.iter_mut() {
+            *value *= 10;
+        }
+        assert!(list == list_of(&[10, 20, 30]));
+        for value in &mut list {
+            *value += 1;
+        }
+        assert!(list == list_of(&[11, 21, 31]));
+        assert_eq!(LinkedList::<u32>::new().iter_mut().next(), None);
+    }
+
+    #[test]
+    fn test_cursor_insert_and_remove_in_one_walk() {
+        // Put a 0 after every 2 and drop every 3
+        let mut list = list_of(&[1, 2, 3, 2, 3]);
+        let mut cursor = list.cursor_front_mut();
+        while let Some(value) = cursor.current() {
+            match *value {
+                2 => {
+                    cursor.insert_after(0);
+                    cursor.move_next();
+                    cursor.move_next();
+                }
+                3 => {
+                    assert_eq!(cursor.remove_current(), Some(3));
+                }
+                _ => cursor.move_next(),
             }
-            None => None,
         }
+        assert_eq!(cursor.remove_current(), None);
+        assert_eq!(cursor.peek_next(), None);
+        assert!(list == list_of(&[1, 2, 0, 2, 0]));
+        assert_eq!(list.get_size(), 5);
     }
-}
 
-impl<'a, T: Clone> IntoIterator for &'a LinkedList<T> {
-    type Item = T;
-    type IntoIter = LinkedListIter<'a, T>;
-    fn into_iter(self) -> LinkedListIter<'a, T> {
-        LinkedListIter {
-            current: &self.head,
+    #[test]
+    fn test_cursor_edges() {
+        let mut list = LinkedList::new();
+        let mut cursor = list.cursor_front_mut();
+        assert_eq!(cursor.current(), None);
+        // Inserting past the end appends, and the new element becomes the current one
+        cursor.insert_after(1);
+        assert_eq!(cursor.current(), Some(&mut 1));
+        cursor.insert_after(3);
+        assert_eq!(cursor.peek_next(), Some(&mut 3));
+        *cursor.current().unwrap() = 2;
+        cursor.move_next();
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(cursor.current(), None);
+        cursor.insert_after(4);
+        assert!(list == list_of(&[2, 3, 4]));
+        assert_eq!(list.get_size(), 3);
+
+        let mut cursor = list.cursor_front_mut();
+        while cursor.remove_current().is_some() {}
+        assert!(list.is_empty());
+        assert_eq!(list.peek_front(), None);
+    }
+
+    #[test]
+    fn test_fold() {
+        let list = list_of(&[1, 2, 3, 4]);
+        assert_eq!(list.fold(0, |sum, value| sum + value), 10);
+        // Elements are visited front to back
+        assert_eq!(
+            list.fold(String::new(), |acc, value| acc + &value.to_string()),
+            "1234"
+        );
+    }
+
+    #[test]
+    fn test_split_off() {
+        let mut list = list_of(&[1, 2, 3, 4, 5]);
+        let rest = list.split_off(2);
+        assert!(list == list_of(&[1, 2]));
+        assert_eq!(list.get_size(), 2);
+        assert!(rest == list_of(&[3, 4, 5]));
+        assert_eq!(rest.get_size(), 3);
+        assert!(list.split_off(2).is_empty());
+    }
+
+    #[test]
+    fn test_swap() {
+        let mut list = list_of(&[1, 2, 3, 4, 5]);
+        list.swap(0, 4);
+        assert!(list == list_of(&[5, 2, 3, 4, 1]));
+        list.swap(3, 1);
+        assert!(list == list_of(&[5, 4, 3, 2, 1]));
+        list.swap(2, 2);
+        assert!(list == list_of(&[5, 4, 3, 2, 1]));
+        assert_eq!(list.get_size(), 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_swap_out_of_bounds() {
+        list_of(&[1, 2]).swap(0, 2);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut list = list_of(&[1, 2, 3, 4, 5]);
+        list.rotate_left(2);
+        assert!(list == list_of(&[3, 4, 5, 1, 2]));
+        list.rotate_right(2);
+        assert!(list == list_of(&[1, 2, 3, 4, 5]));
+        list.rotate_right(1);
+        assert!(list == list_of(&[5, 1, 2, 3, 4]));
+        assert_eq!(list.get_size(), 5);
+        assert_eq!(list.peek_back(), Some(&4));
+    }
+
+    #[test]
+    fn test_rotate_wraps() {
+        let mut list = list_of(&[1, 2, 3, 4, 5]);
+        list.rotate_left(7);
+        assert!(list == list_of(&[3, 4, 5, 1, 2]));
+        list.rotate_right(12);
+        assert!(list == list_of(&[1, 2, 3, 4, 5]));
+        list.rotate_left(5);
+        assert!(list == list_of(&[1, 2, 3, 4, 5]));
+
+        let mut empty: LinkedList<u32> = LinkedList::new();
+        empty.rotate_left(3);
+        empty.rotate_right(3);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut list = list_of(&[3, 1, 2]);
+        assert!(!list.is_sorted());
+        list.sort();
+        assert!(list.is_sorted());
+        assert!(list == list_of(&[1, 2, 3]));
+        assert_eq!(list.get_size(), 3);
+
+        let mut empty: LinkedList<u32> = LinkedList::new();
+        empty.sort();
+        assert!(empty.is_sorted());
+    }
+
+    #[test]
+    fn test_sort_is_stable() {
+        // Compared by key only, so that elements with equal keys can still be told apart by tag
+        struct Tagged {
+            key: u32,
+            tag: char,
         }
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.key == other.key
+            }
+        }
+        impl Eq for Tagged {}
+        impl PartialOrd for Tagged {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Tagged {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.key.cmp(&other.key)
+            }
+        }
+
+        let mut list: LinkedList<Tagged> = [(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (2, 'e')]
+            .iter()
+            .map(|&(key, tag)| Tagged { key, tag })
+            .collect();
+        list.sort();
+        assert_eq!(
+            list.map(|value| format!("{}{}", value.key, value.tag))
+                .to_string(),
+            " 1b 1d 2a 2c 2e"
+        );
+    }
+
+    #[test]
+    fn test_std_round_trip() {
+        let values = vec![1, 2, 3, 4];
+        let list: LinkedList<u32> = values.iter().copied().collect();
+        let std_list = StdLinkedList::from(list);
+        assert_eq!(std_list.iter().copied().collect::<Vec<_>>(), values);
+        let list = LinkedList::from(std_list);
+        assert!(list == list_of(&values));
+        let deque = VecDeque::from(list);
+        assert_eq!(deque, VecDeque::from(values.clone()));
+        let list = LinkedList::from(deque);
+        assert!(list == list_of(&values));
+
+        let empty: StdLinkedList<u32> = LinkedList::new().into();
+        assert!(empty.is_empty());
     }
 }
@@ -1,58 +1,324 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::os::raw::{c_int, c_long};
+use std::sync::atomic::{AtomicUsize, Ordering};
 #[allow(unused_imports)]
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 #[allow(unused_imports)]
 use std::{env, process, thread};
 
-/// Determines whether a number is prime. This function is taken from CS 110 factor.py.
-///
-/// You don't need to read or understand this code.
-fn is_prime(num: u32) -> bool {
-    if num <= 1 {
-        return false;
+/// A cache of every prime up to some limit, shared between the worker threads so that each
+/// factorization can reuse the primes found while factoring earlier numbers. Since factoring
+/// a u32 only ever needs primes up to 2^16, the cache stays small.
+struct PrimeCache {
+    /// All primes <= `limit`, in ascending order.
+    primes: Vec<u32>,
+    limit: u32,
+}
+
+impl PrimeCache {
+    fn new() -> PrimeCache {
+        PrimeCache {
+            primes: Vec::new(),
+            limit: 1,
+        }
     }
-    for factor in 2..((num as f64).sqrt().floor() as u32) {
-        if num % factor == 0 {
-            return false;
+
+    /// Extends the cache so that it contains every prime <= `bound`. Each new candidate is
+    /// tested by trial division against the primes already in the cache.
+    fn extend_to(&mut self, bound: u32) {
+        for candidate in (self.limit + 1)..=bound {
+            let is_prime = self
+                .primes
+                .iter()
+                .take_while(|&&p| (p as u64) * (p as u64) <= candidate as u64)
+                .all(|&p| !candidate.is_multiple_of(p));
+            if is_prime {
+                self.primes.push(candidate);
+            }
         }
+        self.limit = self.limit.max(bound);
     }
-    true
 }
 
-/// Determines the prime factors of a number and prints them to stdout. This function is taken
-/// from CS 110 factor.py.
-///
-/// You don't need to read or understand this code.
-fn factor_number(num: u32) {
-    let start = Instant::now();
+/// Returns the integer square root of `num`, rounded down.
+fn isqrt(num: u32) -> u32 {
+    let mut root = (num as f64).sqrt() as u32;
+    // Correct for floating point error in either direction
+    while (root as u64) * (root as u64) > num as u64 {
+        root -= 1;
+    }
+    while ((root + 1) as u64) * ((root + 1) as u64) <= num as u64 {
+        root += 1;
+    }
+    root
+}
 
-    if num == 1 || is_prime(num) {
-        println!("{} = {} [time: {:?}]", num, num, start.elapsed());
-        return;
+/// Returns the prime factors of `num` in ascending order, using (and if necessary extending) the
+/// shared prime cache. Returns an empty vector for 0 and 1, which have no prime factorization.
+fn prime_factors(num: u32, cache: &RwLock<PrimeCache>) -> Vec<u32> {
+    if num <= 1 {
+        return Vec::new();
+    }
+    let bound = isqrt(num);
+    if cache.read().unwrap().limit < bound {
+        // Another thread may have extended the cache between our check and acquiring the write
+        // lock, but extend_to is a no-op in that case.
+        cache.write().unwrap().extend_to(bound);
     }
 
+    let cache = cache.read().unwrap();
     let mut factors = Vec::new();
     let mut curr_num = num;
-    for factor in 2..num {
-        while curr_num % factor == 0 {
-            factors.push(factor);
-            curr_num /= factor;
+    for &prime in cache.primes.iter().take_while(|&&p| p <= bound) {
+        if (prime as u64) * (prime as u64) > curr_num as u64 {
+            break;
         }
+        while curr_num.is_multiple_of(prime) {
+            factors.push(prime);
+            curr_num /= prime;
+        }
+    }
+    // Whatever is left has no factors <= its square root, so it must be prime
+    if curr_num > 1 {
+        factors.push(curr_num);
+    }
+    factors
+}
+
+/// Formats the prime factors of a number, e.g. "12 = 2 * 2 * 3". 0 and 1 have no prime
+/// factorization, so they are shown as "0 = 0" and "1 = 1".
+fn format_factorization(num: u32, factors: &[u32]) -> String {
+    if num <= 1 {
+        return format!("{} = {}", num, num);
     }
-    factors.sort();
     let factors_str = factors
-        .into_iter()
+        .iter()
         .map(|f| f.to_string())
         .collect::<Vec<String>>()
         .join(" * ");
-    println!("{} = {} [time: {:?}]", num, factors_str, start.elapsed());
+    format!("{} = {}", num, factors_str)
+}
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: c_long,
+    tv_nsec: c_long,
+}
+
+const CLOCK_THREAD_CPUTIME_ID: c_int = 3;
+
+extern "C" {
+    fn clock_gettime(clock_id: c_int, tp: *mut Timespec) -> c_int;
+}
+
+/// Returns the CPU time used so far by the calling thread. Unlike wall-clock time, this doesn't
+/// count time spent blocked on a lock or waiting to be scheduled.
+fn thread_cpu_time() -> Duration {
+    let mut time = Timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // clock_gettime can only fail for an invalid clock or pointer, and both are fine here
+    unsafe { clock_gettime(CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Determines the prime factors of a number, timing how long that takes.
+fn factor_number(num: u32, copies: usize, cache: &RwLock<PrimeCache>) -> FactorResult {
+    let start = Instant::now();
+    let cpu_start = thread_cpu_time();
+    let factors = prime_factors(num, cache);
+    FactorResult {
+        num,
+        copies,
+        factors,
+        elapsed: start.elapsed(),
+        cpu_time: thread_cpu_time().saturating_sub(cpu_start),
+    }
+}
+
+/// The factorization of one distinct input number, along with how many times it was supplied.
+struct FactorResult {
+    num: u32,
+    copies: usize,
+    factors: Vec<u32>,
+    /// How long the worker spent factoring the number
+    elapsed: Duration,
+    /// CPU time the worker used factoring the number
+    cpu_time: Duration,
+}
+
+impl FactorResult {
+    /// The lines of output for this number, one for each of the `copies` times it was supplied.
+    fn output_lines(&self) -> Vec<String> {
+        let line = format!(
+            "{} [time: {:?}]",
+            format_factorization(self.num, &self.factors),
+            self.elapsed
+        );
+        vec![line; self.copies]
+    }
+}
+
+/// Sorts the results by number and returns their lines of output in that order, so that the
+/// output doesn't depend on which worker happened to finish first.
+fn sorted_output(results: &mut [FactorResult]) -> Vec<String> {
+    results.sort_by_key(|result| result.num);
+    results
+        .iter()
+        .flat_map(FactorResult::output_lines)
+        .collect()
+}
+
+/// The outcome of factoring a batch of numbers on a pool of worker threads.
+struct FarmRun {
+    results: Vec<FactorResult>,
+    /// Number of worker threads that actually ran, counted by the threads themselves
+    workers: usize,
+    wall_time: Duration,
+}
+
+impl FarmRun {
+    /// CPU time spent factoring summed over every number, divided by the wall time: how many
+    /// workers were busy at once, on average.
+    fn effective_parallelism(&self) -> f64 {
+        let busy: Duration = self.results.iter().map(|result| result.cpu_time).sum();
+        if self.wall_time.as_secs_f64() > 0.0 {
+            busy.as_secs_f64() / self.wall_time.as_secs_f64()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Factors each distinct number in `numbers` on up to `max_workers` threads. No more workers are
+/// spawned than there are numbers, since the extra ones would have nothing to do. If
+/// `print_as_done` is set, each worker prints its results as soon as it has them.
+fn factor_all(
+    numbers: VecDeque<(u32, usize)>,
+    max_workers: usize,
+    prime_cache: &Arc<RwLock<PrimeCache>>,
+    print_as_done: bool,
+) -> FarmRun {
+    let start = Instant::now();
+    let workers = max_workers.min(numbers.len());
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let queue = Arc::new(Mutex::new(numbers));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let mut threads = vec![];
+
+    // spawn `workers` threads, each of which pops numbers off the queue and calls
+    for _ in 0..workers {
+        let queue = queue.clone();
+        let prime_cache = prime_cache.clone();
+        let results = results.clone();
+        let spawned = spawned.clone();
+        let handle = thread::spawn(move || {
+            spawned.fetch_add(1, Ordering::SeqCst);
+            // factor_number() until the queue is empty
+            while let Some((num, copies)) = queue.lock().unwrap().pop_front() {
+                let result = factor_number(num, copies, &prime_cache);
+                if print_as_done {
+                    for line in result.output_lines() {
+                        println!("{}", line);
+                    }
+                }
+                results.lock().unwrap().push(result);
+            }
+        });
+        threads.push(handle);
+    }
+
+    // join all the threads you created
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let results = std::mem::take(&mut *results.lock().unwrap());
+    FarmRun {
+        results,
+        workers: spawned.load(Ordering::SeqCst),
+        wall_time: start.elapsed(),
+    }
 }
 
-/// Returns a list of numbers supplied via argv.
-fn get_input_numbers() -> VecDeque<u32> {
+/// Statistics over a whole batch, printed with --summary. Repeated inputs count once per copy.
+#[derive(Debug, PartialEq)]
+struct FactorSummary {
+    inputs: usize,
+    primes: usize,
+    /// The input with the most prime factors (counted with multiplicity) and how many it has.
+    /// Ties go to the smallest number.
+    most_factors: Option<(u32, usize)>,
+    /// Maps a number of prime factors to how many inputs have that many
+    histogram: BTreeMap<usize, usize>,
+}
+
+impl FactorSummary {
+    fn new(results: &[FactorResult]) -> FactorSummary {
+        let mut summary = FactorSummary {
+            inputs: 0,
+            primes: 0,
+            most_factors: None,
+            histogram: BTreeMap::new(),
+        };
+        for result in results {
+            let count = result.factors.len();
+            summary.inputs += result.copies;
+            if count == 1 {
+                summary.primes += result.copies;
+            }
+            *summary.histogram.entry(count).or_insert(0) += result.copies;
+            let is_new_max = match summary.most_factors {
+                None => true,
+                Some((num, max)) => count > max || (count == max && result.num < num),
+            };
+            if is_new_max {
+                summary.most_factors = Some((result.num, count));
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for FactorSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Summary:")?;
+        writeln!(f, "  inputs: {}, primes: {}", self.inputs, self.primes)?;
+        if let Some((num, count)) = self.most_factors {
+            writeln!(f, "  most prime factors: {} ({})", num, count)?;
+        }
+        writeln!(f, "  prime factor counts:")?;
+        for (count, inputs) in &self.histogram {
+            writeln!(f, "  {:>4} | {} {}", count, "#".repeat(*inputs), inputs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collapses repeated numbers into a single entry with a count, so that each distinct number is
+/// only factored once. Numbers stay in the order they first appeared.
+fn dedup_input_numbers(numbers: VecDeque<u32>) -> VecDeque<(u32, usize)> {
+    let mut deduped: VecDeque<(u32, usize)> = VecDeque::new();
+    let mut positions = HashMap::new();
+    for num in numbers {
+        match positions.get(&num) {
+            Some(&idx) => deduped[idx].1 += 1,
+            None => {
+                positions.insert(num, deduped.len());
+                deduped.push_back((num, 1));
+            }
+        }
+    }
+    deduped
+}
+
+/// Returns a list of the numbers supplied in `args`.
+fn get_input_numbers(args: &[String]) -> VecDeque<u32> {
     let mut numbers = VecDeque::new();
-    for arg in env::args().skip(1) {
+    for arg in args {
         if let Ok(val) = arg.parse::<u32>() {
             numbers.push_back(val);
         } else {
@@ -68,26 +334,228 @@ fn main() {
     println!("Farm starting on {} CPUs", num_threads);
     let start = Instant::now();
 
-    // call get_input_numbers() and store a queue of numbers to factor
-    let vec_deq = Arc::new(Mutex::new(get_input_numbers()));
-    let mut threads = vec![];
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // Print statistics over all the factorizations at the end
+    let summary = args.iter().any(|arg| arg == "--summary");
+    args.retain(|arg| arg != "--summary");
+    // Print the results in ascending order of the input numbers once they are all in, rather
+    // than as each one is done
+    let sort = args.iter().any(|arg| arg == "--sort");
+    args.retain(|arg| arg != "--sort");
 
-    // spawn `num_threads` threads, each of which pops numbers off the queue and calls
-    for _ in 0..num_threads {
-        let vec = vec_deq.clone();
-        let handle = thread::spawn(move || {
-            // factor_number() until the queue is empty
-            while let Some(num) = vec.lock().unwrap().pop_front() {
-                factor_number(num);
-            }
-        });
-        threads.push(handle);
+    // call get_input_numbers() and factor the distinct numbers
+    let numbers = dedup_input_numbers(get_input_numbers(&args));
+    let prime_cache = Arc::new(RwLock::new(PrimeCache::new()));
+    let mut run = factor_all(numbers, num_threads, &prime_cache, !sort);
+    if sort {
+        for line in sorted_output(&mut run.results) {
+            println!("{}", line);
+        }
     }
 
-    // join all the threads you created
-    for thread in threads {
-        thread.join().unwrap();
+    if summary {
+        print!("{}", FactorSummary::new(&run.results));
+        println!(
+            "  workers: {}, effective parallelism: {:.2}",
+            run.workers,
+            run.effective_parallelism()
+        );
     }
 
+    // The cache is only filled in if some number needed trial division
+    let prime_cache = prime_cache.read().unwrap();
+    if !prime_cache.primes.is_empty() {
+        println!(
+            "Prime cache: {} primes up to {}",
+            prime_cache.primes.len(),
+            prime_cache.limit
+        );
+    }
     println!("Total execution time: {:?}", start.elapsed());
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The original trial division from CS 110 factor.py, kept as a reference implementation.
+    fn naive_prime_factors(num: u32) -> Vec<u32> {
+        let mut factors = Vec::new();
+        let mut curr_num = num;
+        for factor in 2..=num {
+            while curr_num.is_multiple_of(factor) {
+                factors.push(factor);
+                curr_num /= factor;
+            }
+        }
+        factors
+    }
+
+    #[test]
+    fn test_prime_factors_match_naive() {
+        let cache = RwLock::new(PrimeCache::new());
+        for num in (0..5000).chain(vec![65521, 65536, 99991, 1_000_000, 1_046_527]) {
+            assert_eq!(
+                prime_factors(num, &cache),
+                naive_prime_factors(num),
+                "Factorization of {} differs from the naive version",
+                num
+            );
+        }
+    }
+
+    #[test]
+    fn test_prime_factors_large_inputs() {
+        let cache = RwLock::new(PrimeCache::new());
+        assert_eq!(prime_factors(4294967291, &cache), vec![4294967291]);
+        assert_eq!(
+            prime_factors(4294967295, &cache),
+            vec![3, 5, 17, 257, 65537]
+        );
+        assert_eq!(prime_factors(65521 * 65521, &cache), vec![65521, 65521]);
+        assert_eq!(cache.read().unwrap().limit, 65535);
+    }
+
+    #[test]
+    fn test_format_factorization_small_inputs() {
+        let cache = RwLock::new(PrimeCache::new());
+        assert_eq!(format_factorization(0, &prime_factors(0, &cache)), "0 = 0");
+        assert_eq!(format_factorization(1, &prime_factors(1, &cache)), "1 = 1");
+        assert_eq!(format_factorization(2, &prime_factors(2, &cache)), "2 = 2");
+        assert_eq!(
+            format_factorization(12, &prime_factors(12, &cache)),
+            "12 = 2 * 2 * 3"
+        );
+    }
+
+    #[test]
+    fn test_dedup_input_numbers() {
+        let numbers: VecDeque<u32> = vec![12, 0, 12, 1, 2, 12, 0].into_iter().collect();
+        assert_eq!(
+            dedup_input_numbers(numbers),
+            vec![(12, 3), (0, 2), (1, 1), (2, 1)]
+        );
+        assert!(dedup_input_numbers(VecDeque::new()).is_empty());
+    }
+
+    #[test]
+    fn test_prime_cache_speedup() {
+        let nums: Vec<u32> = (0..50).map(|i| 1_000_000 + 7919 * i).collect();
+
+        let start = Instant::now();
+        let naive: Vec<Vec<u32>> = nums.iter().map(|&n| naive_prime_factors(n)).collect();
+        let naive_time = start.elapsed();
+
+        let cache = RwLock::new(PrimeCache::new());
+        let start = Instant::now();
+        let cached: Vec<Vec<u32>> = nums.iter().map(|&n| prime_factors(n, &cache)).collect();
+        let cached_time = start.elapsed();
+
+        assert_eq!(naive, cached);
+        println!(
+            "naive: {:?}, cached: {:?}, speedup: {:.1}x",
+            naive_time,
+            cached_time,
+            naive_time.as_secs_f64() / cached_time.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn test_factor_summary() {
+        let cache = RwLock::new(PrimeCache::new());
+        let results: Vec<FactorResult> = vec![(12, 1), (7, 2), (1, 1), (8, 1), (13, 1)]
+            .into_iter()
+            .map(|(num, copies)| FactorResult {
+                num,
+                copies,
+                factors: prime_factors(num, &cache),
+                elapsed: Duration::from_millis(1),
+                cpu_time: Duration::from_millis(1),
+            })
+            .collect();
+        let summary = FactorSummary::new(&results);
+        assert_eq!(
+            summary,
+            FactorSummary {
+                inputs: 6,
+                primes: 3,
+                most_factors: Some((8, 3)),
+                histogram: vec![(0, 1), (1, 3), (3, 2)].into_iter().collect(),
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "Summary:\n  inputs: 6, primes: 3\n  most prime factors: 8 (3)\n  \
+             prime factor counts:\n     0 | # 1\n     1 | ### 3\n     3 | ## 2\n"
+        );
+        assert_eq!(FactorSummary::new(&[]).most_factors, None);
+    }
+
+    #[test]
+    fn test_workers_bounded_by_inputs() {
+        let cache = Arc::new(RwLock::new(PrimeCache::new()));
+        let run = factor_all(vec![(12, 3)].into_iter().collect(), 16, &cache, false);
+        assert_eq!(run.workers, 1);
+        assert_eq!(run.results.len(), 1);
+        assert_eq!(run.results[0].factors, vec![2, 2, 3]);
+
+        let numbers: VecDeque<(u32, usize)> = (2..10).map(|num| (num, 1)).collect();
+        let run = factor_all(numbers, 4, &cache, false);
+        assert_eq!(run.workers, 4);
+        assert_eq!(run.results.len(), 8);
+        assert_eq!(factor_all(VecDeque::new(), 4, &cache, false).workers, 0);
+    }
+
+    #[test]
+    fn test_sorted_output() {
+        let cache = Arc::new(RwLock::new(PrimeCache::new()));
+        let numbers = dedup_input_numbers(vec![12, 7, 30, 1, 7].into_iter().collect());
+        let mut run = factor_all(numbers, 4, &cache, false);
+        // Leave out the timings, which differ from run to run
+        let factorizations: Vec<String> = sorted_output(&mut run.results)
+            .iter()
+            .map(|line| line.split(" [time: ").next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            factorizations,
+            vec![
+                "1 = 1",
+                "7 = 7",
+                "7 = 7",
+                "12 = 2 * 2 * 3",
+                "30 = 2 * 3 * 5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_cpu_time_skips_sleep() {
+        let cpu_start = thread_cpu_time();
+        thread::sleep(Duration::from_millis(100));
+        assert!(thread_cpu_time() - cpu_start < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_effective_parallelism() {
+        let result = |ms| FactorResult {
+            num: 2,
+            copies: 1,
+            factors: vec![2],
+            // Waiting on the cache lock or the scheduler doesn't count
+            elapsed: Duration::from_millis(ms * 3),
+            cpu_time: Duration::from_millis(ms),
+        };
+        let run = FarmRun {
+            results: vec![result(30), result(50), result(20)],
+            workers: 2,
+            wall_time: Duration::from_millis(50),
+        };
+        assert!((run.effective_parallelism() - 2.0).abs() < 1e-9);
+        let idle = FarmRun {
+            results: Vec::new(),
+            workers: 0,
+            wall_time: Duration::from_secs(0),
+        };
+        assert_eq!(idle.effective_parallelism(), 0.0);
+    }
+}
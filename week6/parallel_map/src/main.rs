@@ -1,7 +1,39 @@
-use crossbeam_channel;
-use std::{thread, time};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::{fmt, io, thread, time};
 
-fn parallel_map<T, U, F>(mut input_vec: Vec<T>, num_threads: usize, f: F) -> Vec<U>
+/// 启动工作线程失败（例如线程数或内存达到上限）。`started` 是失败前已经成功启动的线程数。
+#[derive(Debug)]
+pub struct SpawnError {
+    pub started: usize,
+    pub source: io::Error,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "parallel_map: failed to spawn a worker thread after starting {}: {}",
+            self.started, self.source
+        )
+    }
+}
+
+/// 从 panic 的 payload 中取出消息。`panic!` 的参数通常是 `&str` 或 `String`。
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+/// 用 num_threads 个工作线程并行地把 f 作用到每个元素上，结果保持输入顺序。
+/// 工作线程命名为 `parallel_map-worker-{i}`，便于在调试器和 /proc 中辨认。
+/// 如果有线程启动失败，已启动的线程会在没有任务后退出，并返回 SpawnError。
+fn parallel_map<T, U, F>(input_vec: Vec<T>, num_threads: usize, f: F) -> Result<Vec<U>, SpawnError>
 where
     F: FnOnce(T) -> U + Send + Copy + 'static,
     T: Send + 'static,
@@ -9,24 +41,32 @@ where
 {
     let len = input_vec.len();
     let mut output_vec: Vec<U> = Vec::with_capacity(input_vec.len()); // 初始化输出向量
-    for i in 0..len {
+    for _ in 0..len {
         output_vec.push(U::default());
     }
 
     // 创建通道：发送任务和接收结果
     let (task_sender, task_receiver) = crossbeam_channel::unbounded::<(usize, T)>();
-    let (result_sender, result_receiver) = crossbeam_channel::unbounded::<(usize, U)>();
+    let (result_sender, result_receiver) =
+        crossbeam_channel::unbounded::<(usize, thread::Result<U>)>();
 
     // 启动工作线程
-    for _ in 0..num_threads {
+    for i in 0..num_threads {
         let task_receiver = task_receiver.clone();
         let result_sender = result_sender.clone();
-        thread::spawn(move || {
-            while let Ok((index, input)) = task_receiver.recv() {
-                let output = f(input); // 执行 f
-                result_sender.send((index, output)).unwrap();
-            }
-        });
+        let spawned = thread::Builder::new()
+            .name(format!("parallel_map-worker-{}", i))
+            .spawn(move || {
+                while let Ok((index, input)) = task_receiver.recv() {
+                    // 执行 f，并捕获 panic，把 payload 交给主线程
+                    let output = panic::catch_unwind(AssertUnwindSafe(|| f(input)));
+                    result_sender.send((index, output)).unwrap();
+                }
+            });
+        // 此时还没有分发任务；返回时 task_sender 被丢弃，已启动的线程会随之退出
+        if let Err(source) = spawned {
+            return Err(SpawnError { started: i, source });
+        }
     }
 
     // 分发任务
@@ -38,10 +78,18 @@ where
     // 收集结果
     for _ in 0..len {
         let (index, output) = result_receiver.recv().unwrap();
-        output_vec[index] = output;
+        match output {
+            Ok(output) => output_vec[index] = output,
+            // 在主线程重新 panic，带上出错元素的下标和原始消息
+            Err(payload) => panic!(
+                "parallel_map: closure panicked on element {}: {}",
+                index,
+                panic_message(payload.as_ref())
+            ),
+        }
     }
 
-    output_vec
+    Ok(output_vec)
 }
 
 fn main() {
@@ -51,5 +99,71 @@ fn main() {
         thread::sleep(time::Duration::from_millis(500));
         num * num
     });
-    println!("squares: {:?}", squares);
+    match squares {
+        Ok(squares) => println!("squares: {:?}", squares),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parallel_map() {
+        let squares = parallel_map((0..20).collect(), 4, |num: u32| num * num).unwrap();
+        assert_eq!(squares, (0..20).map(|num| num * num).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parallel_map_worker_names() {
+        let names = parallel_map((0..8).collect(), 3, |_: u32| {
+            thread::current().name().unwrap_or("").to_string()
+        })
+        .unwrap();
+        let workers = [
+            "parallel_map-worker-0",
+            "parallel_map-worker-1",
+            "parallel_map-worker-2",
+        ];
+        assert!(names.iter().all(|name| workers.contains(&name.as_str())));
+    }
+
+    #[test]
+    fn test_spawn_error_message() {
+        let err = SpawnError {
+            started: 2,
+            source: io::Error::from(io::ErrorKind::OutOfMemory),
+        };
+        assert!(err
+            .to_string()
+            .starts_with("parallel_map: failed to spawn a worker thread after starting 2"));
+    }
+
+    #[test]
+    #[should_panic(expected = "closure panicked on element 3: cannot handle 3")]
+    fn test_parallel_map_panic_message() {
+        parallel_map(vec![0, 1, 2, 3, 4], 2, |num: u32| {
+            if num == 3 {
+                panic!("cannot handle {}", num);
+            }
+            num
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "closure panicked on element 1: static message")]
+    fn test_parallel_map_panic_str_payload() {
+        parallel_map(vec![0, 1], 1, |num: u32| {
+            if num == 1 {
+                panic!("static message");
+            }
+            num
+        })
+        .unwrap();
+    }
 }